@@ -1,5 +1,5 @@
 use anyhow::{Error, anyhow};
-use docs_rs_builder::{RustwideBuilder, queue_builder};
+use docs_rs_builder::queue_builder;
 use docs_rs_config::AppConfig as _;
 use docs_rs_context::Context;
 use docs_rs_watcher::{
@@ -48,13 +48,11 @@ pub fn start_daemon(context: Context) -> Result<(), Error> {
     start_registry_watcher(watcher_config.clone(), context.clone())?;
 
     // build new crates every minute
-    let rustwide_builder = RustwideBuilder::init(builder_config.clone(), &context)?;
-
     thread::Builder::new()
         .name("build queue reader".to_string())
         .spawn({
             let context = context.clone();
-            move || queue_builder(&context, &builder_config, rustwide_builder).unwrap()
+            move || queue_builder(&context, &builder_config).unwrap()
         })
         .unwrap();
 
@@ -6,15 +6,19 @@ use tracing::{info, instrument};
 
 async fn check_archive(storage: &AsyncStorage, path: impl AsRef<str>) -> Result<()> {
     let path = path.as_ref();
-    if !storage.exists(path).await? {
-        bail!("archive {} missing", path);
-    }
-
     let index_path = format!("{path}.index");
     if !storage.exists(&index_path).await? {
         bail!("archive index {} missing", index_path);
     }
 
+    // content-addressed releases only have the archive index.
+    // Repacked archives are in another object, see `AsyncStorage::repack_archive`.
+    if let Some(object) = storage.archive_object(path, None).await?
+        && !storage.exists(&object).await?
+    {
+        bail!("archive {} missing", path);
+    }
+
     Ok(())
 }
 
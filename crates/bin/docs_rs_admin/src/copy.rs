@@ -0,0 +1,325 @@
+use anyhow::{Context as _, Result, bail};
+use docs_rs_storage::{AsyncStorage, Config, StorageLocation};
+use futures_util::{StreamExt as _, TryStreamExt as _, future, stream};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing::{info, instrument};
+
+/// how many objects we copy between two checkpoints.
+const CHECKPOINT_INTERVAL: usize = 1000;
+
+/// A storage to copy from or to, from a TOML file like
+///
+/// ```toml
+/// backend = "s3"
+/// bucket = "rust-docs-rs"
+/// region = "eu-central-1"
+/// endpoint = "https://s3.example.com"
+/// ```
+///
+/// or `backend = "filesystem"` with a `path`. The region and endpoint default to the
+/// storage configuration, like all other settings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum StorageConfigFile {
+    S3 {
+        bucket: String,
+        region: Option<String>,
+        endpoint: Option<String>,
+    },
+    Filesystem {
+        path: PathBuf,
+    },
+}
+
+impl StorageConfigFile {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("invalid storage configuration in {}", path.display()))
+    }
+
+    pub(crate) fn location(&self) -> StorageLocation {
+        match self {
+            Self::S3 { bucket, .. } => StorageLocation::S3 {
+                bucket: bucket.clone(),
+            },
+            Self::Filesystem { path } => StorageLocation::Filesystem { path: path.clone() },
+        }
+    }
+
+    /// The storage configuration for this storage, based on `config`.
+    pub(crate) fn apply(&self, config: &Config) -> Config {
+        let mut config = config.with_location(&self.location());
+        if let Self::S3 {
+            region, endpoint, ..
+        } = self
+        {
+            if let Some(region) = region {
+                config.s3_region = region.clone();
+            }
+            if let Some(endpoint) = endpoint {
+                config.s3_endpoint = Some(endpoint.clone());
+            }
+        }
+        config
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CopyStatistics {
+    /// the path we resumed after, from the last checkpoint.
+    pub(crate) resumed_after: Option<String>,
+    /// copied objects and their stored size, including the ones copied before resuming.
+    pub(crate) copied_objects: u64,
+    pub(crate) copied_bytes: u64,
+}
+
+/// Copy all objects under `prefix` from `source` to `destination`, as they are stored.
+///
+/// Objects are copied in batches, in the order of their paths. After each batch we store a
+/// checkpoint named `name`, and the next copy with the same name and prefix resumes after it.
+/// So objects created during a copy might be missed, use `Config::migrate_from` on the
+/// servers for that, with `source` as the storage they migrate from. Objects they
+/// change or delete while we copy them are copied again or skipped, see
+/// `AsyncStorage::copy_object_to`. With `verify` we compare each copy with its source.
+#[instrument(skip(conn, source, destination))]
+pub(crate) async fn copy_storage(
+    conn: &mut sqlx::PgConnection,
+    source: &AsyncStorage,
+    destination: &AsyncStorage,
+    name: &str,
+    prefix: &str,
+    parallelism: usize,
+    verify: bool,
+) -> Result<CopyStatistics> {
+    let mut stats = sqlx::query!(
+        "SELECT last_path, copied_objects, copied_bytes
+         FROM storage_copy_checkpoints
+         WHERE name = $1 AND prefix = $2",
+        name,
+        prefix,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .map(|row| CopyStatistics {
+        resumed_after: Some(row.last_path),
+        copied_objects: row.copied_objects as u64,
+        copied_bytes: row.copied_bytes as u64,
+    })
+    .unwrap_or_default();
+
+    if let Some(last_path) = &stats.resumed_after {
+        info!(last_path, "resuming copy");
+    }
+
+    let resume_after = stats.resumed_after.clone();
+    let mut batches = source
+        .list_prefix(prefix)
+        .await
+        .try_filter(|path| {
+            future::ready(
+                resume_after
+                    .as_ref()
+                    .is_none_or(|last_path| path > last_path),
+            )
+        })
+        .try_chunks(CHECKPOINT_INTERVAL)
+        .map_err(|err| err.1);
+
+    while let Some(batch) = batches.try_next().await? {
+        let (copied_objects, copied_bytes) = stream::iter(&batch)
+            .map(|path| async move {
+                let Some(size) = source.copy_object_to(path, destination).await? else {
+                    info!(path, "object was deleted, skipping");
+                    return Ok(None);
+                };
+                if verify && !source.verify_object_copy(path, destination).await? {
+                    bail!("the copy of {path} differs from the original");
+                }
+                Ok(Some(size))
+            })
+            .buffer_unordered(parallelism)
+            .try_fold((0, 0), |(objects, bytes), size| async move {
+                Ok(match size {
+                    Some(size) => (objects + 1, bytes + size),
+                    None => (objects, bytes),
+                })
+            })
+            .await?;
+
+        stats.copied_objects += copied_objects;
+        stats.copied_bytes += copied_bytes;
+        let last_path = batch.last().expect("batches aren't empty");
+
+        sqlx::query!(
+            "INSERT INTO storage_copy_checkpoints
+                 (name, prefix, last_path, copied_objects, copied_bytes)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (name, prefix) DO UPDATE
+             SET last_path = EXCLUDED.last_path,
+                 copied_objects = EXCLUDED.copied_objects,
+                 copied_bytes = EXCLUDED.copied_bytes,
+                 updated_at = NOW()",
+            name,
+            prefix,
+            last_path,
+            stats.copied_objects as i64,
+            stats.copied_bytes as i64,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        info!(
+            last_path,
+            copied_objects = stats.copied_objects,
+            copied_bytes = stats.copied_bytes,
+            "stored checkpoint"
+        );
+    }
+
+    Ok(stats)
+}
+
+/// Forget the checkpoint of a copy, so the next one starts from the beginning.
+pub(crate) async fn reset_checkpoint(
+    conn: &mut sqlx::PgConnection,
+    name: &str,
+    prefix: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM storage_copy_checkpoints WHERE name = $1 AND prefix = $2",
+        name,
+        prefix,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnvironment;
+    use docs_rs_opentelemetry::testing::TestMetrics;
+    use docs_rs_storage::{StorageKind, testing::TestStorage};
+    use pretty_assertions::assert_eq;
+
+    const NAME: &str = "memory -> memory";
+
+    #[test]
+    fn test_storage_config_file() -> Result<()> {
+        let config = Config::test_config_with_kind(StorageKind::Memory)?;
+
+        let s3: StorageConfigFile = toml::from_str(
+            r#"
+            backend = "s3"
+            bucket = "other-bucket"
+            endpoint = "https://s3.example.com"
+            "#,
+        )?;
+        let s3 = s3.apply(&config);
+        assert!(matches!(s3.storage_backend, StorageKind::S3));
+        assert_eq!(s3.s3_bucket, "other-bucket");
+        assert_eq!(s3.s3_region, config.s3_region);
+        assert_eq!(s3.s3_endpoint.as_deref(), Some("https://s3.example.com"));
+        assert!(s3.migrate_from.is_none());
+
+        let filesystem: StorageConfigFile = toml::from_str(
+            r#"
+            backend = "filesystem"
+            path = "/srv/storage"
+            "#,
+        )?;
+        assert_eq!(
+            filesystem.location(),
+            StorageLocation::Filesystem {
+                path: "/srv/storage".into()
+            }
+        );
+
+        for invalid in [
+            r#"backend = "ftp""#,
+            r#"backend = "s3""#,
+            r#"backend = "filesystem"
+               path = "/srv/storage"
+               bucket = "typo""#,
+        ] {
+            assert!(
+                toml::from_str::<StorageConfigFile>(invalid).is_err(),
+                "{invalid}"
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn list(storage: &AsyncStorage) -> Result<Vec<String>> {
+        storage.list_prefix("").await.try_collect().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_copy_storage() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+        let metrics = TestMetrics::new();
+        let source = TestStorage::from_kind(StorageKind::Memory, metrics.provider()).await?;
+        let destination = TestStorage::from_kind(StorageKind::Memory, metrics.provider()).await?;
+
+        source.store_one("rustdoc/foo/1.0.0.zip", "foo").await?;
+        source
+            .store_one_uncompressed("sources/foo/1.0.0.zip", "foo")
+            .await?;
+        source.store_one("sources/bar/1.0.0.zip", "bar").await?;
+
+        let stats =
+            copy_storage(&mut conn, &source, &destination, NAME, "sources/", 2, true).await?;
+        assert_eq!(
+            stats,
+            CopyStatistics {
+                resumed_after: None,
+                copied_objects: 2,
+                copied_bytes: stats.copied_bytes,
+            }
+        );
+        assert!(stats.copied_bytes > 0);
+        assert_eq!(
+            list(&destination).await?,
+            vec!["sources/bar/1.0.0.zip", "sources/foo/1.0.0.zip"]
+        );
+        assert!(
+            source
+                .verify_object_copy("sources/foo/1.0.0.zip", &destination)
+                .await?
+        );
+
+        // resume after the last copied object
+        source.store_one("sources/baz/1.0.0.zip", "baz").await?;
+        source.store_one("sources/qux/1.0.0.zip", "qux").await?;
+        let resumed =
+            copy_storage(&mut conn, &source, &destination, NAME, "sources/", 2, true).await?;
+        assert_eq!(
+            resumed.resumed_after.as_deref(),
+            Some("sources/foo/1.0.0.zip")
+        );
+        assert_eq!(resumed.copied_objects, 3);
+        assert_eq!(
+            list(&destination).await?,
+            vec![
+                "sources/bar/1.0.0.zip",
+                "sources/foo/1.0.0.zip",
+                "sources/qux/1.0.0.zip"
+            ]
+        );
+
+        // start from the beginning
+        reset_checkpoint(&mut conn, NAME, "sources/").await?;
+        let stats = copy_storage(&mut conn, &source, &destination, NAME, "", 2, false).await?;
+        assert_eq!(stats.resumed_after, None);
+        assert_eq!(stats.copied_objects, 5);
+        assert_eq!(list(&destination).await?, list(&source).await?);
+
+        Ok(())
+    }
+}
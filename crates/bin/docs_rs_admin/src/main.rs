@@ -1,29 +1,44 @@
 mod cleanup_s3;
+mod copy;
 mod rebuilds;
+mod repack;
 #[cfg(test)]
 pub(crate) mod testing;
+mod verify;
 
 use anyhow::{Context as _, Result, bail};
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveTime, Utc};
 use clap::{Parser, Subcommand};
-use docs_rs_build_limits::{Overrides, blacklist};
+use docs_rs_build_limits::{
+    Overrides, blacklist,
+    requests::{self, RequestStatus},
+    toolchain_overrides,
+};
 use docs_rs_build_queue::priority::{
     get_crate_pattern_and_priority, list_crate_priorities, remove_crate_priority,
     set_crate_priority,
 };
+use docs_rs_config::AppConfig as _;
 use docs_rs_context::Context;
 use docs_rs_database::{
-    crate_details,
-    service_config::{Abnormality, ConfigName, remove_config, set_config},
+    audit_log::{self, AuditLogFilter},
+    build_failures::{self, FailureSignature, MAX_FAILURE_CLUSTER_DAYS},
+    crate_details, release_dependencies,
+    service_config::{Abnormality, ConfigName, ToolchainWindow, remove_config, set_config},
 };
 use docs_rs_fastly::CdnBehaviour as _;
 use docs_rs_headers::SurrogateKey;
 use docs_rs_repository_stats::workspaces;
+use docs_rs_storage::AsyncStorage;
 use docs_rs_types::{CrateId, KrateName, Version};
 use docs_rs_uri::EscapedURI;
+use docs_rs_webhooks::subscriptions as webhooks;
 use futures_util::StreamExt;
 use rebuilds::queue_rebuilds_faulty_rustdoc;
-use std::iter;
+use serde_json::{Value, json};
+use sqlx::Connection as _;
+use std::{iter, path::PathBuf, sync::Arc};
+use url::Url;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -38,6 +53,36 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Record a change made through the admin CLI in the audit log.
+async fn audit(
+    conn: &mut sqlx::PgConnection,
+    action: &str,
+    target: Option<&str>,
+    old_value: Value,
+    new_value: Value,
+) -> Result<()> {
+    audit_log::record(
+        conn,
+        &audit_log::current_actor(),
+        action,
+        target,
+        old_value,
+        new_value,
+    )
+    .await
+    .context("failed to write the audit log")
+}
+
+fn overrides_json(overrides: Option<&Overrides>) -> Value {
+    overrides.map_or(Value::Null, |overrides| {
+        json!({
+            "memory": overrides.memory,
+            "targets": overrides.targets,
+            "timeout": overrides.timeout.map(|timeout| timeout.as_secs()),
+        })
+    })
+}
+
 #[derive(Debug, Clone, PartialEq, Parser)]
 #[command(
     about = env!("CARGO_PKG_DESCRIPTION"),
@@ -66,6 +111,18 @@ enum CommandLine {
         #[command(subcommand)]
         subcommand: CdnSubcommand,
     },
+
+    /// The log of administrative changes
+    Audit {
+        #[command(subcommand)]
+        subcommand: AuditSubcommand,
+    },
+
+    /// Operations on the files in storage
+    Storage {
+        #[command(subcommand)]
+        subcommand: StorageSubcommand,
+    },
 }
 
 impl CommandLine {
@@ -89,6 +146,8 @@ impl CommandLine {
             Self::Database { subcommand } => subcommand.handle_args(ctx).await?,
             Self::Queue { subcommand } => subcommand.handle_args(ctx).await?,
             Self::Cdn { subcommand } => subcommand.handle_args(ctx).await?,
+            Self::Audit { subcommand } => subcommand.handle_args(ctx).await?,
+            Self::Storage { subcommand } => subcommand.handle_args(ctx).await?,
         }
 
         Ok(())
@@ -138,6 +197,16 @@ enum QueueSubcommand {
         #[arg(name = "END", short = 'e', long = "end")]
         end_nightly_date: Option<NaiveDate>,
     },
+
+    /// Queue rebuilds for the releases in a failure cluster, see `build failure-clusters`
+    RebuildFailureCluster {
+        /// The compiler error of the cluster, like "error[E0432]: unresolved import `foo`"
+        #[arg(name = "SIGNATURE")]
+        signature: String,
+        /// Only include builds finished in the last N days
+        #[arg(long, default_value = "7")]
+        days: u32,
+    },
 }
 
 impl QueueSubcommand {
@@ -150,7 +219,17 @@ impl QueueSubcommand {
             } => {
                 ctx.build_queue()?
                     .add_crate(&crate_name, &crate_version, build_priority)
-                    .await?
+                    .await?;
+
+                let mut conn = ctx.pool()?.get_async().await?;
+                audit(
+                    &mut conn,
+                    "queue.add",
+                    Some(&format!("{crate_name} {crate_version}")),
+                    Value::Null,
+                    json!({ "priority": build_priority }),
+                )
+                .await?;
             }
 
             Self::DefaultPriority { subcommand } => subcommand.handle_args(ctx).await?,
@@ -172,6 +251,40 @@ impl QueueSubcommand {
                 println!(
                     "Queued {queued_rebuilds_amount} rebuilds for broken nightly versions of rustdoc"
                 );
+                audit(
+                    &mut conn,
+                    "queue.rebuild-broken-nightly",
+                    None,
+                    Value::Null,
+                    json!({
+                        "start": start_nightly_date,
+                        "end": end_nightly_date,
+                        "queued": queued_rebuilds_amount,
+                    }),
+                )
+                .await?;
+            }
+
+            Self::RebuildFailureCluster { signature, days } => {
+                let signature = FailureSignature::from_line(&signature)
+                    .context("invalid signature, expected a compiler error like \"error: ...\"")?;
+                let days = days.min(MAX_FAILURE_CLUSTER_DAYS);
+                let since = Utc::now() - chrono::Duration::days(days.into());
+                let queued = ctx
+                    .build_queue()?
+                    .queue_failure_cluster_rebuilds(&signature, since)
+                    .await?;
+                println!("Queued {queued} rebuilds for {signature}");
+
+                let mut conn = ctx.pool()?.get_async().await?;
+                audit(
+                    &mut conn,
+                    "queue.rebuild-failure-cluster",
+                    Some(&signature.to_string()),
+                    Value::Null,
+                    json!({ "days": days, "queued": queued }),
+                )
+                .await?;
             }
         }
         Ok(())
@@ -207,10 +320,13 @@ enum RepositoryPrioritySubcommand {
 impl RepositoryPrioritySubcommand {
     async fn handle_args(self, ctx: Context) -> Result<()> {
         let mut conn = ctx.pool()?.get_async().await?;
+        // audit log entries are written in the same transaction as the change they record.
+        let mut transaction = conn.begin().await?;
         match self {
             Self::Get { repository_name } => {
                 let priority =
-                    workspaces::get_repository_build_priority(&mut conn, &repository_name).await?;
+                    workspaces::get_repository_build_priority(&mut transaction, &repository_name)
+                        .await?;
 
                 match priority {
                     Some(priority) => println!("{repository_name} : {priority}"),
@@ -219,7 +335,9 @@ impl RepositoryPrioritySubcommand {
             }
 
             Self::List => {
-                for (name, prio) in workspaces::list_repository_build_priorities(&mut conn).await? {
+                for (name, prio) in
+                    workspaces::list_repository_build_priorities(&mut transaction).await?
+                {
                     println!("{:>20} : {:>3}", name, prio);
                 }
             }
@@ -228,16 +346,44 @@ impl RepositoryPrioritySubcommand {
                 repository_name,
                 priority,
             } => {
-                workspaces::set_repository_build_priority(&mut conn, &repository_name, priority)
-                    .await?;
+                let old_priority =
+                    workspaces::get_repository_build_priority(&mut transaction, &repository_name)
+                        .await?;
+                workspaces::set_repository_build_priority(
+                    &mut transaction,
+                    &repository_name,
+                    priority,
+                )
+                .await?;
+                audit(
+                    &mut transaction,
+                    "queue.repository-priority.set",
+                    Some(&repository_name),
+                    json!(old_priority),
+                    json!(priority),
+                )
+                .await?;
 
                 println!("Set repository '{repository_name}' to priority {priority}");
             }
 
             Self::Remove { repository_name } => {
-                workspaces::remove_repository_build_priority(&mut conn, &repository_name).await?;
+                let old_priority =
+                    workspaces::get_repository_build_priority(&mut transaction, &repository_name)
+                        .await?;
+                workspaces::remove_repository_build_priority(&mut transaction, &repository_name)
+                    .await?;
+                audit(
+                    &mut transaction,
+                    "queue.repository-priority.remove",
+                    Some(&repository_name),
+                    json!(old_priority),
+                    Value::Null,
+                )
+                .await?;
             }
         }
+        transaction.commit().await?;
         Ok(())
     }
 }
@@ -273,16 +419,18 @@ enum PrioritySubcommand {
 impl PrioritySubcommand {
     async fn handle_args(self, ctx: Context) -> Result<()> {
         let mut conn = ctx.pool()?.get_async().await?;
+        // audit log entries are written in the same transaction as the change they record.
+        let mut transaction = conn.begin().await?;
         match self {
             Self::List => {
-                for (pattern, priority) in list_crate_priorities(&mut conn).await? {
+                for (pattern, priority) in list_crate_priorities(&mut transaction).await? {
                     println!("{pattern:>20} : {priority:>3}");
                 }
             }
 
             Self::Get { crate_name } => {
                 if let Some((pattern, priority)) =
-                    get_crate_pattern_and_priority(&mut conn, &crate_name).await?
+                    get_crate_pattern_and_priority(&mut transaction, &crate_name).await?
                 {
                     println!("{pattern} : {priority}");
                 } else {
@@ -291,23 +439,44 @@ impl PrioritySubcommand {
             }
 
             Self::Set { pattern, priority } => {
-                set_crate_priority(&mut conn, &pattern, priority)
+                let old_priority = list_crate_priorities(&mut transaction)
+                    .await?
+                    .into_iter()
+                    .find_map(|(existing, priority)| (existing == pattern).then_some(priority));
+                set_crate_priority(&mut transaction, &pattern, priority)
                     .await
                     .context("Could not set pattern's priority")?;
                 println!("Set pattern '{pattern}' to priority {priority}");
+                audit(
+                    &mut transaction,
+                    "queue.default-priority.set",
+                    Some(&pattern),
+                    json!(old_priority),
+                    json!(priority),
+                )
+                .await?;
             }
 
             Self::Remove { pattern } => {
-                if let Some(priority) = remove_crate_priority(&mut conn, &pattern)
+                if let Some(priority) = remove_crate_priority(&mut transaction, &pattern)
                     .await
                     .context("Could not remove pattern's priority")?
                 {
                     println!("Removed pattern '{pattern}' with priority {priority}");
+                    audit(
+                        &mut transaction,
+                        "queue.default-priority.remove",
+                        Some(&pattern),
+                        json!(priority),
+                        Value::Null,
+                    )
+                    .await?;
                 } else {
                     println!("Pattern '{pattern}' did not exist and so was not removed");
                 }
             }
         }
+        transaction.commit().await?;
         Ok(())
     }
 }
@@ -318,6 +487,28 @@ enum BuildSubcommand {
         toolchain_name: String,
     },
 
+    /// Set the nightlies crates can request with `toolchain` in `[package.metadata.docs.rs]`
+    SetToolchainWindow {
+        /// Date of the oldest nightly crates can request
+        #[arg(long)]
+        oldest: NaiveDate,
+        /// Date of the newest nightly crates can request, all newer ones when unset
+        #[arg(long)]
+        newest: Option<NaiveDate>,
+    },
+
+    /// Stop building crates with the toolchains they request
+    RemoveToolchainWindow,
+
+    /// List failed builds grouped by their first compiler error
+    FailureClusters {
+        /// Only include builds finished in the last N days
+        #[arg(long, default_value = "7")]
+        days: u32,
+        #[arg(long, default_value = "50")]
+        limit: i64,
+    },
+
     /// Locks the daemon, preventing it from building new crates
     Lock,
 
@@ -339,6 +530,51 @@ impl BuildSubcommand {
                     .context("failed to set toolchain in database")?;
             }
 
+            Self::SetToolchainWindow { oldest, newest } => {
+                if newest.is_some_and(|newest| newest < oldest) {
+                    bail!("the newest nightly can't be older than the oldest one");
+                }
+                let mut conn = ctx
+                    .pool()?
+                    .get_async()
+                    .await
+                    .context("failed to get a database connection")?;
+                set_config(
+                    &mut conn,
+                    ConfigName::ToolchainWindow,
+                    ToolchainWindow { oldest, newest },
+                )
+                .await
+                .context("failed to set toolchain window in database")?;
+            }
+
+            Self::RemoveToolchainWindow => {
+                let mut conn = ctx
+                    .pool()?
+                    .get_async()
+                    .await
+                    .context("failed to get a database connection")?;
+                remove_config(&mut conn, ConfigName::ToolchainWindow)
+                    .await
+                    .context("failed to remove toolchain window from database")?;
+            }
+
+            Self::FailureClusters { days, limit } => {
+                let mut conn = ctx.pool()?.get_async().await?;
+                let days = days.min(MAX_FAILURE_CLUSTER_DAYS);
+                let since = Utc::now() - chrono::Duration::days(days.into());
+                for cluster in build_failures::clusters(&mut conn, since, limit).await? {
+                    println!(
+                        "{:>6} builds {:>6} crates  {} .. {}  {}",
+                        cluster.builds,
+                        cluster.crates,
+                        cluster.first_seen.format("%F %R"),
+                        cluster.last_seen.format("%F %R"),
+                        cluster.signature,
+                    );
+                }
+            }
+
             Self::Lock => ctx.build_queue()?.lock().await.context("Failed to lock")?,
             Self::Unlock => ctx
                 .build_queue()?
@@ -381,6 +617,9 @@ enum DatabaseSubcommand {
     /// Backfill GitHub/GitLab stats for crates.
     BackfillRepositoryStats,
 
+    /// Backfill the normalized release dependencies used for reverse-dependency lookups.
+    BackfillReleaseDependencies,
+
     /// Updates info for a crate from the registry's API
     UpdateCrateRegistryFields {
         #[arg(name = "CRATE")]
@@ -398,6 +637,18 @@ enum DatabaseSubcommand {
         #[command(subcommand)]
         command: LimitsSubcommand,
     },
+
+    /// Toolchains pinned for crates or single releases
+    ToolchainOverrides {
+        #[command(subcommand)]
+        command: ToolchainOverridesSubcommand,
+    },
+
+    /// Webhook subscription operations
+    Webhooks {
+        #[command(subcommand)]
+        command: WebhooksSubcommand,
+    },
 }
 
 impl DatabaseSubcommand {
@@ -405,9 +656,18 @@ impl DatabaseSubcommand {
         match self {
             Self::Migrate { version } => {
                 let mut conn = ctx.pool()?.get_async().await?;
-                docs_rs_database::migrate(&mut conn, version).await
+                docs_rs_database::migrate(&mut conn, version)
+                    .await
+                    .context("Failed to run database migrations")?;
+                audit(
+                    &mut conn,
+                    "database.migrate",
+                    None,
+                    Value::Null,
+                    json!(version),
+                )
+                .await?;
             }
-            .context("Failed to run database migrations")?,
 
             Self::Abnormality { command } => command.handle_args(ctx).await?,
 
@@ -447,6 +707,16 @@ impl DatabaseSubcommand {
                 let storage = ctx.storage()?;
 
                 cleanup_s3::cleanup_s3_bucket(&mut conn, storage, dry_run).await?;
+                if !dry_run {
+                    audit(
+                        &mut conn,
+                        "database.clean-s3-bucket",
+                        None,
+                        Value::Null,
+                        Value::Null,
+                    )
+                    .await?;
+                }
             }
 
             Self::BackfillRepositoryStats => {
@@ -458,6 +728,13 @@ impl DatabaseSubcommand {
                 workspaces::rewrite_repository_stats(&mut conn).await?;
             }
 
+            Self::BackfillReleaseDependencies => {
+                println!("backfill release dependencies...");
+                let mut conn = ctx.pool()?.get_async().await?;
+                let count = release_dependencies::backfill_release_dependencies(&mut conn).await?;
+                println!("backfilled the dependencies of {count} releases");
+            }
+
             Self::UpdateCrateRegistryFields { name } => {
                 let mut conn = ctx.pool()?.get_async().await?;
                 let registry_data = ctx.registry_api()?.get_crate_data(&name).await?;
@@ -471,11 +748,24 @@ impl DatabaseSubcommand {
                 if let Some(cdn) = ctx.cdn() {
                     cdn.queue_crate_invalidation(&name).await?
                 }
+
+                audit(
+                    &mut conn,
+                    "database.update-crate-registry-fields",
+                    Some(name.as_str()),
+                    Value::Null,
+                    Value::Null,
+                )
+                .await?;
             }
 
             Self::Blacklist { command } => command.handle_args(ctx).await?,
 
             Self::Limits { command } => command.handle_args(ctx).await?,
+
+            Self::ToolchainOverrides { command } => command.handle_args(ctx).await?,
+
+            Self::Webhooks { command } => command.handle_args(ctx).await?,
         }
         Ok(())
     }
@@ -556,20 +846,45 @@ enum LimitsSubcommand {
 
     /// Remove sandbox limits overrides for a crate
     Remove { crate_name: KrateName },
+
+    /// List the sandbox limit increases requested by crate owners
+    ListRequests {
+        /// Also list approved and rejected requests
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Approve a requested limit increase, and apply it to the overrides of the crate
+    ApproveRequest {
+        #[arg(name = "REQUEST_ID")]
+        id: i32,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+
+    /// Reject a requested limit increase
+    RejectRequest {
+        #[arg(name = "REQUEST_ID")]
+        id: i32,
+        #[arg(long)]
+        comment: Option<String>,
+    },
 }
 
 impl LimitsSubcommand {
     async fn handle_args(self, ctx: Context) -> Result<()> {
         let mut conn = ctx.pool()?.get_async().await?;
+        // audit log entries are written in the same transaction as the change they record.
+        let mut transaction = conn.begin().await?;
 
         match self {
             Self::Get { crate_name } => {
-                let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
+                let overrides = Overrides::for_crate(&mut transaction, &crate_name).await?;
                 println!("sandbox limit overrides for {crate_name} = {overrides:?}");
             }
 
             Self::List => {
-                for (crate_name, overrides) in Overrides::all(&mut conn).await? {
+                for (crate_name, overrides) in Overrides::all(&mut transaction).await? {
                     println!("sandbox limit overrides for {crate_name} = {overrides:?}");
                 }
             }
@@ -580,28 +895,199 @@ impl LimitsSubcommand {
                 targets,
                 timeout,
             } => {
-                let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
-                println!("previous sandbox limit overrides for {crate_name} = {overrides:?}");
+                let previous = Overrides::for_crate(&mut transaction, &crate_name).await?;
+                println!("previous sandbox limit overrides for {crate_name} = {previous:?}");
                 let overrides = Overrides {
                     memory,
                     targets,
                     timeout: timeout.map(|timeout| std::time::Duration::from_secs(timeout as _)),
                 };
-                Overrides::save(&mut conn, &crate_name, overrides).await?;
-                let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
+                Overrides::save(&mut transaction, &crate_name, overrides).await?;
+                let overrides = Overrides::for_crate(&mut transaction, &crate_name).await?;
                 println!("new sandbox limit overrides for {crate_name} = {overrides:?}");
+                audit(
+                    &mut transaction,
+                    "database.limits.set",
+                    Some(crate_name.as_str()),
+                    overrides_json(previous.as_ref()),
+                    overrides_json(overrides.as_ref()),
+                )
+                .await?;
             }
 
             Self::Remove { crate_name } => {
-                let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
+                let overrides = Overrides::for_crate(&mut transaction, &crate_name).await?;
                 println!("previous overrides for {crate_name} = {overrides:?}");
-                Overrides::remove(&mut conn, &crate_name).await?;
+                Overrides::remove(&mut transaction, &crate_name).await?;
+                audit(
+                    &mut transaction,
+                    "database.limits.remove",
+                    Some(crate_name.as_str()),
+                    overrides_json(overrides.as_ref()),
+                    Value::Null,
+                )
+                .await?;
+            }
+
+            Self::ListRequests { all } => {
+                let status = (!all).then_some(RequestStatus::Pending);
+                for request in requests::list(&mut transaction, status).await? {
+                    println!(
+                        "#{} {} by {} at {} ({:?}): {:?}\n    reason: {}",
+                        request.id,
+                        request.crate_name,
+                        request.requested_by,
+                        request.created_at,
+                        request.status,
+                        request.overrides,
+                        request.reason,
+                    );
+                    if let Some(reviewed_by) = request.reviewed_by {
+                        println!(
+                            "    reviewed by {reviewed_by}: {}",
+                            request.review_comment.unwrap_or_default()
+                        );
+                    }
+                }
+            }
+
+            Self::ApproveRequest { id, comment } => {
+                let overrides = requests::approve(
+                    &mut transaction,
+                    id,
+                    &audit_log::current_actor(),
+                    comment.as_deref(),
+                )
+                .await?;
+                println!("approved request #{id}, new sandbox limit overrides = {overrides:?}");
+                audit(
+                    &mut transaction,
+                    "database.limits.approve-request",
+                    Some(&id.to_string()),
+                    Value::Null,
+                    overrides_json(Some(&overrides)),
+                )
+                .await?;
+            }
+
+            Self::RejectRequest { id, comment } => {
+                requests::reject(
+                    &mut transaction,
+                    id,
+                    &audit_log::current_actor(),
+                    comment.as_deref(),
+                )
+                .await?;
+                println!("rejected request #{id}");
+                audit(
+                    &mut transaction,
+                    "database.limits.reject-request",
+                    Some(&id.to_string()),
+                    Value::Null,
+                    json!(comment),
+                )
+                .await?;
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum ToolchainOverridesSubcommand {
+    /// List the pinned toolchains
+    List,
+
+    /// Pin a toolchain for a crate, or only for one release with `--version`
+    Set {
+        crate_name: KrateName,
+        /// Toolchain to build with, like `nightly-2024-01-01`
+        toolchain: String,
+        #[arg(long)]
+        version: Option<Version>,
+    },
+
+    /// Remove the toolchain pinned for a crate, or for one release with `--version`
+    Remove {
+        crate_name: KrateName,
+        #[arg(long)]
+        version: Option<Version>,
+    },
+}
+
+impl ToolchainOverridesSubcommand {
+    async fn handle_args(self, ctx: Context) -> Result<()> {
+        let mut conn = ctx.pool()?.get_async().await?;
+        // audit log entries are written in the same transaction as the change they record.
+        let mut transaction = conn.begin().await?;
+
+        match self {
+            Self::List => {
+                for pin in toolchain_overrides::all(&mut transaction).await? {
+                    match pin.version {
+                        Some(version) => {
+                            println!("{} {version}: {}", pin.crate_name, pin.toolchain)
+                        }
+                        None => println!("{}: {}", pin.crate_name, pin.toolchain),
+                    }
+                }
+            }
+
+            Self::Set {
+                crate_name,
+                toolchain,
+                version,
+            } => {
+                let previous = toolchain_overrides::save(
+                    &mut transaction,
+                    &crate_name,
+                    version.as_ref(),
+                    &toolchain,
+                )
+                .await?;
+                println!("previous pinned toolchain for {crate_name} = {previous:?}");
+                audit(
+                    &mut transaction,
+                    "database.toolchain-overrides.set",
+                    Some(&release_target(&crate_name, version.as_ref())),
+                    json!(previous),
+                    json!(toolchain),
+                )
+                .await?;
+            }
+
+            Self::Remove {
+                crate_name,
+                version,
+            } => {
+                let previous =
+                    toolchain_overrides::remove(&mut transaction, &crate_name, version.as_ref())
+                        .await?;
+                println!("previous pinned toolchain for {crate_name} = {previous:?}");
+                audit(
+                    &mut transaction,
+                    "database.toolchain-overrides.remove",
+                    Some(&release_target(&crate_name, version.as_ref())),
+                    json!(previous),
+                    Value::Null,
+                )
+                .await?;
             }
         }
+        transaction.commit().await?;
         Ok(())
     }
 }
 
+/// audit log target for a crate, or one of its releases.
+fn release_target(crate_name: &KrateName, version: Option<&Version>) -> String {
+    match version {
+        Some(version) => format!("{crate_name}@{version}"),
+        None => crate_name.to_string(),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
 enum BlacklistSubcommand {
     /// List all crates on the blacklist
@@ -625,9 +1111,11 @@ enum BlacklistSubcommand {
 impl BlacklistSubcommand {
     async fn handle_args(self, ctx: Context) -> Result<()> {
         let mut conn = ctx.pool()?.get_async().await?;
+        // audit log entries are written in the same transaction as the change they record.
+        let mut transaction = conn.begin().await?;
         match self {
             Self::List => {
-                let crates: Vec<_> = blacklist::list_crates(&mut conn)
+                let crates: Vec<_> = blacklist::list_crates(&mut transaction)
                     .await
                     .context("failed to list crates on blacklist")?
                     .into_iter()
@@ -637,13 +1125,148 @@ impl BlacklistSubcommand {
                 println!("{}", crates.join("\n"));
             }
 
-            Self::Add { crate_name } => blacklist::add_crate(&mut conn, &crate_name)
-                .await
-                .context("failed to add crate to blacklist")?,
+            Self::Add { crate_name } => {
+                let previous = blacklist::is_blacklisted(&mut transaction, &crate_name).await?;
+                blacklist::add_crate(&mut transaction, &crate_name)
+                    .await
+                    .context("failed to add crate to blacklist")?;
+                let current = blacklist::is_blacklisted(&mut transaction, &crate_name).await?;
+                audit(
+                    &mut transaction,
+                    "database.blacklist.add",
+                    Some(crate_name.as_str()),
+                    json!(previous),
+                    json!(current),
+                )
+                .await?;
+            }
 
-            Self::Remove { crate_name } => blacklist::remove_crate(&mut conn, &crate_name)
-                .await
-                .context("failed to remove crate from blacklist")?,
+            Self::Remove { crate_name } => {
+                let previous = blacklist::is_blacklisted(&mut transaction, &crate_name).await?;
+                blacklist::remove_crate(&mut transaction, &crate_name)
+                    .await
+                    .context("failed to remove crate from blacklist")?;
+                let current = blacklist::is_blacklisted(&mut transaction, &crate_name).await?;
+                audit(
+                    &mut transaction,
+                    "database.blacklist.remove",
+                    Some(crate_name.as_str()),
+                    json!(previous),
+                    json!(current),
+                )
+                .await?;
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum WebhooksSubcommand {
+    /// List all webhook subscriptions
+    List,
+
+    /// Subscribe an URL to the build events of a crate, or of all crates
+    Add {
+        /// URL the signed event payloads are posted to
+        #[arg(name = "URL")]
+        url: Url,
+
+        /// Only send events for this crate
+        #[arg(long = "crate")]
+        crate_name: Option<KrateName>,
+    },
+
+    /// Remove a webhook subscription and its delivery log
+    Remove {
+        #[arg(name = "SUBSCRIPTION_ID")]
+        id: i32,
+    },
+
+    /// Show the latest delivery attempts of a webhook subscription
+    Deliveries {
+        #[arg(name = "SUBSCRIPTION_ID")]
+        id: i32,
+
+        #[arg(long, default_value = "20")]
+        limit: i64,
+    },
+}
+
+impl WebhooksSubcommand {
+    async fn handle_args(self, ctx: Context) -> Result<()> {
+        let mut conn = ctx.pool()?.get_async().await?;
+        match self {
+            Self::List => {
+                for subscription in webhooks::list(&mut conn)
+                    .await
+                    .context("failed to list webhook subscriptions")?
+                {
+                    println!(
+                        "{}: {} ({})",
+                        subscription.id,
+                        subscription.url,
+                        subscription
+                            .crate_name
+                            .map(|name| name.to_string())
+                            .unwrap_or_else(|| "all crates".into()),
+                    );
+                }
+            }
+
+            Self::Add { url, crate_name } => {
+                let secret_key = docs_rs_webhooks::Config::from_environment()?
+                    .secret_key
+                    .context("DOCSRS_WEBHOOK_SECRET_KEY is needed to sign the payloads")?;
+                let id = webhooks::add(&mut conn, crate_name.as_ref(), &url)
+                    .await
+                    .context("failed to add webhook subscription")?;
+                println!("added webhook subscription {id}");
+                // the secret is only shown here, it's derived from the secret key.
+                println!(
+                    "payloads are signed with the secret {}",
+                    webhooks::secret(&secret_key, id)
+                );
+                audit(
+                    &mut conn,
+                    "database.webhooks.add",
+                    Some(&id.to_string()),
+                    Value::Null,
+                    json!({ "url": url, "crate": crate_name }),
+                )
+                .await?;
+            }
+
+            Self::Remove { id } => {
+                webhooks::remove(&mut conn, id)
+                    .await
+                    .context("failed to remove webhook subscription")?;
+                audit(
+                    &mut conn,
+                    "database.webhooks.remove",
+                    Some(&id.to_string()),
+                    Value::Null,
+                    Value::Null,
+                )
+                .await?;
+            }
+
+            Self::Deliveries { id, limit } => {
+                for delivery in webhooks::deliveries(&mut conn, id, limit)
+                    .await
+                    .context("failed to list webhook deliveries")?
+                {
+                    println!(
+                        "{} {} build {} attempt {}: {}",
+                        delivery.attempted_at,
+                        delivery.event,
+                        delivery.build_id,
+                        delivery.attempt,
+                        delivery.error.unwrap_or_else(|| "ok".into()),
+                    );
+                }
+            }
         }
         Ok(())
     }
@@ -664,9 +1287,19 @@ impl CdnSubcommand {
         match self {
             Self::Purge { surrogate_key } => {
                 if let Some(cdn) = ctx.cdn() {
-                    cdn.purge_surrogate_keys(iter::once(surrogate_key))
+                    cdn.purge_surrogate_keys(iter::once(surrogate_key.clone()))
                         .await
                         .context("failed to purge CDN by surrogate key")?;
+
+                    let mut conn = ctx.pool()?.get_async().await?;
+                    audit(
+                        &mut conn,
+                        "cdn.purge",
+                        surrogate_key.to_str().ok(),
+                        Value::Null,
+                        Value::Null,
+                    )
+                    .await?;
                 } else {
                     bail!("CDN is not configured, cannot purge");
                 }
@@ -675,3 +1308,309 @@ impl CdnSubcommand {
         Ok(())
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum StorageSubcommand {
+    /// Re-compress the rustdoc and source archives of a crate with the compression
+    /// used for new archives, and print how much storage that saves.
+    RepackArchives {
+        #[arg(name = "CRATE")]
+        crate_name: KrateName,
+
+        /// Only repack the archives of this version
+        #[arg(name = "VERSION")]
+        version: Option<Version>,
+
+        /// Replace the existing archives. Without this we only measure the savings.
+        ///
+        /// Needs `DOCSRS_NEW_ARCHIVE_INDEX_FORMAT`. Web servers switch to the replaced
+        /// archives when they revalidate their locally cached archive indexes.
+        #[arg(long)]
+        replace: bool,
+    },
+
+    /// Check that the rustdoc and source archives of all built releases match their
+    /// indexes, verify the CRC-32 of sampled files, and look for orphaned objects.
+    Verify {
+        /// Only verify the releases of this crate
+        #[arg(name = "CRATE")]
+        crate_name: Option<KrateName>,
+
+        /// How many files to check the CRC-32 of, per archive
+        #[arg(long, default_value = "10")]
+        crc_samples: usize,
+
+        /// Replace broken archive indexes, and queue rebuilds for releases with other
+        /// problems. Orphaned objects are only reported.
+        #[arg(long)]
+        repair: bool,
+
+        /// Ignore the checkpoint of an interrupted run, and verify all releases again
+        #[arg(long)]
+        restart: bool,
+    },
+
+    /// Copy all objects from one storage to another, as they are stored.
+    ///
+    /// The copy stores checkpoints in the database, and running it again resumes after the
+    /// last one. Objects written during the copy might be missed, configure
+    /// `DOCSRS_STORAGE_MIGRATE_FROM` so they are written to both storages.
+    Copy {
+        /// TOML file with the storage to copy from, with the `backend` and its
+        /// `bucket`, `region` and `endpoint`, or `path`. Other settings come from the
+        /// storage configuration.
+        #[arg(long)]
+        from: PathBuf,
+
+        /// TOML file with the storage to copy to, like `--from`
+        #[arg(long)]
+        to: PathBuf,
+
+        /// Only copy objects with paths starting with this prefix
+        #[arg(long, default_value = "")]
+        prefix: String,
+
+        /// How many objects to copy at the same time
+        #[arg(long, default_value = "8")]
+        parallelism: usize,
+
+        /// Compare each copied object with the original
+        #[arg(long)]
+        verify: bool,
+
+        /// Ignore the checkpoint of an earlier copy, and copy all objects again
+        #[arg(long)]
+        restart: bool,
+    },
+}
+
+impl StorageSubcommand {
+    async fn handle_args(self, ctx: Context) -> Result<()> {
+        match self {
+            Self::RepackArchives {
+                crate_name,
+                version,
+                replace,
+            } => {
+                let mut conn = ctx.pool()?.get_async().await?;
+                let stats = repack::repack_archives(
+                    &mut conn,
+                    ctx.storage()?,
+                    &crate_name,
+                    version.as_ref(),
+                    replace,
+                )
+                .await?;
+
+                let saved = stats.old_size as i64 - stats.new_size as i64;
+                println!(
+                    "{} files, {} bytes before, {} bytes after, saved {saved} bytes ({:.1}%)",
+                    stats.file_count,
+                    stats.old_size,
+                    stats.new_size,
+                    if stats.old_size > 0 {
+                        saved as f64 * 100.0 / stats.old_size as f64
+                    } else {
+                        0.0
+                    },
+                );
+
+                if replace {
+                    if let Some(cdn) = ctx.cdn() {
+                        cdn.queue_crate_invalidation(&crate_name).await?;
+                    }
+
+                    audit(
+                        &mut conn,
+                        "storage.repack-archives",
+                        Some(&release_target(&crate_name, version.as_ref())),
+                        Value::Null,
+                        json!({ "old_size": stats.old_size, "new_size": stats.new_size }),
+                    )
+                    .await?;
+                }
+            }
+
+            Self::Verify {
+                crate_name,
+                crc_samples,
+                repair,
+                restart,
+            } => {
+                let mut conn = ctx.pool()?.get_async().await?;
+                let report = verify::verify_storage(
+                    &mut conn,
+                    ctx.storage()?,
+                    ctx.build_queue()?,
+                    crate_name.as_ref(),
+                    crc_samples,
+                    repair,
+                    restart,
+                )
+                .await?;
+
+                if let Some(last_crate_name) = &report.resumed_after {
+                    println!("resumed after {last_crate_name}");
+                }
+                for (archive_path, problem) in &report.problems {
+                    println!("{archive_path}: {problem}");
+                }
+                for (archive_path, err) in &report.failed {
+                    println!("{archive_path}: could not verify: {err}");
+                }
+                for path in &report.orphaned {
+                    println!("{path}: orphaned");
+                }
+                println!(
+                    "{} archives, {} files checked, {} problems, {} failed, {} orphaned objects, \
+                     {} indexes repaired, {} rebuilds queued",
+                    report.archives,
+                    report.checked_files,
+                    report.problems.len(),
+                    report.failed.len(),
+                    report.orphaned.len(),
+                    report.repaired_indexes,
+                    report.queued_rebuilds,
+                );
+
+                if repair {
+                    audit(
+                        &mut conn,
+                        "storage.verify",
+                        crate_name.as_ref().map(|name| name.to_string()).as_deref(),
+                        Value::Null,
+                        json!({
+                            "problems": report.problems.len(),
+                            "repaired_indexes": report.repaired_indexes,
+                            "queued_rebuilds": report.queued_rebuilds,
+                        }),
+                    )
+                    .await?;
+                }
+            }
+            Self::Copy {
+                from,
+                to,
+                prefix,
+                parallelism,
+                verify,
+                restart,
+            } => {
+                let from = copy::StorageConfigFile::load(&from)?;
+                let to = copy::StorageConfigFile::load(&to)?;
+                if from.location() == to.location() {
+                    bail!("can't copy {} to itself", from.location());
+                }
+
+                let config = ctx.config().storage()?;
+                let source =
+                    AsyncStorage::new(Arc::new(from.apply(config)), ctx.meter_provider()).await?;
+                let destination =
+                    AsyncStorage::new(Arc::new(to.apply(config)), ctx.meter_provider()).await?;
+
+                let name = format!("{} -> {}", from.location(), to.location());
+                let mut conn = ctx.pool()?.get_async().await?;
+                if restart {
+                    copy::reset_checkpoint(&mut conn, &name, &prefix).await?;
+                }
+
+                let stats = copy::copy_storage(
+                    &mut conn,
+                    &source,
+                    &destination,
+                    &name,
+                    &prefix,
+                    parallelism.max(1),
+                    verify,
+                )
+                .await?;
+
+                if let Some(last_path) = &stats.resumed_after {
+                    println!("resumed after {last_path}");
+                }
+                println!(
+                    "copied {} objects, {} bytes",
+                    stats.copied_objects, stats.copied_bytes
+                );
+
+                audit(
+                    &mut conn,
+                    "storage.copy",
+                    Some(&name),
+                    Value::Null,
+                    json!({
+                        "prefix": prefix,
+                        "restart": restart,
+                        "copied_objects": stats.copied_objects,
+                        "copied_bytes": stats.copied_bytes,
+                    }),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum AuditSubcommand {
+    /// List the latest administrative changes, newest first
+    List {
+        /// Only changes made by this user
+        #[arg(long)]
+        actor: Option<String>,
+
+        /// Only this action, or all actions starting with it, like `database.limits`
+        #[arg(long)]
+        action: Option<String>,
+
+        /// Only changes to this crate, pattern, config name or id
+        #[arg(long)]
+        target: Option<String>,
+
+        /// Only changes on or after this date
+        #[arg(long)]
+        since: Option<NaiveDate>,
+
+        #[arg(long, default_value = "50")]
+        limit: i64,
+    },
+}
+
+impl AuditSubcommand {
+    async fn handle_args(self, ctx: Context) -> Result<()> {
+        let mut conn = ctx.pool()?.get_async().await?;
+        match self {
+            Self::List {
+                actor,
+                action,
+                target,
+                since,
+                limit,
+            } => {
+                let filter = AuditLogFilter {
+                    actor,
+                    action,
+                    target,
+                    since: since.map(|date| date.and_time(NaiveTime::MIN).and_utc()),
+                    limit,
+                };
+                for entry in audit_log::list(&mut conn, &filter)
+                    .await
+                    .context("failed to list the audit log")?
+                {
+                    println!(
+                        "{} {} {} {}: {} -> {}",
+                        entry.created_at,
+                        entry.actor,
+                        entry.action,
+                        entry.target.unwrap_or_default(),
+                        entry.old_value.unwrap_or_default(),
+                        entry.new_value.unwrap_or_default(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use docs_rs_storage::{
+    AsyncStorage, PathNotFoundError, RepackStatistics, rustdoc_archive_path, source_archive_path,
+};
+use docs_rs_types::{BuildId, KrateName, Version};
+use tracing::{info, instrument};
+
+/// Re-compress the rustdoc & source archives of a crate's releases with the compression
+/// we use for new archives, and sum up the sizes before and after.
+///
+/// Only with `replace` the existing archives are replaced.
+#[instrument(skip(conn, storage))]
+pub(crate) async fn repack_archives(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+    name: &KrateName,
+    version: Option<&Version>,
+    replace: bool,
+) -> Result<RepackStatistics> {
+    let releases = sqlx::query!(
+        r#"SELECT
+             releases.version as "version: Version",
+             releases.rustdoc_status,
+             (
+                 SELECT builds.id
+                 FROM builds
+                 WHERE builds.rid = releases.id AND builds.build_status = 'success'
+                 ORDER BY builds.build_finished DESC
+                 LIMIT 1
+             ) as "latest_build_id: BuildId"
+         FROM crates
+         INNER JOIN releases ON releases.crate_id = crates.id
+         WHERE
+             crates.name = $1 AND
+             ($2::TEXT IS NULL OR releases.version = $2)
+         ORDER BY releases.version"#,
+        name as _,
+        version as _,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut total = RepackStatistics::default();
+    for release in releases {
+        let mut archives = vec![source_archive_path(name, &release.version)];
+        if release.rustdoc_status.unwrap_or_default() {
+            archives.push(rustdoc_archive_path(name, &release.version));
+        }
+
+        for archive_path in archives {
+            let stats = match storage
+                .repack_archive(&archive_path, release.latest_build_id, replace)
+                .await
+            {
+                Ok(stats) => stats,
+                Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {
+                    info!(archive_path, "archive not found, skipping");
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            info!(
+                archive_path,
+                old_size = stats.old_size,
+                new_size = stats.new_size,
+                "repacked archive"
+            );
+            total.file_count += stats.file_count;
+            total.old_size += stats.old_size;
+            total.new_size += stats.new_size;
+        }
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnvironment;
+    use docs_rs_types::testing::{KRATE, V1, V2};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_repack_archives() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        for version in [V1, V2] {
+            env.fake_release()
+                .await
+                .name(&KRATE)
+                .version(version)
+                .rustdoc_file_with("krate/index.html", b"<html>krate</html>")
+                .create()
+                .await?;
+        }
+
+        let storage = env.storage()?;
+        let mut conn = env.async_conn().await?;
+
+        let one = repack_archives(&mut conn, storage, &KRATE, Some(&V1), false).await?;
+        assert!(one.file_count > 0);
+        assert!(one.old_size > 0 && one.new_size > 0);
+
+        let all = repack_archives(&mut conn, storage, &KRATE, None, true).await?;
+        assert_eq!(all.file_count, one.file_count * 2);
+
+        // the replaced archives can still be read
+        let blob = storage
+            .stream_rustdoc_file(&KRATE, &V1, None, "krate/index.html")
+            .await?
+            .materialize(usize::MAX)
+            .await?;
+        assert_eq!(blob.content, b"<html>krate</html>");
+
+        // nothing found
+        let none = repack_archives(&mut conn, storage, &"other".parse()?, None, false).await?;
+        assert_eq!(none, RepackStatistics::default());
+
+        Ok(())
+    }
+}
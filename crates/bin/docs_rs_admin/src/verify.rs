@@ -0,0 +1,415 @@
+use anyhow::Result;
+use docs_rs_build_queue::{AsyncBuildQueue, PRIORITY_CONSISTENCY_CHECK};
+use docs_rs_storage::{ArchiveProblem, AsyncStorage, rustdoc_archive_path, source_archive_path};
+use docs_rs_types::{BuildId, KrateName, ReleaseId, Version};
+use futures_util::TryStreamExt as _;
+use std::collections::HashSet;
+use tracing::{info, instrument, warn};
+
+/// storage prefixes with one sub-folder per crate, and the releases below it.
+static RELEASE_STORAGE_PREFIXES: &[&str] = &["rustdoc", "rustdoc-json", "sources"];
+
+/// how many releases we verify between two checkpoints.
+const CHECKPOINT_INTERVAL: i64 = 100;
+
+#[derive(Debug, Default)]
+pub(crate) struct VerifyReport {
+    /// the crate we resumed after, from the last checkpoint.
+    pub(crate) resumed_after: Option<String>,
+    pub(crate) archives: u64,
+    /// files we checked the CRC-32 of.
+    pub(crate) checked_files: u64,
+    pub(crate) problems: Vec<(String, ArchiveProblem)>,
+    /// archives we couldn't verify, like when fetching them failed, with the error.
+    pub(crate) failed: Vec<(String, String)>,
+    pub(crate) repaired_indexes: u64,
+    pub(crate) queued_rebuilds: u64,
+    /// objects in storage for releases we don't have in the database,
+    /// and content-addressed blobs nothing references.
+    pub(crate) orphaned: Vec<String>,
+}
+
+/// The release a storage path like `rustdoc/{name}/{version}.zip` or
+/// `sources/{name}/{version}/src/lib.rs` belongs to.
+fn release_for_path(path: &str) -> Option<(&str, &str)> {
+    let mut segments = path.split('/').skip(1);
+    let name = segments.next()?;
+    let version = segments.next()?;
+    let version = version
+        .strip_suffix(".zip.index")
+        .or_else(|| version.strip_suffix(".zip"))
+        .unwrap_or(version);
+    Some((name, version))
+}
+
+/// Verify the rustdoc and source archives of all built releases, or only the releases
+/// of one crate, see `AsyncStorage::verify_archive`, and look for orphaned objects.
+///
+/// Releases are verified in pages. After each page we store a checkpoint, and the next
+/// run for the same crate, or all crates, resumes after it. A complete run removes the
+/// checkpoint, `restart` ignores it.
+///
+/// With `repair` we replace broken archive indexes, and queue rebuilds for releases with
+/// problems a new index doesn't fix.
+#[instrument(skip(conn, storage, queue))]
+pub(crate) async fn verify_storage(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+    queue: &AsyncBuildQueue,
+    name: Option<&KrateName>,
+    crc_samples: usize,
+    repair: bool,
+    restart: bool,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let crate_filter = name.map(|name| name.to_string()).unwrap_or_default();
+
+    if restart {
+        sqlx::query!(
+            "DELETE FROM storage_verify_checkpoints WHERE crate_filter = $1",
+            crate_filter,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    let mut checkpoint = sqlx::query!(
+        r#"SELECT last_crate_name, last_release_id as "last_release_id: ReleaseId"
+         FROM storage_verify_checkpoints
+         WHERE crate_filter = $1"#,
+        crate_filter,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .map(|row| (row.last_crate_name, row.last_release_id));
+
+    if let Some((last_crate_name, _)) = &checkpoint {
+        info!(last_crate_name, "resuming verification");
+        report.resumed_after = Some(last_crate_name.clone());
+    }
+
+    loop {
+        // keyset pagination, so we don't keep a cursor open while we talk to the storage.
+        let releases = sqlx::query!(
+            r#"SELECT
+                 crates.name as "name: KrateName",
+                 releases.id as "id: ReleaseId",
+                 releases.version as "version: Version",
+                 releases.rustdoc_status,
+                 (
+                     SELECT builds.id
+                     FROM builds
+                     WHERE builds.rid = releases.id AND builds.build_status = 'success'
+                     ORDER BY builds.build_finished DESC
+                     LIMIT 1
+                 ) as "latest_build_id: BuildId"
+             FROM crates
+             INNER JOIN releases ON releases.crate_id = crates.id
+             WHERE
+                 ($1::TEXT IS NULL OR crates.name = $1) AND
+                 ($2::TEXT IS NULL OR (crates.name, releases.id) > ($2, $3))
+             ORDER BY crates.name, releases.id
+             LIMIT $4"#,
+            name as _,
+            checkpoint.as_ref().map(|(name, _)| name.as_str()),
+            checkpoint.as_ref().map(|(_, id)| id) as _,
+            CHECKPOINT_INTERVAL,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let Some(last) = releases.last() else {
+            break;
+        };
+        checkpoint = Some((last.name.to_string(), last.id));
+
+        for release in releases {
+            verify_release(
+                storage,
+                queue,
+                &mut report,
+                &release.name,
+                &release.version,
+                release.rustdoc_status.unwrap_or_default(),
+                release.latest_build_id,
+                crc_samples,
+                repair,
+            )
+            .await?;
+        }
+
+        let (last_crate_name, last_release_id) = checkpoint.as_ref().expect("set above");
+        sqlx::query!(
+            "INSERT INTO storage_verify_checkpoints
+                 (crate_filter, last_crate_name, last_release_id)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (crate_filter) DO UPDATE
+             SET last_crate_name = EXCLUDED.last_crate_name,
+                 last_release_id = EXCLUDED.last_release_id,
+                 updated_at = NOW()",
+            crate_filter,
+            last_crate_name,
+            last_release_id as _,
+        )
+        .execute(&mut *conn)
+        .await?;
+        info!(last_crate_name, "stored checkpoint");
+    }
+
+    for prefix in RELEASE_STORAGE_PREFIXES {
+        let prefix = match name {
+            Some(name) => format!("{prefix}/{name}/"),
+            None => format!("{prefix}/"),
+        };
+        // the listing is sorted, so we only need the versions of one crate at a time.
+        let mut known_versions: Option<(String, HashSet<String>)> = None;
+        let mut paths = storage.list_prefix(&prefix).await;
+        while let Some(path) = paths.try_next().await? {
+            let is_orphaned = match release_for_path(&path) {
+                None => true,
+                Some((name, version)) => {
+                    if known_versions
+                        .as_ref()
+                        .is_none_or(|(known_name, _)| known_name != name)
+                    {
+                        known_versions =
+                            Some((name.to_owned(), release_versions(&mut *conn, name).await?));
+                    }
+                    let (_, versions) = known_versions.as_ref().expect("set above");
+                    !versions.contains(version)
+                }
+            };
+            if is_orphaned {
+                warn!(path, "orphaned object");
+                report.orphaned.push(path);
+            }
+        }
+    }
+
+    // blobs can be shared between crates, so we only look for them in a full run.
+    if name.is_none() {
+        for blob_path in storage.unreferenced_blobs().await? {
+            warn!(blob_path, "unreferenced blob");
+            report.orphaned.push(blob_path);
+        }
+    }
+
+    sqlx::query!(
+        "DELETE FROM storage_verify_checkpoints WHERE crate_filter = $1",
+        crate_filter,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(report)
+}
+
+/// The versions of a crate we have in the database.
+async fn release_versions(conn: &mut sqlx::PgConnection, name: &str) -> Result<HashSet<String>> {
+    Ok(sqlx::query_scalar!(
+        "SELECT releases.version
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE crates.name = $1",
+        name,
+    )
+    .fetch(conn)
+    .try_collect()
+    .await?)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn verify_release(
+    storage: &AsyncStorage,
+    queue: &AsyncBuildQueue,
+    report: &mut VerifyReport,
+    name: &KrateName,
+    version: &Version,
+    rustdoc_status: bool,
+    latest_build_id: Option<BuildId>,
+    crc_samples: usize,
+    repair: bool,
+) -> Result<()> {
+    // releases without a successful build don't have archives.
+    if latest_build_id.is_none() {
+        return Ok(());
+    }
+
+    let mut archives = vec![source_archive_path(name, version)];
+    if rustdoc_status {
+        archives.push(rustdoc_archive_path(name, version));
+    }
+
+    let mut needs_rebuild = false;
+    for archive_path in archives {
+        let result = match storage
+            .verify_archive(&archive_path, latest_build_id, crc_samples, repair)
+            .await
+        {
+            Ok(result) => result,
+            // probably a temporary problem with the storage, we try again in the next run.
+            Err(err) => {
+                warn!(archive_path, ?err, "could not verify archive");
+                report.failed.push((archive_path, format!("{err:#}")));
+                continue;
+            }
+        };
+
+        report.archives += 1;
+        report.checked_files += result.checked_files;
+        if result.index_repaired {
+            info!(archive_path, "replaced archive index");
+            report.repaired_indexes += 1;
+        }
+        for problem in result.problems {
+            warn!(archive_path, %problem, "archive problem");
+            needs_rebuild |= !(result.index_repaired && problem.fixed_by_new_index());
+            report.problems.push((archive_path.clone(), problem));
+        }
+    }
+
+    if repair && needs_rebuild && !queue.has_build_queued(name, version).await? {
+        info!(%name, %version, "queueing rebuild");
+        queue
+            .add_crate(name, version, PRIORITY_CONSISTENCY_CHECK)
+            .await?;
+        report.queued_rebuilds += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnvironment;
+    use docs_rs_types::testing::{BAR, FOO, V1};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_release_for_path() {
+        for (path, expected) in [
+            ("rustdoc/foo/1.0.0.zip", Some(("foo", "1.0.0"))),
+            ("sources/foo/1.0.0.zip.index", Some(("foo", "1.0.0"))),
+            (
+                "rustdoc-json/foo/1.0.0/x86_64-unknown-linux-gnu/foo.json.zst",
+                Some(("foo", "1.0.0")),
+            ),
+            ("rustdoc/foo", None),
+        ] {
+            assert_eq!(release_for_path(path), expected, "{path}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_verify_storage() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let storage = env.storage()?;
+        let queue = env.build_queue()?;
+        let mut conn = env.async_conn().await?;
+
+        env.fake_release()
+            .await
+            .name(&FOO)
+            .version(V1)
+            .create()
+            .await?;
+        env.fake_release()
+            .await
+            .name(&BAR)
+            .version(V1)
+            .create()
+            .await?;
+
+        let report = verify_storage(&mut conn, storage, queue, None, 5, false, false).await?;
+        assert_eq!(report.archives, 4);
+        assert!(report.checked_files > 0);
+        assert!(report.problems.is_empty());
+        assert!(report.orphaned.is_empty());
+
+        // missing archive index, and an archive without release
+        let foo_index = format!("{}.index", source_archive_path(&FOO, &V1));
+        storage.delete_prefix(&foo_index).await?;
+        let bar_archive = rustdoc_archive_path(&BAR, &V1);
+        storage
+            .delete_prefix(&format!("{bar_archive}.index"))
+            .await?;
+        storage.delete_prefix(&bar_archive).await?;
+        storage
+            .store_one_uncompressed("rustdoc/baz/1.0.0.zip", "orphan")
+            .await?;
+
+        let report = verify_storage(&mut conn, storage, queue, None, 5, true, false).await?;
+        assert_eq!(
+            report.problems,
+            vec![
+                (bar_archive.clone(), ArchiveProblem::MissingArchive),
+                (bar_archive, ArchiveProblem::MissingIndex),
+                (source_archive_path(&FOO, &V1), ArchiveProblem::MissingIndex),
+            ]
+        );
+        assert_eq!(report.repaired_indexes, 1);
+        assert_eq!(report.queued_rebuilds, 1);
+        assert_eq!(report.orphaned, vec!["rustdoc/baz/1.0.0.zip"]);
+
+        assert!(storage.exists(&foo_index).await?);
+        assert!(queue.has_build_queued(&BAR, &V1).await?);
+        assert!(!queue.has_build_queued(&FOO, &V1).await?);
+
+        // only one crate
+        let report = verify_storage(&mut conn, storage, queue, Some(&FOO), 5, false, false).await?;
+        assert_eq!(report.archives, 2);
+        assert!(report.problems.is_empty());
+        assert!(report.orphaned.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resume_verification() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let storage = env.storage()?;
+        let queue = env.build_queue()?;
+        let mut conn = env.async_conn().await?;
+
+        for name in [&BAR, &FOO] {
+            env.fake_release()
+                .await
+                .name(name)
+                .version(V1)
+                .create()
+                .await?;
+        }
+        let bar_release_id = sqlx::query_scalar!(
+            r#"SELECT releases.id as "id: ReleaseId"
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1"#,
+            BAR as _,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        // an interrupted run verified `bar`
+        sqlx::query!(
+            "INSERT INTO storage_verify_checkpoints (crate_filter, last_crate_name, last_release_id)
+             VALUES ('', $1, $2)",
+            BAR as _,
+            bar_release_id as _,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        let report = verify_storage(&mut conn, storage, queue, None, 5, false, false).await?;
+        assert_eq!(report.resumed_after.as_deref(), Some("bar"));
+        assert_eq!(report.archives, 2);
+        assert!(report.failed.is_empty());
+
+        // the complete run removed the checkpoint
+        let report = verify_storage(&mut conn, storage, queue, None, 5, false, false).await?;
+        assert_eq!(report.resumed_after, None);
+        assert_eq!(report.archives, 4);
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::BuilderMetrics;
-use crate::{PackageKind, RustwideBuilder};
+use crate::RustwideBuilder;
 use anyhow::Result;
 use docs_rs_build_queue::{BuildPackageSummary, QueuedCrate};
 use docs_rs_context::Context;
@@ -14,6 +14,7 @@ use tracing::{error, info_span};
 fn process_next_crate(
     context: &Context,
     builder_metrics: &BuilderMetrics,
+    lease_holder: &str,
     f: impl FnOnce(&QueuedCrate) -> Result<BuildPackageSummary>,
 ) -> Result<()> {
     let queue = context.blocking_build_queue()?.clone();
@@ -21,7 +22,7 @@ fn process_next_crate(
     let runtime: Handle = context.runtime().clone().into();
     let queue_config = context.config().build_queue()?;
 
-    let next_attempt = queue.process_next_crate(|to_process| {
+    let next_attempt = queue.process_next_crate_as(lease_holder, |to_process| {
         let res = {
             let instant = Instant::now();
             let res = f(to_process);
@@ -66,41 +67,43 @@ fn process_next_crate(
 pub(crate) fn build_next_queue_package(
     context: &Context,
     builder: &mut RustwideBuilder,
+    lease_holder: &str,
 ) -> Result<bool> {
     let mut processed = false;
     let queue = context.blocking_build_queue()?.clone();
 
-    process_next_crate(context, &builder.builder_metrics.clone(), |krate| {
-        let _span = info_span!(
-            parent: None,
-            BUILD_PACKAGE_TRANSACTION_NAME,
-            crate_name = %krate.name,
-            crate_version = %krate.version,
-            attempt = krate.attempt,
-        )
-        .entered();
-
-        processed = true;
-
-        if let Err(err) = retry(|| builder.reinitialize_workspace_if_interval_passed(), 3) {
-            error!(?err, "Reinitialize workspace failed after retries");
-            queue.lock()?;
-            return Err(err);
-        }
-
-        if let Err(err) = builder.update_toolchain_and_add_essential_files() {
-            error!(?err, "Updating toolchain failed, locking queue");
-            queue.lock()?;
-            return Err(err);
-        }
-
-        builder.build_package(
-            &krate.name,
-            &krate.version,
-            PackageKind::CratesIo,
-            krate.attempt == 0,
-        )
-    })?;
+    process_next_crate(
+        context,
+        &builder.builder_metrics.clone(),
+        lease_holder,
+        |krate| {
+            let _span = info_span!(
+                parent: None,
+                BUILD_PACKAGE_TRANSACTION_NAME,
+                crate_name = %krate.name,
+                crate_version = %krate.version,
+                attempt = krate.attempt,
+            )
+            .entered();
+
+            processed = true;
+
+            if let Err(err) = retry(|| builder.reinitialize_workspace_if_interval_passed(), 3) {
+                error!(?err, "Reinitialize workspace failed after retries");
+                queue.lock()?;
+                return Err(err);
+            }
+
+            if let Err(err) = builder.update_toolchain_and_add_essential_files() {
+                error!(?err, "Updating toolchain failed, locking queue");
+                queue.lock()?;
+                return Err(err);
+            }
+
+            let kind = builder.registry_package_kind();
+            builder.build_package(&krate.name, &krate.version, kind, krate.attempt == 0)
+        },
+    )?;
 
     Ok(processed)
 }
@@ -125,7 +128,7 @@ mod tests {
 
         queue.add_crate(&WILL_FAIL, &V1, 0)?;
 
-        process_next_crate(&env, &builder_metrics, |krate| {
+        process_next_crate(&env, &builder_metrics, "builder", |krate| {
             assert_eq!(WILL_FAIL, krate.name);
             anyhow::bail!("simulate a failure");
         })?;
@@ -147,7 +150,7 @@ mod tests {
         const WILL_SUCCEED: KrateName = KrateName::from_static("will_succeed");
         queue.add_crate(&WILL_SUCCEED, &V1, -1)?;
 
-        process_next_crate(&env, &builder_metrics, |krate| {
+        process_next_crate(&env, &builder_metrics, "builder", |krate| {
             assert_eq!(WILL_SUCCEED, krate.name);
             Ok(BuildPackageSummary::default())
         })?;
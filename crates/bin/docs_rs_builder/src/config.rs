@@ -12,7 +12,7 @@ use std::{
 };
 use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub prefix: PathBuf,
     pub temp_dir: PathBuf,
@@ -35,9 +35,12 @@ pub struct Config {
     pub disable_memory_limit: bool,
     /// Docker runtime the builder should use.
     pub docker_runtime: DockerRuntime,
+    /// How many builds this builder runs concurrently.
+    pub build_workers: usize,
 
     // other module configs
     pub build_limits: Arc<docs_rs_build_limits::Config>,
+    pub webhooks: Arc<docs_rs_webhooks::Config>,
 }
 
 impl AppConfig for Config {
@@ -61,12 +64,17 @@ impl AppConfig for Config {
             )?),
             compiler_metrics_collection_path: maybe_env("DOCSRS_COMPILER_METRICS_PATH")?,
             docker_runtime: maybe_env("DOCSRS_DOCKER_RUNTIME")?.unwrap_or_default(),
+            build_workers: env("DOCSRS_BUILD_WORKERS", 1)?,
             build_limits: Arc::new(docs_rs_build_limits::Config::from_environment()?),
+            webhooks: Arc::new(docs_rs_webhooks::Config::from_environment()?),
         };
 
         if config.build_cpu_limit.is_some() && config.build_cpu_cores.is_some() {
             bail!("you only can define one of build_cpu_limit and build_cpu_cores");
         }
+        if config.build_workers == 0 {
+            bail!("we need at least one build worker");
+        }
 
         Ok(config)
     }
@@ -82,6 +90,20 @@ impl AppConfig for Config {
 }
 
 impl Config {
+    /// The config for one of the build workers.
+    ///
+    /// With more than one worker, each worker gets its own rustwide workspace
+    /// and temporary directory.
+    pub fn for_worker(&self, worker: usize) -> Self {
+        let mut config = self.clone();
+        if self.build_workers > 1 {
+            let name = format!("worker-{worker}");
+            config.rustwide_workspace = self.rustwide_workspace.join(&name);
+            config.temp_dir = self.temp_dir.join(&name);
+        }
+        config
+    }
+
     /// The cargo job-limit we should set in builds.
     ///
     /// If we set either of the two CPU-limits, cargo should
@@ -94,7 +116,7 @@ impl Config {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BuildCores(pub RangeInclusive<usize>);
 
 impl BuildCores {
@@ -253,6 +275,32 @@ mod tests {
         assert_eq!(config.cargo_job_limit(), None);
     }
 
+    #[test]
+    fn worker_config_with_single_worker() {
+        let mut config = config_with_cpu_settings(None, None);
+        config.rustwide_workspace = "/workspace".into();
+        config.temp_dir = "/tmp".into();
+
+        let worker = config.for_worker(0);
+        assert_eq!(worker.rustwide_workspace, PathBuf::from("/workspace"));
+        assert_eq!(worker.temp_dir, PathBuf::from("/tmp"));
+    }
+
+    #[test]
+    fn worker_config_with_multiple_workers() {
+        let mut config = config_with_cpu_settings(None, None);
+        config.rustwide_workspace = "/workspace".into();
+        config.temp_dir = "/tmp".into();
+        config.build_workers = 2;
+
+        let worker = config.for_worker(1);
+        assert_eq!(
+            worker.rustwide_workspace,
+            PathBuf::from("/workspace/worker-1")
+        );
+        assert_eq!(worker.temp_dir, PathBuf::from("/tmp/worker-1"));
+    }
+
     fn config_with_cpu_settings(
         build_cpu_limit: Option<u32>,
         build_cpu_cores: Option<BuildCores>,
@@ -270,7 +318,9 @@ mod tests {
             include_default_targets: true,
             disable_memory_limit: false,
             build_limits: Arc::new(docs_rs_build_limits::Config::default()),
+            webhooks: Arc::new(docs_rs_webhooks::Config::from_environment().unwrap()),
             docker_runtime: DockerRuntime::default(),
+            build_workers: 1,
         }
     }
 }
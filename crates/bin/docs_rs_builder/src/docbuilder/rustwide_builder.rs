@@ -9,20 +9,22 @@ use crate::{
 };
 use anyhow::{Context as _, Error, Result, anyhow, bail};
 use bytes::Bytes;
-use docs_rs_build_limits::{Limits, blacklist::is_blacklisted};
+use chrono::{NaiveDate, TimeDelta, Utc};
+use docs_rs_build_limits::{Limits, blacklist::is_blacklisted, toolchain_overrides};
 use docs_rs_build_queue::BuildPackageSummary;
 use docs_rs_cargo_metadata::{CargoMetadata, MetadataPackage};
 use docs_rs_context::Context;
 use docs_rs_database::{
     Pool,
+    build_events::{BuildEventStatus, BuildPhase, add_build_event, set_build_log_tail},
     releases::{
         add_build_logs, add_doc_coverage, finish_build, finish_release, initialize_build,
-        initialize_crate, initialize_release, update_build_with_error,
-        update_crate_data_in_database,
+        initialize_crate, initialize_release, set_build_pinned_toolchain, set_release_registry,
+        update_build_with_error, update_crate_data_in_database,
     },
-    service_config::{ConfigName, get_config, set_config},
+    service_config::{ConfigName, ToolchainWindow, get_config, set_config},
 };
-use docs_rs_registry_api::RegistryApi;
+use docs_rs_registry_api::{CRATES_IO, RegistryApi};
 use docs_rs_repository_stats::{RepositoryStatsUpdater, workspaces};
 use docs_rs_rustdoc_json::{
     RUSTDOC_JSON_COMPRESSION_ALGORITHMS, RustdocJsonFormatVersion,
@@ -36,10 +38,12 @@ use docs_rs_types::{
     doc_coverage::{self, DocCoverage},
 };
 use docs_rs_utils::{
-    Handle, RUSTDOC_STATIC_STORAGE_PREFIX, retry, rustc_version::parse_rustc_version,
+    Handle, RUSTDOC_STATIC_STORAGE_PREFIX, retry,
+    rustc_version::{parse_rustc_date, parse_rustc_version},
     spawn_blocking,
 };
-use docsrs_metadata::{BuildTargets, DEFAULT_TARGETS, HOST_TARGET, Metadata};
+use docs_rs_webhooks::{WebhookEvent, Webhooks};
+use docsrs_metadata::{BuildTargets, DEFAULT_TARGETS, HOST_TARGET, Metadata, NightlyToolchain};
 use futures_util::future::try_join_all;
 use regex::Regex;
 use rustwide::{
@@ -53,32 +57,116 @@ use std::{
     fmt,
     fs::{self, File},
     io::{BufRead as _, BufReader},
+    ops::{Deref, DerefMut},
     path::{Path, PathBuf},
-    sync::{Arc, LazyLock},
-    time::Instant,
+    sync::{
+        Arc, LazyLock, Mutex,
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
 };
 use tracing::{debug, error, info, info_span, instrument, warn};
 
 const USER_AGENT: &str = "docs.rs builder (https://github.com/rust-lang/docs.rs)";
 const COMPONENTS: &[&str] = &["llvm-tools-preview", "rustc-dev", "rustfmt"];
+/// How many toolchains requested by crates we keep installed next to the global one.
+const MAX_BUILD_TOOLCHAINS: usize = 3;
 static DUMMY_CRATE_NAME: LazyLock<KrateName> = LazyLock::new(|| "empty-library".parse().unwrap());
 const DUMMY_CRATE_VERSION: Version = Version::new(1, 0, 0);
+/// how often we store the tail of the log of a running cargo command.
+const LOG_TAIL_UPDATE_INTERVAL: Duration = Duration::from_secs(2);
 
 async fn get_configured_toolchain(conn: &mut sqlx::PgConnection) -> Result<Toolchain> {
     let name: String = get_config(conn, ConfigName::Toolchain)
         .await?
         .unwrap_or_else(|| "nightly".into());
 
+    Ok(toolchain_from_name(&name))
+}
+
+/// The rustup name of an installed dated nightly, without the host triple rustup adds
+/// to the names of installed toolchains.
+fn installed_dated_nightly(toolchain: &Toolchain) -> Option<String> {
+    let name = toolchain.as_dist()?.name();
+    let (nightly, host) = name.split_at_checked("nightly-YYYY-MM-DD".len())?;
+    if !(host.is_empty() || host.starts_with('-')) {
+        return None;
+    }
+    nightly.parse::<NightlyToolchain>().ok()?;
+    Some(nightly.to_string())
+}
+
+fn toolchain_from_name(name: &str) -> Toolchain {
     // If the toolchain is all hex, assume it references an artifact from
     // CI, for instance an `@bors try` build.
     let re = Regex::new(r"^[a-fA-F0-9]+$").unwrap();
-    if re.is_match(&name) {
-        debug!("using CI build {}", &name);
-        Ok(Toolchain::ci(&name, false))
+    if re.is_match(name) {
+        debug!("using CI build {}", name);
+        Toolchain::ci(name, false)
     } else {
-        debug!("using toolchain {}", &name);
-        Ok(Toolchain::dist(&name))
+        debug!("using toolchain {}", name);
+        Toolchain::dist(name)
+    }
+}
+
+/// Check the toolchain a crate requested with `toolchain` in `[package.metadata.docs.rs]`
+/// against the global toolchain, and the nightlies we currently allow.
+///
+/// The crate needs at least the requested nightly, so we only switch to it when the
+/// global toolchain is older. `global_date` is the commit date from `rustc --version`
+/// of the global toolchain, nightlies are built from the commits of the day before.
+///
+/// Returns `None` when the crate didn't request a toolchain or we build with the
+/// global toolchain, and the reason why we can't build with the requested toolchain
+/// as error, for the build log.
+fn check_requested_toolchain(
+    metadata: &Metadata,
+    global_date: Option<NaiveDate>,
+    window: Option<&ToolchainWindow>,
+    today: NaiveDate,
+) -> Result<Option<NightlyToolchain>, String> {
+    let Some(toolchain) = metadata
+        .toolchain()
+        .map_err(|err| format!("{err} in [package.metadata.docs.rs]"))?
+    else {
+        return Ok(None);
+    };
+
+    let date = NaiveDate::from_ymd_opt(
+        toolchain.year().into(),
+        toolchain.month().into(),
+        toolchain.day().into(),
+    )
+    .expect("nightly toolchains are always valid dates");
+
+    if global_date.is_some_and(|global_date| global_date >= date - TimeDelta::days(1)) {
+        return Ok(None);
+    }
+
+    if date > today {
+        return Err(format!("requested toolchain {toolchain} doesn't exist yet"));
     }
+
+    let Some(window) = window else {
+        return Err(format!(
+            "requested toolchain {toolchain} is not allowed, docs.rs currently doesn't build with requested toolchains"
+        ));
+    };
+
+    if !window.contains(date) {
+        return Err(match window.newest {
+            Some(newest) => format!(
+                "requested toolchain {toolchain} is not allowed, docs.rs currently only builds with nightlies from {} to {newest}",
+                window.oldest
+            ),
+            None => format!(
+                "requested toolchain {toolchain} is not allowed, docs.rs currently only builds with nightlies since {}",
+                window.oldest
+            ),
+        });
+    }
+
+    Ok(Some(toolchain))
 }
 
 #[instrument(skip(config))]
@@ -122,6 +210,67 @@ fn load_metadata_from_rustwide(
 pub enum PackageKind<'a> {
     Local(&'a Path),
     CratesIo,
+    /// the alternative registry configured for this instance.
+    Registry,
+}
+
+/// Unpack a downloaded `.crate` file into `dest`.
+///
+/// Returns the path to the crate sources, `dest/{name}-{version}`.
+fn unpack_crate_file(
+    content: &[u8],
+    name: &KrateName,
+    version: &Version,
+    dest: &Path,
+) -> Result<PathBuf> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(content));
+    archive.unpack(dest)?;
+
+    let root = dest.join(format!("{name}-{version}"));
+    if !root.join("Cargo.toml").is_file() {
+        bail!("crate file doesn't contain {name}-{version}/Cargo.toml");
+    }
+    Ok(root)
+}
+
+/// Restores the global toolchain of a builder when dropped, after a build with another
+/// toolchain, see [`RustwideBuilder::switch_toolchain`].
+///
+/// The builder is reused after a build panicked, so we can't restore it at the end of
+/// the build.
+struct RestoreToolchain<'a> {
+    builder: &'a mut RustwideBuilder,
+    global_toolchain: Toolchain,
+}
+
+impl<'a> RestoreToolchain<'a> {
+    fn new(builder: &'a mut RustwideBuilder) -> Self {
+        let global_toolchain = builder.toolchain.clone();
+        Self {
+            builder,
+            global_toolchain,
+        }
+    }
+}
+
+impl Deref for RestoreToolchain<'_> {
+    type Target = RustwideBuilder;
+
+    fn deref(&self) -> &Self::Target {
+        self.builder
+    }
+}
+
+impl DerefMut for RestoreToolchain<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.builder
+    }
+}
+
+impl Drop for RestoreToolchain<'_> {
+    fn drop(&mut self) {
+        self.builder.toolchain = self.global_toolchain.clone();
+    }
 }
 
 pub struct RustwideBuilder {
@@ -134,7 +283,17 @@ pub struct RustwideBuilder {
     storage: Arc<AsyncStorage>,
     registry_api: Arc<RegistryApi>,
     repository_stats: Arc<RepositoryStatsUpdater>,
+    webhooks: Webhooks,
     workspace_initialize_time: Instant,
+    /// rustc versions we uploaded the essential files for, from this builder.
+    essential_files_uploaded: HashSet<String>,
+    /// `rustc --version` of the toolchains we built with, so we don't run rustc for every build.
+    rustc_versions: Mutex<HashMap<Toolchain, String>>,
+    /// toolchains requested by crates we built with, least recently used first.
+    recent_build_toolchains: Vec<String>,
+    /// what happened to the toolchain the crate requested, added to the build logs
+    /// of the current build.
+    toolchain_message: Option<(log::Level, String)>,
     pub(crate) builder_metrics: Arc<BuilderMetrics>,
 }
 
@@ -156,11 +315,25 @@ impl RustwideBuilder {
             storage: context.storage()?.clone(),
             registry_api: context.registry_api()?.clone(),
             repository_stats: context.repository_stats()?.clone(),
+            webhooks: Webhooks::new(config.webhooks.clone())?,
             workspace_initialize_time: Instant::now(),
+            essential_files_uploaded: HashSet::new(),
+            rustc_versions: Mutex::new(HashMap::new()),
+            recent_build_toolchains: Vec::new(),
+            toolchain_message: None,
             builder_metrics: BuilderMetrics::new(context.meter_provider()).into(),
         })
     }
 
+    /// The package kind for releases from the registry this instance is configured for.
+    pub fn registry_package_kind(&self) -> PackageKind<'static> {
+        if self.registry_api.registry().is_crates_io() {
+            PackageKind::CratesIo
+        } else {
+            PackageKind::Registry
+        }
+    }
+
     #[instrument(skip(self))]
     pub fn reinitialize_workspace_if_interval_passed(&mut self) -> Result<()> {
         let interval = self.config.build_workspace_reinitialization_interval;
@@ -227,6 +400,7 @@ impl RustwideBuilder {
 
     #[instrument(skip_all)]
     fn update_toolchain(&mut self) -> Result<bool> {
+        self.rustc_versions.lock().unwrap().clear();
         self.toolchain = self.runtime.block_on(async {
             let mut conn = self.db.get_async().await?;
             get_configured_toolchain(&mut conn).await
@@ -373,6 +547,10 @@ impl RustwideBuilder {
     }
 
     fn rustc_version(&self) -> Result<String> {
+        if let Some(version) = self.rustc_versions.lock().unwrap().get(&self.toolchain) {
+            return Ok(version.clone());
+        }
+
         let version = self
             .toolchain
             .as_ci()
@@ -383,6 +561,10 @@ impl RustwideBuilder {
                 Ok(format!("rustc 1.9999.0-nightly ({} 2999-12-29)", ci.sha()))
             })
             .unwrap_or_else(|| self.detect_rustc_version())?;
+        self.rustc_versions
+            .lock()
+            .unwrap()
+            .insert(self.toolchain.clone(), version.clone());
         Ok(version)
     }
 
@@ -416,6 +598,19 @@ impl RustwideBuilder {
     }
 
     pub fn add_essential_files(&mut self) -> Result<()> {
+        let rustc_version = self.upload_essential_files()?;
+
+        self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            set_config(&mut conn, ConfigName::RustcVersion, rustc_version).await
+        })
+    }
+
+    /// Build a dummy crate with the current toolchain, and upload the static rustdoc files
+    /// it generated.
+    ///
+    /// Returns the rustc version of the toolchain.
+    fn upload_essential_files(&mut self) -> Result<String> {
         let rustc_version = self.rustc_version()?;
         let parsed_rustc_version = parse_rustc_version(&rustc_version)?;
 
@@ -483,16 +678,12 @@ impl RustwideBuilder {
                     self.runtime
                         .block_on(self.storage.store_all(RUSTDOC_STATIC_STORAGE_PREFIX, &dest))?;
                 }
-
-                self.runtime.block_on(async {
-                    let mut conn = self.db.get_async().await?;
-                    set_config(&mut conn, ConfigName::RustcVersion, rustc_version).await
-                })?;
                 Ok(())
             })?;
 
         krate.purge_from_cache(&self.workspace)?;
-        Ok(())
+        self.essential_files_uploaded.insert(rustc_version.clone());
+        Ok(rustc_version)
     }
 
     pub fn build_local_package(&mut self, path: &Path) -> Result<BuildPackageSummary> {
@@ -520,23 +711,42 @@ impl RustwideBuilder {
         kind: PackageKind<'_>,
         collect_metrics: bool,
     ) -> Result<BuildPackageSummary> {
-        let (crate_id, release_id, build_id) = self.runtime.block_on(async {
+        let (crate_id, release_id, build_id, pinned_toolchain) = self.runtime.block_on(async {
             let mut conn = self.db.get_async().await?;
             let crate_id = initialize_crate(&mut conn, name).await?;
             let release_id = initialize_release(&mut conn, crate_id, version).await?;
+            match kind {
+                PackageKind::Local(_) => {}
+                PackageKind::CratesIo => {
+                    set_release_registry(&mut conn, release_id, CRATES_IO).await?
+                }
+                PackageKind::Registry => {
+                    set_release_registry(&mut conn, release_id, &self.registry_api.registry().name)
+                        .await?
+                }
+            }
             let build_id = initialize_build(&mut conn, release_id).await?;
-            Ok::<_, Error>((crate_id, release_id, build_id))
+            let pinned_toolchain =
+                toolchain_overrides::for_release(&mut conn, name, version).await?;
+            if let Some(pinned_toolchain) = &pinned_toolchain {
+                set_build_pinned_toolchain(&mut conn, build_id, pinned_toolchain).await?;
+            }
+            Ok::<_, Error>((crate_id, release_id, build_id, pinned_toolchain))
         })?;
 
-        match self.build_package_inner(
+        self.notify_webhooks(WebhookEvent::BuildStarted, build_id);
+
+        let result = RestoreToolchain::new(self).build_package_inner(
             name,
             version,
             kind,
             crate_id,
             release_id,
             build_id,
+            pinned_toolchain.as_deref(),
             collect_metrics,
-        ) {
+        );
+        let result = match result {
             Ok(successful) => Ok(BuildPackageSummary {
                 successful,
                 should_reattempt: false,
@@ -556,6 +766,175 @@ impl RustwideBuilder {
                     should_reattempt: true,
                 })
             }),
+        };
+
+        self.notify_webhooks(WebhookEvent::BuildFinished, build_id);
+
+        result
+    }
+
+    /// Pick the toolchain for a build, `None` builds with the global toolchain.
+    ///
+    /// A toolchain pinned by the admins wins over the toolchain the crate requested,
+    /// see [`check_requested_toolchain`] for the requested one.
+    /// Sets the message for the build log about the requested toolchain.
+    fn select_toolchain(
+        &mut self,
+        pinned_toolchain: Option<&str>,
+        metadata: Option<&Metadata>,
+    ) -> Result<Option<String>> {
+        self.toolchain_message = None;
+
+        let Some(metadata) =
+            metadata.filter(|metadata| !metadata.toolchain().is_ok_and(|t| t.is_none()))
+        else {
+            return Ok(pinned_toolchain.map(ToOwned::to_owned));
+        };
+
+        if let Some(pinned_toolchain) = pinned_toolchain {
+            self.toolchain_message = Some((
+                log::Level::Warn,
+                format!(
+                    "ignoring the toolchain requested in [package.metadata.docs.rs], docs.rs pinned {pinned_toolchain} for this crate"
+                ),
+            ));
+            return Ok(Some(pinned_toolchain.to_owned()));
+        }
+
+        let window: Option<ToolchainWindow> = self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            get_config(&mut conn, ConfigName::ToolchainWindow).await
+        })?;
+
+        let global_date = parse_rustc_date(self.rustc_version()?).ok();
+
+        Ok(
+            match check_requested_toolchain(
+                metadata,
+                global_date,
+                window.as_ref(),
+                Utc::now().date_naive(),
+            ) {
+                Ok(Some(toolchain)) => {
+                    self.toolchain_message = Some((
+                        log::Level::Info,
+                        format!(
+                            "building with {toolchain}, requested in [package.metadata.docs.rs]"
+                        ),
+                    ));
+                    Some(toolchain.to_string())
+                }
+                Ok(None) => {
+                    self.toolchain_message = Some((
+                        log::Level::Info,
+                        "the default toolchain is not older than the toolchain requested in [package.metadata.docs.rs], building with it".into(),
+                    ));
+                    None
+                }
+                Err(reason) => {
+                    self.toolchain_message = Some((
+                        log::Level::Warn,
+                        format!("{reason}, building with the default toolchain"),
+                    ));
+                    None
+                }
+            },
+        )
+    }
+
+    /// Replace the global toolchain with the given one for a build.
+    ///
+    /// [`RestoreToolchain`] restores the global toolchain after the build.
+    fn switch_toolchain(&mut self, toolchain: &str) -> Result<()> {
+        info!(toolchain, "building with a different toolchain");
+        self.uninstall_old_build_toolchains(toolchain);
+        self.toolchain = toolchain_from_name(toolchain);
+        self.install_build_toolchain()
+            .with_context(|| format!("failed to install toolchain {toolchain}"))
+    }
+
+    /// Install the current toolchain like `update_toolchain` does for the global one, and
+    /// make sure we have the essential files for it.
+    fn install_build_toolchain(&mut self) -> Result<()> {
+        self.toolchain.install(&self.workspace)?;
+        self.rustc_versions.lock().unwrap().remove(&self.toolchain);
+
+        if self.toolchain.as_ci().is_none() {
+            for target in DEFAULT_TARGETS {
+                self.toolchain.add_target(&self.workspace, target)?;
+            }
+            for component in COMPONENTS {
+                if let Err(err) = self.toolchain.add_component(&self.workspace, component) {
+                    warn!("failed to install {component} for build toolchain: {err}");
+                }
+            }
+        }
+
+        let rustc_version = self.rustc_version()?;
+        if !self.essential_files_uploaded.contains(&rustc_version) {
+            self.upload_essential_files()?;
+        }
+
+        Ok(())
+    }
+
+    /// Keep at most [`MAX_BUILD_TOOLCHAINS`] toolchains requested by crates installed,
+    /// including `next`, by uninstalling the least recently used dated nightlies.
+    ///
+    /// Must be called while the global toolchain is active, so it's never uninstalled.
+    fn uninstall_old_build_toolchains(&mut self, next: &str) {
+        self.recent_build_toolchains.retain(|name| name != next);
+        self.recent_build_toolchains.push(next.to_string());
+
+        let installed = match self.workspace.installed_toolchains() {
+            Ok(installed) => installed,
+            Err(err) => {
+                warn!(?err, "failed to list installed toolchains");
+                return;
+            }
+        };
+
+        let global = installed_dated_nightly(&self.toolchain);
+        let mut old: Vec<String> = installed
+            .iter()
+            .filter_map(installed_dated_nightly)
+            .filter(|name| Some(name) != global.as_ref() && name != next)
+            .collect();
+        // toolchains we didn't use since the builder started are the oldest.
+        old.sort_by_key(|name| {
+            self.recent_build_toolchains
+                .iter()
+                .position(|recent| recent == name)
+        });
+
+        let excess = old.len().saturating_sub(MAX_BUILD_TOOLCHAINS - 1);
+        for name in old.into_iter().take(excess) {
+            info!(toolchain = name, "uninstalling old build toolchain");
+            if let Err(err) = Toolchain::dist(&name).uninstall(&self.workspace) {
+                warn!(
+                    ?err,
+                    toolchain = name,
+                    "failed to uninstall build toolchain"
+                );
+            }
+            self.recent_build_toolchains
+                .retain(|recent| *recent != name);
+            self.rustc_versions
+                .lock()
+                .unwrap()
+                .remove(&Toolchain::dist(&name));
+        }
+    }
+
+    /// Send a build event to the webhook subscribers of the crate.
+    ///
+    /// Webhook errors are only logged, they shouldn't fail the build.
+    fn notify_webhooks(&self, event: WebhookEvent, build_id: BuildId) {
+        if let Err(err) = self
+            .runtime
+            .block_on(self.webhooks.notify(&self.db, event, build_id))
+        {
+            error!(?err, ?event, %build_id, "error sending webhooks");
         }
     }
 
@@ -569,16 +948,19 @@ impl RustwideBuilder {
         crate_id: CrateId,
         release_id: ReleaseId,
         build_id: BuildId,
+        pinned_toolchain: Option<&str>,
         collect_metrics: bool,
     ) -> Result<bool> {
         info!("building package {} {}", name, version);
 
-        let is_blacklisted = self.runtime.block_on(async {
-            let mut conn = self.db.get_async().await?;
+        let is_blacklisted = self.build_phase(build_id, BuildPhase::BlacklistCheck, || {
+            self.runtime.block_on(async {
+                let mut conn = self.db.get_async().await?;
 
-            let is_blacklisted = is_blacklisted(&mut conn, name).await?;
+                let is_blacklisted = is_blacklisted(&mut conn, name).await?;
 
-            Ok::<_, Error>(is_blacklisted)
+                Ok::<_, Error>(is_blacklisted)
+            })
         })?;
 
         if is_blacklisted {
@@ -623,24 +1005,36 @@ impl RustwideBuilder {
 
         let mut build_dir = self.workspace.build_dir(&format!("{name}-{version}"));
 
+        fs::create_dir_all(&self.config.temp_dir)?;
+
         let is_local = matches!(kind, PackageKind::Local(_));
-        let krate = {
+        // rustwide can only fetch from crates.io, so for other registries we download
+        // and unpack the crate file ourselves, and build it like a local crate.
+        // The directory has to live until the build is finished.
+        let mut registry_source = None;
+        let krate = self.build_phase(build_id, BuildPhase::Fetch, || {
             let _span = info_span!("krate.fetch").entered();
 
-            let version = version.to_string();
             let krate = match kind {
                 PackageKind::Local(path) => Crate::local(path),
-                PackageKind::CratesIo => Crate::crates_io(name.as_str(), &version),
+                PackageKind::CratesIo => Crate::crates_io(name.as_str(), &version.to_string()),
+                PackageKind::Registry => {
+                    let content = self
+                        .runtime
+                        .block_on(self.registry_api.download_crate(name, version))?;
+                    let dir = registry_source.insert(tempfile::tempdir_in(&self.config.temp_dir)?);
+                    Crate::local(&unpack_crate_file(&content, name, version, dir.path())?)
+                }
             };
             krate.fetch(&self.workspace)?;
-            krate
-        };
+            Ok(krate)
+        })?;
 
-        fs::create_dir_all(&self.config.temp_dir)?;
         let local_storage = tempfile::tempdir_in(&self.config.temp_dir)?;
 
         let mut algs = HashSet::new();
-        let source_stats = {
+        let mut source_metadata = None;
+        let source_stats = self.build_phase(build_id, BuildPhase::SourceUpload, || {
             let _span = info_span!("adding sources into database").entered();
             debug!("adding sources into database");
             let temp_dir = tempfile::tempdir_in(&self.config.temp_dir)?;
@@ -652,13 +1046,23 @@ impl RustwideBuilder {
                     .store_all_in_archive(&source_archive_path(name, version), &temp_dir),
             )?;
 
+            // we need the requested toolchain before the build, invalid manifests
+            // are reported by the build itself.
+            source_metadata = Metadata::from_crate_root(temp_dir.path()).ok();
+
             fs::remove_dir_all(temp_dir.path())?;
 
             algs.insert(stats.alg);
-            stats
-        };
+            Ok(stats)
+        })?;
 
-        let successful = build_dir
+        if let Some(toolchain) =
+            self.select_toolchain(pinned_toolchain, source_metadata.as_ref())?
+        {
+            self.switch_toolchain(&toolchain)?;
+        }
+
+        let result = build_dir
             .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
             .run(|build| {
                 // NOTE: rustwide will run `copy_source_to` again when preparing the call to this
@@ -682,30 +1086,35 @@ impl RustwideBuilder {
                 let mut successful_targets = Vec::new();
 
                 // Perform an initial build
-                let mut res =
-                    self.execute_build(build_id, name, version, default_target, true, build, &limits, &metadata, false, collect_metrics)?;
+                let mut res = self.target_build_phase(build_id, BuildPhase::DefaultTargetBuild, default_target, || {
+                    self.execute_build(build_id, name, version, default_target, true, build, &limits, &metadata, false, collect_metrics)
+                })?;
 
                 // If the build fails with the lockfile given, try using only the dependencies listed in Cargo.toml.
                 let cargo_lock = build.host_source_dir().join("Cargo.lock");
                 if !res.successful() && cargo_lock.exists() {
                     info!("removing lockfile and reattempting build");
-                    std::fs::remove_file(cargo_lock)?;
-                    {
-                        let _span = info_span!("cargo_generate_lockfile").entered();
-                        Command::new(&self.workspace, self.toolchain.cargo())
-                            .current_directory(build.host_source_dir())
-                            .arg("generate-lockfile")
-                            .run_capture()?;
-                    }
-                    {
-                        let _span = info_span!("cargo fetch --locked").entered();
-                        Command::new(&self.workspace, self.toolchain.cargo())
-                            .current_directory(build.host_source_dir())
-                            .args(["fetch", "--locked"])
-                            .run_capture()?;
-                    }
-                    res =
-                        self.execute_build(build_id, name, version, default_target, true, build, &limits, &metadata, false, collect_metrics)?;
+                    self.build_phase(build_id, BuildPhase::LockfileRegeneration, || {
+                        std::fs::remove_file(cargo_lock)?;
+                        {
+                            let _span = info_span!("cargo_generate_lockfile").entered();
+                            Command::new(&self.workspace, self.toolchain.cargo())
+                                .current_directory(build.host_source_dir())
+                                .arg("generate-lockfile")
+                                .run_capture()?;
+                        }
+                        {
+                            let _span = info_span!("cargo fetch --locked").entered();
+                            Command::new(&self.workspace, self.toolchain.cargo())
+                                .current_directory(build.host_source_dir())
+                                .args(["fetch", "--locked"])
+                                .run_capture()?;
+                        }
+                        Ok(())
+                    })?;
+                    res = self.target_build_phase(build_id, BuildPhase::DefaultTargetBuild, default_target, || {
+                        self.execute_build(build_id, name, version, default_target, true, build, &limits, &metadata, false, collect_metrics)
+                    })?;
                 }
 
                 let has_docs = res.has_docs();
@@ -739,12 +1148,13 @@ impl RustwideBuilder {
                         target_build_logs.insert(target, (target_res.build_log, successful));
                     }
 
-                    let doc_stats  =
+                    let doc_stats = self.build_phase(build_id, BuildPhase::DocUpload, || {
                         self.runtime.block_on(
                         self.storage.store_all_in_archive(
                             &rustdoc_archive_path(name, version),
                             local_storage.path(),
-                        ))?;
+                        ))
+                    })?;
                     self.builder_metrics.documentation_size.record(doc_stats.original_size, &[]);
                     algs.insert(doc_stats.alg);
                     Some(doc_stats.original_size)
@@ -773,12 +1183,11 @@ impl RustwideBuilder {
 
                 let successful = res.successful();
 
-                {
+                self.build_phase(build_id, BuildPhase::LogUpload, || {
                     let mut build_logs = Vec::new();
 
                     let _span = info_span!("store_build_logs").entered();
                     let build_log_path = format!("build-logs/{build_id}/{default_target}.txt");
-                    let successful = res.successful();
                     self.blocking_storage.store_one(build_log_path, res.build_log)?;
                     build_logs.push((format!("{default_target}.txt"), successful));
                     for (target, (log, successful)) in target_build_logs {
@@ -786,8 +1195,8 @@ impl RustwideBuilder {
                         self.blocking_storage.store_one(build_log_path, log)?;
                         build_logs.push((format!("{target}.txt"), successful));
                     }
-                    self.runtime.block_on(add_build_logs(&mut async_conn, build_id, build_logs))?;
-                }
+                    self.runtime.block_on(add_build_logs(&mut async_conn, build_id, build_logs))
+                })?;
 
                 if successful {
                     self.builder_metrics.successful_builds.add(1, &[]);
@@ -797,86 +1206,94 @@ impl RustwideBuilder {
                     self.builder_metrics.non_library_builds.add(1, &[]);
                 }
 
-                let release_data = if !is_local {
-                    match self
-                        .runtime
-                        .block_on(self.registry_api.get_release_data(name, version))
-                        {
-                        Ok(data) => data,
-                        Err(err) => {
-                            error!(%name, %version, ?err, "could not fetch releases-data");
-                            None
+                let release_updated = self.build_phase(build_id, BuildPhase::FinishRelease, || {
+                    let release_data = if !is_local {
+                        match self
+                            .runtime
+                            .block_on(self.registry_api.get_release_data(name, version))
+                            {
+                            Ok(data) => data,
+                            Err(err) => {
+                                error!(%name, %version, ?err, "could not fetch releases-data");
+                                None
+                            }
                         }
+                    } else {
+                        None
+                    }
+                    .unwrap_or_default();
+
+                    let cargo_metadata = res.cargo_metadata.root();
+                    let repository = self.get_repo(cargo_metadata)?;
+
+                    // when we have an unsuccessful build, but the release was already successfullly
+                    // built in the past, don't touch the release record so the docs stay intact.
+                    // This mainly happens with manually triggered or automated rebuilds.
+                    // The `release_build_status` table is already updated with the information from
+                    // the current build via `finish_build`.
+                    let current_release_build_status = self.runtime.block_on(sqlx::query_scalar!(
+                        r#"
+                        SELECT build_status AS "build_status: BuildStatus"
+                        FROM release_build_status
+                        WHERE rid = $1
+                        "#,
+                        release_id.0,
+                    ).fetch_optional(&mut *async_conn))?;
+
+                    if !successful && current_release_build_status == Some(BuildStatus::Success) {
+                        info!("build was unsuccessful, but the release was already successfully built in the past. Skipping release record update.");
+                        return Ok(false);
                     }
-                } else {
-                    None
-                }
-                .unwrap_or_default();
-
-                let cargo_metadata = res.cargo_metadata.root();
-                let repository = self.get_repo(cargo_metadata)?;
-
-                // when we have an unsuccessful build, but the release was already successfullly
-                // built in the past, don't touch the release record so the docs stay intact.
-                // This mainly happens with manually triggered or automated rebuilds.
-                // The `release_build_status` table is already updated with the information from
-                // the current build via `finish_build`.
-                let current_release_build_status = self.runtime.block_on(sqlx::query_scalar!(
-                    r#"
-                    SELECT build_status AS "build_status: BuildStatus"
-                    FROM release_build_status
-                    WHERE rid = $1
-                    "#,
-                    release_id.0,
-                ).fetch_optional(&mut *async_conn))?;
-
-                if !successful && current_release_build_status == Some(BuildStatus::Success) {
-                    info!("build was unsuccessful, but the release was already successfully built in the past. Skipping release record update.");
-                    return Ok(false);
-                }
-
-                let has_examples = build.host_source_dir().join("examples").is_dir();
-                self.runtime.block_on(finish_release(
-                    &mut async_conn,
-                    crate_id,
-                    release_id,
-                    cargo_metadata,
-                    &build.host_source_dir(),
-                    &res.target,
-                    successful_targets,
-                    &release_data,
-                    has_docs,
-                    has_examples,
-                    algs,
-                    repository,
-                    source_stats.original_size,
-                ))?;
-
-                if let Some(repository_id) = repository {
-                    self.runtime.block_on(workspaces::update_repository_stats(&mut async_conn, repository_id))?;
-                }
 
-                if let Some(doc_coverage) = res.doc_coverage {
-                    self.runtime.block_on(add_doc_coverage(
+                    let has_examples = build.host_source_dir().join("examples").is_dir();
+                    self.runtime.block_on(finish_release(
                         &mut async_conn,
+                        crate_id,
                         release_id,
-                        doc_coverage,
+                        cargo_metadata,
+                        &build.host_source_dir(),
+                        &res.target,
+                        successful_targets,
+                        &release_data,
+                        has_docs,
+                        has_examples,
+                        algs,
+                        repository,
+                        source_stats.original_size,
                     ))?;
-                }
 
-                // Some crates.io crate data is mutable, so we proactively update it during a release
-                if !is_local {
-                    match self
-                        .runtime
-                        .block_on(self.registry_api.get_crate_data(name))
-                    {
-                        Ok(crate_data) => self.runtime.block_on(update_crate_data_in_database(
+                    if let Some(repository_id) = repository {
+                        self.runtime.block_on(workspaces::update_repository_stats(&mut async_conn, repository_id))?;
+                    }
+
+                    if let Some(doc_coverage) = res.doc_coverage {
+                        self.runtime.block_on(add_doc_coverage(
                             &mut async_conn,
-                            name,
-                            &crate_data,
-                        ))?,
-                        Err(err) => warn!("{:#?}", err),
+                            release_id,
+                            doc_coverage,
+                        ))?;
                     }
+
+                    // Some crates.io crate data is mutable, so we proactively update it during a release
+                    if !is_local {
+                        match self
+                            .runtime
+                            .block_on(self.registry_api.get_crate_data(name))
+                        {
+                            Ok(crate_data) => self.runtime.block_on(update_crate_data_in_database(
+                                &mut async_conn,
+                                name,
+                                &crate_data,
+                            ))?,
+                            Err(err) => warn!("{:#?}", err),
+                        }
+                    }
+
+                    Ok(true)
+                })?;
+
+                if !release_updated {
+                    return Ok(false);
                 }
 
                 if successful {
@@ -897,7 +1314,10 @@ impl RustwideBuilder {
                 });
 
                 Ok(successful)
-            })?;
+            });
+
+        self.toolchain_message = None;
+        let successful = result?;
 
         {
             let _span = info_span!("purge_from_cache").entered();
@@ -922,18 +1342,21 @@ impl RustwideBuilder {
         metadata: &Metadata,
         collect_metrics: bool,
     ) -> Result<FullBuildResult> {
-        let target_res = self.execute_build(
-            build_id,
-            name,
-            version,
-            target,
-            false,
-            build,
-            limits,
-            metadata,
-            false,
-            collect_metrics,
-        )?;
+        let target_res =
+            self.target_build_phase(build_id, BuildPhase::TargetBuild, target, || {
+                self.execute_build(
+                    build_id,
+                    name,
+                    version,
+                    target,
+                    false,
+                    build,
+                    limits,
+                    metadata,
+                    false,
+                    collect_metrics,
+                )
+            })?;
         if target_res.successful() {
             // Cargo is not giving any error and not generating documentation of some crates
             // when we use a target compile options. Check documentation exists before
@@ -1127,6 +1550,112 @@ impl RustwideBuilder {
         )
     }
 
+    /// Record an event in the timeline of a build.
+    ///
+    /// Errors are only logged, the timeline is informational and shouldn't fail the build.
+    fn record_build_event(
+        &self,
+        build_id: BuildId,
+        phase: BuildPhase,
+        status: BuildEventStatus,
+        message: Option<&str>,
+    ) {
+        if let Err(err) = self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            add_build_event(&mut conn, build_id, phase, status, message).await
+        }) {
+            warn!(?err, ?phase, ?status, "could not record build event");
+        }
+    }
+
+    /// Run one phase of a build, and record its start and end in the timeline of the build.
+    fn build_phase<T>(
+        &self,
+        build_id: BuildId,
+        phase: BuildPhase,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        self.record_build_event(build_id, phase, BuildEventStatus::Started, None);
+        let result = f();
+        match &result {
+            Ok(_) => self.record_build_event(build_id, phase, BuildEventStatus::Finished, None),
+            Err(err) => self.record_build_event(
+                build_id,
+                phase,
+                BuildEventStatus::Failed,
+                Some(&format!("{err:#}")),
+            ),
+        }
+        result
+    }
+
+    /// Like [`Self::build_phase`], for the documentation build of a target.
+    ///
+    /// The phase failed when the build wasn't successful.
+    fn target_build_phase(
+        &self,
+        build_id: BuildId,
+        phase: BuildPhase,
+        target: &str,
+        f: impl FnOnce() -> Result<FullBuildResult>,
+    ) -> Result<FullBuildResult> {
+        self.record_build_event(build_id, phase, BuildEventStatus::Started, Some(target));
+        let result = f();
+        let status = match &result {
+            Ok(res) if res.successful() => BuildEventStatus::Finished,
+            _ => BuildEventStatus::Failed,
+        };
+        self.record_build_event(build_id, phase, status, Some(target));
+        result
+    }
+
+    /// Run `f` while capturing its log output into `storage`.
+    ///
+    /// While `f` is running, the end of the captured log is regularly stored with the build,
+    /// so the progress of the build can be followed.
+    fn capture_with_log_tail<T>(
+        &self,
+        build_id: BuildId,
+        storage: &LogStorage,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+
+        let updater = std::thread::spawn({
+            let storage = storage.clone();
+            let runtime = self.runtime.clone();
+            let db = self.db.clone();
+            move || {
+                let mut last_len = 0;
+                while let Err(RecvTimeoutError::Timeout) =
+                    done_rx.recv_timeout(LOG_TAIL_UPDATE_INTERVAL)
+                {
+                    let log = storage.to_string();
+                    if log.len() == last_len {
+                        continue;
+                    }
+                    last_len = log.len();
+
+                    if let Err(err) = runtime.block_on(async {
+                        let mut conn = db.get_async().await?;
+                        set_build_log_tail(&mut conn, build_id, &log).await
+                    }) {
+                        warn!(?err, "could not store the build log tail");
+                    }
+                }
+            }
+        });
+
+        let result = logging::capture(storage, f);
+
+        drop(done_tx);
+        if updater.join().is_err() {
+            warn!("build log tail updater panicked");
+        }
+
+        result
+    }
+
     #[instrument(skip(self, build))]
     #[allow(clippy::too_many_arguments)]
     fn execute_build(
@@ -1200,7 +1729,7 @@ impl RustwideBuilder {
 
         let result = {
             let _span = info_span!("cargo_build", target = %target, is_default_target).entered();
-            logging::capture(&storage, || {
+            self.capture_with_log_tail(build_id, &storage, || {
                 self.prepare_command(
                     build,
                     target,
@@ -1251,7 +1780,10 @@ impl RustwideBuilder {
             },
             doc_coverage,
             cargo_metadata,
-            build_log: storage.to_string(),
+            build_log: match &self.toolchain_message {
+                Some((level, message)) => format!("[{level}] {message}\n{storage}"),
+                None => storage.to_string(),
+            },
             target: target.to_string(),
             is_default_target,
             doc_output_dir,
@@ -1434,6 +1966,119 @@ mod tests {
     use std::{collections::BTreeMap, io, iter, path::PathBuf};
     use test_case::test_case;
 
+    #[test]
+    fn requested_toolchain() {
+        let metadata = |toolchain: &str| -> Metadata {
+            format!("[package.metadata.docs.rs]\ntoolchain = \"{toolchain}\"")
+                .parse()
+                .unwrap()
+        };
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let today = date("2024-06-15");
+        let window = ToolchainWindow {
+            oldest: date("2024-03-01"),
+            newest: Some(date("2024-06-01")),
+        };
+
+        let global = Some(date("2024-03-15"));
+
+        assert_eq!(
+            check_requested_toolchain(&Metadata::default(), global, Some(&window), today),
+            Ok(None)
+        );
+        assert_eq!(
+            check_requested_toolchain(
+                &metadata("nightly-2024-04-01"),
+                global,
+                Some(&window),
+                today
+            )
+            .unwrap()
+            .map(|toolchain| toolchain.to_string())
+            .as_deref(),
+            Some("nightly-2024-04-01")
+        );
+
+        // the global toolchain is new enough, also outside of the window. The nightly
+        // of a day contains the commits of the day before.
+        for toolchain in [
+            "nightly-2024-03-16",
+            "nightly-2024-03-01",
+            "nightly-2024-02-01",
+        ] {
+            assert_eq!(
+                check_requested_toolchain(&metadata(toolchain), global, Some(&window), today),
+                Ok(None),
+                "{toolchain}"
+            );
+        }
+
+        for (toolchain, window, expected) in [
+            ("stable", Some(&window), "invalid toolchain \"stable\""),
+            ("nightly-2023-06-01", Some(&window), "older than the oldest"),
+            (
+                "nightly-2024-04-01",
+                None,
+                "doesn't build with requested toolchains",
+            ),
+            (
+                "nightly-2024-02-01",
+                Some(&window),
+                "from 2024-03-01 to 2024-06-01",
+            ),
+            (
+                "nightly-2024-06-02",
+                Some(&window),
+                "from 2024-03-01 to 2024-06-01",
+            ),
+            ("nightly-2024-07-01", Some(&window), "doesn't exist yet"),
+        ] {
+            let err =
+                check_requested_toolchain(&metadata(toolchain), None, window, today).unwrap_err();
+            assert!(err.contains(expected), "{toolchain}: {err}");
+        }
+
+        let open_window = ToolchainWindow {
+            oldest: date("2024-03-01"),
+            newest: None,
+        };
+        assert!(
+            check_requested_toolchain(
+                &metadata("nightly-2024-06-15"),
+                global,
+                Some(&open_window),
+                today
+            )
+            .unwrap()
+            .is_some()
+        );
+    }
+
+    #[test]
+    fn test_installed_dated_nightly() {
+        for (installed, expected) in [
+            (
+                "nightly-2024-06-15-x86_64-unknown-linux-gnu",
+                Some("nightly-2024-06-15"),
+            ),
+            ("nightly-2024-06-15", Some("nightly-2024-06-15")),
+            ("nightly-x86_64-unknown-linux-gnu", None),
+            ("stable-x86_64-unknown-linux-gnu", None),
+            ("1.80.0-x86_64-unknown-linux-gnu", None),
+            ("nightly-2024-06-150", None),
+        ] {
+            assert_eq!(
+                installed_dated_nightly(&Toolchain::dist(installed)).as_deref(),
+                expected,
+                "{installed}"
+            );
+        }
+        assert_eq!(
+            installed_dated_nightly(&Toolchain::ci("abcdef", false)),
+            None
+        );
+    }
+
     fn get_features(
         env: &TestEnvironment,
         name: &KrateName,
@@ -1454,6 +2099,118 @@ mod tests {
         })
     }
 
+    fn crate_file(files: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, path, content.as_bytes())?;
+        }
+        Ok(archive.into_inner()?.finish()?)
+    }
+
+    #[test]
+    fn test_unpack_crate_file() -> Result<()> {
+        let name: KrateName = "foo".parse()?;
+        let dir = tempfile::tempdir()?;
+
+        let content = crate_file(&[
+            ("foo-0.1.0/Cargo.toml", "[package]\nname = \"foo\""),
+            ("foo-0.1.0/src/lib.rs", "pub fn foo() {}"),
+        ])?;
+        let root = unpack_crate_file(&content, &name, &V0_1, dir.path())?;
+        assert_eq!(root, dir.path().join("foo-0.1.0"));
+        assert_eq!(
+            fs::read_to_string(root.join("src/lib.rs"))?,
+            "pub fn foo() {}"
+        );
+
+        // a crate file for another release
+        let content = crate_file(&[("bar-0.1.0/Cargo.toml", "")])?;
+        assert!(unpack_crate_file(&content, &name, &V0_1, tempfile::tempdir()?.path()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_build_crate_from_alternative_registry() -> Result<()> {
+        let mut registry = mockito::Server::new();
+        let env = TestEnvironment::builder()
+            .registry_api_config(
+                docs_rs_registry_api::Config::builder()
+                    .registry_api_host(registry.url().parse()?)
+                    .registry_name("company".into())
+                    .registry_index_url(format!("sparse+{}/index/", registry.url()))
+                    .registry_download_url(format!("{}/api/v1/crates", registry.url()))
+                    .crates_io_api_call_retries(0)
+                    .build(),
+            )
+            .build()?;
+
+        let crate_ = KrateName::from_static("company-internal");
+        let version = V0_1;
+
+        let download = registry
+            .mock("GET", "/api/v1/crates/company-internal/0.1.0/download")
+            .with_body(crate_file(&[
+                (
+                    "company-internal-0.1.0/Cargo.toml",
+                    "[package]\nname = \"company-internal\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+                ),
+                (
+                    "company-internal-0.1.0/src/lib.rs",
+                    "//! internal docs\npub fn internal() {}\n",
+                ),
+            ])?)
+            .create();
+
+        let mut builder = env.build_builder()?;
+        builder.update_toolchain()?;
+
+        let kind = builder.registry_package_kind();
+        assert!(matches!(kind, PackageKind::Registry));
+        assert!(
+            builder
+                .build_package(&crate_, &version, kind, false)?
+                .successful
+        );
+        download.assert();
+
+        let row = block_on_async_with_conn!(env, |mut conn| async {
+            sqlx::query!(
+                "SELECT
+                    r.rustdoc_status,
+                    r.registry
+                 FROM
+                    crates as c
+                    INNER JOIN releases AS r ON c.id = r.crate_id
+                 WHERE
+                    c.name = $1 AND
+                    r.version = $2",
+                crate_ as _,
+                version as _
+            )
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(Into::into)
+        })?;
+
+        assert_eq!(row.rustdoc_status, Some(true));
+        assert_eq!(row.registry, "company");
+
+        let storage = env.blocking_storage()?;
+        assert!(storage.exists(&rustdoc_archive_path(&crate_, &version))?);
+        assert!(storage.exists(&source_archive_path(&crate_, &version))?);
+
+        Ok(())
+    }
+
     fn remove_cache_files(env: &TestEnvironment, crate_: &str, version: &Version) -> Result<()> {
         let paths = [
             format!("cache/index.crates.io-6f17d22bba15001f/{crate_}-{version}.crate"),
@@ -2290,6 +3047,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[ignore]
+    fn test_global_toolchain_restored_after_panic() -> Result<()> {
+        let env = TestEnvironment::new()?;
+
+        let mut builder = env.build_builder()?;
+        let global_toolchain = builder.toolchain.clone();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut builder = RestoreToolchain::new(&mut builder);
+            builder.toolchain = toolchain_from_name("nightly-2020-01-01");
+            panic!("build panicked");
+        }));
+        assert!(result.is_err());
+        assert_eq!(builder.toolchain, global_toolchain);
+
+        Ok(())
+    }
+
     #[test]
     fn test_read_format_version_from_rustdoc_json() -> Result<()> {
         let buf = serde_json::to_vec(&serde_json::json!({
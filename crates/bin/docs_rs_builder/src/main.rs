@@ -1,10 +1,9 @@
-use anyhow::{Context as _, Result, anyhow, bail};
+use anyhow::{Context as _, Result, anyhow};
 use clap::{Parser, Subcommand};
-use docs_rs_builder::{Config, PackageKind, RustwideBuilder, queue_builder};
+use docs_rs_builder::{Config, RustwideBuilder, queue_builder};
 use docs_rs_config::AppConfig as _;
 use docs_rs_context::Context;
 use docs_rs_database::service_config::{ConfigName, get_config};
-use docs_rs_env_vars::maybe_env;
 use docs_rs_types::{KrateName, Version};
 use std::{path::PathBuf, sync::Arc};
 use tokio::runtime;
@@ -62,7 +61,7 @@ impl CommandLine {
 
         match self {
             Self::Start => {
-                queue_builder(&ctx, &config, RustwideBuilder::init(config.clone(), &ctx)?)?;
+                queue_builder(&ctx, &config)?;
             }
             Self::Build { subcommand } => subcommand.handle_args(ctx, config)?,
         }
@@ -119,20 +118,14 @@ impl BuildSubcommand {
                         .build_local_package(&path)
                         .context("Building documentation failed")?;
                 } else {
-                    if maybe_env::<String>("REGISTRY_URL")?.is_some() {
-                        bail!("we temporarily don't support custom registries in this commmand.");
-                    }
-
+                    let kind = builder.registry_package_kind();
                     builder
                         .build_package(
                             &crate_name
                                 .with_context(|| anyhow!("must specify name if not local"))?,
                             &crate_version
                                 .with_context(|| anyhow!("must specify version if not local"))?,
-                            PackageKind::CratesIo,
-                            // registry_url
-                            //     .map(|s| PackageKind::Registry(s.as_str()))
-                            //     .unwrap_or(PackageKind::CratesIo
+                            kind,
                             true,
                         )
                         .context("Building documentation failed")?;
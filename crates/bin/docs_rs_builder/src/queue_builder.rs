@@ -1,21 +1,118 @@
 use crate::build_queue::build_next_queue_package;
 use crate::{Config, RustwideBuilder};
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use docs_rs_build_queue::default_lease_holder;
 use docs_rs_context::Context;
+use docs_rs_utils::start_async_cron_in_runtime;
+use docs_rs_webhooks::Webhooks;
 use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc,
+};
 use std::time::Duration;
 use std::{fs, io, path::Path, thread};
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
-/// the main build-server loop
-pub fn queue_builder(
+/// how often we look for webhook deliveries to retry.
+const WEBHOOK_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// the main build-server loop.
+///
+/// Starts `config.build_workers` workers, which take releases from the queue concurrently,
+/// and a background task retrying failed webhook deliveries.
+///
+/// Workers only stop on errors. When one of them stops, we stop the others after their
+/// current build, and return once all of them are done.
+pub fn queue_builder(context: &Context, config: &Config) -> Result<()> {
+    let webhooks = Webhooks::new(config.webhooks.clone())?;
+    let pool = context.pool()?.clone();
+    start_async_cron_in_runtime(
+        context.runtime(),
+        "webhook delivery retries",
+        WEBHOOK_RETRY_INTERVAL,
+        move || {
+            let webhooks = webhooks.clone();
+            let pool = pool.clone();
+            async move {
+                let delivered = webhooks.deliver_pending(&pool).await?;
+                if delivered > 0 {
+                    debug!(delivered, "retried webhook deliveries");
+                }
+                Ok(())
+            }
+        },
+    );
+
+    let lease_holder = default_lease_holder();
+    let shutdown = AtomicBool::new(false);
+    let (results_tx, results) = mpsc::channel();
+    thread::scope(|scope| {
+        for worker in 0..config.build_workers {
+            let config = config.for_worker(worker);
+            let lease_holder = if config.build_workers > 1 {
+                format!("{lease_holder}/worker-{worker}")
+            } else {
+                lease_holder.clone()
+            };
+            let results_tx = results_tx.clone();
+            let shutdown = &shutdown;
+            thread::Builder::new()
+                .name(format!("build-worker-{worker}"))
+                .spawn_scoped(scope, move || {
+                    let result = catch_unwind(AssertUnwindSafe(|| {
+                        info!(lease_holder, "starting build worker");
+                        let builder = RustwideBuilder::init(Arc::new(config.clone()), context)?;
+                        queue_worker(context, &config, &lease_holder, builder, shutdown)
+                    }))
+                    .unwrap_or_else(|_| Err(anyhow!("build worker panicked")));
+                    // the receiver is only gone when the main thread stopped waiting
+                    let _ = results_tx.send((worker, result));
+                })
+                .inspect_err(|_| shutdown.store(true, Ordering::Relaxed))?;
+        }
+        drop(results_tx);
+
+        let mut errors = Vec::new();
+        for (worker, result) in results {
+            shutdown.store(true, Ordering::Relaxed);
+            match result {
+                Ok(()) => info!(worker, "build worker stopped"),
+                Err(err) => {
+                    error!(worker, ?err, "build worker failed");
+                    errors.push(err);
+                }
+            }
+        }
+
+        let failed = errors.len();
+        match errors.into_iter().next() {
+            Some(err) => Err(err.context(format!(
+                "{failed} of {} build workers failed",
+                config.build_workers
+            ))),
+            None => Ok(()),
+        }
+    })
+}
+
+/// the build loop of a single worker.
+fn queue_worker(
     context: &Context,
     config: &Config,
+    lease_holder: &str,
     mut builder: RustwideBuilder,
+    shutdown: &AtomicBool,
 ) -> Result<()> {
     let build_queue = context.blocking_build_queue()?;
 
     loop {
+        if shutdown.load(Ordering::Relaxed) {
+            info!(lease_holder, "another build worker stopped, stopping");
+            return Ok(());
+        }
+
         let temp_dir = &config.temp_dir;
         if temp_dir.exists()
             && let Err(e) = remove_tempdirs(temp_dir)
@@ -41,7 +138,7 @@ pub fn queue_builder(
         // If a panic occurs while building a crate, lock the queue until an admin has a chance to look at it.
         debug!("Checking build queue");
         let res = catch_unwind(AssertUnwindSafe(|| {
-            match build_next_queue_package(context, &mut builder) {
+            match build_next_queue_package(context, &mut builder, lease_holder) {
                 Ok(true) => {}
                 Ok(false) => {
                     debug!("Queue is empty, going back to sleep");
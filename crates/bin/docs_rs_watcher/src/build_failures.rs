@@ -0,0 +1,235 @@
+//! Extract the first compiler error of failed builds from their build logs, so
+//! failures can be grouped across all builds.
+
+use anyhow::Result;
+use docs_rs_database::build_failures::{self, FailureSignature};
+use docs_rs_storage::{AsyncStorage, PathNotFoundError, SizeLimitReached};
+use docs_rs_types::BuildId;
+use futures_util::TryStreamExt as _;
+use tracing::{debug, instrument, warn};
+
+/// how many failed builds we look at in one run.
+const BATCH_SIZE: i64 = 100;
+
+/// Read the build log of a failed build. We prefer the log of a failed target,
+/// and fall back to any log we have for the build.
+///
+/// `None` when there is no log we can read, also when it's too big. Retrying
+/// wouldn't change that, so these builds are recorded without a failure signature.
+async fn fetch_build_log(
+    storage: &AsyncStorage,
+    build_id: BuildId,
+    failed_log: Option<String>,
+) -> Result<Option<String>> {
+    let prefix = format!("build-logs/{build_id}/");
+
+    let filename = match failed_log {
+        Some(filename) => Some(filename),
+        None => storage
+            .list_prefix(&prefix)
+            .await
+            .try_next()
+            .await?
+            .map(|path| path.trim_start_matches(&prefix).to_owned()),
+    };
+    let Some(filename) = filename else {
+        return Ok(None);
+    };
+
+    match storage
+        .get(
+            &format!("{prefix}{filename}"),
+            storage.config().max_file_size,
+        )
+        .await
+    {
+        Ok(blob) => Ok(Some(String::from_utf8_lossy(&blob.content).into_owned())),
+        Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => Ok(None),
+        Err(err)
+            if err.downcast_ref::<std::io::Error>().is_some_and(|err| {
+                err.get_ref()
+                    .is_some_and(|err| err.is::<SizeLimitReached>())
+            }) =>
+        {
+            warn!(%build_id, filename, "build log too big to extract the build failure");
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Extract the first compiler error of failed builds we didn't look at yet, newest first.
+///
+/// Returns the number of processed builds.
+#[instrument(skip_all)]
+pub(crate) async fn extract_build_failures(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+) -> Result<usize> {
+    let builds = sqlx::query!(
+        r#"SELECT
+             builds.id as "id: BuildId",
+             builds.output,
+             (
+                 SELECT log_filename
+                 FROM builds_logs
+                 WHERE builds_logs.build_id = builds.id AND builds_logs.success = FALSE
+                 ORDER BY log_filename
+                 LIMIT 1
+             ) as failed_log
+         FROM builds
+         LEFT OUTER JOIN build_failures ON build_failures.build_id = builds.id
+         WHERE
+             builds.build_status = 'failure' AND
+             build_failures.build_id IS NULL
+         ORDER BY builds.id DESC
+         LIMIT $1"#,
+        BATCH_SIZE,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let count = builds.len();
+    for build in builds {
+        let log = match build.output {
+            // legacy builds have their log in the database.
+            Some(output) => Some(output),
+            None => fetch_build_log(storage, build.id, build.failed_log).await?,
+        };
+
+        let signature = log.as_deref().and_then(FailureSignature::from_build_log);
+        debug!(build_id=%build.id, ?signature, "extracted build failure");
+        build_failures::record(&mut *conn, build.id, signature.as_ref()).await?;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnvironment;
+    use docs_rs_storage::StorageKind;
+    use docs_rs_test_fakes::FakeBuild;
+    use docs_rs_types::{
+        BuildStatus,
+        testing::{BAR, FOO, V1},
+    };
+    use pretty_assertions::assert_eq;
+
+    async fn failures(env: &TestEnvironment) -> Result<Vec<(Option<String>, Option<String>)>> {
+        let mut conn = env.async_conn().await?;
+        Ok(
+            sqlx::query!("SELECT error_code, message FROM build_failures ORDER BY build_id")
+                .fetch_all(&mut *conn)
+                .await?
+                .into_iter()
+                .map(|row| (row.error_code, row.message))
+                .collect(),
+        )
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_extract_build_failures() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+
+        env.fake_release()
+            .await
+            .name(&FOO)
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::Failure)
+                    .db_build_log("[INFO] [stderr] error[E0599]: no method named `foo` found"),
+            ])
+            .create()
+            .await?;
+        env.fake_release()
+            .await
+            .name(&BAR)
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::Failure)
+                    .s3_build_log(
+                        "[INFO] [stderr] error: cannot find macro `bar` in this scope\n\
+                 [INFO] [stderr] error: could not compile `bar`",
+                        false,
+                    ),
+            ])
+            .create()
+            .await?;
+        env.fake_release()
+            .await
+            .name("baz")
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::Failure)
+                    .s3_build_log("[INFO] [stderr] error: could not compile `baz`", false),
+            ])
+            .create()
+            .await?;
+        // successful builds are ignored
+        env.fake_release()
+            .await
+            .name("qux")
+            .version(V1)
+            .create()
+            .await?;
+
+        assert_eq!(extract_build_failures(&mut conn, env.storage()?).await?, 3);
+        assert_eq!(
+            failures(&env).await?,
+            vec![
+                (
+                    Some("E0599".into()),
+                    Some("no method named `foo` found".into())
+                ),
+                (None, Some("cannot find macro `bar` in this scope".into())),
+                (None, None),
+            ]
+        );
+
+        // nothing left to do
+        assert_eq!(extract_build_failures(&mut conn, env.storage()?).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_build_log_too_big() -> Result<()> {
+        let env = TestEnvironment::builder()
+            .storage_config(
+                docs_rs_storage::Config::test_config_with_kind(StorageKind::Memory)?.set(
+                    |mut cfg| {
+                        cfg.max_file_size = 1;
+                        cfg
+                    },
+                ),
+            )
+            .build()
+            .await?;
+        let mut conn = env.async_conn().await?;
+
+        env.fake_release()
+            .await
+            .name(&FOO)
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::Failure)
+                    .s3_build_log("[INFO] [stderr] error: could not compile `foo`", false),
+            ])
+            .create()
+            .await?;
+
+        // recorded without a signature, so we don't try again.
+        assert_eq!(extract_build_failures(&mut conn, env.storage()?).await?, 1);
+        assert_eq!(failures(&env).await?, vec![(None, None)]);
+        assert_eq!(extract_build_failures(&mut conn, env.storage()?).await?, 0);
+
+        Ok(())
+    }
+}
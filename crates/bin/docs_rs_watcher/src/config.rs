@@ -1,21 +1,31 @@
 use anyhow::Result;
+use chrono::NaiveDate;
 use docs_rs_config::AppConfig;
 use docs_rs_env_vars::{env, maybe_env, require_env};
-use std::{path::PathBuf, time::Duration};
+use docs_rs_registry_api::Registry;
+use std::{num::ParseIntError, path::PathBuf, str::FromStr, time::Duration};
+use thiserror::Error;
 
 #[derive(Debug)]
 pub struct Config {
+    /// local path of the git index clone, or of the sparse index cache.
     pub registry_index_path: PathBuf,
-    pub registry_url: Option<String>,
+    /// the registry we watch, its index is a git repository or a `sparse+` URL.
+    pub registry: Registry,
 
     /// How long to wait between registry checks
     pub delay_between_registry_fetches: Duration,
 
+    /// How long to wait between checks for new events pushed by crates.io
+    pub delay_between_crates_io_event_checks: Duration,
+
     // Time between 'git gc --auto' calls in seconds
     pub registry_gc_interval: u64,
 
     // automatic rebuild configuration
     pub max_queued_rebuilds: Option<u16>,
+    /// Which releases we rebuild, applied in order until `max_queued_rebuilds` is reached.
+    pub rebuild_policies: RebuildPolicies,
 
     /// Maximum time to wait for queue row locks when deleting crates/releases.
     pub delete_lock_timeout: Duration,
@@ -28,13 +38,18 @@ impl AppConfig for Config {
         let prefix: PathBuf = require_env("DOCSRS_PREFIX")?;
         Ok(Self {
             registry_index_path: env("REGISTRY_INDEX_PATH", prefix.join("crates.io-index"))?,
-            registry_url: maybe_env("REGISTRY_URL")?,
+            registry: docs_rs_registry_api::Config::from_environment()?.registry(),
             delay_between_registry_fetches: Duration::from_secs(env::<u64>(
                 "DOCSRS_DELAY_BETWEEN_REGISTRY_FETCHES",
                 60,
             )?),
+            delay_between_crates_io_event_checks: Duration::from_secs(env::<u64>(
+                "DOCSRS_DELAY_BETWEEN_CRATES_IO_EVENT_CHECKS",
+                5,
+            )?),
             registry_gc_interval: env("DOCSRS_REGISTRY_GC_INTERVAL", 60 * 60)?,
             max_queued_rebuilds: maybe_env("DOCSRS_MAX_QUEUED_REBUILDS")?,
+            rebuild_policies: maybe_env("DOCSRS_REBUILD_POLICIES")?.unwrap_or_default(),
             delete_lock_timeout: Duration::from_secs(env::<u64>(
                 "DOCSRS_DELETE_LOCK_TIMEOUT_SECONDS",
                 20 * 60,
@@ -43,3 +58,188 @@ impl AppConfig for Config {
         })
     }
 }
+
+impl Config {
+    /// Do we watch the registry through the sparse (HTTP) index instead of a git clone?
+    ///
+    /// Like in cargo, sparse index URLs start with `sparse+`.
+    pub fn uses_sparse_index(&self) -> bool {
+        self.registry.index_url.starts_with("sparse+")
+    }
+}
+
+/// A strategy to pick releases for rebuilds, with the maximum amount of rebuilds
+/// it queues in one run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildPolicy {
+    pub kind: RebuildPolicyKind,
+    /// `None` means the policy can use all rebuilds left in `max_queued_rebuilds`.
+    pub budget: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebuildPolicyKind {
+    /// The latest release of each crate with documentation, oldest build first.
+    Latest,
+    /// The latest release of each crate when its last build failed with a
+    /// transient error, like a timeout.
+    TransientFailures,
+    /// The latest release of the most-downloaded crates, oldest build first.
+    TopDownloads(u32),
+    /// Every release whose last build used a nightly from this date range, including
+    /// failed builds a newer nightly might fix.
+    RustcNightlyRange(NaiveDate, NaiveDate),
+}
+
+/// The configured rebuild policies.
+///
+/// Parsed from a comma-separated list of `<policy>[=<budget>]`, with these policies:
+/// * `latest`
+/// * `transient-failures`
+/// * `top-downloads:<crates>`
+/// * `rustc:<from>..<to>`, with nightly dates like `2024-01-31`
+///
+/// For example: `transient-failures=10,top-downloads:1000=50,latest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildPolicies(pub Vec<RebuildPolicy>);
+
+impl Default for RebuildPolicies {
+    fn default() -> Self {
+        Self(vec![RebuildPolicy {
+            kind: RebuildPolicyKind::Latest,
+            budget: None,
+        }])
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseRebuildPolicyError {
+    #[error("unknown rebuild policy `{0}`")]
+    UnknownPolicy(String),
+    #[error("invalid budget `{value}`: {source}")]
+    InvalidBudget {
+        value: String,
+        #[source]
+        source: ParseIntError,
+    },
+    #[error("invalid crate count `{value}`: {source}")]
+    InvalidCrateCount {
+        value: String,
+        #[source]
+        source: ParseIntError,
+    },
+    #[error("expected a nightly date range in the form <from>..<to>, got `{0}`")]
+    InvalidDateRange(String),
+}
+
+impl FromStr for RebuildPolicy {
+    type Err = ParseRebuildPolicyError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, budget) =
+            match s.split_once('=') {
+                Some((kind, budget)) => (
+                    kind,
+                    Some(budget.parse().map_err(|source| {
+                        ParseRebuildPolicyError::InvalidBudget {
+                            value: budget.to_string(),
+                            source,
+                        }
+                    })?),
+                ),
+                None => (s, None),
+            };
+
+        let kind = match kind.split_once(':') {
+            None if kind == "latest" => RebuildPolicyKind::Latest,
+            None if kind == "transient-failures" => RebuildPolicyKind::TransientFailures,
+            Some(("top-downloads", crates)) => {
+                RebuildPolicyKind::TopDownloads(crates.parse().map_err(|source| {
+                    ParseRebuildPolicyError::InvalidCrateCount {
+                        value: crates.to_string(),
+                        source,
+                    }
+                })?)
+            }
+            Some(("rustc", range)) => {
+                let invalid = || ParseRebuildPolicyError::InvalidDateRange(range.to_string());
+                let (from, to) = range.split_once("..").ok_or_else(invalid)?;
+                let from = from.parse().map_err(|_| invalid())?;
+                let to = to.parse().map_err(|_| invalid())?;
+                if from > to {
+                    return Err(invalid());
+                }
+                RebuildPolicyKind::RustcNightlyRange(from, to)
+            }
+            _ => return Err(ParseRebuildPolicyError::UnknownPolicy(kind.to_string())),
+        };
+
+        Ok(Self { kind, budget })
+    }
+}
+
+impl FromStr for RebuildPolicies {
+    type Err = ParseRebuildPolicyError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|policy| !policy.is_empty())
+            .map(str::parse)
+            .collect::<std::result::Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_rebuild_policies() {
+        let policies: RebuildPolicies =
+            "transient-failures=10, top-downloads:1000=50,rustc:2024-01-01..2024-02-01=5,latest"
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            policies.0,
+            vec![
+                RebuildPolicy {
+                    kind: RebuildPolicyKind::TransientFailures,
+                    budget: Some(10),
+                },
+                RebuildPolicy {
+                    kind: RebuildPolicyKind::TopDownloads(1000),
+                    budget: Some(50),
+                },
+                RebuildPolicy {
+                    kind: RebuildPolicyKind::RustcNightlyRange(
+                        date("2024-01-01"),
+                        date("2024-02-01")
+                    ),
+                    budget: Some(5),
+                },
+                RebuildPolicy {
+                    kind: RebuildPolicyKind::Latest,
+                    budget: None,
+                },
+            ]
+        );
+    }
+
+    #[test_case("unknown")]
+    #[test_case("latest=many")]
+    #[test_case("top-downloads")]
+    #[test_case("top-downloads:lots")]
+    #[test_case("rustc:2024-01-01")]
+    #[test_case("rustc:2024-02-01..2024-01-01"; "descending")]
+    fn rejects_invalid_rebuild_policies(policies: &str) {
+        assert!(policies.parse::<RebuildPolicies>().is_err());
+    }
+}
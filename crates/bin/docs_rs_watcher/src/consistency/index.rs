@@ -1,18 +1,45 @@
 use super::data::{Crate, Crates, Release, Releases};
-use crate::Config;
+use crate::{Config, SparseIndex};
 use anyhow::Result;
-use docs_rs_types::Version;
+use docs_rs_types::{KrateName, Version};
 use docs_rs_utils::run_blocking;
+use futures_util::{StreamExt as _, TryStreamExt as _, stream};
 use rayon::iter::ParallelIterator;
 use tracing::debug;
 
+/// how many index files we fetch at the same time from a sparse index.
+const SPARSE_INDEX_CONCURRENCY: usize = 16;
+
+fn crate_from_index(krate: &crates_index::Crate) -> Crate {
+    let mut releases: Releases = krate
+        .versions()
+        .iter()
+        .filter_map(|version| {
+            version
+                .version()
+                .parse::<Version>()
+                .ok()
+                .map(|semversion| Release {
+                    version: semversion,
+                    yanked: Some(version.is_yanked()),
+                })
+        })
+        .collect();
+
+    releases.sort_by(|lhs, rhs| lhs.version.cmp(&rhs.version));
+
+    Crate {
+        name: krate
+            .name()
+            .parse()
+            .expect("all crate names in the index vare valid"),
+        releases,
+    }
+}
+
 pub(super) async fn load(config: &Config) -> Result<Crates> {
     let registry_index_path = config.registry_index_path.clone();
-    let registry_url = config
-        .registry_url
-        .as_deref()
-        .unwrap_or("https://github.com/rust-lang/crates.io-index")
-        .to_owned();
+    let registry_url = config.registry.index_url.clone();
 
     run_blocking("load-crates-index", move || {
         debug!("Opening with `crates_index`");
@@ -26,33 +53,7 @@ pub(super) async fn load(config: &Config) -> Result<Crates> {
 
         let mut result: Crates = index
             .crates_parallel()
-            .map(|krate| {
-                krate.map(|krate| {
-                    let mut releases: Releases =
-                        krate
-                            .versions()
-                            .iter()
-                            .filter_map(|version| {
-                                version.version().parse::<Version>().ok().map(|semversion| {
-                                    Release {
-                                        version: semversion,
-                                        yanked: Some(version.is_yanked()),
-                                    }
-                                })
-                            })
-                            .collect();
-
-                    releases.sort_by(|lhs, rhs| lhs.version.cmp(&rhs.version));
-
-                    Crate {
-                        name: krate
-                            .name()
-                            .parse()
-                            .expect("all crate names in the index vare valid"),
-                        releases,
-                    }
-                })
-            })
+            .map(|krate| krate.map(|krate| crate_from_index(&krate)))
             .collect::<Result<_, _>>()?;
 
         result.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
@@ -61,3 +62,22 @@ pub(super) async fn load(config: &Config) -> Result<Crates> {
     })
     .await
 }
+
+/// Load the given crates from a sparse index.
+///
+/// The sparse index can't list all crates, so crates we don't know about can't be found.
+pub(super) async fn load_sparse(index: SparseIndex, names: Vec<KrateName>) -> Result<Crates> {
+    let mut result: Crates = stream::iter(names)
+        .map(move |name| {
+            let index = index.clone();
+            async move { index.fetch_crate(&name).await }
+        })
+        .buffer_unordered(SPARSE_INDEX_CONCURRENCY)
+        .try_filter_map(|krate| async move { Ok(krate.as_ref().map(crate_from_index)) })
+        .try_collect()
+        .await?;
+
+    result.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+
+    Ok(result)
+}
@@ -1,4 +1,4 @@
-use crate::{Config, db::delete, index_watcher::set_yanked};
+use crate::{Config, SparseIndex, db::delete, index_watcher::set_yanked};
 use anyhow::{Context as _, Result};
 use docs_rs_build_queue::PRIORITY_CONSISTENCY_CHECK;
 use docs_rs_context::Context;
@@ -24,6 +24,9 @@ mod index;
 ///
 /// Even when activities fail, the command can just be re-run. While the diff calculation will
 /// be repeated, we won't re-execute fixing activities.
+///
+/// With a sparse index we can't list all crates in the index, so we only check the crates
+/// that are in our database. Crates that are only in the index can't be found.
 pub async fn run_check(config: &Config, ctx: &Context, dry_run: bool) -> Result<()> {
     info!("Loading data from database...");
     let mut conn = ctx.pool()?.get_async().await?;
@@ -32,9 +35,16 @@ pub async fn run_check(config: &Config, ctx: &Context, dry_run: bool) -> Result<
         .context("Loading crate data from database for consistency check")?;
 
     tracing::info!("Loading data from index...");
-    let index_data = index::load(config)
+    let index_data = if config.uses_sparse_index() {
+        index::load_sparse(
+            SparseIndex::from_config(config)?,
+            db_data.iter().map(|krate| krate.name.clone()).collect(),
+        )
         .await
-        .context("Loading crate data from index for consistency check")?;
+    } else {
+        index::load(config).await
+    }
+    .context("Loading crate data from index for consistency check")?;
 
     let diff = diff::calculate_diff(db_data.iter(), index_data.iter());
     let result = handle_diff(config, ctx, diff.iter(), dry_run).await?;
@@ -0,0 +1,402 @@
+//! Apply index change events pushed by crates.io.
+//!
+//! The web server receives the events and stores them in the `crates_io_events`
+//! table, deduplicated by their ID. Here we apply them with the same logic we use
+//! for changes we find in the git index.
+//!
+//! Polling the git index stays active as a reconciliation fallback, so changes that
+//! were already applied from an event are skipped there.
+
+use crate::{
+    Config,
+    index_watcher::{
+        CrateVersion, process_crate_deleted, process_version_added, process_version_deleted,
+        process_version_yank_status,
+    },
+};
+use anyhow::{Result, anyhow};
+use docs_rs_context::Context;
+use docs_rs_crates_io::events::{self, IndexChangeV1};
+use docs_rs_types::{KrateName, Version};
+use tracing::{debug, error};
+
+/// how many events we fetch from the database at once.
+const BATCH_SIZE: i64 = 100;
+
+/// After this many failed attempts we stop retrying an event.
+/// The last error stays in the table for debugging.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// How long we keep processed events around, so redeliveries are still deduplicated.
+const RETENTION_DAYS: i32 = 30;
+
+impl CrateVersion {
+    fn from_event(value: &events::CrateVersion, yanked: bool) -> Result<Self> {
+        Ok(Self {
+            name: value.name.parse()?,
+            version: value.version.parse()?,
+            yanked,
+        })
+    }
+}
+
+/// Is this release already in our database?
+async fn release_exists(
+    conn: &mut sqlx::PgConnection,
+    name: &KrateName,
+    version: &Version,
+) -> Result<bool> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1
+            FROM releases
+            INNER JOIN crates ON crates.id = releases.crate_id
+            WHERE crates.name = $1 AND releases.version = $2
+        ) as "exists!""#,
+        name as _,
+        version as _,
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+/// Is this release already in our database, or waiting in the build queue?
+pub(crate) async fn release_is_known(
+    context: &Context,
+    name: &KrateName,
+    version: &Version,
+) -> Result<bool> {
+    let mut conn = context.pool()?.get_async().await?;
+    Ok(release_exists(&mut conn, name, version).await?
+        || context
+            .build_queue()?
+            .has_build_queued(name, version)
+            .await?)
+}
+
+/// Was this change already successfully applied from a crates.io event?
+pub(crate) async fn event_was_applied(
+    context: &Context,
+    kind: &str,
+    name: &KrateName,
+    version: Option<&Version>,
+) -> Result<bool> {
+    let mut conn = context.pool()?.get_async().await?;
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1
+            FROM crates_io_events
+            WHERE
+                kind = $1 AND
+                name = $2 AND
+                version IS NOT DISTINCT FROM $3 AND
+                processed_at IS NOT NULL AND
+                last_error IS NULL
+        ) as "applied!""#,
+        kind,
+        name.as_str(),
+        version.map(|v| v.to_string()),
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+/// Apply a single change, returns whether a release was added to the build queue.
+async fn apply_change(context: &Context, config: &Config, change: &IndexChangeV1) -> Result<bool> {
+    match change {
+        IndexChangeV1::Added(release) => {
+            let release = CrateVersion::from_event(release, false)?;
+            if release_is_known(context, &release.name, &release.version).await? {
+                debug!(
+                    name=%release.name,
+                    version=%release.version,
+                    "release already known, skipping",
+                );
+                return Ok(false);
+            }
+            process_version_added(context, &release).await?;
+            Ok(true)
+        }
+        IndexChangeV1::Yanked(release) | IndexChangeV1::Unyanked(release) => {
+            let release = CrateVersion::from_event(release, change.yanked().is_some())?;
+            process_version_yank_status(context, &release).await?;
+            Ok(false)
+        }
+        IndexChangeV1::CrateDeleted { name } => {
+            process_crate_deleted(context, config, &name.parse()?).await?;
+            Ok(false)
+        }
+        IndexChangeV1::VersionDeleted(release) => {
+            let release = CrateVersion::from_event(release, false)?;
+            let mut conn = context.pool()?.get_async().await?;
+            if release_exists(&mut conn, &release.name, &release.version).await? {
+                process_version_deleted(context, config, &release).await?;
+            } else {
+                // not built yet, or the index poll was faster.
+                context
+                    .build_queue()?
+                    .remove_version_from_queue(&release.name, &release.version)
+                    .await?;
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Apply pending crates.io events, oldest first.
+///
+/// Returns the number of releases that were added to the build queue.
+pub(crate) async fn process_pending_events(context: &Context, config: &Config) -> Result<usize> {
+    let mut conn = context.pool()?.get_async().await?;
+
+    let pending = sqlx::query!(
+        "SELECT id, kind, name, version
+         FROM crates_io_events
+         WHERE processed_at IS NULL
+         ORDER BY occurred_at, received_at
+         LIMIT $1",
+        BATCH_SIZE,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let mut crates_added = 0;
+    for event in pending {
+        let result = match IndexChangeV1::from_parts(&event.kind, event.name, event.version) {
+            Some(change) => apply_change(context, config, &change).await,
+            None => Err(anyhow!("unknown event kind {}", event.kind)),
+        };
+
+        match result {
+            Ok(added) => {
+                if added {
+                    crates_added += 1;
+                }
+                sqlx::query!(
+                    "UPDATE crates_io_events
+                     SET
+                        processed_at = NOW(),
+                        attempts = attempts + 1,
+                        last_error = NULL
+                     WHERE id = $1",
+                    event.id,
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Err(err) => {
+                error!(id = event.id, ?err, "failed to process crates.io event");
+                sqlx::query!(
+                    "UPDATE crates_io_events
+                     SET
+                        attempts = attempts + 1,
+                        last_error = $2,
+                        processed_at = CASE
+                            WHEN attempts + 1 >= $3 THEN NOW()
+                            ELSE NULL
+                        END
+                     WHERE id = $1",
+                    event.id,
+                    format!("{err:?}"),
+                    MAX_ATTEMPTS,
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+    }
+
+    if crates_added > 0
+        && let Err(err) = context.build_queue()?.reevaluate_priorities().await
+    {
+        error!(?err, "error reevaluating queued release priorities");
+    }
+
+    sqlx::query!(
+        "DELETE FROM crates_io_events
+         WHERE processed_at < NOW() - make_interval(days => $1)",
+        RETENTION_DAYS,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(crates_added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnvironment;
+    use chrono::{TimeZone as _, Utc};
+    use docs_rs_types::testing::{KRATE, V1, V2};
+    use pretty_assertions::assert_eq;
+
+    async fn insert_event(
+        conn: &mut sqlx::PgConnection,
+        id: &str,
+        seconds: i64,
+        change: &IndexChangeV1,
+    ) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO crates_io_events (id, occurred_at, kind, name, version)
+             VALUES ($1, $2, $3, $4, $5)",
+            id,
+            Utc.timestamp_opt(seconds, 0).unwrap(),
+            change.kind(),
+            change.crate_name(),
+            change.crate_version().map(|v| v.version.as_str()),
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    fn krate(version: &Version) -> events::CrateVersion {
+        events::CrateVersion {
+            name: KRATE.to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_added_events_are_queued_once() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+        let build_queue = env.build_queue()?;
+
+        insert_event(&mut conn, "1", 1, &IndexChangeV1::Added(krate(&V1))).await?;
+        insert_event(&mut conn, "2", 2, &IndexChangeV1::Added(krate(&V2))).await?;
+
+        assert_eq!(process_pending_events(&env, env.config()).await?, 2);
+
+        let queue = build_queue.queued_crates().await?;
+        assert_eq!(queue.len(), 2);
+
+        // nothing pending anymore
+        assert_eq!(process_pending_events(&env, env.config()).await?, 0);
+
+        // a new event for an already queued release doesn't queue it again
+        insert_event(&mut conn, "3", 3, &IndexChangeV1::Added(krate(&V1))).await?;
+        assert_eq!(process_pending_events(&env, env.config()).await?, 0);
+        assert_eq!(build_queue.queued_crates().await?.len(), 2);
+
+        assert!(event_was_applied(&env, "added", &KRATE, Some(&V1)).await?);
+        assert!(!event_was_applied(&env, "version_deleted", &KRATE, Some(&V1)).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_yank_and_delete_events() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+
+        let id = env
+            .fake_release()
+            .await
+            .name(&KRATE)
+            .version(V1)
+            .create()
+            .await?;
+        env.fake_release()
+            .await
+            .name(&KRATE)
+            .version(V2)
+            .create()
+            .await?;
+
+        insert_event(&mut conn, "1", 1, &IndexChangeV1::Yanked(krate(&V1))).await?;
+        process_pending_events(&env, env.config()).await?;
+
+        let yanked = sqlx::query_scalar!("SELECT yanked FROM releases WHERE id = $1", id.0)
+            .fetch_one(&mut *conn)
+            .await?;
+        assert_eq!(yanked, Some(true));
+
+        insert_event(
+            &mut conn,
+            "2",
+            2,
+            &IndexChangeV1::VersionDeleted(krate(&V1)),
+        )
+        .await?;
+        // deleting a release that's already gone is fine too.
+        insert_event(
+            &mut conn,
+            "3",
+            3,
+            &IndexChangeV1::VersionDeleted(krate(&V1)),
+        )
+        .await?;
+        process_pending_events(&env, env.config()).await?;
+
+        let versions: Vec<Version> =
+            sqlx::query_scalar!(r#"SELECT version as "version: Version" FROM releases"#)
+                .fetch_all(&mut *conn)
+                .await?;
+        assert_eq!(versions, vec![V2]);
+
+        insert_event(
+            &mut conn,
+            "4",
+            4,
+            &IndexChangeV1::CrateDeleted {
+                name: KRATE.to_string(),
+            },
+        )
+        .await?;
+        process_pending_events(&env, env.config()).await?;
+
+        let crates = sqlx::query_scalar!("SELECT COUNT(*) FROM crates")
+            .fetch_one(&mut *conn)
+            .await?;
+        assert_eq!(crates, Some(0));
+
+        let errors = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM crates_io_events WHERE last_error IS NOT NULL"
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        assert_eq!(errors, Some(0));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failing_events_are_retried_then_given_up() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+
+        insert_event(
+            &mut conn,
+            "1",
+            1,
+            &IndexChangeV1::Added(events::CrateVersion {
+                name: KRATE.to_string(),
+                version: "not-a-version".into(),
+            }),
+        )
+        .await?;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            process_pending_events(&env, env.config()).await?;
+
+            let row = sqlx::query!(
+                "SELECT attempts, last_error, processed_at FROM crates_io_events WHERE id = '1'"
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            assert_eq!(row.attempts, attempt);
+            assert!(row.last_error.is_some());
+            assert_eq!(row.processed_at.is_some(), attempt == MAX_ATTEMPTS);
+        }
+
+        assert!(env.build_queue()?.queued_crates().await?.is_empty());
+
+        Ok(())
+    }
+}
@@ -279,7 +279,7 @@ mod tests {
 
     use super::*;
     use docs_rs_rustdoc_json::RustdocJsonFormatVersion;
-    use docs_rs_storage::rustdoc_json_path;
+    use docs_rs_storage::{StorageKind, rustdoc_json_path};
     use docs_rs_test_fakes::{CrateOwner, OwnerKind, fake_release_that_failed_before_build};
     use docs_rs_types::{
         CompressionAlgorithm, ReleaseId, SimpleBuildError,
@@ -409,6 +409,62 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_delete_content_addressed_releases() -> Result<()> {
+        use futures_util::TryStreamExt as _;
+
+        let env = TestEnvironment::builder()
+            .storage_config(
+                docs_rs_storage::Config::test_config_with_kind(StorageKind::Memory)?.set(
+                    |mut cfg| {
+                        cfg.content_addressed_storage = true;
+                        cfg
+                    },
+                ),
+            )
+            .build()
+            .await?;
+        let storage = env.storage()?;
+        let mut conn = env.async_conn().await?;
+
+        async fn blob_count(storage: &AsyncStorage) -> Result<usize> {
+            Ok(storage
+                .list_prefix("blobs/")
+                .await
+                .try_collect::<Vec<_>>()
+                .await?
+                .len())
+        }
+
+        for version in [V1, V2] {
+            env.fake_release()
+                .await
+                .name(&FOO)
+                .version(version)
+                .create()
+                .await?;
+        }
+        let blobs = blob_count(storage).await?;
+        assert!(blobs > 0);
+
+        delete_version(&mut conn, storage, env.config(), &FOO, &V1).await?;
+
+        // the files of the other release are still there, including the shared ones.
+        assert!(blob_count(storage).await? > 0);
+        let path = format!("{FOO}/index.html");
+        assert!(storage.rustdoc_file_exists(&FOO, &V2, None, &path).await?);
+        storage
+            .stream_rustdoc_file(&FOO, &V2, None, &path)
+            .await?
+            .materialize(usize::MAX)
+            .await?;
+
+        delete_crate(&mut conn, storage, env.config(), &FOO).await?;
+        assert_eq!(blob_count(storage).await?, 0);
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_delete_version() -> Result<()> {
         let env = TestEnvironment::new().await?;
@@ -24,7 +24,11 @@ pub struct Index {
 
 impl Index {
     pub async fn from_config(config: &Config) -> Result<Self> {
-        Index::from_url(&config.registry_index_path, config.registry_url.as_deref()).await
+        Index::from_url(
+            &config.registry_index_path,
+            Some(&config.registry.index_url),
+        )
+        .await
     }
 
     pub async fn from_url(
@@ -1,9 +1,12 @@
 use crate::{
     Config,
+    crates_io_events::{event_was_applied, release_is_known},
     db::{delete_crate, delete_version},
     index::Index,
+    sparse_index::SparseIndex,
 };
 use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
 use crates_index_diff::Change;
 use docs_rs_build_queue::PRIORITY_MANUAL_FROM_CRATES_IO;
 use docs_rs_context::Context;
@@ -12,9 +15,13 @@ use docs_rs_database::{
     service_config::{ConfigName, get_config, set_config},
 };
 use docs_rs_fastly::{Cdn, CdnBehaviour as _};
+use docs_rs_registry_api::UpdatedCrate;
 use docs_rs_types::{CrateId, KrateName, Version};
 use tracing::{debug, error, info, warn};
 
+/// how many recently updated crates we process before moving the last seen update.
+const SPARSE_INDEX_UPDATE_CHUNK_SIZE: usize = 100;
+
 #[derive(Debug)]
 pub(crate) struct CrateVersion {
     pub name: KrateName,
@@ -76,6 +83,22 @@ pub async fn set_last_seen_reference(
     Ok(())
 }
 
+/// The `updated_at` of the most recently updated crate we have seen in the registry API,
+/// the cursor when watching a sparse index.
+pub async fn last_seen_sparse_index_update(
+    conn: &mut sqlx::PgConnection,
+) -> Result<Option<DateTime<Utc>>> {
+    get_config(conn, ConfigName::LastSeenSparseIndexUpdate).await
+}
+
+pub async fn set_last_seen_sparse_index_update(
+    conn: &mut sqlx::PgConnection,
+    updated_at: DateTime<Utc>,
+) -> Result<()> {
+    set_config(conn, ConfigName::LastSeenSparseIndexUpdate, updated_at).await?;
+    Ok(())
+}
+
 async fn queue_crate_invalidation(krate: &KrateName, cdn: Option<&Cdn>) {
     let Some(cdn) = &cdn else {
         info!(%krate, "no CDN configured, skippping crate invalidation");
@@ -129,6 +152,80 @@ pub(crate) async fn get_new_crates(
     Ok(crates_added)
 }
 
+/// Checks the sparse index entries of recently updated crates, and adds new crates into
+/// the build queue.
+///
+/// The crates are processed oldest first, in chunks. After each chunk we store the index
+/// entries in our local cache, and move the last seen update, so a failure only repeats
+/// the current chunk in the next run.
+///
+/// Returns the number of crates added
+pub(crate) async fn get_new_crates_from_sparse_index(
+    context: &Context,
+    index: &SparseIndex,
+    config: &Config,
+) -> Result<usize> {
+    let mut conn = context.pool()?.get_async().await?;
+
+    let Some(last_seen_update) = last_seen_sparse_index_update(&mut conn).await? else {
+        warn!(
+            "no last-seen sparse index update found in our database. We assume a fresh install
+                     and start from now. This means we will then start to queue
+                     builds for new releases only from now on, and not for all existing releases."
+        );
+        set_last_seen_sparse_index_update(&mut conn, Utc::now()).await?;
+        return Ok(0);
+    };
+
+    // The API lists the most recently updated crates first, so to start with the oldest
+    // we have to go back to the last seen update.
+    let updated = context
+        .registry_api()?
+        .recently_updated_crates(last_seen_update, usize::MAX)
+        .await?;
+
+    debug!(%last_seen_update, crates=updated.crates.len(), "queueing changes");
+
+    let mut crates_added = 0;
+    // oldest first, like the changes we get from the git index.
+    let mut crates = updated.crates.iter().rev().peekable();
+    while crates.peek().is_some() {
+        // crates updated at the same time go into the same chunk, the next run only
+        // looks at crates updated after the last seen update.
+        let mut chunk: Vec<&UpdatedCrate> = Vec::new();
+        while let Some(krate) = crates.next_if(|krate| {
+            chunk.len() < SPARSE_INDEX_UPDATE_CHUNK_SIZE
+                || chunk
+                    .last()
+                    .is_some_and(|last| last.updated_at == krate.updated_at)
+        }) {
+            chunk.push(krate);
+        }
+
+        let mut changes = Vec::new();
+        let mut entries = Vec::new();
+        for krate in &chunk {
+            let (crate_changes, entry) = index.fetch_changes(&krate.name).await?;
+            changes.extend(crate_changes);
+            entries.push(entry);
+        }
+
+        crates_added += process_changes(context, &changes, config).await;
+
+        for entry in entries {
+            index.store(entry).await?;
+        }
+        let new_update = chunk.last().expect("chunks aren't empty").updated_at;
+        set_last_seen_sparse_index_update(&mut conn, new_update).await?;
+    }
+
+    if let Err(err) = context.build_queue()?.reevaluate_priorities().await {
+        error!(?err, "error reevaluating queued release priorities");
+    }
+
+    Ok(crates_added)
+}
+
 async fn process_changes(context: &Context, changes: &Vec<Change>, config: &Config) -> usize {
     let mut crates_added = 0;
 
@@ -156,28 +253,56 @@ async fn process_change(context: &Context, change: &Change, config: &Config) ->
         .clone()
         .try_into()?;
 
+    // crates.io also pushes these changes as events, which might have been
+    // applied already. In that case we skip them here.
     match change {
-        Change::Added(_release) => process_version_added(context, &crate_version).await?,
-        Change::AddedAndYanked(_release) => {
-            process_version_added(context, &crate_version).await?;
-            process_version_yank_status(context, &crate_version).await?;
+        Change::Added(_release) | Change::AddedAndYanked(_release) => {
+            let known =
+                release_is_known(context, &crate_version.name, &crate_version.version).await?;
+            if known {
+                debug!(
+                    name=%crate_version.name,
+                    version=%crate_version.version,
+                    "release already known, skipping",
+                );
+            } else {
+                process_version_added(context, &crate_version).await?;
+            }
+            if matches!(change, Change::AddedAndYanked(_)) {
+                process_version_yank_status(context, &crate_version).await?;
+            }
+            return Ok(!known);
         }
         Change::Unyanked(_release) | Change::Yanked(_release) => {
             process_version_yank_status(context, &crate_version).await?
         }
         Change::CrateDeleted { name, .. } => {
             let name: KrateName = name.parse()?;
-            process_crate_deleted(context, config, &name).await?
+            if !event_was_applied(context, "crate_deleted", &name, None).await? {
+                process_crate_deleted(context, config, &name).await?
+            }
         }
         Change::VersionDeleted(_release) => {
-            process_version_deleted(context, config, &crate_version).await?
+            if !event_was_applied(
+                context,
+                "version_deleted",
+                &crate_version.name,
+                Some(&crate_version.version),
+            )
+            .await?
+            {
+                process_version_deleted(context, config, &crate_version).await?
+            }
         }
     };
     Ok(change.added().is_some())
 }
 
 /// Processes crate changes, whether they got yanked or unyanked.
-async fn process_version_yank_status(context: &Context, release: &CrateVersion) -> Result<()> {
+pub(crate) async fn process_version_yank_status(
+    context: &Context,
+    release: &CrateVersion,
+) -> Result<()> {
     // FIXME: delay yanks of crates that have not yet finished building
     // https://github.com/rust-lang/docs.rs/issues/1934
     set_yanked(context, &release.name, &release.version, release.yanked).await?;
@@ -185,7 +310,7 @@ async fn process_version_yank_status(context: &Context, release: &CrateVersion)
     Ok(())
 }
 
-async fn process_version_added(context: &Context, release: &CrateVersion) -> Result<()> {
+pub(crate) async fn process_version_added(context: &Context, release: &CrateVersion) -> Result<()> {
     let build_queue = context.build_queue()?;
 
     let priority = build_queue.find_priority(&release.name).await?;
@@ -217,7 +342,7 @@ async fn process_version_added(context: &Context, release: &CrateVersion) -> Res
     Ok(())
 }
 
-async fn process_version_deleted(
+pub(crate) async fn process_version_deleted(
     context: &Context,
     config: &Config,
     release: &CrateVersion,
@@ -251,7 +376,7 @@ async fn process_version_deleted(
     Ok(())
 }
 
-async fn process_crate_deleted(
+pub(crate) async fn process_crate_deleted(
     context: &Context,
     config: &Config,
     krate: &KrateName,
@@ -282,7 +407,8 @@ pub(crate) async fn set_yanked(
 
     if let Some(crate_id) = sqlx::query_scalar!(
         r#"UPDATE releases
-         SET yanked = $3
+         SET yanked = $3,
+             yanked_at = CASE WHEN $3 THEN COALESCE(yanked_at, NOW()) END
          FROM crates
          WHERE crates.id = releases.crate_id
              AND name = $1
@@ -393,7 +519,7 @@ mod tests {
 
         // And verify it's actually marked as yanked
         let row = sqlx::query!(
-            "SELECT yanked
+            "SELECT yanked, yanked_at
              FROM releases
              WHERE id = $1",
             id.0
@@ -401,6 +527,7 @@ mod tests {
         .fetch_one(&mut *conn)
         .await?;
         assert_eq!(row.yanked, Some(true));
+        assert!(row.yanked_at.is_some());
 
         // Verify whether we can unyank it too
         let krate = CrateVersion {
@@ -411,7 +538,7 @@ mod tests {
         process_version_yank_status(&env, &krate).await?;
 
         let row = sqlx::query!(
-            "SELECT yanked
+            "SELECT yanked, yanked_at
              FROM releases
              WHERE id = $1",
             id.0
@@ -419,6 +546,7 @@ mod tests {
         .fetch_one(&mut *conn)
         .await?;
         assert_eq!(row.yanked, Some(false));
+        assert!(row.yanked_at.is_none());
 
         Ok(())
     }
@@ -538,6 +666,63 @@ mod tests {
         Ok(())
     }
 
+    /// Changes that were already applied from crates.io events are skipped
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_process_changes_skips_applied_events() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+        let build_queue = env.build_queue()?;
+
+        let krate = CrateVersion {
+            name: KRATE,
+            version: V1,
+            ..Default::default()
+        };
+        process_version_added(&env, &krate).await?;
+
+        env.fake_release()
+            .await
+            .name("other")
+            .version(V1)
+            .create()
+            .await?;
+        sqlx::query!(
+            "INSERT INTO crates_io_events (id, occurred_at, kind, name, version, processed_at)
+             VALUES ('1', NOW(), 'version_deleted', 'other', $1, NOW())",
+            V1.to_string(),
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        let added = process_changes(
+            &env,
+            &vec![
+                // already queued
+                Change::Added(krate.into()),
+                // already deleted through an event
+                Change::VersionDeleted(
+                    CrateVersion {
+                        name: "other".parse()?,
+                        version: V1,
+                        ..Default::default()
+                    }
+                    .into(),
+                ),
+            ],
+            env.config(),
+        )
+        .await;
+
+        assert_eq!(added, 0);
+        assert_eq!(build_queue.queued_crates().await?.len(), 1);
+        let releases = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM releases"#)
+            .fetch_one(&mut *conn)
+            .await?;
+        assert_eq!(releases, 1);
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_last_seen_reference_in_db() -> Result<()> {
         let env = TestEnvironment::new().await?;
@@ -575,4 +760,159 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_get_new_crates_from_sparse_index() -> Result<()> {
+        let mut registry = mockito::Server::new_async().await;
+        let env = TestEnvironment::builder()
+            .registry_api_config(
+                docs_rs_registry_api::Config::builder()
+                    .registry_api_host(registry.url().parse()?)
+                    .crates_io_api_call_retries(0)
+                    .build(),
+            )
+            .build()
+            .await?;
+        let mut conn = env.async_conn().await?;
+        let build_queue = env.build_queue()?;
+
+        let cache = tempfile::tempdir()?;
+        let index = SparseIndex::new(cache.path(), format!("sparse+{}/index/", registry.url()))?;
+
+        // a fresh install starts from now
+        assert_eq!(
+            get_new_crates_from_sparse_index(&env, &index, env.config()).await?,
+            0
+        );
+        assert!(last_seen_sparse_index_update(&mut conn).await?.is_some());
+
+        let since: DateTime<Utc> = "2024-01-01T10:00:00Z".parse()?;
+        let updated_at: DateTime<Utc> = "2024-01-01T10:05:00Z".parse()?;
+        set_last_seen_sparse_index_update(&mut conn, since).await?;
+
+        let _updates = registry
+            .mock("GET", "/api/v1/crates?sort=recent-updates&per_page=100")
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({
+                    "crates": [
+                        { "name": "krate", "updated_at": updated_at },
+                        { "name": "old", "updated_at": since },
+                    ],
+                    "meta": { "next_page": null },
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let _index = registry
+            .mock("GET", "/index/kr/at/krate")
+            .with_body(crate::sparse_index::tests::index_line(
+                "krate",
+                &V1.to_string(),
+                false,
+            ))
+            .create_async()
+            .await;
+
+        assert_eq!(
+            get_new_crates_from_sparse_index(&env, &index, env.config()).await?,
+            1
+        );
+
+        let queue = build_queue.queued_crates().await?;
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].name, KRATE);
+        assert_eq!(queue[0].version, V1);
+        assert_eq!(
+            last_seen_sparse_index_update(&mut conn).await?,
+            Some(updated_at)
+        );
+
+        // the changes of a chunk are only stored when all crates of the chunk were fetched.
+        _updates.remove_async().await;
+        let since = updated_at;
+        let updated_at = |seconds: i64| since + chrono::TimeDelta::seconds(seconds);
+        let older_crates: Vec<_> = (1..=SPARSE_INDEX_UPDATE_CHUNK_SIZE as i64)
+            .rev()
+            .map(|i| serde_json::json!({ "name": format!("c{i}"), "updated_at": updated_at(i) }))
+            .collect();
+        let _first_page = registry
+            .mock("GET", "/api/v1/crates?sort=recent-updates&per_page=100")
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({
+                    "crates": [{ "name": "krate", "updated_at": updated_at(1000) }],
+                    "meta": { "next_page": "?sort=recent-updates&per_page=100&page=2" },
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let _second_page = registry
+            .mock(
+                "GET",
+                "/api/v1/crates?sort=recent-updates&per_page=100&page=2",
+            )
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({
+                    "crates": older_crates,
+                    "meta": { "next_page": null },
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let _older_index = registry
+            .mock("GET", mockito::Matcher::Regex("^/index/.+/c[0-9]+$".into()))
+            .with_status(404)
+            .create_async()
+            .await;
+        _index.remove_async().await;
+        let broken_index = registry
+            .mock("GET", "/index/kr/at/krate")
+            .with_status(500)
+            .create_async()
+            .await;
+
+        assert!(
+            get_new_crates_from_sparse_index(&env, &index, env.config())
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            last_seen_sparse_index_update(&mut conn).await?,
+            Some(updated_at(SPARSE_INDEX_UPDATE_CHUNK_SIZE as i64))
+        );
+
+        broken_index.remove_async().await;
+        let _index = registry
+            .mock("GET", "/index/kr/at/krate")
+            .with_body(
+                [V1, V2]
+                    .iter()
+                    .map(|version| {
+                        crate::sparse_index::tests::index_line("krate", &version.to_string(), false)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+            .create_async()
+            .await;
+
+        assert_eq!(
+            get_new_crates_from_sparse_index(&env, &index, env.config()).await?,
+            1
+        );
+        let queue = build_queue.queued_crates().await?;
+        assert_eq!(queue.len(), 2);
+        assert!(queue.iter().any(|krate| krate.version == V2));
+        assert_eq!(
+            last_seen_sparse_index_update(&mut conn).await?,
+            Some(updated_at(1000))
+        );
+
+        Ok(())
+    }
 }
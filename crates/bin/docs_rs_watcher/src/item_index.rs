@@ -0,0 +1,371 @@
+//! Index the public items of the latest release of each crate, for item-level search.
+//!
+//! The items are read from the rustdoc JSON we store for the default target.
+//! Rebuilding a release resets `releases.items_indexed_at`, so it's picked up again.
+
+use anyhow::Result;
+use docs_rs_rustdoc_json::{
+    PublicItem, RUSTDOC_JSON_COMPRESSION_ALGORITHMS, RustdocJsonFormatVersion, extract_public_items,
+};
+use docs_rs_storage::{
+    AsyncStorage, PathNotFoundError, SizeLimitReached, decompress, rustdoc_json_path,
+};
+use docs_rs_types::{KrateName, ReleaseId, Version};
+use docs_rs_utils::spawn_blocking;
+use std::io;
+use tracing::{debug, instrument, warn};
+
+/// how many releases we index in one run.
+const BATCH_SIZE: i64 = 50;
+
+/// is this error from reading a file over the size limit?
+fn is_size_limit_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<io::Error>()
+        .and_then(|err| err.get_ref())
+        .is_some_and(|err| err.is::<SizeLimitReached>())
+}
+
+/// Fetch & parse the rustdoc JSON of a release.
+///
+/// The outer error is for fetch errors, which might go away when we retry, the inner one
+/// for files that are too big, or that we can't decompress or parse.
+///
+/// Returns `None` when there is no rustdoc JSON for this release, for example
+/// for releases that were built before we started storing it.
+async fn fetch_public_items(
+    storage: &AsyncStorage,
+    name: &KrateName,
+    version: &Version,
+    target: &str,
+) -> Result<Result<Option<Vec<PublicItem>>>> {
+    let max_size = storage.config().max_file_size;
+
+    for alg in RUSTDOC_JSON_COMPRESSION_ALGORITHMS {
+        let path = rustdoc_json_path(
+            name,
+            version,
+            target,
+            RustdocJsonFormatVersion::Latest,
+            Some(*alg),
+        );
+
+        let blob = match storage.get_raw_stream(&path).await {
+            Ok(stream) => match stream.materialize(max_size).await {
+                Ok(blob) => blob,
+                Err(err) if is_size_limit_error(&err) => return Ok(Err(err)),
+                Err(err) => return Err(err),
+            },
+            Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => continue,
+            Err(err) => return Err(err),
+        };
+
+        let alg = *alg;
+        return Ok(spawn_blocking(move || {
+            let json = decompress(blob.content.as_slice(), alg, max_size)?;
+            Ok(Some(extract_public_items(json.as_slice())?))
+        })
+        .await);
+    }
+
+    Ok(Ok(None))
+}
+
+/// Replace the indexed items of the crate with the items of this release.
+async fn store_items(
+    conn: &mut sqlx::PgConnection,
+    release_id: ReleaseId,
+    items: &[PublicItem],
+) -> Result<()> {
+    let mut transaction = sqlx::Acquire::begin(&mut *conn).await?;
+
+    // we only keep the items of the latest release of each crate.
+    sqlx::query!(
+        "DELETE FROM release_items
+         WHERE release_id IN (
+             SELECT id FROM releases
+             WHERE crate_id = (SELECT crate_id FROM releases WHERE id = $1)
+         )",
+        release_id.0,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE releases
+         SET items_indexed_at = NULL
+         WHERE
+            crate_id = (SELECT crate_id FROM releases WHERE id = $1) AND
+            id != $1 AND
+            items_indexed_at IS NOT NULL",
+        release_id.0,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let mut paths = Vec::with_capacity(items.len());
+    let mut names = Vec::with_capacity(items.len());
+    let mut kinds = Vec::with_capacity(items.len());
+    let mut signatures = Vec::with_capacity(items.len());
+    for item in items {
+        paths.push(item.path.clone());
+        names.push(item.name().to_owned());
+        kinds.push(item.kind.to_string());
+        signatures.push(item.signature.clone());
+    }
+
+    sqlx::query!(
+        "INSERT INTO release_items (release_id, path, name, kind, signature)
+         SELECT $1, * FROM UNNEST($2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[])",
+        release_id.0,
+        &paths,
+        &names,
+        &kinds,
+        &signatures as &[Option<String>],
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE releases SET items_indexed_at = NOW() WHERE id = $1",
+        release_id.0,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Index the public items of latest releases that weren't indexed yet.
+///
+/// Returns the number of indexed releases.
+#[instrument(skip_all)]
+pub(crate) async fn index_release_items(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+) -> Result<usize> {
+    let releases = sqlx::query!(
+        r#"SELECT
+             releases.id as "id: ReleaseId",
+             crates.name as "name: KrateName",
+             releases.version as "version: Version",
+             releases.default_target
+         FROM crates
+         INNER JOIN releases ON releases.id = crates.latest_version_id
+         WHERE
+             releases.rustdoc_status = TRUE AND
+             releases.is_library = TRUE AND
+             releases.items_indexed_at IS NULL
+         ORDER BY releases.release_time DESC NULLS LAST
+         LIMIT $1"#,
+        BATCH_SIZE,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let count = releases.len();
+    for release in releases {
+        let items = match release.default_target {
+            Some(ref target) => {
+                // fetch errors fail the run, the next one tries again.
+                match fetch_public_items(storage, &release.name, &release.version, target).await? {
+                    Ok(items) => items.unwrap_or_default(),
+                    Err(err) => {
+                        // A broken, too big or unexpected JSON file won't get better when
+                        // retried, and would block the other releases in the batch.
+                        // We mark the release as indexed, a rebuild tries again.
+                        warn!(
+                            name=%release.name,
+                            version=%release.version,
+                            ?err,
+                            "couldn't read rustdoc JSON, skipping item index",
+                        );
+                        Vec::new()
+                    }
+                }
+            }
+            None => Vec::new(),
+        };
+
+        debug!(
+            name=%release.name,
+            version=%release.version,
+            items=items.len(),
+            "indexing public items",
+        );
+        store_items(&mut *conn, release.id, &items).await?;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestEnvironment;
+    use docs_rs_storage::compress;
+    use docs_rs_types::{
+        CompressionAlgorithm,
+        testing::{KRATE, V1, V2},
+    };
+    use pretty_assertions::assert_eq;
+
+    const TARGET: &str = "x86_64-unknown-linux-gnu";
+
+    async fn store_rustdoc_json(
+        env: &TestEnvironment,
+        version: &Version,
+        json: serde_json::Value,
+    ) -> Result<()> {
+        let alg = CompressionAlgorithm::Zstd;
+        env.storage()?
+            .store_one_uncompressed(
+                rustdoc_json_path(
+                    &KRATE,
+                    version,
+                    TARGET,
+                    RustdocJsonFormatVersion::Latest,
+                    Some(alg),
+                ),
+                compress(serde_json::to_vec(&json)?.as_slice(), alg)?,
+            )
+            .await?;
+        Ok(())
+    }
+
+    fn rustdoc_json(item_name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "format_version": 45,
+            "index": {
+                "0": { "visibility": "public", "inner": { "module": {} } },
+                "1": { "visibility": "public", "inner": { "struct": {} } },
+            },
+            "paths": {
+                "0": { "crate_id": 0, "path": [KRATE.to_string()], "kind": "module" },
+                "1": { "crate_id": 0, "path": [KRATE.to_string(), item_name], "kind": "struct" },
+            },
+        })
+    }
+
+    async fn indexed_items(env: &TestEnvironment) -> Result<Vec<(String, String)>> {
+        let mut conn = env.async_conn().await?;
+        Ok(sqlx::query!(
+            r#"SELECT releases.version, release_items.path
+               FROM release_items
+               INNER JOIN releases ON releases.id = release_items.release_id
+               ORDER BY releases.version, release_items.path"#
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|row| (row.version, row.path))
+        .collect())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_index_latest_release() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+
+        env.fake_release()
+            .await
+            .name(&KRATE)
+            .version(V1)
+            .create()
+            .await?;
+        store_rustdoc_json(&env, &V1, rustdoc_json("Old")).await?;
+
+        assert_eq!(index_release_items(&mut conn, env.storage()?).await?, 1);
+        assert_eq!(
+            indexed_items(&env).await?,
+            vec![
+                (V1.to_string(), KRATE.to_string()),
+                (V1.to_string(), format!("{KRATE}::Old")),
+            ]
+        );
+
+        // nothing left to do
+        assert_eq!(index_release_items(&mut conn, env.storage()?).await?, 0);
+
+        env.fake_release()
+            .await
+            .name(&KRATE)
+            .version(V2)
+            .create()
+            .await?;
+        store_rustdoc_json(&env, &V2, rustdoc_json("New")).await?;
+
+        assert_eq!(index_release_items(&mut conn, env.storage()?).await?, 1);
+        assert_eq!(
+            indexed_items(&env).await?,
+            vec![
+                (V2.to_string(), KRATE.to_string()),
+                (V2.to_string(), format!("{KRATE}::New")),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unparseable_json_is_skipped() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+
+        // the fake releases only contain the format version
+        env.fake_release()
+            .await
+            .name(&KRATE)
+            .version(V1)
+            .create()
+            .await?;
+
+        assert_eq!(index_release_items(&mut conn, env.storage()?).await?, 1);
+        assert!(indexed_items(&env).await?.is_empty());
+        assert_eq!(index_release_items(&mut conn, env.storage()?).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_undecompressable_json_is_skipped() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let mut conn = env.async_conn().await?;
+
+        env.fake_release()
+            .await
+            .name(&KRATE)
+            .version(V1)
+            .create()
+            .await?;
+        env.storage()?
+            .store_one_uncompressed(
+                rustdoc_json_path(
+                    &KRATE,
+                    &V1,
+                    TARGET,
+                    RustdocJsonFormatVersion::Latest,
+                    Some(CompressionAlgorithm::Zstd),
+                ),
+                "not zstd",
+            )
+            .await?;
+
+        assert_eq!(index_release_items(&mut conn, env.storage()?).await?, 1);
+        assert!(indexed_items(&env).await?.is_empty());
+        assert_eq!(index_release_items(&mut conn, env.storage()?).await?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_size_limit_error() {
+        assert!(is_size_limit_error(
+            &io::Error::other(SizeLimitReached).into()
+        ));
+        assert!(!is_size_limit_error(
+            &io::Error::other("connection reset").into()
+        ));
+        assert!(!is_size_limit_error(&anyhow::anyhow!("timeout")));
+    }
+}
@@ -1,10 +1,14 @@
+mod build_failures;
 mod config;
 pub mod consistency;
+mod crates_io_events;
 mod db;
 mod index;
 pub mod index_watcher;
+mod item_index;
 mod rebuilds;
 mod service_metrics;
+mod sparse_index;
 #[cfg(test)]
 mod testing;
 
@@ -12,8 +16,15 @@ pub use config::Config;
 pub use db::{delete_crate, delete_version};
 pub use index::Index;
 pub use rebuilds::queue_rebuilds;
+pub use sparse_index::SparseIndex;
 
-use crate::{index_watcher::get_new_crates, service_metrics::OtelServiceMetrics};
+use crate::{
+    build_failures::extract_build_failures,
+    crates_io_events::process_pending_events,
+    index_watcher::{get_new_crates, get_new_crates_from_sparse_index},
+    item_index::index_release_items,
+    service_metrics::OtelServiceMetrics,
+};
 use anyhow::Result;
 use docs_rs_context::Context;
 use docs_rs_utils::start_async_cron;
@@ -22,10 +33,18 @@ use tokio::time::{self, Instant};
 use tracing::{debug, error, info, trace};
 
 /// Run the registry watcher
+///
+/// Events pushed by crates.io are applied every
+/// `delay_between_crates_io_event_checks`, while the registry index is
+/// polled every `delay_between_registry_fetches` to catch anything we missed.
+/// The index is either a git clone, or a sparse (HTTP) index when the
+/// configured registry URL starts with `sparse+`.
+///
 /// NOTE: this should only be run once, otherwise crates would be added
 /// to the queue multiple times.
 pub async fn watch_registry(config: &Config, context: &Context) -> Result<()> {
     let mut last_gc = Instant::now();
+    let mut last_fetch: Option<Instant> = None;
 
     let queue = context.build_queue()?;
 
@@ -33,22 +52,48 @@ pub async fn watch_registry(config: &Config, context: &Context) -> Result<()> {
         if queue.is_locked().await? {
             debug!("Queue is locked, skipping checking new crates");
         } else {
-            debug!("Checking new crates");
-            let index = Index::from_config(config).await?;
-
-            match get_new_crates(context, &index, config).await {
-                Ok(n) => debug!("{} crates added to queue", n),
+            match process_pending_events(context, config).await {
+                Ok(n) if n > 0 => debug!("{} crates added to queue from crates.io events", n),
+                Ok(_) => {}
                 Err(e) => {
-                    error!(?e, "Failed to get new crates");
+                    error!(?e, "Failed to process crates.io events");
                 }
             }
 
-            if last_gc.elapsed().as_secs() >= config.registry_gc_interval {
-                index.run_git_gc().await;
-                last_gc = Instant::now();
+            if last_fetch.is_none_or(|last_fetch| {
+                last_fetch.elapsed() >= config.delay_between_registry_fetches
+            }) {
+                debug!("Checking new crates");
+                last_fetch = Some(Instant::now());
+
+                let result = if config.uses_sparse_index() {
+                    let index = SparseIndex::from_config(config)?;
+                    get_new_crates_from_sparse_index(context, &index, config).await
+                } else {
+                    let index = Index::from_config(config).await?;
+                    let result = get_new_crates(context, &index, config).await;
+
+                    if last_gc.elapsed().as_secs() >= config.registry_gc_interval {
+                        index.run_git_gc().await;
+                        last_gc = Instant::now();
+                    }
+                    result
+                };
+
+                match result {
+                    Ok(n) => debug!("{} crates added to queue", n),
+                    Err(e) => {
+                        error!(?e, "Failed to get new crates");
+                    }
+                }
             }
         }
-        time::sleep(config.delay_between_registry_fetches).await;
+        time::sleep(
+            config
+                .delay_between_crates_io_event_checks
+                .min(config.delay_between_registry_fetches),
+        )
+        .await;
     }
 }
 
@@ -118,3 +163,43 @@ pub async fn start_background_repository_stats_updater(context: &Context) -> Res
     );
     Ok(())
 }
+
+pub async fn start_background_item_indexer(context: &Context) -> Result<()> {
+    let pool = context.pool()?.clone();
+    let storage = context.storage()?.clone();
+    start_async_cron("public item indexer", Duration::from_secs(60), move || {
+        let pool = pool.clone();
+        let storage = storage.clone();
+        async move {
+            let mut conn = pool.get_async().await?;
+            let indexed = index_release_items(&mut conn, &storage).await?;
+            if indexed > 0 {
+                debug!(indexed, "indexed public items of releases");
+            }
+            Ok(())
+        }
+    });
+    Ok(())
+}
+
+pub async fn start_background_build_failure_extractor(context: &Context) -> Result<()> {
+    let pool = context.pool()?.clone();
+    let storage = context.storage()?.clone();
+    start_async_cron(
+        "build failure extractor",
+        Duration::from_secs(60),
+        move || {
+            let pool = pool.clone();
+            let storage = storage.clone();
+            async move {
+                let mut conn = pool.get_async().await?;
+                let extracted = extract_build_failures(&mut conn, &storage).await?;
+                if extracted > 0 {
+                    debug!(extracted, "extracted build failures");
+                }
+                Ok(())
+            }
+        },
+    );
+    Ok(())
+}
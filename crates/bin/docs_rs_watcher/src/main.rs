@@ -1,4 +1,5 @@
-use anyhow::{Context as _, Result};
+use anyhow::{Context as _, Result, bail};
+use chrono::Utc;
 use clap::{Parser, Subcommand};
 use docs_rs_config::AppConfig as _;
 use docs_rs_context::Context;
@@ -35,6 +36,12 @@ enum CommandLine {
         /// enable or disable the automatic rebuild of old releases
         #[arg(long = "queue-rebuilds", default_value = "true")]
         queue_rebuilds: bool,
+        /// enable or disable indexing the public items of releases for item search
+        #[arg(long = "item-indexer", default_value = "true")]
+        item_indexer: bool,
+        /// enable or disable extracting the compiler errors of failed builds
+        #[arg(long = "build-failure-extractor", default_value = "true")]
+        build_failure_extractor: bool,
     },
 
     /// Interactions with the build queue
@@ -63,6 +70,7 @@ impl CommandLine {
             .await?
             .with_maybe_cdn()?
             .with_build_queue()?
+            .with_registry_api()?
             .with_repository_stats()?
             .build()?;
 
@@ -70,6 +78,8 @@ impl CommandLine {
             Self::Start {
                 repository_stats_updater,
                 queue_rebuilds,
+                item_indexer,
+                build_failure_extractor,
             } => {
                 if repository_stats_updater {
                     docs_rs_watcher::start_background_repository_stats_updater(&ctx).await?;
@@ -77,6 +87,12 @@ impl CommandLine {
                 if queue_rebuilds {
                     docs_rs_watcher::start_background_queue_rebuild(config.clone(), &ctx).await?;
                 }
+                if item_indexer {
+                    docs_rs_watcher::start_background_item_indexer(&ctx).await?;
+                }
+                if build_failure_extractor {
+                    docs_rs_watcher::start_background_build_failure_extractor(&ctx).await?;
+                }
 
                 // We assume that we can collect service metrics from the registry watcher,
                 // which should only run once, and all the time.
@@ -104,7 +120,8 @@ enum QueueSubcommand {
         #[arg(conflicts_with("head"))]
         reference: Option<crates_index_diff::gix::ObjectId>,
 
-        /// Fetch the current HEAD of the remote index and use it
+        /// Fetch the current HEAD of the remote index and use it.
+        /// With a sparse index, start from the current time.
         #[arg(long, conflicts_with("reference"))]
         head: bool,
     },
@@ -113,6 +130,17 @@ enum QueueSubcommand {
 impl QueueSubcommand {
     async fn handle_args(self, config: Arc<Config>, ctx: Context) -> Result<()> {
         match self {
+            Self::GetLastSeenReference if config.uses_sparse_index() => {
+                let mut conn = ctx.pool()?.get_async().await?;
+                if let Some(updated_at) =
+                    index_watcher::last_seen_sparse_index_update(&mut conn).await?
+                {
+                    println!("Last seen sparse index update: {updated_at}");
+                } else {
+                    println!("No last seen sparse index update available");
+                }
+            }
+
             Self::GetLastSeenReference => {
                 let mut conn = ctx.pool()?.get_async().await?;
                 if let Some(reference) = index_watcher::last_seen_reference(&mut conn).await? {
@@ -122,6 +150,17 @@ impl QueueSubcommand {
                 }
             }
 
+            Self::SetLastSeenReference { reference, head } if config.uses_sparse_index() => {
+                if reference.is_some() || !head {
+                    bail!("a sparse index has no commits, use `--head` to start from now");
+                }
+
+                let now = Utc::now();
+                let mut conn = ctx.pool()?.get_async().await?;
+                index_watcher::set_last_seen_sparse_index_update(&mut conn, now).await?;
+                println!("Set last seen sparse index update: {now}");
+            }
+
             Self::SetLastSeenReference { reference, head } => {
                 let reference = match (reference, head) {
                     (Some(reference), false) => reference,
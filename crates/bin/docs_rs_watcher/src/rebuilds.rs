@@ -1,22 +1,41 @@
-use crate::Config;
+use crate::{
+    Config,
+    config::{RebuildPolicy, RebuildPolicyKind},
+};
 use anyhow::Result;
 use docs_rs_build_queue::{AsyncBuildQueue, PRIORITY_CONTINUOUS};
 use docs_rs_types::{KrateName, Version};
-use futures_util::StreamExt;
+use futures_util::TryStreamExt as _;
 use tracing::{info, instrument};
 
+/// `builds.error_kind` values of failures that might succeed with a simple retry.
+const TRANSIENT_ERROR_KINDS: &[&str] = &[
+    "NoOutputFor",
+    "Timeout",
+    "KillAfterTimeoutFailed",
+    "SandboxImagePullFailed",
+    "SandboxContainerCreate",
+    "IO",
+];
+
+/// we stop retrying a release after this many failed builds since its last
+/// successful build, some crates just don't build in time.
+const MAX_TRANSIENT_FAILURE_BUILDS: i64 = 3;
+
 /// Queue rebuilds as configured.
 ///
-/// The idea is to rebuild:
+/// The configured rebuild policies are applied in order, each queueing up to its own budget,
+/// until the rebuilds in the queue reach `max_queued_rebuilds`.
+/// Releases that already have a build queued are skipped.
+///
+/// The default policy rebuilds
 /// * the latest release of each crate
-/// * when the nightly version is older than our configured threshold
-/// * and there was a successful build for that release, that included documentation.
-/// * starting with the oldest nightly versions.
-/// * also checking if there is already a build queued.
+/// * when there was a successful build for that release, that included documentation.
+/// * starting with the oldest build.
 ///
-/// This might exclude releases from rebuilds that
-/// * previously failed but would succeed with a newer nightly version
-/// * previously failed but would succeed just with a retry.
+/// Failed releases are only retried with the `transient-failures` policy, up to
+/// `MAX_TRANSIENT_FAILURE_BUILDS` failed builds in a row, or when a newer nightly might fix
+/// them with the `rustc` policy.
 #[instrument(skip_all)]
 pub async fn queue_rebuilds(
     conn: &mut sqlx::PgConnection,
@@ -30,61 +49,218 @@ pub async fn queue_rebuilds(
         .filter_map(|(priority, count)| (*priority >= PRIORITY_CONTINUOUS).then_some(count))
         .sum();
 
-    let rebuilds_to_queue = config
+    let mut rebuilds_to_queue = config
         .max_queued_rebuilds
         .expect("config.max_queued_rebuilds not set") as i64
         - already_queued_rebuilds as i64;
 
-    if rebuilds_to_queue <= 0 {
-        info!("not queueing rebuilds; queue limit reached");
-        return Ok(());
-    }
+    for policy in &config.rebuild_policies.0 {
+        if rebuilds_to_queue <= 0 {
+            info!("not queueing rebuilds; queue limit reached");
+            return Ok(());
+        }
+
+        let limit = policy.budget.map_or(rebuilds_to_queue, |budget| {
+            rebuilds_to_queue.min(budget as i64)
+        });
 
-    let mut results = sqlx::query!(
-        r#"SELECT i.* FROM (
-             SELECT
-                 c.name as "name: KrateName",
-                 r.version as "version: Version",
-                 (
-                    SELECT MAX(COALESCE(b.build_finished, b.build_started))
-                    FROM builds AS b
-                    WHERE b.rid = r.id
-                 ) AS last_build_attempt
-             FROM crates AS c
-             INNER JOIN releases AS r ON c.latest_version_id = r.id
-
-             WHERE
-                 r.rustdoc_status = TRUE
-         ) as i
-         ORDER BY i.last_build_attempt ASC
-         LIMIT $1"#,
-        rebuilds_to_queue,
-    )
-    .fetch(&mut *conn);
-
-    while let Some(row) = results.next().await {
-        let row = row?;
-
-        if !queue.has_build_queued(&row.name, &row.version).await? {
-            info!("queueing rebuild for {} {}...", &row.name, &row.version);
+        for (name, version) in rebuild_candidates(&mut *conn, policy, limit).await? {
+            info!(?policy.kind, "queueing rebuild for {} {}...", &name, &version);
             queue
-                .add_crate(&row.name, &row.version, PRIORITY_CONTINUOUS)
+                .add_crate(&name, &version, PRIORITY_CONTINUOUS)
                 .await?;
+            rebuilds_to_queue -= 1;
         }
     }
 
     Ok(())
 }
 
+/// Fetch up to `limit` releases without a queued build to rebuild for a policy,
+/// the oldest build first.
+async fn rebuild_candidates(
+    conn: &mut sqlx::PgConnection,
+    policy: &RebuildPolicy,
+    limit: i64,
+) -> Result<Vec<(KrateName, Version)>> {
+    Ok(match policy.kind {
+        RebuildPolicyKind::Latest => {
+            sqlx::query!(
+                r#"SELECT i.* FROM (
+                     SELECT
+                         c.name as "name: KrateName",
+                         r.version as "version: Version",
+                         (
+                            SELECT MAX(COALESCE(b.build_finished, b.build_started))
+                            FROM builds AS b
+                            WHERE b.rid = r.id
+                         ) AS last_build_attempt
+                     FROM crates AS c
+                     INNER JOIN releases AS r ON c.latest_version_id = r.id
+
+                     WHERE
+                         r.rustdoc_status = TRUE AND
+                         NOT EXISTS (SELECT 1 FROM queue WHERE queue.name = c.name AND queue.version = r.version)
+                 ) as i
+                 ORDER BY i.last_build_attempt ASC
+                 LIMIT $1"#,
+                limit,
+            )
+            .fetch(&mut *conn)
+            .map_ok(|row| (row.name, row.version))
+            .try_collect()
+            .await?
+        }
+
+        RebuildPolicyKind::TransientFailures => {
+            sqlx::query!(
+                r#"SELECT
+                     c.name as "name: KrateName",
+                     r.version as "version: Version"
+                 FROM crates AS c
+                 INNER JOIN releases AS r ON c.latest_version_id = r.id
+                 INNER JOIN LATERAL (
+                     SELECT
+                         b.build_status,
+                         b.error_kind,
+                         COALESCE(b.build_finished, b.build_started) AS build_attempt
+                     FROM builds AS b
+                     WHERE b.rid = r.id
+                     ORDER BY b.id DESC
+                     LIMIT 1
+                 ) AS last_build ON TRUE
+
+                 WHERE
+                     NOT EXISTS (SELECT 1 FROM queue WHERE queue.name = c.name AND queue.version = r.version) AND
+                     last_build.build_status = 'failure' AND
+                     last_build.error_kind = ANY($1) AND
+                     (
+                         SELECT COUNT(*)
+                         FROM builds AS b
+                         WHERE
+                             b.rid = r.id AND
+                             b.build_status = 'failure' AND
+                             b.id > COALESCE((
+                                 SELECT MAX(s.id)
+                                 FROM builds AS s
+                                 WHERE s.rid = r.id AND s.build_status = 'success'
+                             ), 0)
+                     ) < $2
+                 ORDER BY last_build.build_attempt ASC
+                 LIMIT $3"#,
+                TRANSIENT_ERROR_KINDS as &[&str],
+                MAX_TRANSIENT_FAILURE_BUILDS,
+                limit,
+            )
+            .fetch(&mut *conn)
+            .map_ok(|row| (row.name, row.version))
+            .try_collect()
+            .await?
+        }
+
+        RebuildPolicyKind::TopDownloads(crates) => {
+            sqlx::query!(
+                r#"SELECT
+                     i.name as "name!: KrateName",
+                     i.version as "version!: Version"
+                 FROM (
+                     SELECT
+                         c.name,
+                         r.version,
+                         (
+                            SELECT MAX(COALESCE(b.build_finished, b.build_started))
+                            FROM builds AS b
+                            WHERE b.rid = r.id
+                         ) AS last_build_attempt
+                     FROM crates AS c
+                     INNER JOIN releases AS r ON c.latest_version_id = r.id
+
+                     WHERE
+                         r.rustdoc_status = TRUE
+                     ORDER BY r.downloads DESC NULLS LAST
+                     LIMIT $1
+                 ) as i
+                 WHERE
+                     NOT EXISTS (SELECT 1 FROM queue WHERE queue.name = i.name AND queue.version = i.version)
+                 ORDER BY i.last_build_attempt ASC
+                 LIMIT $2"#,
+                crates as i64,
+                limit,
+            )
+            .fetch(&mut *conn)
+            .map_ok(|row| (row.name, row.version))
+            .try_collect()
+            .await?
+        }
+
+        RebuildPolicyKind::RustcNightlyRange(from, to) => {
+            sqlx::query!(
+                r#"SELECT
+                     c.name as "name: KrateName",
+                     r.version as "version: Version"
+                 FROM crates AS c
+                 INNER JOIN releases AS r ON r.crate_id = c.id
+                 INNER JOIN LATERAL (
+                     SELECT
+                         b.rustc_nightly_date,
+                         COALESCE(b.build_finished, b.build_started) AS build_attempt
+                     FROM builds AS b
+                     WHERE b.rid = r.id
+                     ORDER BY b.id DESC
+                     LIMIT 1
+                 ) AS last_build ON TRUE
+
+                 WHERE
+                     NOT EXISTS (SELECT 1 FROM queue WHERE queue.name = c.name AND queue.version = r.version) AND
+                     last_build.rustc_nightly_date BETWEEN $1 AND $2
+                 ORDER BY last_build.build_attempt ASC
+                 LIMIT $3"#,
+                from,
+                to,
+                limit,
+            )
+            .fetch(&mut *conn)
+            .map_ok(|row| (row.name, row.version))
+            .try_collect()
+            .await?
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::testing::TestEnvironment;
     use docs_rs_config::AppConfig as _;
     use docs_rs_test_fakes::FakeBuild;
-    use docs_rs_types::testing::{BAR, BAZ, FOO, V1};
+    use docs_rs_types::{
+        BuildStatus,
+        testing::{BAR, BAZ, FOO, OTHER, V1},
+    };
     use pretty_assertions::assert_eq;
 
+    async fn env_with_policies(policies: &str) -> Result<TestEnvironment> {
+        let mut config = Config::test_config()?;
+        config.max_queued_rebuilds = Some(100);
+        config.rebuild_policies = policies.parse()?;
+        TestEnvironment::builder().config(config).build().await
+    }
+
+    async fn queue_rebuilds_and_list(env: &TestEnvironment) -> Result<Vec<KrateName>> {
+        let build_queue = env.build_queue()?;
+        let mut conn = env.async_conn().await?;
+        queue_rebuilds(&mut conn, env.config(), build_queue).await?;
+
+        let mut names: Vec<_> = build_queue
+            .queued_crates()
+            .await?
+            .into_iter()
+            .map(|krate| krate.name)
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_rebuild_when_old() -> Result<()> {
         let mut config = Config::test_config()?;
@@ -188,4 +364,160 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rebuild_transient_failures() -> Result<()> {
+        let env = env_with_policies("transient-failures").await?;
+
+        for (name, statuses) in [
+            (&FOO, &[BuildStatus::Failure][..]),
+            (&BAR, &[BuildStatus::Failure]),
+            (&BAZ, &[BuildStatus::Success]),
+            // only failures after the last successful build count
+            (
+                &OTHER,
+                &[
+                    BuildStatus::Failure,
+                    BuildStatus::Failure,
+                    BuildStatus::Failure,
+                    BuildStatus::Success,
+                    BuildStatus::Failure,
+                ],
+            ),
+        ] {
+            env.fake_release()
+                .await
+                .name(name)
+                .version(V1)
+                .builds(
+                    statuses
+                        .iter()
+                        .map(|status| FakeBuild::default().build_status(*status))
+                        .collect(),
+                )
+                .create()
+                .await?;
+        }
+
+        let mut conn = env.async_conn().await?;
+        for (name, error_kind) in [
+            (&FOO, "Timeout"),
+            (&BAR, "ExecutionFailed"),
+            (&OTHER, "Timeout"),
+        ] {
+            sqlx::query!(
+                "UPDATE builds SET error_kind = $2
+                 FROM releases, crates
+                 WHERE builds.rid = releases.id AND releases.crate_id = crates.id AND crates.name = $1",
+                name as _,
+                error_kind,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        assert_eq!(queue_rebuilds_and_list(&env).await?, vec![FOO, OTHER]);
+
+        // every retry of FOO fails with a timeout again
+        for failed_builds in 2..=MAX_TRANSIENT_FAILURE_BUILDS {
+            sqlx::query!("DELETE FROM queue")
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query!(
+                "INSERT INTO builds (rid, build_status, error_kind, build_started, build_finished)
+                 SELECT releases.id, 'failure', 'Timeout', NOW(), NOW()
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE crates.name = $1",
+                FOO as _,
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            let queued = queue_rebuilds_and_list(&env).await?;
+            if failed_builds < MAX_TRANSIENT_FAILURE_BUILDS {
+                assert_eq!(queued, vec![FOO, OTHER]);
+            } else {
+                assert_eq!(queued, vec![OTHER]);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rebuild_top_downloads() -> Result<()> {
+        let env = env_with_policies("top-downloads:2").await?;
+
+        let mut conn = env.async_conn().await?;
+        for (name, downloads) in [(&FOO, 100), (&BAR, 10), (&BAZ, 1000)] {
+            env.fake_release()
+                .await
+                .name(name)
+                .version(V1)
+                .create()
+                .await?;
+            sqlx::query!(
+                "UPDATE releases SET downloads = $2
+                 FROM crates
+                 WHERE releases.crate_id = crates.id AND crates.name = $1",
+                name as _,
+                downloads,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        assert_eq!(queue_rebuilds_and_list(&env).await?, vec![BAZ, FOO]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rebuild_rustc_nightly_range() -> Result<()> {
+        let env = env_with_policies("rustc:2020-10-01..2020-10-31").await?;
+
+        env.fake_release()
+            .await
+            .name(&FOO)
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default()
+                    .rustc_version("rustc 1.84.0-nightly (e7c0d2750 2020-10-15)")
+                    .build_status(BuildStatus::Failure),
+            ])
+            .create()
+            .await?;
+        env.fake_release()
+            .await
+            .name(&BAR)
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default().rustc_version("rustc 1.84.0-nightly (e7c0d2750 2020-11-15)"),
+            ])
+            .create()
+            .await?;
+
+        assert_eq!(queue_rebuilds_and_list(&env).await?, vec![FOO]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rebuild_policy_budgets() -> Result<()> {
+        let env = env_with_policies("top-downloads:10=1,latest=1").await?;
+
+        for name in [&FOO, &BAR, &BAZ] {
+            env.fake_release()
+                .await
+                .name(name)
+                .version(V1)
+                .create()
+                .await?;
+        }
+
+        assert_eq!(queue_rebuilds_and_list(&env).await?.len(), 2);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,359 @@
+//! Watch a registry through the sparse (HTTP) index protocol.
+//!
+//! The sparse protocol can't list crates or changes, so we find the crates to check
+//! through the registry web API, which lists crates by their last update. The newest
+//! update we have seen is stored as cursor in the database, like the last seen commit
+//! when we use the git index.
+//!
+//! Index files are fetched with conditional requests, using the ETag / Last-Modified
+//! of our local cache, and the changes are calculated by comparing them to the
+//! cached version.
+//!
+//! Changes that don't update the crate in the registry API are picked up by the
+//! consistency check.
+
+use crate::Config;
+use anyhow::{Context as _, Result, bail};
+use crates_index_diff::Change;
+use docs_rs_types::KrateName;
+use docs_rs_utils::{APP_USER_AGENT, spawn_blocking};
+use http::{
+    HeaderMap, StatusCode,
+    header::{ACCEPT_ENCODING, ETAG, LAST_MODIFIED},
+};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+/// async-friendly wrapper around `crates_index::SparseIndex`, with an HTTP client.
+#[derive(Clone)]
+pub struct SparseIndex {
+    index: Arc<crates_index::SparseIndex>,
+    client: reqwest::Client,
+}
+
+impl SparseIndex {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Self::new(
+            config.registry_index_path.clone(),
+            config.registry.index_url.as_str(),
+        )
+    }
+
+    /// `url` is the index URL, starting with `sparse+`,
+    /// `path` the directory where we cache the index files.
+    pub fn new(path: impl Into<PathBuf>, url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        if !url.starts_with("sparse+http") {
+            bail!("invalid sparse index URL: {url}");
+        }
+
+        Ok(Self {
+            index: Arc::new(crates_index::SparseIndex::at_path(path.into(), url)),
+            client: reqwest::Client::builder()
+                .user_agent(APP_USER_AGENT)
+                .build()?,
+        })
+    }
+
+    /// Fetch the index entry of a crate, without storing it in our local cache.
+    async fn fetch(&self, name: &KrateName) -> Result<FetchedEntry> {
+        let previous = self.index.crate_from_cache(name.as_str()).ok();
+
+        let request = self.index.make_cache_request(name.as_str())?.body(())?;
+        let mut headers = request.headers().clone();
+        // reqwest handles the response compression for us.
+        headers.remove(ACCEPT_ENCODING);
+
+        let response = self
+            .client
+            .get(request.uri().to_string())
+            .headers(headers)
+            .send()
+            .await
+            .with_context(|| format!("fetching sparse index entry for {name}"))?;
+
+        let mut entry = FetchedEntry {
+            name: name.to_string(),
+            status: response.status(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            previous,
+            current: None,
+        };
+        for header in [ETAG, LAST_MODIFIED] {
+            if let Some(value) = response.headers().get(&header) {
+                entry.headers.insert(header, value.clone());
+            }
+        }
+        entry.body = response.bytes().await?.to_vec();
+
+        let index = self.index.clone();
+        let response = entry.response()?;
+        let name = entry.name.clone();
+        entry.current = spawn_blocking(move || {
+            index
+                .parse_cache_response(&name, response, false)
+                .with_context(|| format!("parsing sparse index entry for {name}"))
+        })
+        .await?;
+
+        Ok(entry)
+    }
+
+    /// Fetch the current index entry of a crate, `None` if it doesn't exist.
+    ///
+    /// Doesn't update our local cache, so the changes of the crate are still returned
+    /// by the next [`SparseIndex::fetch_changes`].
+    pub async fn fetch_crate(&self, name: &KrateName) -> Result<Option<crates_index::Crate>> {
+        Ok(self.fetch(name).await?.current)
+    }
+
+    /// Fetch the index entry of a crate, and return the changes since we last stored it.
+    ///
+    /// The entry has to be stored with [`SparseIndex::store`] after the changes are
+    /// processed, until then we return the same changes again.
+    pub async fn fetch_changes(&self, name: &KrateName) -> Result<(Vec<Change>, FetchedEntry)> {
+        let entry = self.fetch(name).await?;
+        let changes = diff_index_entries(entry.previous.as_ref(), entry.current.as_ref());
+        Ok((changes, entry))
+    }
+
+    /// Store a fetched index entry in our local cache.
+    pub async fn store(&self, entry: FetchedEntry) -> Result<()> {
+        let index = self.index.clone();
+        spawn_blocking(move || {
+            index
+                .parse_cache_response(&entry.name, entry.response()?, true)
+                .with_context(|| format!("storing sparse index entry for {}", entry.name))?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// An index entry we fetched, but didn't store in our local cache yet.
+pub struct FetchedEntry {
+    name: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    previous: Option<crates_index::Crate>,
+    current: Option<crates_index::Crate>,
+}
+
+impl FetchedEntry {
+    /// the response in the form `crates_index` needs it.
+    fn response(&self) -> Result<http::Response<Vec<u8>>> {
+        let mut builder = http::Response::builder().status(self.status);
+        for (header, value) in &self.headers {
+            builder = builder.header(header, value);
+        }
+        Ok(builder.body(self.body.clone())?)
+    }
+}
+
+fn crate_version(version: &crates_index::Version) -> crates_index_diff::CrateVersion {
+    crates_index_diff::CrateVersion {
+        name: version.name().into(),
+        version: version.version().into(),
+        yanked: version.is_yanked(),
+        ..Default::default()
+    }
+}
+
+fn added(version: &crates_index::Version) -> Change {
+    if version.is_yanked() {
+        Change::AddedAndYanked(crate_version(version))
+    } else {
+        Change::Added(crate_version(version))
+    }
+}
+
+/// Compare two versions of the index entry of a crate.
+///
+/// Without a previous entry we can't know which releases are new. In that case we only
+/// return the last published release, which is the last line in the index file.
+fn diff_index_entries(
+    previous: Option<&crates_index::Crate>,
+    current: Option<&crates_index::Crate>,
+) -> Vec<Change> {
+    match (previous, current) {
+        (None, None) => Vec::new(),
+        (None, Some(current)) => vec![added(current.most_recent_version())],
+        (Some(previous), None) => vec![Change::CrateDeleted {
+            name: previous.name().to_owned(),
+            versions: previous.versions().iter().map(crate_version).collect(),
+        }],
+        (Some(previous), Some(current)) => {
+            let previous_versions: HashMap<_, _> = previous
+                .versions()
+                .iter()
+                .map(|version| (version.version(), version))
+                .collect();
+            let current_versions: HashMap<_, _> = current
+                .versions()
+                .iter()
+                .map(|version| (version.version(), version))
+                .collect();
+
+            let mut changes = Vec::new();
+            for version in current.versions() {
+                match previous_versions.get(version.version()) {
+                    None => changes.push(added(version)),
+                    Some(old) if old.is_yanked() && !version.is_yanked() => {
+                        changes.push(Change::Unyanked(crate_version(version)))
+                    }
+                    Some(old) if !old.is_yanked() && version.is_yanked() => {
+                        changes.push(Change::Yanked(crate_version(version)))
+                    }
+                    Some(_) => {}
+                }
+            }
+            for version in previous.versions() {
+                if !current_versions.contains_key(version.version()) {
+                    changes.push(Change::VersionDeleted(crate_version(version)));
+                }
+            }
+            changes
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// one line of a sparse index file
+    pub(crate) fn index_line(name: &str, version: &str, yanked: bool) -> String {
+        serde_json::json!({
+            "name": name,
+            "vers": version,
+            "deps": [],
+            "cksum": "0000000000000000000000000000000000000000000000000000000000000000",
+            "features": {},
+            "yanked": yanked,
+        })
+        .to_string()
+    }
+
+    fn krate(versions: &[(&str, bool)]) -> crates_index::Crate {
+        let lines: Vec<_> = versions
+            .iter()
+            .map(|(version, yanked)| index_line("krate", version, *yanked))
+            .collect();
+        crates_index::Crate::from_slice(lines.join("\n").as_bytes()).unwrap()
+    }
+
+    fn summary(changes: &[Change]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Added(v) => format!("added {}", v.version),
+                Change::AddedAndYanked(v) => format!("added yanked {}", v.version),
+                Change::Yanked(v) => format!("yanked {}", v.version),
+                Change::Unyanked(v) => format!("unyanked {}", v.version),
+                Change::VersionDeleted(v) => format!("deleted {}", v.version),
+                Change::CrateDeleted { name, versions } => {
+                    format!("deleted crate {name} with {} versions", versions.len())
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_without_previous_entry() {
+        assert!(diff_index_entries(None, None).is_empty());
+        assert_eq!(
+            summary(&diff_index_entries(
+                None,
+                Some(&krate(&[("1.0.0", false), ("0.9.1", true)]))
+            )),
+            vec!["added yanked 0.9.1"]
+        );
+    }
+
+    #[test]
+    fn diff_crate_deleted() {
+        assert_eq!(
+            summary(&diff_index_entries(
+                Some(&krate(&[("1.0.0", false), ("1.1.0", false)])),
+                None
+            )),
+            vec!["deleted crate krate with 2 versions"]
+        );
+    }
+
+    #[test]
+    fn diff_versions() {
+        assert_eq!(
+            summary(&diff_index_entries(
+                Some(&krate(&[
+                    ("0.1.0", false),
+                    ("0.2.0", true),
+                    ("0.3.0", false),
+                    ("0.4.0", false),
+                ])),
+                Some(&krate(&[
+                    ("0.1.0", false),
+                    ("0.2.0", false),
+                    ("0.3.0", true),
+                    ("0.5.0", false),
+                    ("0.6.0", true),
+                ]))
+            )),
+            vec![
+                "unyanked 0.2.0",
+                "yanked 0.3.0",
+                "added 0.5.0",
+                "added yanked 0.6.0",
+                "deleted 0.4.0",
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetch_uses_conditional_requests() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let cache = tempfile::tempdir()?;
+        let index = SparseIndex::new(cache.path(), format!("sparse+{}/", server.url()))?;
+        let name: KrateName = "krate".parse()?;
+
+        let first = server
+            .mock("GET", "/kr/at/krate")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_header("etag", "\"v1\"")
+            .with_body(index_line("krate", "1.0.0", false))
+            .expect(2)
+            .create_async()
+            .await;
+
+        let (changes, _) = index.fetch_changes(&name).await?;
+        assert_eq!(summary(&changes), vec!["added 1.0.0"]);
+
+        // until we store the entry, we get the same changes again.
+        let (changes, entry) = index.fetch_changes(&name).await?;
+        assert_eq!(summary(&changes), vec!["added 1.0.0"]);
+        index.store(entry).await?;
+        first.assert_async().await;
+
+        let not_modified = server
+            .mock("GET", "/kr/at/krate")
+            .match_header("if-none-match", "\"v1\"")
+            .with_status(304)
+            .expect(2)
+            .create_async()
+            .await;
+
+        assert!(index.fetch_changes(&name).await?.0.is_empty());
+        assert_eq!(
+            index
+                .fetch_crate(&name)
+                .await?
+                .map(|krate| krate.versions().len()),
+            Some(1)
+        );
+        not_modified.assert_async().await;
+
+        Ok(())
+    }
+}
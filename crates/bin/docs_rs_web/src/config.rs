@@ -2,6 +2,19 @@ use anyhow::Result;
 use docs_rs_config::AppConfig;
 use docs_rs_env_vars::maybe_env;
 use std::time::Duration;
+use strum::EnumString;
+use url::Url;
+
+/// Where we get the results for the crate search from.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum SearchBackend {
+    /// full-text search on our own database.
+    #[default]
+    Database,
+    /// forward the search to the crates.io API.
+    CratesIo,
+}
 
 #[derive(Debug, bon::Builder)]
 #[builder(on(_, overwritable))]
@@ -10,12 +23,36 @@ pub struct Config {
     // (careful: use constant_time_eq for comparisons!)
     pub(crate) cratesio_token: Option<String>,
 
+    // Shared secret to verify the signature of index change events
+    // pushed by crates.io.
+    pub(crate) cratesio_events_secret: Option<String>,
+
+    // Token for the admin pages under `/-/admin/`, as bearer token or as
+    // basic auth password, the pages are disabled without it.
+    // (careful: use constant_time_eq for comparisons!)
+    pub(crate) admin_token: Option<String>,
+
+    // GitHub API used to identify crate owners requesting sandbox limit increases.
+    #[builder(default = Url::parse("https://api.github.com").unwrap())]
+    pub(crate) github_api_host: Url,
+
+    // Which backend serves the crate search on `/releases/search`.
+    #[builder(default)]
+    pub(crate) search_backend: SearchBackend,
+
     // request timeout in seconds
     #[builder(with = |secs: u64| Duration::from_secs(secs))]
     pub(crate) request_timeout: Option<Duration>,
     #[builder(default)]
     pub(crate) report_request_timeouts: bool,
 
+    // The build event stream of an in-progress build ends when nothing changed
+    // for this many seconds, or when it's open for longer than the max lifetime.
+    #[builder(default = Duration::from_secs(15 * 60), with = |secs: u64| Duration::from_secs(secs))]
+    pub(crate) build_events_idle_timeout: Duration,
+    #[builder(default = Duration::from_secs(2 * 60 * 60), with = |secs: u64| Duration::from_secs(secs))]
+    pub(crate) build_events_max_lifetime: Duration,
+
     // The most memory that can be used to parse an HTML file
     // LOL HTML only uses as much memory as the size of the start tag!
     // https://github.com/rust-lang/docs.rs/pull/930#issuecomment-667729380
@@ -58,10 +95,16 @@ impl<S: State> ConfigBuilder<S> {
     pub(crate) fn load_environment(self) -> Result<ConfigBuilder<S>> {
         Ok(self
             .maybe_cratesio_token(maybe_env("DOCSRS_CRATESIO_TOKEN")?)
+            .maybe_cratesio_events_secret(maybe_env("DOCSRS_CRATESIO_EVENTS_SECRET")?)
+            .maybe_admin_token(maybe_env("DOCSRS_ADMIN_TOKEN")?)
+            .maybe_github_api_host(maybe_env("DOCSRS_GITHUB_API_HOST")?)
+            .maybe_search_backend(maybe_env("DOCSRS_SEARCH_BACKEND")?)
             .maybe_max_parse_memory(maybe_env("DOCSRS_MAX_PARSE_MEMORY")?)
             .maybe_render_threads(maybe_env("DOCSRS_RENDER_THREADS")?)
             .maybe_request_timeout(maybe_env("DOCSRS_REQUEST_TIMEOUT")?)
             .maybe_report_request_timeouts(maybe_env("DOCSRS_REPORT_REQUEST_TIMEOUTS")?)
+            .maybe_build_events_idle_timeout(maybe_env("DOCSRS_BUILD_EVENTS_IDLE_TIMEOUT")?)
+            .maybe_build_events_max_lifetime(maybe_env("DOCSRS_BUILD_EVENTS_MAX_LIFETIME")?)
             .maybe_random_crate_search_view_size(maybe_env("DOCSRS_RANDOM_CRATE_SEARCH_VIEW_SIZE")?)
             .maybe_csp_report_only(maybe_env("DOCSRS_CSP_REPORT_ONLY")?)
             .maybe_cache_control_stale_while_revalidate(maybe_env(
@@ -0,0 +1,113 @@
+//! extractor for the content-encodings a client accepts
+
+use axum::{
+    extract::FromRequestParts,
+    http::{header::ACCEPT_ENCODING, request::Parts},
+};
+use docs_rs_types::CompressionAlgorithm;
+use std::convert::Infallible;
+
+/// The content-encodings from the `Accept-Encoding` header we can serve without
+/// decompressing, see `AsyncStorage::stream_from_archive_encoded`.
+///
+/// We don't choose between encodings, we only serve a file in the encoding it's
+/// stored with, so we can ignore the priorities and only drop encodings with `q=0`.
+///
+/// Responses depending on this have to set `Vary: Accept-Encoding`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct AcceptedEncodings(pub(crate) Vec<CompressionAlgorithm>);
+
+impl AcceptedEncodings {
+    fn parse(header: &str) -> Self {
+        let mut accepted = Vec::new();
+        let mut rejected = Vec::new();
+        let mut wildcard = false;
+
+        for item in header.split(',') {
+            let mut params = item.split(';').map(str::trim);
+            let coding = params.next().unwrap_or_default().to_ascii_lowercase();
+
+            let weight = params
+                .find_map(|param| param.strip_prefix("q="))
+                .map(|q| q.parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+
+            let alg = match coding.as_str() {
+                "gzip" | "x-gzip" => CompressionAlgorithm::Gzip,
+                "zstd" => CompressionAlgorithm::Zstd,
+                "*" => {
+                    wildcard = weight > 0.0;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if weight > 0.0 {
+                accepted.push(alg);
+            } else {
+                rejected.push(alg);
+            }
+        }
+
+        // `*` matches all encodings that weren't listed explicitly.
+        if wildcard {
+            accepted.extend([CompressionAlgorithm::Gzip, CompressionAlgorithm::Zstd]);
+        }
+        accepted.retain(|alg| !rejected.contains(alg));
+        let mut deduped = Vec::with_capacity(accepted.len());
+        for alg in accepted {
+            if !deduped.contains(&alg) {
+                deduped.push(alg);
+            }
+        }
+
+        Self(deduped)
+    }
+}
+
+impl core::ops::Deref for AcceptedEncodings {
+    type Target = [CompressionAlgorithm];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<S> FromRequestParts<S> for AcceptedEncodings
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = parts
+            .headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Ok(Self::parse(&header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("", &[])]
+    #[test_case("identity", &[])]
+    #[test_case("gzip", &[CompressionAlgorithm::Gzip])]
+    #[test_case("gzip, deflate, br, zstd", &[CompressionAlgorithm::Gzip, CompressionAlgorithm::Zstd])]
+    #[test_case("zstd;q=0.5, GZIP;q=1.0", &[CompressionAlgorithm::Zstd, CompressionAlgorithm::Gzip])]
+    #[test_case("gzip;q=0, zstd", &[CompressionAlgorithm::Zstd])]
+    #[test_case("gzip;q=invalid", &[])]
+    #[test_case("*", &[CompressionAlgorithm::Gzip, CompressionAlgorithm::Zstd])]
+    #[test_case("zstd, *", &[CompressionAlgorithm::Zstd, CompressionAlgorithm::Gzip])]
+    #[test_case("gzip;q=0, *", &[CompressionAlgorithm::Zstd])]
+    fn parse_accept_encoding(header: &str, expected: &[CompressionAlgorithm]) {
+        assert_eq!(AcceptedEncodings::parse(header).0, expected);
+    }
+}
@@ -1,6 +1,8 @@
+mod accept_encoding;
 mod context;
 mod path;
 pub(crate) mod rustdoc;
 
+pub(crate) use accept_encoding::AcceptedEncodings;
 pub(crate) use context::DbConnection;
 pub(crate) use path::{Path, WantedCompression};
@@ -85,6 +85,7 @@ impl std::fmt::Debug for RustdocParams {
                 "build_details_url(42, Some(\"log.txt\")",
                 &self.build_details_url(BuildId(42), Some("log.txt")),
             )
+            .field("build_events_url(42)", &self.build_events_url(BuildId(42)))
             .field("features_url()", &self.features_url())
             .field("source_url()", &self.source_url())
             .field("target_redirect_url()", &self.target_redirect_url())
@@ -569,6 +570,11 @@ impl RustdocParams {
         ))
     }
 
+    /// release metadata in the versioned JSON API.
+    pub(crate) fn api_release_url(&self) -> EscapedURI {
+        EscapedURI::from_path(format!("/api/v1/crates/{}/{}", self.name, self.req_version))
+    }
+
     pub(crate) fn build_details_url(&self, id: BuildId, filename: Option<&str>) -> EscapedURI {
         let mut path = format!("/crate/{}/{}/builds/{}", self.name, self.req_version, id);
 
@@ -580,6 +586,14 @@ impl RustdocParams {
         EscapedURI::from_path(path)
     }
 
+    /// server-sent events with the progress of a build.
+    pub(crate) fn build_events_url(&self, id: BuildId) -> EscapedURI {
+        EscapedURI::from_path(format!(
+            "/crate/{}/{}/builds/{}/events",
+            self.name, self.req_version, id
+        ))
+    }
+
     pub(crate) fn zip_download_url(&self) -> EscapedURI {
         EscapedURI::from_path(format!(
             "/crate/{}/{}/download",
@@ -5,7 +5,10 @@ use anyhow::Result;
 use axum::{
     body::Body,
     extract::Extension,
-    http::StatusCode,
+    http::{
+        HeaderValue, StatusCode,
+        header::{CONTENT_ENCODING, VARY},
+    },
     response::{IntoResponse, Response as AxumResponse},
 };
 use axum_extra::{
@@ -14,6 +17,7 @@ use axum_extra::{
 };
 use docs_rs_headers::IfNoneMatch;
 use docs_rs_storage::{AsyncStorage, Blob, StreamingBlob};
+use docs_rs_types::CompressionAlgorithm;
 use std::time::SystemTime;
 use tokio_util::io::ReaderStream;
 use tracing::warn;
@@ -134,6 +138,43 @@ impl StreamingFile {
                 .into_response()
         }
     }
+
+    /// Response for a file from `AsyncStorage::stream_rustdoc_file_encoded`, where
+    /// `compression` is the content-encoding of the stream.
+    ///
+    /// The encoding depends on the `Accept-Encoding` request header, so we always
+    /// set `Vary: Accept-Encoding`, also when we serve the file decompressed.
+    pub fn into_encoded_response(
+        mut self,
+        if_none_match: Option<&IfNoneMatch>,
+        cache_policy: CachePolicy,
+    ) -> AxumResponse {
+        let encoding = self.0.compression.take();
+
+        let mut response = self.into_response(if_none_match, cache_policy);
+        let is_ok = response.status() == StatusCode::OK;
+        let headers = response.headers_mut();
+        headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+        if let Some(encoding) = encoding
+            && is_ok
+        {
+            headers.insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(content_encoding(encoding)),
+            );
+        }
+        response
+    }
+}
+
+/// the `Content-Encoding` value for a compression algorithm.
+fn content_encoding(alg: CompressionAlgorithm) -> &'static str {
+    match alg {
+        CompressionAlgorithm::Zstd => "zstd",
+        CompressionAlgorithm::Gzip => "gzip",
+        CompressionAlgorithm::Deflate => "deflate",
+        CompressionAlgorithm::Bzip2 => "bzip2",
+    }
 }
 
 #[cfg(test)]
@@ -144,7 +185,6 @@ mod tests {
     use chrono::Utc;
     use docs_rs_headers::compute_etag;
     use docs_rs_storage::StorageKind;
-    use docs_rs_types::CompressionAlgorithm;
     use http::header::{CACHE_CONTROL, CONTENT_LENGTH, ETAG, LAST_MODIFIED};
     use std::{io, rc::Rc};
 
@@ -230,6 +270,21 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_encoded_stream_into_response() {
+        let stream = StreamingFile(streaming_blob(CONTENT, Some(CompressionAlgorithm::Gzip)));
+        let resp = stream.into_encoded_response(None, STATIC_ASSET_CACHE_POLICY);
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(resp.headers().get(VARY).unwrap(), "Accept-Encoding");
+
+        // decompressed files still vary by the accepted encodings
+        let stream = StreamingFile(streaming_blob(CONTENT, None));
+        let resp = stream.into_encoded_response(None, STATIC_ASSET_CACHE_POLICY);
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(resp.headers().get(VARY).unwrap(), "Accept-Encoding");
+    }
+
     #[tokio::test]
     async fn test_stream_into_response_without_content_length() -> Result<()> {
         let mut stream = streaming_blob(CONTENT, None);
@@ -61,6 +61,7 @@ about_page!(AboutPageMetadata, "core/about/metadata.html");
 about_page!(AboutPageRedirection, "core/about/redirections.html");
 about_page!(AboutPageDownload, "core/about/download.html");
 about_page!(AboutPageRustdocJson, "core/about/rustdoc-json.html");
+about_page!(AboutPageApi, "core/about/api.html");
 
 pub(crate) async fn about_handler(subpage: Option<Path<String>>) -> AxumResult<impl IntoResponse> {
     let subpage = match subpage {
@@ -75,6 +76,7 @@ pub(crate) async fn about_handler(subpage: Option<Path<String>>) -> AxumResult<i
         "redirections" => AboutPageRedirection.into_response(),
         "download" => AboutPageDownload.into_response(),
         "rustdoc-json" => AboutPageRustdocJson.into_response(),
+        "api" => AboutPageApi.into_response(),
         _ => {
             let msg = "This /about page does not exist. \
                 Perhaps you are interested in <a href=\"https://github.com/rust-lang/docs.rs/tree/master/templates/core/about\">creating</a> it?";
@@ -0,0 +1,605 @@
+//! Admin pages, only available with the admin token, see [`AdminAuth`].
+
+use crate::{
+    Config,
+    cache::CachePolicy,
+    error::{AxumNope, AxumResult},
+    extractors::DbConnection,
+    impl_axum_webpage,
+    page::templates::{RenderBrands, RenderSolid, filters},
+};
+use anyhow::anyhow;
+use askama::Template;
+use axum::{
+    Form, RequestPartsExt as _,
+    extract::{Extension, FromRequestParts, Query},
+    response::{IntoResponse, Response},
+};
+use axum_extra::{
+    TypedHeader,
+    headers::{
+        Authorization,
+        authorization::{Basic, Bearer},
+    },
+};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use constant_time_eq::constant_time_eq;
+use docs_rs_build_queue::AsyncBuildQueue;
+use docs_rs_crates_io::signature;
+use docs_rs_database::{
+    audit_log::{self, AuditLogEntry, AuditLogFilter},
+    build_failures::{self, FailureCluster, FailureSignature, MAX_FAILURE_CLUSTER_DAYS},
+};
+use http::{HeaderValue, header::WWW_AUTHENTICATE, request::Parts};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+/// how many audit log entries we show.
+const AUDIT_LOG_ENTRIES: i64 = 200;
+
+/// how many failure clusters we show.
+const FAILURE_CLUSTERS: i64 = 100;
+
+/// the default time window for failure clusters, in days.
+const DEFAULT_FAILURE_CLUSTER_DAYS: u32 = 7;
+
+/// the realm browsers show when they ask for the admin token.
+const ADMIN_REALM: &str = r#"Basic realm="docs.rs admin", charset="UTF-8""#;
+
+/// How a request to the admin pages was authenticated, with the admin token either as
+/// bearer token, or as password for HTTP basic auth. The user name is ignored.
+///
+/// Browsers ask for basic auth credentials, and then send them with every request,
+/// also for forms on other sites. So forms that change something need a CSRF token,
+/// see [`csrf_token`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AdminAuth {
+    Bearer,
+    Basic,
+}
+
+fn check_admin_token(
+    config: &Config,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+) -> AxumResult<AdminAuth> {
+    let expected_token = config
+        .admin_token
+        .as_ref()
+        .ok_or(AxumNope::Unauthorized("Endpoint is not configured"))?;
+
+    let (auth, token) = match (bearer, basic) {
+        (Some(TypedHeader(bearer)), _) => (AdminAuth::Bearer, bearer.token().to_owned()),
+        (None, Some(TypedHeader(basic))) => (AdminAuth::Basic, basic.password().to_owned()),
+        (None, None) => return Err(AxumNope::Unauthorized("Missing authentication token")),
+    };
+    if !constant_time_eq(token.as_bytes(), expected_token.as_bytes()) {
+        return Err(AxumNope::Unauthorized(
+            "The token used for authentication is not valid",
+        ));
+    }
+
+    Ok(auth)
+}
+
+impl<S> FromRequestParts<S> for AdminAuth
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(config) = parts
+            .extract::<Extension<Arc<Config>>>()
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let bearer = parts.extract().await.ok();
+        let basic = parts.extract().await.ok();
+
+        check_admin_token(&config, bearer, basic).map_err(|err| {
+            let mut response = err.into_response();
+            // makes browsers ask for the credentials.
+            if config.admin_token.is_some() {
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static(ADMIN_REALM));
+            }
+            response
+        })
+    }
+}
+
+/// The CSRF token for forms on the admin pages, derived from the admin token.
+fn csrf_token(config: &Config) -> String {
+    signature::sign(
+        config.admin_token.as_deref().unwrap_or_default().as_bytes(),
+        b"admin-csrf-token",
+    )
+}
+
+/// Check the CSRF token of a form that was sent with the credentials the browser
+/// remembered, see [`AdminAuth`].
+fn check_csrf_token(config: &Config, auth: AdminAuth, token: Option<&str>) -> AxumResult<()> {
+    if auth == AdminAuth::Basic
+        && !token
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), csrf_token(config).as_bytes()))
+    {
+        return Err(AxumNope::BadRequest(anyhow!("invalid CSRF token")));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct AuditLogParams {
+    actor: Option<String>,
+    action: Option<String>,
+    target: Option<String>,
+    since: Option<String>,
+}
+
+impl AuditLogParams {
+    fn into_filter(self) -> AxumResult<AuditLogFilter> {
+        // empty form fields are sent as empty strings, we ignore them.
+        let non_empty = |value: Option<String>| value.filter(|value| !value.trim().is_empty());
+
+        let since = non_empty(self.since)
+            .map(|since| since.parse::<NaiveDate>())
+            .transpose()
+            .map_err(|err| AxumNope::BadRequest(anyhow!("invalid date: {err}")))?;
+
+        Ok(AuditLogFilter {
+            actor: non_empty(self.actor),
+            action: non_empty(self.action),
+            target: non_empty(self.target),
+            since: since.map(|date| date.and_time(NaiveTime::MIN).and_utc()),
+            limit: AUDIT_LOG_ENTRIES,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "core/admin_audit.html")]
+#[derive(Debug, Clone)]
+struct AuditLogPage {
+    filter: AuditLogFilter,
+    entries: Vec<AuditLogEntry>,
+}
+
+impl_axum_webpage! {
+    AuditLogPage,
+    cache_policy = |_| CachePolicy::NoStoreMustRevalidate,
+}
+
+pub(crate) async fn audit_log_handler(
+    _auth: AdminAuth,
+    mut conn: DbConnection,
+    Query(params): Query<AuditLogParams>,
+) -> AxumResult<impl IntoResponse> {
+    let filter = params.into_filter()?;
+
+    Ok(AuditLogPage {
+        entries: audit_log::list(&mut conn, &filter).await?,
+        filter,
+    })
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct BuildFailuresParams {
+    days: Option<String>,
+}
+
+impl BuildFailuresParams {
+    fn days(&self) -> AxumResult<u32> {
+        match self.days.as_deref().map(str::trim) {
+            None | Some("") => Ok(DEFAULT_FAILURE_CLUSTER_DAYS),
+            Some(days) => days
+                .parse::<u32>()
+                .map(|days| days.min(MAX_FAILURE_CLUSTER_DAYS))
+                .map_err(|err| AxumNope::BadRequest(anyhow!("invalid days: {err}"))),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "core/admin_build_failures.html")]
+#[derive(Debug, Clone)]
+struct BuildFailuresPage {
+    days: u32,
+    clusters: Vec<FailureCluster>,
+    /// shown after queueing the rebuilds for a cluster.
+    notice: Option<String>,
+    csrf_token: String,
+}
+
+impl_axum_webpage! {
+    BuildFailuresPage,
+    cache_policy = |_| CachePolicy::NoStoreMustRevalidate,
+}
+
+/// the start of the time window for failure clusters.
+fn failures_since(days: u32) -> DateTime<Utc> {
+    Utc::now() - Duration::days(days.min(MAX_FAILURE_CLUSTER_DAYS).into())
+}
+
+async fn build_failures_page(
+    conn: &mut sqlx::PgConnection,
+    config: &Config,
+    days: u32,
+    notice: Option<String>,
+) -> AxumResult<BuildFailuresPage> {
+    Ok(BuildFailuresPage {
+        days,
+        clusters: build_failures::clusters(conn, failures_since(days), FAILURE_CLUSTERS).await?,
+        notice,
+        csrf_token: csrf_token(config),
+    })
+}
+
+pub(crate) async fn build_failures_handler(
+    _auth: AdminAuth,
+    mut conn: DbConnection,
+    Extension(config): Extension<Arc<Config>>,
+    Query(params): Query<BuildFailuresParams>,
+) -> AxumResult<impl IntoResponse> {
+    build_failures_page(&mut conn, &config, params.days()?, None).await
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RebuildClusterForm {
+    signature: String,
+    days: u32,
+    csrf_token: Option<String>,
+}
+
+pub(crate) async fn rebuild_failure_cluster_handler(
+    auth: AdminAuth,
+    mut conn: DbConnection,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(build_queue): Extension<Arc<AsyncBuildQueue>>,
+    Form(form): Form<RebuildClusterForm>,
+) -> AxumResult<impl IntoResponse> {
+    check_csrf_token(&config, auth, form.csrf_token.as_deref())?;
+
+    let signature = FailureSignature::from_line(&form.signature)
+        .ok_or_else(|| AxumNope::BadRequest(anyhow!("invalid signature: {}", form.signature)))?;
+
+    let days = form.days.min(MAX_FAILURE_CLUSTER_DAYS);
+    let since = failures_since(days);
+    let queued = build_queue
+        .queue_failure_cluster_rebuilds(&signature, since)
+        .await?;
+
+    audit_log::record(
+        &mut conn,
+        "admin page",
+        "queue.rebuild-failure-cluster",
+        Some(&signature.to_string()),
+        Value::Null,
+        json!({ "days": days, "queued": queued }),
+    )
+    .await?;
+
+    build_failures_page(
+        &mut conn,
+        &config,
+        days,
+        Some(format!("Queued {queued} rebuilds for {signature}")),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ADMIN_REALM, csrf_token};
+    use crate::{
+        Config,
+        cache::CachePolicy,
+        testing::{
+            AxumResponseTestExt, AxumRouterTestExt, TestEnvironment, TestEnvironmentExt as _,
+        },
+    };
+    use anyhow::Result;
+    use axum::{body::Body, http::Request};
+    use base64::{Engine as _, engine::general_purpose::STANDARD as b64};
+    use docs_rs_database::{
+        audit_log,
+        build_failures::{self, FailureSignature},
+    };
+    use docs_rs_test_fakes::FakeBuild;
+    use docs_rs_types::{
+        BuildId, BuildStatus,
+        testing::{FOO, V1},
+    };
+    use http::StatusCode;
+    use http_body_util::BodyExt as _;
+    use serde_json::{Value, json};
+    use tower::ServiceExt as _;
+
+    async fn env_with_token() -> Result<TestEnvironment> {
+        TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .admin_token("secret".into())
+                    .build(),
+            )
+            .build()
+            .await
+    }
+
+    async fn get_with_token(
+        env: &TestEnvironment,
+        path: &str,
+        token: &str,
+    ) -> Result<(StatusCode, String)> {
+        let response = env
+            .web_app()
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri(path)
+                    .header("authorization", format!("Bearer {token}"))
+                    .body(Body::empty())?,
+            )
+            .await?;
+
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        Ok((status, String::from_utf8(body.to_vec())?))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn audit_log_page() -> Result<()> {
+        let env = env_with_token().await?;
+
+        let mut conn = env.async_conn().await?;
+        audit_log::record(
+            &mut conn,
+            "alice",
+            "database.blacklist.add",
+            Some("foo"),
+            json!(false),
+            json!(true),
+        )
+        .await?;
+        audit_log::record(
+            &mut conn,
+            "bob",
+            "cdn.purge",
+            Some("crate-bar"),
+            Value::Null,
+            Value::Null,
+        )
+        .await?;
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/-/admin/audit")
+                    .header("authorization", "Bearer secret")
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        response.assert_cache_control(CachePolicy::NoStoreMustRevalidate, env.config());
+
+        let body = response.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8(body.to_vec())?;
+        assert!(body.contains("database.blacklist.add"));
+        assert!(body.contains("crate-bar"));
+
+        let (status, body) = get_with_token(
+            &env,
+            "/-/admin/audit?actor=&action=database&since=",
+            "secret",
+        )
+        .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("database.blacklist.add"));
+        assert!(!body.contains("crate-bar"));
+
+        let (status, _) = get_with_token(&env, "/-/admin/audit?since=yesterday", "secret").await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn audit_log_requires_token() -> Result<()> {
+        let env = env_with_token().await?;
+
+        let response = env.web_app().await.get("/-/admin/audit").await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["www-authenticate"], ADMIN_REALM);
+
+        let (status, _) = get_with_token(&env, "/-/admin/audit", "invalid").await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // browsers send the token with basic auth
+        let response = env
+            .web_app()
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/-/admin/audit")
+                    .header(
+                        "authorization",
+                        format!("Basic {}", b64.encode("admin:secret")),
+                    )
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn audit_log_disabled_without_token() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        let (status, _) = get_with_token(&env, "/-/admin/audit", "secret").await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_failures_page_and_rebuild() -> Result<()> {
+        let env = env_with_token().await?;
+
+        env.fake_release()
+            .await
+            .name(&FOO)
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::Failure)
+                    .db_build_log("error[E0432]: unresolved import `std::simd`"),
+            ])
+            .create()
+            .await?;
+
+        let mut conn = env.async_conn().await?;
+        let build_id = sqlx::query_scalar!(r#"SELECT id as "id: BuildId" FROM builds"#)
+            .fetch_one(&mut *conn)
+            .await?;
+        let signature = FailureSignature::from_line("error[E0432]: unresolved import `std::simd`");
+        build_failures::record(&mut conn, build_id, signature.as_ref()).await?;
+
+        let (status, body) = get_with_token(&env, "/-/admin/build-failures", "secret").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("E0432"));
+        assert!(body.contains("Queue rebuilds"));
+
+        let (status, _) =
+            get_with_token(&env, "/-/admin/build-failures?days=many", "secret").await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) =
+            get_with_token(&env, "/-/admin/build-failures?days=4294967295", "secret").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("E0432"));
+
+        let build_queue = env.build_queue()?;
+        assert!(!build_queue.has_build_queued(&FOO, &V1).await?);
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/-/admin/build-failures/rebuild")
+                    .method("POST")
+                    .header("authorization", "Bearer secret")
+                    .header("content-type", "application/x-www-form-urlencoded")
+                    .body(Body::from(
+                        "signature=error%5BE0432%5D%3A+unresolved+import+%60std%3A%3Asimd%60&days=7",
+                    ))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await?.to_bytes();
+        assert!(String::from_utf8(body.to_vec())?.contains("Queued 1 rebuilds"));
+
+        assert!(build_queue.has_build_queued(&FOO, &V1).await?);
+
+        let (status, body) = get_with_token(
+            &env,
+            "/-/admin/audit?action=queue.rebuild-failure-cluster",
+            "secret",
+        )
+        .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("E0432"));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_failures_requires_token() -> Result<()> {
+        let env = env_with_token().await?;
+
+        let (status, _) = get_with_token(&env, "/-/admin/build-failures", "invalid").await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/-/admin/build-failures/rebuild")
+                    .method("POST")
+                    .header("content-type", "application/x-www-form-urlencoded")
+                    .body(Body::from("signature=error%3A+foo&days=7"))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_failures_with_basic_auth() -> Result<()> {
+        let env = env_with_token().await?;
+        // the user name is ignored
+        let credentials = format!("Basic {}", b64.encode("admin:secret"));
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/-/admin/build-failures")
+                    .header("authorization", &credentials)
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let rebuild = |body: String| {
+            Request::builder()
+                .uri("/-/admin/build-failures/rebuild")
+                .method("POST")
+                .header("authorization", &credentials)
+                .header("content-type", "application/x-www-form-urlencoded")
+                .body(Body::from(body))
+        };
+
+        // forms sent with the credentials of the browser need the CSRF token
+        for body in [
+            "signature=error%3A+foo&days=7".to_owned(),
+            "signature=error%3A+foo&days=7&csrf_token=invalid".to_owned(),
+        ] {
+            let response = env.web_app().await.oneshot(rebuild(body)?).await?;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let body = format!(
+            "signature=error%3A+foo&days=4294967295&csrf_token={}",
+            csrf_token(env.config()).replace('=', "%3D")
+        );
+        let response = env.web_app().await.oneshot(rebuild(body)?).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/-/admin/build-failures")
+                    .header(
+                        "authorization",
+                        format!("Basic {}", b64.encode("admin:wrong")),
+                    )
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+}
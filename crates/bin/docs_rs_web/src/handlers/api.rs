@@ -0,0 +1,239 @@
+//! Versioned JSON API for crate and release metadata, documented in `/about/api`.
+
+use crate::{
+    cache::CachePolicy,
+    error::{AxumNope, AxumResult},
+    extractors::{DbConnection, rustdoc::RustdocParams},
+    handlers::crate_details::CrateDetails,
+    match_release::match_version,
+};
+use axum::{
+    Json, extract::Extension, http::header::ACCESS_CONTROL_ALLOW_ORIGIN, response::IntoResponse,
+};
+
+/// Metadata of the latest release of a crate, with the list of all its releases.
+pub(crate) async fn crate_handler(
+    params: RustdocParams,
+    mut conn: DbConnection,
+) -> impl IntoResponse {
+    (
+        Extension(CachePolicy::NoStoreMustRevalidate),
+        [(ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        // like in `build_status::status_handler`, the async block applies the CORS header and
+        // cache policy to successful and failed responses.
+        async move {
+            let matched_release = match_version(&mut conn, params.name(), params.req_version())
+                .await?
+                .assume_exact_name()?;
+
+            let details = CrateDetails::from_matched_release(&mut conn, matched_release).await?;
+
+            let mut json = details.to_json();
+            json["releases"] = details.releases_json();
+
+            AxumResult::Ok(Json(json).into_response())
+        }
+        .await,
+    )
+}
+
+/// Metadata of a single release, the version can be any version request.
+pub(crate) async fn release_handler(
+    params: RustdocParams,
+    mut conn: DbConnection,
+) -> impl IntoResponse {
+    (
+        Extension(CachePolicy::NoStoreMustRevalidate),
+        [(ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        async move {
+            let matched_release = match_version(&mut conn, params.name(), params.req_version())
+                .await?
+                .assume_exact_name()?
+                .into_canonical_req_version_or_else(|confirmed_name, version| {
+                    AxumNope::Redirect(
+                        params
+                            .clone()
+                            .with_name(confirmed_name)
+                            .with_req_version(version)
+                            .api_release_url(),
+                        CachePolicy::NoCaching,
+                    )
+                })?;
+
+            let details = CrateDetails::from_matched_release(&mut conn, matched_release).await?;
+
+            AxumResult::Ok(Json(details.to_json()).into_response())
+        }
+        .await,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cache::CachePolicy,
+        testing::{AxumResponseTestExt, AxumRouterTestExt, TestEnvironmentExt as _, async_wrapper},
+    };
+    use docs_rs_cargo_metadata::Dependency;
+    use docs_rs_registry_api::{CrateOwner, OwnerKind};
+    use reqwest::StatusCode;
+    use test_case::test_case;
+
+    #[test]
+    fn release_metadata() {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.1.0")
+                .add_owner(CrateOwner {
+                    login: "foobar".into(),
+                    avatar: "https://example.com/avatar".into(),
+                    kind: OwnerKind::User,
+                })
+                .add_dependency(Dependency::new("bar".into(), "^1.0".parse()?))
+                .add_dependency(Dependency {
+                    kind: Some("dev".into()),
+                    ..Dependency::new("baz".into(), "0.2".parse()?).set_optional(true)
+                })
+                .create()
+                .await?;
+
+            let response = env.web_app().await.get("/api/v1/crates/foo/0.1.0").await?;
+            response.assert_cache_control(CachePolicy::NoStoreMustRevalidate, env.config());
+            assert_eq!(response.headers()["access-control-allow-origin"], "*");
+            assert_eq!(response.status(), StatusCode::OK);
+            let value: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+
+            assert_eq!(value["name"], "foo");
+            assert_eq!(value["version"], "0.1.0");
+            assert_eq!(value["build_status"], "success");
+            assert_eq!(value["rustdoc_status"], true);
+            assert_eq!(value["yanked"], false);
+            assert_eq!(
+                value["owners"],
+                serde_json::json!([{
+                    "login": "foobar",
+                    "avatar": "https://example.com/avatar",
+                    "kind": "user",
+                }])
+            );
+            // the fake release always comes with `fake-dependency` first
+            assert_eq!(
+                value["dependencies"].as_array().unwrap()[1..],
+                serde_json::json!([
+                    {"name": "bar", "req": "^1.0", "kind": "normal", "optional": false, "rename": null},
+                    {"name": "baz", "req": "^0.2", "kind": "dev", "optional": true, "rename": null},
+                ])
+                .as_array()
+                .unwrap()[..]
+            );
+            assert!(value["doc_targets"].is_array());
+            assert!(value.get("releases").is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn crate_metadata_uses_latest_release() {
+        async_wrapper(|env| async move {
+            for version in ["0.1.0", "0.2.0"] {
+                env.fake_release()
+                    .await
+                    .name("foo")
+                    .version(version)
+                    .create()
+                    .await?;
+            }
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.3.0-beta.1")
+                .create()
+                .await?;
+
+            let response = env.web_app().await.get("/api/v1/crates/foo").await?;
+            response.assert_cache_control(CachePolicy::NoStoreMustRevalidate, env.config());
+            assert_eq!(response.headers()["access-control-allow-origin"], "*");
+            assert_eq!(response.status(), StatusCode::OK);
+            let value: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+
+            assert_eq!(value["version"], "0.2.0");
+            let versions: Vec<_> = value["releases"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|release| release["version"].as_str().unwrap())
+                .collect();
+            assert_eq!(versions, ["0.3.0-beta.1", "0.2.0", "0.1.0"]);
+
+            Ok(())
+        });
+    }
+
+    #[test_case("0.1")]
+    #[test_case("~0.1"; "semver")]
+    fn redirect(req_version: &str) {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.1.0")
+                .create()
+                .await?;
+
+            let redirect = env
+                .web_app()
+                .await
+                .assert_redirect(
+                    &format!("/api/v1/crates/foo/{req_version}"),
+                    "/api/v1/crates/foo/0.1.0",
+                )
+                .await?;
+            redirect.assert_cache_control(CachePolicy::NoStoreMustRevalidate, env.config());
+            assert_eq!(redirect.headers()["access-control-allow-origin"], "*");
+
+            Ok(())
+        });
+    }
+
+    #[test_case("/api/v1/crates/bar")]
+    #[test_case("/api/v1/crates/bar/0.1.0")]
+    #[test_case("/api/v1/crates/foo/0.2.0")]
+    #[test_case("/api/v1/crates/Foo"; "wrong_case")]
+    fn not_found(path: &str) {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.1.0")
+                .create()
+                .await?;
+
+            let response = env.web_app().await.get(path).await?;
+            response.assert_cache_control(CachePolicy::NoStoreMustRevalidate, env.config());
+            assert_eq!(response.headers()["access-control-allow-origin"], "*");
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn crate_named_api_still_serves_docs() {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("api")
+                .version("0.1.0")
+                .create()
+                .await?;
+
+            let web = env.web_app().await;
+            web.assert_success("/api/0.1.0/api/").await?;
+
+            Ok(())
+        });
+    }
+}
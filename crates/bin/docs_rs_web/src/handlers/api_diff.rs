@@ -0,0 +1,618 @@
+//! Compare the public API of two releases of a crate.
+//!
+//! `/crate/{name}/{from}...{to}/api-diff` lists the public items that were added,
+//! removed or changed, `/crate/{name}/{from}...{to}/api-diff.json` returns the same as JSON.
+//! Both are computed from the rustdoc JSON we store for the default target of each release.
+
+use crate::{
+    cache::CachePolicy,
+    error::{AxumNope, AxumResult},
+    extractors::{
+        DbConnection, Path,
+        rustdoc::{PageKind, RustdocParams},
+    },
+    handlers::source_diff::split_version_range,
+    impl_axum_webpage,
+    match_release::match_version,
+    metadata::MetaData,
+    page::templates::{RenderBrands, RenderRegular, RenderSolid, filters},
+};
+use anyhow::Result;
+use askama::Template;
+use axum::{Extension, Json, response::IntoResponse};
+use docs_rs_rustdoc_json::{
+    ApiChanges, ApiDiff, ApiItem, ApiItemKind, PublicApi, RUSTDOC_JSON_COMPRESSION_ALGORITHMS,
+    RustdocJsonFormatVersion, diff_public_api, extract_public_api, rustdoc_page_path,
+};
+use docs_rs_storage::{
+    AsyncStorage, PathNotFoundError, SizeLimitReached, decompress, rustdoc_json_path,
+};
+use docs_rs_types::{KrateName, ReqVersion, Version};
+use docs_rs_uri::EscapedURI;
+use docs_rs_utils::spawn_blocking;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{instrument, warn};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApiDiffParams {
+    name: KrateName,
+    version: String,
+}
+
+/// A release we compare.
+#[derive(Debug, Clone)]
+struct ReleaseApi {
+    req_version: ReqVersion,
+    version: Version,
+    /// rustdoc params for links to the docs of this release.
+    params: RustdocParams,
+    /// target we read the rustdoc JSON for, `None` when the release has no docs.
+    json_target: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ApiDiffResult {
+    /// we don't have rustdoc JSON we can read for this version, for example because
+    /// it was built before we started storing it.
+    MissingJson(Version),
+    Incomparable {
+        old_format_version: u16,
+        new_format_version: u16,
+    },
+    Changes(ApiChanges),
+}
+
+struct LoadedApiDiff {
+    from: ReleaseApi,
+    to: ReleaseApi,
+    result: ApiDiffResult,
+}
+
+impl LoadedApiDiff {
+    fn is_latest_url(&self) -> bool {
+        self.from.req_version.is_latest() || self.to.req_version.is_latest()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "crate/api_diff.html")]
+#[derive(Debug, Clone)]
+struct ApiDiffPage {
+    metadata: MetaData,
+    from: ReqVersion,
+    to: ReqVersion,
+    from_version: Version,
+    to_version: Version,
+    from_params: RustdocParams,
+    result: ApiDiffResult,
+    is_latest_url: bool,
+    params: RustdocParams,
+}
+
+impl_axum_webpage! {
+    ApiDiffPage,
+    cache_policy = |page| if page.is_latest_url {
+        CachePolicy::ForeverInCdn(page.metadata.name.clone().into())
+    } else {
+        CachePolicy::ForeverInCdnAndStaleInBrowser(page.metadata.name.clone().into())
+    },
+    cpu_intensive_rendering = true,
+}
+
+// Used in templates.
+impl ApiDiffPage {
+    pub(crate) fn use_direct_platform_links(&self) -> bool {
+        true
+    }
+
+    pub(crate) fn source_diff_url(&self) -> EscapedURI {
+        EscapedURI::from_path(format!(
+            "/crate/{}/{}...{}/source/",
+            self.metadata.name, self.from, self.to
+        ))
+    }
+
+    /// link to the docs of an item in the old release.
+    pub(crate) fn old_item_url(&self, item: &ApiItem) -> Option<EscapedURI> {
+        item_url(&self.from_params, item)
+    }
+
+    /// link to the docs of an item in the new release.
+    pub(crate) fn new_item_url(&self, item: &ApiItem) -> Option<EscapedURI> {
+        item_url(&self.params, item)
+    }
+}
+
+/// methods & trait impls don't have their own page.
+fn item_url(params: &RustdocParams, item: &ApiItem) -> Option<EscapedURI> {
+    let ApiItemKind::Item(kind) = item.kind else {
+        return None;
+    };
+
+    Some(
+        params
+            .clone()
+            .with_page_kind(PageKind::Rustdoc)
+            .with_inner_path(rustdoc_page_path(&item.path, kind))
+            .rustdoc_url(),
+    )
+}
+
+fn item_to_json(item: &ApiItem) -> serde_json::Value {
+    serde_json::json!({
+        "path": item.path,
+        "kind": item.kind.to_string(),
+        "signature": item.signature,
+    })
+}
+
+#[instrument(skip(conn, storage))]
+pub(crate) async fn api_diff_handler(
+    Path(params): Path<ApiDiffParams>,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+    mut conn: DbConnection,
+) -> AxumResult<impl IntoResponse> {
+    let (from, to) = resolve_releases(&mut conn, &params).await?;
+
+    let metadata = MetaData::from_crate(
+        &mut conn,
+        &params.name,
+        &to.version,
+        Some(to.req_version.clone()),
+    )
+    .await?;
+
+    // NOTE: we want to give back the db connection to the pool
+    // before we do the long S3 requests.
+    drop(conn);
+
+    let diff = load_api_diff(&storage, &params.name, from, to).await?;
+
+    Ok(ApiDiffPage {
+        metadata,
+        is_latest_url: diff.is_latest_url(),
+        from: diff.from.req_version,
+        to: diff.to.req_version,
+        from_version: diff.from.version,
+        to_version: diff.to.version,
+        from_params: diff.from.params,
+        params: diff.to.params,
+        result: diff.result,
+    }
+    .into_response())
+}
+
+#[instrument(skip(conn, storage))]
+pub(crate) async fn api_diff_json_handler(
+    Path(params): Path<ApiDiffParams>,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+    mut conn: DbConnection,
+) -> AxumResult<impl IntoResponse> {
+    let (from, to) = resolve_releases(&mut conn, &params).await?;
+    drop(conn);
+
+    let diff = load_api_diff(&storage, &params.name, from, to).await?;
+
+    let mut json = serde_json::json!({
+        "crate": params.name,
+        "from": diff.from.version.to_string(),
+        "to": diff.to.version.to_string(),
+    });
+
+    match &diff.result {
+        ApiDiffResult::MissingJson(_) => return Err(AxumNope::ResourceNotFound),
+        ApiDiffResult::Incomparable {
+            old_format_version,
+            new_format_version,
+        } => {
+            json["comparable"] = false.into();
+            json["from_format_version"] = (*old_format_version).into();
+            json["to_format_version"] = (*new_format_version).into();
+        }
+        ApiDiffResult::Changes(changes) => {
+            json["comparable"] = true.into();
+            json["added"] = changes.added.iter().map(item_to_json).collect();
+            json["removed"] = changes.removed.iter().map(item_to_json).collect();
+            json["changed"] = changes
+                .changed
+                .iter()
+                .map(|change| {
+                    serde_json::json!({
+                        "path": change.new.path,
+                        "old_kind": change.old.kind.to_string(),
+                        "new_kind": change.new.kind.to_string(),
+                        "old_signature": change.old.signature,
+                        "new_signature": change.new.signature,
+                    })
+                })
+                .collect();
+        }
+    }
+
+    let cache_policy = if diff.is_latest_url() {
+        CachePolicy::ForeverInCdn(params.name.clone().into())
+    } else {
+        CachePolicy::ForeverInCdnAndStaleInBrowser(params.name.clone().into())
+    };
+
+    Ok((Extension(cache_policy), Json(json)))
+}
+
+async fn resolve_releases(
+    conn: &mut sqlx::PgConnection,
+    params: &ApiDiffParams,
+) -> AxumResult<(ReleaseApi, ReleaseApi)> {
+    let (from, to) = split_version_range(&params.version).ok_or(AxumNope::VersionNotFound)?;
+    let from: ReqVersion = from.parse().map_err(|_| AxumNope::VersionNotFound)?;
+    let to: ReqVersion = to.parse().map_err(|_| AxumNope::VersionNotFound)?;
+
+    Ok((
+        resolve_release(&mut *conn, &params.name, from).await?,
+        resolve_release(&mut *conn, &params.name, to).await?,
+    ))
+}
+
+async fn resolve_release(
+    conn: &mut sqlx::PgConnection,
+    name: &KrateName,
+    req_version: ReqVersion,
+) -> AxumResult<ReleaseApi> {
+    let matched_release = match_version(&mut *conn, name, &req_version)
+        .await?
+        .assume_exact_name()?;
+
+    let params = RustdocParams::new(name.clone())
+        .with_req_version(req_version.clone())
+        .with_maybe_target_name(matched_release.release.target_name.clone());
+    // without docs we'll never have JSON docs too
+    let json_target = matched_release
+        .rustdoc_status()
+        .then(|| matched_release.release.default_target.clone())
+        .flatten();
+
+    Ok(ReleaseApi {
+        req_version,
+        version: matched_release.into_version(),
+        params,
+        json_target,
+    })
+}
+
+async fn load_api_diff(
+    storage: &AsyncStorage,
+    name: &KrateName,
+    from: ReleaseApi,
+    to: ReleaseApi,
+) -> Result<LoadedApiDiff> {
+    let fetch = |release: &ReleaseApi| {
+        let target = release.json_target.clone();
+        let version = release.version.clone();
+        async move {
+            match target {
+                Some(target) => fetch_public_api(storage, name, &version, &target).await,
+                None => Ok(None),
+            }
+        }
+    };
+
+    let (old, new) = futures_util::try_join!(fetch(&from), fetch(&to))?;
+
+    let result = match (old, new) {
+        (None, _) => ApiDiffResult::MissingJson(from.version.clone()),
+        (_, None) => ApiDiffResult::MissingJson(to.version.clone()),
+        (Some(old), Some(new)) => match diff_public_api(&old, &new) {
+            ApiDiff::Incomparable {
+                old_format_version,
+                new_format_version,
+            } => ApiDiffResult::Incomparable {
+                old_format_version,
+                new_format_version,
+            },
+            ApiDiff::Changes(changes) => ApiDiffResult::Changes(changes),
+        },
+    };
+
+    Ok(LoadedApiDiff { from, to, result })
+}
+
+/// Fetch & parse the rustdoc JSON of a release.
+///
+/// Returns `None` when there is no rustdoc JSON for this release, when it's
+/// too large, or when we can't parse it.
+async fn fetch_public_api(
+    storage: &AsyncStorage,
+    name: &KrateName,
+    version: &Version,
+    target: &str,
+) -> Result<Option<PublicApi>> {
+    let max_size = storage.config().max_file_size;
+
+    for alg in RUSTDOC_JSON_COMPRESSION_ALGORITHMS {
+        let path = rustdoc_json_path(
+            name,
+            version,
+            target,
+            RustdocJsonFormatVersion::Latest,
+            Some(*alg),
+        );
+
+        let blob = match storage.get_raw_stream(&path).await {
+            Ok(stream) => match stream.materialize(max_size).await {
+                Ok(blob) => blob,
+                Err(err) if is_size_limit_reached(&err) => {
+                    warn!(path, "rustdoc JSON is too large to compare");
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            },
+            Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => continue,
+            Err(err) => return Err(err),
+        };
+
+        let alg = *alg;
+        return spawn_blocking(move || {
+            let json = match decompress(blob.content.as_slice(), alg, max_size) {
+                Ok(json) => json,
+                Err(err) if is_size_limit_reached(&err) => {
+                    warn!(path, "rustdoc JSON is too large to compare");
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            };
+            Ok(extract_public_api(json.as_slice())
+                .inspect_err(|err| warn!(?err, "could not read public API from rustdoc JSON"))
+                .ok())
+        })
+        .await;
+    }
+
+    Ok(None)
+}
+
+fn is_size_limit_reached(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .and_then(|err| err.get_ref())
+        .is_some_and(|err| err.is::<SizeLimitReached>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        AxumResponseTestExt as _, AxumRouterTestExt, TestEnvironment, TestEnvironmentExt as _,
+        async_wrapper,
+    };
+    use docs_rs_storage::{StorageKind, compress};
+    use docs_rs_types::{
+        CompressionAlgorithm,
+        testing::{KRATE, V1, V2},
+    };
+    use kuchikiki::traits::TendrilSink;
+    use pretty_assertions::assert_eq;
+
+    const TARGET: &str = "x86_64-unknown-linux-gnu";
+
+    async fn store_rustdoc_json(
+        env: &TestEnvironment,
+        version: &Version,
+        json: serde_json::Value,
+    ) -> Result<()> {
+        let alg = CompressionAlgorithm::Zstd;
+        env.storage()?
+            .store_one_uncompressed(
+                rustdoc_json_path(
+                    &KRATE,
+                    version,
+                    TARGET,
+                    RustdocJsonFormatVersion::Latest,
+                    Some(alg),
+                ),
+                compress(serde_json::to_vec(&json)?.as_slice(), alg)?,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// rustdoc JSON with a `krate::run` function, and the given structs.
+    fn rustdoc_json(format_version: u16, run_output: &str, structs: &[&str]) -> serde_json::Value {
+        let mut json = serde_json::json!({
+            "format_version": format_version,
+            "index": {
+                "0": { "name": "krate", "visibility": "public", "inner": { "module": {} } },
+                "1": { "name": "run", "visibility": "public", "inner": { "function": {
+                    "sig": { "inputs": [], "output": { "primitive": run_output } },
+                    "generics": { "params": [] },
+                    "header": {},
+                } } },
+            },
+            "paths": {
+                "0": { "crate_id": 0, "path": ["krate"], "kind": "module" },
+                "1": { "crate_id": 0, "path": ["krate", "run"], "kind": "function" },
+            },
+        });
+        for (idx, name) in structs.iter().enumerate() {
+            let id = (idx + 10).to_string();
+            json["index"][&id] = serde_json::json!({
+                "name": name, "visibility": "public", "inner": { "struct": {} },
+            });
+            json["paths"][&id] = serde_json::json!({
+                "crate_id": 0, "path": ["krate", name], "kind": "struct",
+            });
+        }
+        json
+    }
+
+    async fn create_releases(env: &TestEnvironment) -> Result<()> {
+        for version in [V1, V2] {
+            env.fake_release()
+                .await
+                .name(&KRATE)
+                .version(version)
+                .create()
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn api_diff() {
+        async_wrapper(|env| async move {
+            create_releases(&env).await?;
+            store_rustdoc_json(&env, &V1, rustdoc_json(45, "u8", &["Old", "Kept"])).await?;
+            store_rustdoc_json(&env, &V2, rustdoc_json(45, "u16", &["Kept", "New"])).await?;
+
+            let web = env.web_app().await;
+            let url = format!("/crate/{KRATE}/{V1}...{V2}/api-diff");
+            let response = web.get(&url).await?;
+            response.assert_cache_control(
+                CachePolicy::ForeverInCdnAndStaleInBrowser(KRATE.into()),
+                env.config(),
+            );
+            let page = kuchikiki::parse_html().one(response.text().await?);
+
+            let items: Vec<_> = page
+                .select(".api-items li")
+                .unwrap()
+                .map(|li| {
+                    let link = li.as_node().select_first("a").unwrap();
+                    (
+                        li.attributes.borrow().get("class").unwrap().to_owned(),
+                        link.text_contents(),
+                        link.attributes.borrow().get("href").unwrap().to_owned(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                items,
+                vec![
+                    (
+                        "api-item-added".into(),
+                        "krate::New".into(),
+                        format!("/krate/{V2}/krate/struct.New.html")
+                    ),
+                    (
+                        "api-item-removed".into(),
+                        "krate::Old".into(),
+                        format!("/krate/{V1}/krate/struct.Old.html")
+                    ),
+                    (
+                        "api-item-changed".into(),
+                        "krate::run".into(),
+                        format!("/krate/{V2}/krate/fn.run.html")
+                    ),
+                ]
+            );
+
+            let response = web.get(&format!("{url}.json")).await?;
+            response.assert_cache_control(
+                CachePolicy::ForeverInCdnAndStaleInBrowser(KRATE.into()),
+                env.config(),
+            );
+            let value: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+            assert_eq!(
+                value,
+                serde_json::json!({
+                    "crate": "krate",
+                    "from": V1.to_string(),
+                    "to": V2.to_string(),
+                    "comparable": true,
+                    "added": [{ "path": "krate::New", "kind": "struct", "signature": null }],
+                    "removed": [{ "path": "krate::Old", "kind": "struct", "signature": null }],
+                    "changed": [{
+                        "path": "krate::run",
+                        "old_kind": "function",
+                        "new_kind": "function",
+                        "old_signature": "fn run() -> u8",
+                        "new_signature": "fn run() -> u16",
+                    }],
+                })
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_diff_different_format_versions() {
+        async_wrapper(|env| async move {
+            create_releases(&env).await?;
+            store_rustdoc_json(&env, &V1, rustdoc_json(45, "u8", &[])).await?;
+            store_rustdoc_json(&env, &V2, rustdoc_json(46, "u8", &[])).await?;
+
+            let web = env.web_app().await;
+            let url = format!("/crate/{KRATE}/{V1}...{V2}/api-diff");
+
+            let text = web.assert_success(&url).await?.text().await?;
+            assert!(text.contains("different rustdoc JSON format versions"));
+
+            let value: serde_json::Value =
+                serde_json::from_str(&web.get(&format!("{url}.json")).await?.text().await?)?;
+            assert_eq!(
+                value,
+                serde_json::json!({
+                    "crate": "krate",
+                    "from": V1.to_string(),
+                    "to": V2.to_string(),
+                    "comparable": false,
+                    "from_format_version": 45,
+                    "to_format_version": 46,
+                })
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_diff_without_readable_json() {
+        async_wrapper(|env| async move {
+            // the fake releases only contain the format version
+            create_releases(&env).await?;
+            store_rustdoc_json(&env, &V2, rustdoc_json(45, "u8", &[])).await?;
+
+            let web = env.web_app().await;
+            let url = format!("/crate/{KRATE}/{V1}...{V2}/api-diff");
+
+            let text = web.assert_success(&url).await?.text().await?;
+            assert!(text.contains(&format!(
+                "We don't have readable rustdoc JSON for krate {V1}"
+            )));
+
+            web.assert_not_found(&format!("{url}.json")).await?;
+
+            Ok(())
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn api_diff_with_too_large_json() -> Result<()> {
+        let env = TestEnvironment::builder()
+            .storage_config(
+                docs_rs_storage::Config::test_config_with_kind(StorageKind::Memory)?.set(
+                    |mut cfg| {
+                        cfg.max_file_size = 16;
+                        cfg
+                    },
+                ),
+            )
+            .build()
+            .await?;
+
+        create_releases(&env).await?;
+        store_rustdoc_json(&env, &V1, rustdoc_json(45, "u8", &["Old"])).await?;
+        store_rustdoc_json(&env, &V2, rustdoc_json(45, "u16", &["New"])).await?;
+
+        let web = env.web_app().await;
+        let url = format!("/crate/{KRATE}/{V1}...{V2}/api-diff");
+
+        let text = web.assert_success(&url).await?.text().await?;
+        assert!(text.contains(&format!(
+            "We don't have readable rustdoc JSON for krate {V1}"
+        )));
+
+        web.assert_not_found(&format!("{url}.json")).await?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    Config,
     cache::CachePolicy,
     error::{AxumNope, AxumResult},
     extractors::{DbConnection, Path, rustdoc::RustdocParams},
@@ -10,24 +11,44 @@ use crate::{
 };
 use anyhow::Context as _;
 use askama::Template;
-use axum::{extract::Extension, response::IntoResponse};
+use async_stream::stream;
+use axum::{
+    extract::Extension,
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
 use chrono::{DateTime, Utc};
+use docs_rs_database::{
+    Pool,
+    build_events::{BuildEvent, BuildEventStatus, get_build_events},
+};
 use docs_rs_storage::AsyncStorage;
 use docs_rs_types::{BuildId, BuildStatus};
 use futures_util::TryStreamExt;
+use http::{HeaderMap, header::CACHE_CONTROL};
 use serde::Deserialize;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use tokio::time::Instant;
+use tracing::error;
+
+/// how often the build event stream checks for new events.
+const BUILD_EVENTS_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct BuildDetails {
     id: BuildId,
     rustc_version: Option<String>,
     docsrs_version: Option<String>,
+    pinned_toolchain: Option<String>,
     build_status: BuildStatus,
     build_time: Option<DateTime<Utc>>,
     output: String,
     errors: Option<String>,
     error_kind: Option<String>,
+    /// end of the log of the running cargo command, for in-progress builds.
+    log_tail: Option<String>,
 }
 
 #[derive(Template)]
@@ -36,6 +57,7 @@ pub(crate) struct BuildDetails {
 struct BuildDetailsPage {
     metadata: MetaData,
     build_details: BuildDetails,
+    events: Vec<BuildEvent>,
     all_log_filenames: Vec<(String, Option<bool>)>,
     current_filename: Option<String>,
     params: RustdocParams,
@@ -48,6 +70,10 @@ impl BuildDetailsPage {
     pub(crate) fn use_direct_platform_links(&self) -> bool {
         true
     }
+
+    pub(crate) fn is_in_progress(&self) -> bool {
+        self.build_details.build_status == BuildStatus::InProgress
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -87,11 +113,13 @@ pub(crate) async fn build_details_handler(
         r#"SELECT
              builds.rustc_version,
              builds.docsrs_version,
+             builds.pinned_toolchain,
              builds.build_status as "build_status: BuildStatus",
              COALESCE(builds.build_finished, builds.build_started) as build_time,
              builds.output,
              builds.errors,
              builds.error_kind,
+             builds.log_tail,
              releases.default_target,
              (
                  SELECT array_agg(row(bl.log_filename, bl.success))
@@ -123,6 +151,8 @@ pub(crate) async fn build_details_handler(
     .await?;
     let params = params.apply_metadata(&metadata);
 
+    let events = get_build_events(&mut conn, id, None).await?;
+
     // NOTE: we want to give back the db connection to the pool
     // before we do the long S3 requests.
     drop(conn);
@@ -199,12 +229,15 @@ pub(crate) async fn build_details_handler(
             id,
             rustc_version: row.rustc_version,
             docsrs_version: row.docsrs_version,
+            pinned_toolchain: row.pinned_toolchain,
             build_status: row.build_status,
             build_time: row.build_time,
             output,
             errors: row.errors,
             error_kind: row.error_kind,
+            log_tail: row.log_tail,
         },
+        events,
         all_log_filenames,
         current_filename,
         params,
@@ -212,14 +245,166 @@ pub(crate) async fn build_details_handler(
     .into_response())
 }
 
+/// the data of the `build-event` server-sent event.
+fn build_event_data(event: &BuildEvent) -> serde_json::Value {
+    serde_json::json!({
+        "occurred_at": event.occurred_at,
+        "phase": event.phase,
+        "description": event.phase.description(),
+        "status": event.status,
+        "message": event.message,
+    })
+}
+
+/// Stream the progress of a build as server-sent events.
+///
+/// * `build-event`: a new event in the timeline of the build, with the event ID as SSE ID,
+///   so reconnecting clients only get the events they didn't see yet.
+/// * `log`: the current end of the log of the running cargo command.
+/// * `finished`: the build is finished, the stream ends after this event.
+/// * `timeout`: nothing changed for `Config::build_events_idle_timeout`, or the stream was
+///   open for `Config::build_events_max_lifetime`. The stream ends after this event, and
+///   clients shouldn't reconnect.
+pub(crate) async fn build_events_handler(
+    params: RustdocParams,
+    Path(build_params): Path<BuildDetailsParams>,
+    mut conn: DbConnection,
+    Extension(pool): Extension<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    headers: HeaderMap,
+) -> AxumResult<impl IntoResponse> {
+    let id = build_params
+        .id
+        .parse()
+        .map(BuildId)
+        .map_err(|_| AxumNope::BuildNotFound)?;
+
+    let version = match_version(&mut conn, params.name(), params.req_version())
+        .await?
+        .assume_exact_name()?
+        .into_version();
+
+    sqlx::query_scalar!(
+        "SELECT builds.id
+         FROM builds
+         INNER JOIN releases ON releases.id = builds.rid
+         INNER JOIN crates ON releases.crate_id = crates.id
+         WHERE builds.id = $1 AND crates.name = $2 AND releases.version = $3",
+        id.0,
+        params.name() as _,
+        version as _
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AxumNope::BuildNotFound)?;
+
+    // the stream takes connections from the pool when it needs them.
+    drop(conn);
+
+    let mut last_event_id: Option<i64> = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+
+    let stream = stream!({
+        let mut last_status = None;
+        let mut last_log_tail = None;
+        let started_at = Instant::now();
+        let mut last_change_at = started_at;
+
+        loop {
+            let progress = async {
+                let mut conn = pool.get_async().await?;
+                let events = get_build_events(&mut conn, id, last_event_id).await?;
+                let build = sqlx::query!(
+                    r#"SELECT
+                         build_status as "build_status: BuildStatus",
+                         log_tail
+                     FROM builds
+                     WHERE id = $1"#,
+                    id.0,
+                )
+                .fetch_one(&mut *conn)
+                .await?;
+                Ok::<_, anyhow::Error>((events, build.build_status, build.log_tail))
+            }
+            .await;
+
+            let (events, build_status, log_tail) = match progress {
+                Ok(progress) => progress,
+                Err(err) => {
+                    error!(?err, %id, "could not fetch build progress");
+                    break;
+                }
+            };
+
+            let no_new_events = events.is_empty();
+            if !no_new_events {
+                last_change_at = Instant::now();
+            }
+            for event in events {
+                last_event_id = Some(event.id);
+                last_status = Some(event.status);
+                yield Event::default()
+                    .event("build-event")
+                    .id(event.id.to_string())
+                    .json_data(build_event_data(&event));
+            }
+
+            if log_tail.is_some() && log_tail != last_log_tail {
+                yield Event::default()
+                    .event("log")
+                    .json_data(serde_json::json!({ "log": log_tail }));
+                last_log_tail = log_tail;
+                last_change_at = Instant::now();
+            }
+
+            // Some phases run after the build status is set, so we wait until
+            // the last phase is done.
+            if build_status != BuildStatus::InProgress
+                && no_new_events
+                && last_status != Some(BuildEventStatus::Started)
+            {
+                yield Event::default()
+                    .event("finished")
+                    .json_data(serde_json::json!({ "build_status": build_status }));
+                break;
+            }
+
+            if last_change_at.elapsed() >= config.build_events_idle_timeout
+                || started_at.elapsed() >= config.build_events_max_lifetime
+            {
+                yield Event::default()
+                    .event("timeout")
+                    .json_data(serde_json::json!({ "build_status": build_status }));
+                break;
+            }
+
+            tokio::time::sleep(BUILD_EVENTS_POLL_INTERVAL).await;
+        }
+    });
+
+    let mut response = Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response();
+    // `Sse` sets its own `Cache-Control` header, we use our default `NoCaching` policy instead.
+    response.headers_mut().remove(CACHE_CONTROL);
+    Ok(response)
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::Config;
+    use crate::cache::CachePolicy;
     use crate::testing::{
         AxumResponseTestExt, AxumRouterTestExt, TestEnvironment, TestEnvironmentExt as _,
         async_wrapper,
     };
+    use docs_rs_database::build_events::{
+        BuildEventStatus, BuildPhase, add_build_event, get_build_events, set_build_log_tail,
+    };
     use docs_rs_test_fakes::{FakeBuild, fake_release_that_failed_before_build};
-    use docs_rs_types::{BuildId, ReleaseId, SimpleBuildError, testing::V0_1};
+    use docs_rs_types::{BuildId, BuildStatus, ReleaseId, SimpleBuildError, testing::V0_1};
     use kuchikiki::traits::TendrilSink;
     use test_case::test_case;
 
@@ -577,4 +762,254 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_detail_shows_pinned_toolchain() -> anyhow::Result<()> {
+        let env = TestEnvironment::new().await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .create()
+            .await?;
+
+        let mut conn = env.async_conn().await?;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+        docs_rs_database::releases::set_build_pinned_toolchain(
+            &mut conn,
+            build_id,
+            "nightly-2024-01-01",
+        )
+        .await?;
+
+        let web = env.web_app().await;
+        let details = web
+            .get(&format!("/crate/foo/0.1.0/builds/{build_id}"))
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        assert!(details.contains("# pinned toolchain"));
+        assert!(details.contains("nightly-2024-01-01"));
+
+        let builds = web
+            .get("/crate/foo/0.1.0/builds")
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        assert!(builds.contains("pinned: nightly-2024-01-01"));
+
+        Ok(())
+    }
+
+    async fn add_events(conn: &mut sqlx::PgConnection, build_id: BuildId) -> anyhow::Result<()> {
+        for (phase, status, message) in [
+            (BuildPhase::Fetch, BuildEventStatus::Started, None),
+            (BuildPhase::Fetch, BuildEventStatus::Finished, None),
+            (
+                BuildPhase::DefaultTargetBuild,
+                BuildEventStatus::Started,
+                Some("x86_64-unknown-linux-gnu"),
+            ),
+        ] {
+            add_build_event(&mut *conn, build_id, phase, status, message).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_event_timeline() -> anyhow::Result<()> {
+        let env = TestEnvironment::new().await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .create()
+            .await?;
+
+        let mut conn = env.async_conn().await?;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+        add_events(&mut conn, build_id).await?;
+
+        let page = kuchikiki::parse_html().one(
+            env.web_app()
+                .await
+                .assert_success(&format!("/crate/foo/0.1.0/builds/{build_id}"))
+                .await?
+                .text()
+                .await?,
+        );
+
+        let events: Vec<_> = page
+            .select("#build-events li")
+            .unwrap()
+            .map(|event| {
+                event
+                    .text_contents()
+                    .split_whitespace()
+                    .skip(1) // the time
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                "fetch crate started",
+                "fetch crate finished",
+                "build default target x86_64-unknown-linux-gnu started",
+            ]
+        );
+
+        // finished builds don't stream their progress
+        let list = page.select_first("#build-events").unwrap();
+        assert!(list.attributes.borrow().get("data-events-url").is_none());
+        assert!(page.select_first("#build-log-tail").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn in_progress_build_follows_events() -> anyhow::Result<()> {
+        let env = TestEnvironment::new().await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::InProgress)
+                    .no_s3_build_log(),
+            ])
+            .create()
+            .await?;
+
+        let mut conn = env.async_conn().await?;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+        set_build_log_tail(&mut conn, build_id, "Compiling foo v0.1.0\n").await?;
+
+        let page = kuchikiki::parse_html().one(
+            env.web_app()
+                .await
+                .assert_success(&format!("/crate/foo/0.1.0/builds/{build_id}"))
+                .await?
+                .text()
+                .await?,
+        );
+
+        let list = page.select_first("#build-events").unwrap();
+        assert_eq!(
+            list.attributes.borrow().get("data-events-url"),
+            Some(format!("/crate/foo/0.1.0/builds/{build_id}/events").as_str())
+        );
+        assert_eq!(
+            page.select_first("#build-log-tail")
+                .unwrap()
+                .text_contents(),
+            "Compiling foo v0.1.0\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_event_stream() -> anyhow::Result<()> {
+        let env = TestEnvironment::new().await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .create()
+            .await?;
+
+        let mut conn = env.async_conn().await?;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+        add_events(&mut conn, build_id).await?;
+        add_build_event(
+            &mut conn,
+            build_id,
+            BuildPhase::DefaultTargetBuild,
+            BuildEventStatus::Finished,
+            Some("x86_64-unknown-linux-gnu"),
+        )
+        .await?;
+
+        let web = env.web_app().await;
+        let url = format!("/crate/foo/0.1.0/builds/{build_id}/events");
+
+        let response = web.assert_success(&url).await?;
+        response.assert_cache_control(CachePolicy::NoCaching, env.config());
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+        let body = response.text().await?;
+        assert_eq!(body.matches("event: build-event").count(), 4);
+        assert!(body.contains(r#""description":"build default target""#));
+        assert!(body.ends_with("event: finished\ndata: {\"build_status\":\"success\"}\n\n"));
+
+        // reconnecting clients only get the events they didn't see yet.
+        let last_event_id = get_build_events(&mut conn, build_id, None).await?[2].id;
+        let body = web
+            .get_with_headers(&url, |headers| {
+                headers.insert("last-event-id", last_event_id.into());
+            })
+            .await?
+            .text()
+            .await?;
+        assert_eq!(body.matches("event: build-event").count(), 1);
+        assert!(body.contains(&format!("id: {}\n", last_event_id + 1)));
+
+        web.assert_not_found("/crate/foo/0.1.0/builds/42/events")
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_event_stream_times_out() -> anyhow::Result<()> {
+        let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .build_events_idle_timeout(0)
+                    .build(),
+            )
+            .build()
+            .await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::InProgress)
+                    .no_s3_build_log(),
+            ])
+            .create()
+            .await?;
+
+        let mut conn = env.async_conn().await?;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+        set_build_log_tail(&mut conn, build_id, "Compiling foo v0.1.0\n").await?;
+
+        // the build is still running, but the stream ends.
+        let body = env
+            .web_app()
+            .await
+            .assert_success(&format!("/crate/foo/0.1.0/builds/{build_id}/events"))
+            .await?
+            .text()
+            .await?;
+        assert!(body.contains("event: log\n"));
+        assert!(body.ends_with("event: timeout\ndata: {\"build_status\":\"in_progress\"}\n\n"));
+
+        Ok(())
+    }
 }
@@ -30,6 +30,8 @@ pub(crate) struct Build {
     id: BuildId,
     pub rustc_version: Option<String>,
     docsrs_version: Option<String>,
+    /// toolchain pinned by the admins for this crate or release, if any.
+    pinned_toolchain: Option<String>,
     pub build_status: BuildStatus,
     pub build_time: Option<DateTime<Utc>>,
     build_duration: Option<Duration>,
@@ -190,6 +192,7 @@ pub(super) async fn get_builds(
             builds.id as "id: BuildId",
             builds.rustc_version,
             builds.docsrs_version,
+            builds.pinned_toolchain,
             CASE
                 WHEN builds.build_status = 'success'::build_status THEN
                     CASE
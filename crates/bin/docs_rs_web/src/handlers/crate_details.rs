@@ -22,7 +22,7 @@ use chrono::{DateTime, Utc};
 use docs_rs_cargo_metadata::{Dependency, ReleaseDependencyList};
 use docs_rs_database::crate_details::{Release, parse_doc_targets};
 use docs_rs_headers::CanonicalUrl;
-use docs_rs_registry_api::OwnerKind;
+use docs_rs_registry_api::{CRATES_IO, OwnerKind, Registry, RegistryApi};
 use docs_rs_storage::{AsyncStorage, PathNotFoundError};
 use docs_rs_types::{
     BuildId, BuildStatus, CrateId, Duration, KrateName, ReleaseId, ReqVersion, Version,
@@ -70,6 +70,8 @@ pub(crate) struct CrateDetails {
     pub(crate) release_id: ReleaseId,
     source_size: Option<i64>,
     documentation_size: Option<i64>,
+    /// name of the registry this release was published to
+    pub(crate) registry: String,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -137,6 +139,7 @@ impl CrateDetails {
                 releases.documentation_url,
                 releases.default_target,
                 releases.source_size as "source_size?",
+                releases.registry,
                 builds.documentation_size as "documentation_size?",
                 -- we're using the rustc version here to set the correct CSS file
                 -- in the metadata.
@@ -260,6 +263,7 @@ impl CrateDetails {
             release_id: krate.release_id,
             documentation_size: krate.documentation_size,
             source_size: krate.source_size,
+            registry: krate.registry,
         };
 
         // get owners
@@ -343,6 +347,81 @@ impl CrateDetails {
     pub fn latest_release(&self) -> Option<&Release> {
         docs_rs_database::crate_details::latest_release(&self.releases)
     }
+
+    /// The release metadata as served by the versioned JSON API.
+    ///
+    /// Fields may be added, but existing ones must not change, see `/about/api`.
+    pub(crate) fn to_json(&self) -> Value {
+        let has_coverage = self.total_items.is_some() || self.documented_items.is_some();
+
+        serde_json::json!({
+            "name": self.name,
+            "version": self.version.to_string(),
+            "description": self.description,
+            "license": self.license,
+            "keywords": self.keywords,
+            "registry": self.registry,
+            "release_time": self.release_time,
+            "yanked": self.metadata.yanked.unwrap_or(false),
+            "is_library": self.is_library,
+            "target_name": self.target_name,
+            "default_target": self.metadata.default_target,
+            "doc_targets": self.metadata.doc_targets.clone().unwrap_or_default(),
+            "build_status": self.build_status,
+            "rustdoc_status": self.rustdoc_status.unwrap_or(false),
+            "last_successful_build": self.last_successful_build.as_ref().map(ToString::to_string),
+            "latest_build": self.latest_build.as_ref().map(|build| serde_json::json!({
+                "rustc_version": build.rustc_version,
+                "build_status": build.build_status,
+                "build_time": build.build_time,
+            })),
+            "owners": self.owners.iter().map(|(login, avatar, kind)| serde_json::json!({
+                "login": login,
+                "avatar": avatar,
+                "kind": kind,
+            })).collect::<Vec<_>>(),
+            "dependencies": self.dependencies.iter().map(|dependency| serde_json::json!({
+                "name": dependency.name,
+                "req": dependency.req.to_string(),
+                "kind": dependency.kind.as_deref().unwrap_or("normal"),
+                "optional": dependency.optional,
+                "rename": dependency.rename,
+            })).collect::<Vec<_>>(),
+            "doc_coverage": has_coverage.then(|| serde_json::json!({
+                "total_items": self.total_items,
+                "documented_items": self.documented_items,
+                "total_items_needing_examples": self.total_items_needing_examples,
+                "items_with_examples": self.items_with_examples,
+            })),
+            "source_size": self.source_size,
+            "documentation_size": self.documentation_size,
+            "homepage_url": self.homepage_url,
+            "documentation_url": self.documentation_url,
+            "repository_url": self.repository_url,
+            "repository": self.repository_metadata.as_ref().map(|repository| serde_json::json!({
+                "name": repository.name,
+                "stars": repository.stars,
+                "forks": repository.forks,
+                "issues": repository.issues,
+            })),
+        })
+    }
+
+    /// All releases of the crate for the versioned JSON API, newest first.
+    pub(crate) fn releases_json(&self) -> Value {
+        self.releases
+            .iter()
+            .map(|release| {
+                serde_json::json!({
+                    "version": release.version.to_string(),
+                    "build_status": release.build_status,
+                    "rustdoc_status": release.rustdoc_status.unwrap_or(false),
+                    "yanked": release.yanked.unwrap_or(false),
+                    "release_time": release.release_time,
+                })
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -426,6 +505,31 @@ struct CrateDetailsPage {
     documentation_size: Option<i64>,
     canonical_url: CanonicalUrl,
     params: RustdocParams,
+    /// title & URL of the crate page in its registry
+    registry_link: Option<(String, String)>,
+}
+
+/// Link to the page of the crate in the registry the release was published to.
+///
+/// Releases from a registry that is not configured (anymore) don't get a link.
+fn registry_link(
+    registry_api: &RegistryApi,
+    release_registry: &str,
+    name: &KrateName,
+) -> Option<(String, String)> {
+    if release_registry == CRATES_IO {
+        Some((
+            "crates.io".into(),
+            Registry::crates_io().crate_url(name).into(),
+        ))
+    } else if release_registry == registry_api.registry().name {
+        Some((
+            release_registry.into(),
+            registry_api.registry().crate_url(name).into(),
+        ))
+    } else {
+        None
+    }
 }
 
 impl CrateDetailsPage {
@@ -440,10 +544,11 @@ impl_axum_webpage! {
     cpu_intensive_rendering = true,
 }
 
-#[tracing::instrument(skip(conn, storage))]
+#[tracing::instrument(skip(conn, storage, registry_api))]
 pub(crate) async fn crate_details_handler(
     params: RustdocParams,
     Extension(storage): Extension<Arc<AsyncStorage>>,
+    Extension(registry_api): Extension<Arc<RegistryApi>>,
     mut conn: DbConnection,
 ) -> AxumResult<AxumResponse> {
     let matched_release = match_version(&mut conn, params.name(), params.req_version())
@@ -505,12 +610,14 @@ pub(crate) async fn crate_details_handler(
         rustdoc,
         source_size,
         documentation_size,
+        registry,
         ..
     } = details;
 
     let is_latest_version = params.req_version().is_latest();
 
     let mut res = CrateDetailsPage {
+        registry_link: registry_link(&registry_api, &registry, &name),
         version,
         name: name.clone(),
         owners,
@@ -1536,6 +1643,48 @@ mod tests {
         });
     }
 
+    #[test]
+    fn details_link_to_registry() {
+        async_wrapper(|env| async move {
+            let release_id = env
+                .fake_release()
+                .await
+                .name("library")
+                .version("0.1.0")
+                .create()
+                .await?;
+
+            let web = env.web_app().await;
+            let registry_links = || async {
+                let page = kuchikiki::parse_html().one(
+                    web.assert_success("/crate/library/0.1.0")
+                        .await?
+                        .text()
+                        .await?,
+                );
+                Ok::<_, Error>(
+                    page.select("a.pure-menu-link[title^='See library on']")
+                        .unwrap()
+                        .map(|link| link.attributes.borrow().get("href").unwrap().to_string())
+                        .collect::<Vec<_>>(),
+                )
+            };
+
+            assert_eq!(
+                registry_links().await?,
+                vec!["https://crates.io/crates/library"]
+            );
+
+            // releases from registries we don't know don't get a link
+            let mut conn = env.async_conn().await?;
+            docs_rs_database::releases::set_release_registry(&mut conn, release_id, "unknown")
+                .await?;
+            assert!(registry_links().await?.is_empty());
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn feature_flags_report_null() {
         async_wrapper(|env| async move {
@@ -0,0 +1,240 @@
+use crate::{
+    Config,
+    error::{AxumNope, JsonAxumNope, JsonAxumResult},
+    extractors::DbConnection,
+};
+use anyhow::Context as _;
+use axum::{Json, body::Bytes, extract::Extension, response::IntoResponse};
+use docs_rs_crates_io::{
+    events::IndexChangeEventV1,
+    signature::{self, SIGNATURE_HEADER},
+};
+use http::{HeaderMap, StatusCode};
+use std::sync::Arc;
+use tracing::debug;
+
+/// Receive index change events pushed by crates.io.
+///
+/// We only persist the events here, deduplicated by their ID.
+/// The registry watcher picks them up and applies them, the same way it
+/// applies changes it finds in the git index.
+pub(crate) async fn crates_io_events_handler(
+    mut conn: DbConnection,
+    Extension(config): Extension<Arc<Config>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> JsonAxumResult<impl IntoResponse> {
+    let secret =
+        config
+            .cratesio_events_secret
+            .as_ref()
+            .ok_or(JsonAxumNope(AxumNope::Unauthorized(
+                "Endpoint is not configured",
+            )))?;
+
+    let signature = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(JsonAxumNope(AxumNope::Unauthorized("Missing signature")))?;
+
+    if !signature::verify(secret.as_bytes(), &body, signature) {
+        return Err(JsonAxumNope(AxumNope::Unauthorized(
+            "The signature of the request body is not valid",
+        )));
+    }
+
+    let event: IndexChangeEventV1 = serde_json::from_slice(&body)
+        .context("invalid event payload")
+        .map_err(|err| JsonAxumNope(AxumNope::BadRequest(err)))?;
+
+    let inserted = sqlx::query!(
+        "INSERT INTO crates_io_events (id, occurred_at, kind, name, version)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (id) DO NOTHING",
+        event.id,
+        event.occurred_at,
+        event.change.kind(),
+        event.change.crate_name(),
+        event.change.crate_version().map(|v| v.version.as_str()),
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|err| JsonAxumNope(err.into()))?
+    .rows_affected()
+        > 0;
+
+    debug!(id = event.id, change = %event.change, inserted, "received crates.io event");
+
+    Ok((
+        if inserted {
+            StatusCode::ACCEPTED
+        } else {
+            // we already know this event, nothing to do.
+            StatusCode::OK
+        },
+        Json(serde_json::json!({})),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Config,
+        testing::{AxumResponseTestExt as _, TestEnvironment, TestEnvironmentExt as _},
+    };
+    use anyhow::Result;
+    use axum::{body::Body, http::Request};
+    use docs_rs_crates_io::signature::{SIGNATURE_HEADER, sign};
+    use reqwest::StatusCode;
+    use tower::ServiceExt as _;
+
+    const SECRET: &str = "some-secret";
+    const EVENT: &str = r#"{
+        "id": "evt_123",
+        "occurred_at": "2026-05-22T12:34:56Z",
+        "type": "added",
+        "payload": { "name": "foo", "vers": "0.1.0" }
+    }"#;
+
+    async fn post_event(
+        env: &TestEnvironment,
+        body: &'static str,
+        signature: Option<String>,
+    ) -> Result<(StatusCode, serde_json::Value)> {
+        let mut request = Request::builder().uri("/-/crates-io/events").method("POST");
+        if let Some(signature) = signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(request.body(Body::from(body)).unwrap())
+            .await?;
+
+        Ok((response.status(), response.json().await?))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_config() -> Result<()> {
+        let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .maybe_cratesio_events_secret(None)
+                    .build(),
+            )
+            .build()
+            .await?;
+
+        let (status, json) =
+            post_event(&env, EVENT, Some(sign(SECRET.as_bytes(), EVENT.as_bytes()))).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "title": "Unauthorized",
+                "message": "Endpoint is not configured"
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_signatures() -> Result<()> {
+        let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .cratesio_events_secret(SECRET.into())
+                    .build(),
+            )
+            .build()
+            .await?;
+
+        let (status, json) = post_event(&env, EVENT, None).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["message"], "Missing signature");
+
+        let (status, json) =
+            post_event(&env, EVENT, Some(sign(b"wrong-secret", EVENT.as_bytes()))).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            json["message"],
+            "The signature of the request body is not valid"
+        );
+
+        let mut conn = env.async_conn().await?;
+        assert_eq!(
+            sqlx::query_scalar!("SELECT COUNT(*) as \"count!\" FROM crates_io_events")
+                .fetch_one(&mut *conn)
+                .await?,
+            0
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_payload() -> Result<()> {
+        let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .cratesio_events_secret(SECRET.into())
+                    .build(),
+            )
+            .build()
+            .await?;
+
+        const BODY: &str = r#"{"id": "evt_123", "type": "renamed"}"#;
+
+        let (status, _) =
+            post_event(&env, BODY, Some(sign(SECRET.as_bytes(), BODY.as_bytes()))).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn events_are_stored_once() -> Result<()> {
+        let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .cratesio_events_secret(SECRET.into())
+                    .build(),
+            )
+            .build()
+            .await?;
+
+        let signature = sign(SECRET.as_bytes(), EVENT.as_bytes());
+
+        let (status, json) = post_event(&env, EVENT, Some(signature.clone())).await?;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(json, serde_json::json!({}));
+
+        // redelivery of the same event
+        let (status, _) = post_event(&env, EVENT, Some(signature)).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut conn = env.async_conn().await?;
+        let rows = sqlx::query!(
+            "SELECT id, kind, name, version, processed_at
+             FROM crates_io_events"
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        assert_eq!(rows.len(), 1);
+        let row = &rows[0];
+        assert_eq!(row.id, "evt_123");
+        assert_eq!(row.kind, "added");
+        assert_eq!(row.name, "foo");
+        assert_eq!(row.version.as_deref(), Some("0.1.0"));
+        assert!(row.processed_at.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+//! Atom feeds with the documentation builds and yanks of a crate, or of all crates of an owner.
+
+use crate::{
+    cache::CachePolicy,
+    error::{AxumNope, AxumResult},
+    extractors::{DbConnection, Path, rustdoc::RustdocParams},
+    impl_axum_webpage,
+};
+use anyhow::Result;
+use askama::Template;
+use axum::response::IntoResponse;
+use chrono::{DateTime, Utc};
+use docs_rs_headers::SurrogateKeys;
+use docs_rs_types::{BuildId, BuildStatus, CrateId, KrateName, Version};
+use docs_rs_uri::EscapedURI;
+use futures_util::TryStreamExt as _;
+
+/// how many entries we show in a feed.
+const ENTRIES_IN_FEED: i64 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FeedEntryKind {
+    Documented(BuildId),
+    BuildFailed(BuildId),
+    Yanked,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FeedEntry {
+    name: KrateName,
+    version: Version,
+    description: Option<String>,
+    target_name: Option<String>,
+    kind: FeedEntryKind,
+    time: DateTime<Utc>,
+}
+
+impl FeedEntry {
+    fn params(&self) -> RustdocParams {
+        RustdocParams::new(self.name.clone())
+            .with_req_version(self.version.clone())
+            .with_maybe_target_name(self.target_name.clone())
+    }
+
+    fn title(&self) -> String {
+        let event = match self.kind {
+            FeedEntryKind::Documented(_) => "documented",
+            FeedEntryKind::BuildFailed(_) => "failed to build",
+            FeedEntryKind::Yanked => "yanked",
+        };
+        format!("{} {} {}", self.name, self.version, event)
+    }
+
+    /// builds are keyed by release, so a rebuild updates the entry instead of adding one.
+    fn id(&self) -> String {
+        match self.kind {
+            FeedEntryKind::Documented(_) | FeedEntryKind::BuildFailed(_) => {
+                format!("urn:docs-rs:{}:{}:build", self.name, self.version)
+            }
+            FeedEntryKind::Yanked => format!("urn:docs-rs:{}:{}:yanked", self.name, self.version),
+        }
+    }
+
+    fn link(&self) -> EscapedURI {
+        let params = self.params();
+        match self.kind {
+            FeedEntryKind::Documented(_) if self.target_name.is_some() => params.rustdoc_url(),
+            FeedEntryKind::Documented(_) | FeedEntryKind::Yanked => params.crate_details_url(),
+            FeedEntryKind::BuildFailed(id) => params.build_details_url(id, None),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "releases/events_feed.xml")]
+#[derive(Debug, Clone)]
+struct EventsFeed {
+    title: String,
+    feed_id: String,
+    feed_url: EscapedURI,
+    page_url: EscapedURI,
+    entries: Vec<FeedEntry>,
+    cache_policy: CachePolicy,
+}
+
+impl_axum_webpage! {
+    EventsFeed,
+    content_type = "application/xml",
+    cache_policy = |feed| feed.cache_policy.clone(),
+}
+
+/// Fetch the latest successful & failed builds, and the yanks of the given crates.
+///
+/// We only show the last finished build of each release.
+async fn get_feed_entries(
+    conn: &mut sqlx::PgConnection,
+    crate_ids: &[CrateId],
+) -> Result<Vec<FeedEntry>> {
+    let crate_ids: Vec<i32> = crate_ids.iter().map(|id| id.0).collect();
+
+    Ok(sqlx::query!(
+        r#"SELECT
+            name as "name!: KrateName",
+            version as "version!: Version",
+            description,
+            target_name,
+            build_id as "build_id: BuildId",
+            build_status as "build_status: BuildStatus",
+            time as "time!"
+         FROM (
+            (
+                SELECT DISTINCT ON (releases.id)
+                    crates.name,
+                    releases.version,
+                    releases.description,
+                    releases.target_name,
+                    builds.id AS build_id,
+                    builds.build_status,
+                    builds.build_finished AS time
+                FROM builds
+                INNER JOIN releases ON releases.id = builds.rid
+                INNER JOIN crates ON crates.id = releases.crate_id
+                WHERE
+                    crates.id = ANY($1) AND
+                    builds.build_status IN ('success', 'failure') AND
+                    builds.build_finished IS NOT NULL
+                ORDER BY releases.id, builds.build_finished DESC
+            )
+
+            UNION ALL
+
+            SELECT
+                crates.name,
+                releases.version,
+                releases.description,
+                releases.target_name,
+                NULL,
+                NULL,
+                releases.yanked_at
+            FROM releases
+            INNER JOIN crates ON crates.id = releases.crate_id
+            WHERE
+                crates.id = ANY($1) AND
+                releases.yanked = TRUE AND
+                releases.yanked_at IS NOT NULL
+         ) AS events
+         ORDER BY time DESC
+         LIMIT $2"#,
+        &crate_ids,
+        ENTRIES_IN_FEED,
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| FeedEntry {
+        kind: match (row.build_id, row.build_status) {
+            (Some(id), Some(BuildStatus::Success)) => FeedEntryKind::Documented(id),
+            (Some(id), _) => FeedEntryKind::BuildFailed(id),
+            (None, _) => FeedEntryKind::Yanked,
+        },
+        name: row.name,
+        version: row.version,
+        description: row.description,
+        target_name: row.target_name,
+        time: row.time,
+    })
+    .try_collect()
+    .await?)
+}
+
+pub(crate) async fn crate_feed_handler(
+    Path(name): Path<KrateName>,
+    mut conn: DbConnection,
+) -> AxumResult<impl IntoResponse> {
+    let crate_id = sqlx::query_scalar!(
+        r#"SELECT id as "id: CrateId" FROM crates WHERE name = $1"#,
+        name as _,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AxumNope::CrateNotFound)?;
+
+    Ok(EventsFeed {
+        title: format!("{name} on Docs.rs"),
+        feed_id: format!("urn:docs-rs:crate:{name}"),
+        feed_url: EscapedURI::from_path(format!("/crate/{name}/feed.xml")),
+        page_url: RustdocParams::new(name.clone()).crate_details_url(),
+        entries: get_feed_entries(&mut conn, &[crate_id]).await?,
+        // the builder & watcher purge the crate after builds and yanks
+        cache_policy: CachePolicy::ForeverInCdn(name.into()),
+    })
+}
+
+pub(crate) async fn owner_feed_handler(
+    Path(owner): Path<String>,
+    mut conn: DbConnection,
+) -> AxumResult<impl IntoResponse> {
+    let login = owner.strip_prefix('@').unwrap_or(&owner);
+
+    let crates: Vec<(CrateId, KrateName)> = sqlx::query!(
+        r#"SELECT
+            crates.id as "id: CrateId",
+            crates.name as "name: KrateName"
+         FROM owners
+         INNER JOIN owner_rels ON owner_rels.oid = owners.id
+         INNER JOIN crates ON crates.id = owner_rels.cid
+         WHERE owners.login = $1"#,
+        login,
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| (row.id, row.name))
+    .try_collect()
+    .await?;
+
+    if crates.is_empty() {
+        return Err(AxumNope::OwnerNotFound);
+    }
+
+    let crate_ids: Vec<_> = crates.iter().map(|(id, _)| *id).collect();
+
+    // The builder & watcher purge the crate after builds and yanks, which also purges
+    // the feeds of its owners.
+    // Owners with too many crates for the surrogate key header get a short cache instead.
+    let mut keys = SurrogateKeys::new();
+    let cache_policy = match keys.try_extend(crates.into_iter().map(|(_, name)| name.into())) {
+        Ok(()) => CachePolicy::ForeverInCdn(keys),
+        Err(_) => CachePolicy::ShortInCdnAndBrowser,
+    };
+
+    Ok(EventsFeed {
+        title: format!("Crates of {login} on Docs.rs"),
+        feed_id: format!("urn:docs-rs:owner:{login}"),
+        feed_url: EscapedURI::from_path(format!("/releases/{login}/feed.xml")),
+        page_url: EscapedURI::from_path(format!("/releases/{login}")),
+        entries: get_feed_entries(&mut conn, &crate_ids).await?,
+        cache_policy,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cache::CachePolicy,
+        testing::{AxumResponseTestExt, AxumRouterTestExt, TestEnvironmentExt as _, async_wrapper},
+    };
+    use docs_rs_headers::{SurrogateKey, SurrogateKeys};
+    use docs_rs_registry_api::{CrateOwner, OwnerKind};
+
+    fn owner() -> CrateOwner {
+        CrateOwner {
+            login: "some-owner".into(),
+            avatar: "".into(),
+            kind: OwnerKind::User,
+        }
+    }
+
+    fn entry_titles(feed: &str) -> Vec<String> {
+        feed.split("<entry>")
+            .skip(1)
+            .map(|entry| {
+                let start = entry.find("<title>").unwrap() + "<title>".len();
+                let end = entry.find("</title>").unwrap();
+                entry[start..end].to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crate_feed() {
+        async_wrapper(|env| async move {
+            let release_id = env
+                .fake_release()
+                .await
+                .name("foo")
+                .version("0.1.0")
+                .description("a <fake> crate")
+                .create()
+                .await?;
+            // a rebuild replaces the entry of the release
+            let mut conn = env.async_conn().await?;
+            sqlx::query!(
+                "INSERT INTO builds (rid, build_status, build_started, build_finished)
+                 VALUES ($1, 'failure', NOW(), NOW() + INTERVAL '1 minute')",
+                release_id.0,
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.2.0")
+                .build_result_failed()
+                .create()
+                .await?;
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.3.0")
+                .yanked(true)
+                .create()
+                .await?;
+            env.fake_release()
+                .await
+                .name("other")
+                .version("1.0.0")
+                .create()
+                .await?;
+
+            let web = env.web_app().await;
+            let response = web.get("/crate/foo/feed.xml").await?;
+            assert!(response.status().is_success());
+            assert_eq!(response.headers()["content-type"], "application/xml");
+            response.assert_cache_control(
+                CachePolicy::ForeverInCdn(SurrogateKey::from_static("crate-foo").into()),
+                env.config(),
+            );
+
+            let feed = response.text().await?;
+            let mut titles = entry_titles(&feed);
+            titles.sort();
+            assert_eq!(
+                titles,
+                [
+                    "foo 0.1.0 failed to build",
+                    "foo 0.2.0 failed to build",
+                    "foo 0.3.0 documented",
+                    "foo 0.3.0 yanked",
+                ]
+            );
+            assert!(feed.contains("<summary>a &#60;fake&#62; crate</summary>"));
+            assert!(feed.contains("/crate/foo/0.2.0/builds/"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn crate_feed_not_found() {
+        async_wrapper(|env| async move {
+            let web = env.web_app().await;
+            web.assert_not_found("/crate/nonexistent/feed.xml").await?;
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn owner_feed() {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.1.0")
+                .add_owner(owner())
+                .create()
+                .await?;
+            env.fake_release()
+                .await
+                .name("other")
+                .version("1.0.0")
+                .add_owner(owner())
+                .create()
+                .await?;
+            env.fake_release()
+                .await
+                .name("not_owned")
+                .version("1.0.0")
+                .create()
+                .await?;
+
+            let web = env.web_app().await;
+            for path in [
+                "/releases/some-owner/feed.xml",
+                "/releases/@some-owner/feed.xml",
+            ] {
+                let response = web.get(path).await?;
+                assert!(response.status().is_success());
+                response.assert_cache_control(
+                    CachePolicy::ForeverInCdn(SurrogateKeys::try_from_iter([
+                        SurrogateKey::from_static("crate-foo"),
+                        SurrogateKey::from_static("crate-other"),
+                    ])?),
+                    env.config(),
+                );
+
+                let mut titles = entry_titles(&response.text().await?);
+                titles.sort();
+                assert_eq!(titles, ["foo 0.1.0 documented", "other 1.0.0 documented"]);
+            }
+
+            web.assert_not_found("/releases/unknown-owner/feed.xml")
+                .await?;
+
+            Ok(())
+        });
+    }
+}
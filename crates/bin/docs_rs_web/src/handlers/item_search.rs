@@ -0,0 +1,320 @@
+use crate::{
+    error::AxumResult,
+    extractors::{
+        DbConnection,
+        rustdoc::{PageKind, RustdocParams},
+    },
+    impl_axum_webpage,
+    page::templates::{RenderBrands, RenderSolid},
+};
+use anyhow::Result;
+use askama::Template;
+use axum::{Json, extract::Query, response::IntoResponse};
+use docs_rs_rustdoc_json::{ItemKind, rustdoc_page_path};
+use docs_rs_types::{KrateName, ReqVersion, Version};
+use docs_rs_uri::EscapedURI;
+use futures_util::TryStreamExt as _;
+use serde::Deserialize;
+
+/// maximum number of items we return for a search.
+const MAX_RESULTS: i64 = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ItemSearchResult {
+    pub(crate) krate: KrateName,
+    pub(crate) version: Version,
+    pub(crate) path: String,
+    pub(crate) kind: String,
+    pub(crate) signature: Option<String>,
+    pub(crate) url: Option<EscapedURI>,
+}
+
+impl ItemSearchResult {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "crate": self.krate,
+            "version": self.version.to_string(),
+            "path": self.path,
+            "kind": self.kind,
+            "signature": self.signature,
+            "url": self.url.as_ref().map(ToString::to_string),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ItemSearchParams {
+    #[serde(default)]
+    q: String,
+}
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Search the public items of the latest release of all crates.
+///
+/// The last `::`-separated segment of the query is matched as a prefix of the item name,
+/// any leading segments have to be whole segments of the item path.
+/// So `AsyncSeek` finds all items starting with `AsyncSeek`, while `io::AsyncSeek`
+/// only finds the ones in an `io` module.
+async fn search_items(conn: &mut sqlx::PgConnection, query: &str) -> Result<Vec<ItemSearchResult>> {
+    let query = query.trim().to_lowercase();
+    let (path_filter, name) = match query.rsplit_once("::") {
+        Some((path, name)) => (format!("%::{}::%", escape_like(path)), name),
+        None => ("%".to_owned(), query.as_str()),
+    };
+
+    if name.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(sqlx::query!(
+        r#"SELECT
+             crates.name as "name: KrateName",
+             releases.version as "version: Version",
+             releases.target_name,
+             release_items.path,
+             release_items.kind,
+             release_items.signature
+         FROM release_items
+         INNER JOIN releases ON releases.id = release_items.release_id
+         INNER JOIN crates ON crates.latest_version_id = releases.id
+         WHERE
+             lower(release_items.name) LIKE $1 || '%' AND
+             lower('::' || release_items.path) LIKE $2
+         ORDER BY
+             lower(release_items.name) = $3 DESC,
+             length(release_items.name),
+             crates.name,
+             release_items.path
+         LIMIT $4"#,
+        escape_like(name),
+        path_filter,
+        name,
+        MAX_RESULTS,
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| {
+        let url = row.kind.parse::<ItemKind>().ok().map(|kind| {
+            RustdocParams::new(row.name.clone())
+                .with_req_version(ReqVersion::Latest)
+                .with_maybe_target_name(row.target_name)
+                .with_page_kind(PageKind::Rustdoc)
+                .with_inner_path(rustdoc_page_path(&row.path, kind))
+                .rustdoc_url()
+        });
+
+        ItemSearchResult {
+            krate: row.name,
+            version: row.version,
+            path: row.path,
+            kind: row.kind,
+            signature: row.signature,
+            url,
+        }
+    })
+    .try_collect()
+    .await?)
+}
+
+#[derive(Template)]
+#[template(path = "releases/item_search.html")]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ItemSearch {
+    pub(crate) query: String,
+    pub(crate) items: Vec<ItemSearchResult>,
+}
+
+impl_axum_webpage! { ItemSearch }
+
+pub(crate) async fn item_search_handler(
+    mut conn: DbConnection,
+    Query(params): Query<ItemSearchParams>,
+) -> AxumResult<impl IntoResponse> {
+    Ok(ItemSearch {
+        items: search_items(&mut conn, &params.q).await?,
+        query: params.q,
+    })
+}
+
+pub(crate) async fn item_search_json_handler(
+    mut conn: DbConnection,
+    Query(params): Query<ItemSearchParams>,
+) -> AxumResult<impl IntoResponse> {
+    let items = search_items(&mut conn, &params.q).await?;
+
+    Ok(Json(serde_json::json!({
+        "items": items.iter().map(ItemSearchResult::to_json).collect::<Vec<_>>(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        AxumResponseTestExt, AxumRouterTestExt, TestEnvironmentExt as _, async_wrapper,
+    };
+    use kuchikiki::traits::TendrilSink;
+    use pretty_assertions::assert_eq;
+
+    async fn insert_items(
+        conn: &mut sqlx::PgConnection,
+        release_id: docs_rs_types::ReleaseId,
+        items: &[(&str, &str, Option<&str>)],
+    ) -> Result<()> {
+        for (path, kind, signature) in items {
+            sqlx::query!(
+                "INSERT INTO release_items (release_id, path, name, kind, signature)
+                 VALUES ($1, $2, $3, $4, $5)",
+                release_id.0,
+                path,
+                path.rsplit("::").next().unwrap(),
+                kind,
+                *signature,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn search_items_json() {
+        async_wrapper(|env| async move {
+            let tokio_id = env
+                .fake_release()
+                .await
+                .name("tokio")
+                .version("1.0.0")
+                .create()
+                .await?;
+            let futures_id = env
+                .fake_release()
+                .await
+                .name("futures-io")
+                .version("0.3.0")
+                .create()
+                .await?;
+
+            let mut conn = env.async_conn().await?;
+            insert_items(
+                &mut conn,
+                tokio_id,
+                &[
+                    ("tokio::io::AsyncSeek", "trait", None),
+                    ("tokio::io::AsyncSeekExt", "trait", None),
+                    (
+                        "tokio::io::copy",
+                        "function",
+                        Some("async fn copy<R, W>(reader: &mut R, writer: &mut W) -> Result<u64>"),
+                    ),
+                ],
+            )
+            .await?;
+            insert_items(
+                &mut conn,
+                futures_id,
+                &[("futures_io::AsyncSeek", "trait", None)],
+            )
+            .await?;
+
+            let web = env.web_app().await;
+
+            let response = web
+                .assert_success("/-/search/items.json?q=asyncseek")
+                .await?;
+            let json: serde_json::Value = response.json().await?;
+            let items = json["items"].as_array().unwrap();
+            assert_eq!(
+                items
+                    .iter()
+                    .map(|item| item["path"].as_str().unwrap())
+                    .collect::<Vec<_>>(),
+                vec![
+                    "futures_io::AsyncSeek",
+                    "tokio::io::AsyncSeek",
+                    "tokio::io::AsyncSeekExt"
+                ]
+            );
+            assert_eq!(
+                items[1],
+                serde_json::json!({
+                    "crate": "tokio",
+                    "version": "1.0.0",
+                    "path": "tokio::io::AsyncSeek",
+                    "kind": "trait",
+                    "signature": null,
+                    "url": "/tokio/latest/tokio/io/trait.AsyncSeek.html",
+                })
+            );
+
+            // leading path segments restrict the search
+            let json: serde_json::Value = web
+                .get("/-/search/items.json?q=io::AsyncSeek")
+                .await?
+                .json()
+                .await?;
+            assert_eq!(json["items"].as_array().unwrap().len(), 2);
+
+            // LIKE wildcards are matched literally
+            let json: serde_json::Value =
+                web.get("/-/search/items.json?q=%25").await?.json().await?;
+            assert!(json["items"].as_array().unwrap().is_empty());
+
+            let json: serde_json::Value = web.get("/-/search/items.json?q=").await?.json().await?;
+            assert!(json["items"].as_array().unwrap().is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn search_items_html() {
+        async_wrapper(|env| async move {
+            let id = env
+                .fake_release()
+                .await
+                .name("tokio")
+                .version("1.0.0")
+                .create()
+                .await?;
+
+            let mut conn = env.async_conn().await?;
+            insert_items(
+                &mut conn,
+                id,
+                &[(
+                    "tokio::io::copy",
+                    "function",
+                    Some("async fn copy<R, W>(reader: &mut R, writer: &mut W) -> Result<u64>"),
+                )],
+            )
+            .await?;
+
+            let web = env.web_app().await;
+            web.assert_success("/-/search/items").await?;
+
+            let response = web.assert_success("/-/search/items?q=copy").await?;
+            let page = kuchikiki::parse_html().one(response.text().await?);
+
+            let links: Vec<_> = page
+                .select("a.release")
+                .unwrap()
+                .map(|link| link.attributes.borrow().get("href").unwrap().to_string())
+                .collect();
+            assert_eq!(links, vec!["/tokio/latest/tokio/io/fn.copy.html"]);
+            assert!(
+                page.select_first("a.release .description")
+                    .unwrap()
+                    .text_contents()
+                    .contains("async fn copy<R, W>")
+            );
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,373 @@
+//! Crate owners can request higher sandbox limits from the builds page.
+//!
+//! Owners identify themselves with a GitHub token, the GitHub user id is then checked
+//! against the crate owners we know from crates.io. Admins review the requests with
+//! `docs_rs_admin database limits approve-request / reject-request`.
+
+use crate::{
+    Config,
+    cache::CachePolicy,
+    error::{AxumNope, AxumResult},
+    extractors::{DbConnection, Path},
+    impl_axum_webpage,
+    page::templates::{RenderBrands, RenderSolid},
+};
+use anyhow::{Context as _, Result, anyhow};
+use askama::Template;
+use axum::{Form, extract::Extension, response::IntoResponse};
+use docs_rs_build_limits::{Overrides, requests};
+use docs_rs_types::{KrateName, Version};
+use docs_rs_uri::EscapedURI;
+use docs_rs_utils::APP_USER_AGENT;
+use http::{StatusCode, header::ACCEPT};
+use serde::Deserialize;
+use std::{
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+const MIB: usize = 1024 * 1024;
+
+static GITHUB_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .user_agent(APP_USER_AGENT)
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("failed to build the GitHub client")
+});
+
+/// The submitted form. All limits are optional, empty fields are sent as empty strings.
+#[derive(Debug, Deserialize)]
+pub(crate) struct LimitsRequestForm {
+    github_token: String,
+    #[serde(default)]
+    memory_mib: String,
+    #[serde(default)]
+    targets: String,
+    #[serde(default)]
+    timeout_minutes: String,
+    #[serde(default)]
+    reason: String,
+}
+
+impl LimitsRequestForm {
+    /// The requested limits, they have to fit into the database columns.
+    fn overrides(&self) -> Result<Overrides> {
+        let too_large = |field: &str| anyhow!("{field} is too large");
+        Ok(Overrides {
+            memory: parse_optional::<usize>("memory", &self.memory_mib)?
+                .map(|mib| {
+                    mib.checked_mul(MIB)
+                        .filter(|bytes| i64::try_from(*bytes).is_ok())
+                        .ok_or_else(|| too_large("memory"))
+                })
+                .transpose()?,
+            targets: parse_optional::<usize>("targets", &self.targets)?
+                .map(|targets| {
+                    i32::try_from(targets)
+                        .map(|_| targets)
+                        .map_err(|_| too_large("targets"))
+                })
+                .transpose()?,
+            timeout: parse_optional::<u64>("timeout", &self.timeout_minutes)?
+                .map(|minutes| {
+                    minutes
+                        .checked_mul(60)
+                        .filter(|seconds| i32::try_from(*seconds).is_ok())
+                        .map(Duration::from_secs)
+                        .ok_or_else(|| too_large("timeout"))
+                })
+                .transpose()?,
+        })
+    }
+}
+
+fn parse_optional<T: FromStr>(field: &str, value: &str) -> Result<Option<T>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| anyhow!("invalid value for {field}: {value:?}"))
+}
+
+#[derive(Template)]
+#[template(path = "crate/limits_request.html")]
+#[derive(Debug, Clone)]
+struct LimitsRequestPage {
+    name: KrateName,
+    request_id: i32,
+    builds_url: EscapedURI,
+}
+
+impl_axum_webpage! {
+    LimitsRequestPage,
+    status = |_| StatusCode::CREATED,
+    cache_policy = |_| CachePolicy::NoCaching,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubUser {
+    id: i64,
+    login: String,
+}
+
+/// Fetch the GitHub user the token belongs to, `None` if GitHub doesn't accept the token.
+async fn github_user(config: &Config, token: &str) -> Result<Option<GithubUser>> {
+    let response = GITHUB_CLIENT
+        .get(config.github_api_host.join("user")?)
+        .bearer_auth(token)
+        .header(ACCEPT, "application/vnd.github+json")
+        .send()
+        .await
+        .context("error fetching the GitHub user")?;
+
+    if matches!(
+        response.status(),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+    ) {
+        return Ok(None);
+    }
+
+    Ok(Some(response.error_for_status()?.json().await?))
+}
+
+async fn is_crate_owner(
+    conn: &mut sqlx::PgConnection,
+    name: &KrateName,
+    github_id: i64,
+) -> Result<bool> {
+    // logins can be renamed and reused, the user id can't. crates.io only exposes the
+    // id through the GitHub avatar URL of the owner.
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1
+            FROM owners
+            INNER JOIN owner_rels ON owner_rels.oid = owners.id
+            INNER JOIN crates ON crates.id = owner_rels.cid
+            WHERE
+                crates.name = $1 AND
+                owners.kind = 'user' AND
+                substring(
+                    owners.avatar FROM '^https://avatars\.githubusercontent\.com/u/(\d+)(?:\?|$)'
+                )::BIGINT = $2
+         ) as "exists!""#,
+        name as _,
+        github_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+pub(crate) async fn limits_request_handler(
+    Path((name, version)): Path<(KrateName, Version)>,
+    mut conn: DbConnection,
+    Extension(config): Extension<Arc<Config>>,
+    Form(form): Form<LimitsRequestForm>,
+) -> AxumResult<impl IntoResponse> {
+    let overrides = form.overrides().map_err(AxumNope::BadRequest)?;
+    if overrides == Overrides::default() {
+        return Err(AxumNope::BadRequest(anyhow!(
+            "the request doesn't contain any limits"
+        )));
+    }
+
+    let token = form.github_token.trim();
+    if token.is_empty() {
+        return Err(AxumNope::Unauthorized("Missing GitHub token"));
+    }
+
+    let user = github_user(&config, token)
+        .await?
+        .ok_or(AxumNope::Unauthorized(
+            "The GitHub token used for authentication is not valid",
+        ))?;
+
+    if !is_crate_owner(&mut conn, &name, user.id).await? {
+        return Err(AxumNope::Unauthorized(
+            "Only owners of the crate can request higher limits",
+        ));
+    }
+
+    let request_id = requests::create(&mut conn, &name, &user.login, overrides, form.reason.trim())
+        .await?
+        .ok_or_else(|| {
+            AxumNope::BadRequest(anyhow!("there is already a pending request for {name}"))
+        })?;
+
+    Ok(LimitsRequestPage {
+        builds_url: EscapedURI::from_path(format!("/crate/{name}/{version}/builds")),
+        name,
+        request_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Config,
+        testing::{TestEnvironment, TestEnvironmentExt as _},
+    };
+    use anyhow::Result;
+    use axum::{body::Body, http::Request};
+    use docs_rs_build_limits::{Overrides, requests};
+    use docs_rs_registry_api::{CrateOwner, OwnerKind};
+    use docs_rs_types::KrateName;
+    use http::StatusCode;
+    use http_body_util::BodyExt as _;
+    use std::time::Duration;
+    use tower::ServiceExt as _;
+
+    async fn env_with_github(github: &mockito::Server) -> Result<TestEnvironment> {
+        let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .github_api_host(github.url().parse()?)
+                    .build(),
+            )
+            .build()
+            .await?;
+
+        env.fake_release()
+            .await
+            .name("foo")
+            .version("0.1.0")
+            .add_owner(CrateOwner {
+                login: "owner".into(),
+                avatar: "https://avatars.githubusercontent.com/u/1?v=4".into(),
+                kind: OwnerKind::User,
+            })
+            .create()
+            .await?;
+
+        Ok(env)
+    }
+
+    async fn submit(env: &TestEnvironment, body: &str) -> Result<(StatusCode, String)> {
+        let response = env
+            .web_app()
+            .await
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/crate/foo/0.1.0/limits-request")
+                    .header("content-type", "application/x-www-form-urlencoded")
+                    .body(Body::from(body.to_owned()))?,
+            )
+            .await?;
+
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        Ok((status, String::from_utf8(body.to_vec())?))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn owner_creates_request() -> Result<()> {
+        let mut github = mockito::Server::new_async().await;
+        let user = github
+            .mock("GET", "/user")
+            .match_header("authorization", "Bearer secret")
+            .with_body(r#"{"login": "owner", "id": 1}"#)
+            .create_async()
+            .await;
+        let env = env_with_github(&github).await?;
+
+        let (status, body) = submit(
+            &env,
+            "github_token=secret&memory_mib=4096&targets=&timeout_minutes=30&reason=big+crate",
+        )
+        .await?;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        user.assert_async().await;
+
+        let mut conn = env.async_conn().await?;
+        let list = requests::list(&mut conn, None).await?;
+        assert_eq!(list.len(), 1);
+        let request = &list[0];
+        assert_eq!(request.crate_name, KrateName::from_static("foo"));
+        assert_eq!(request.requested_by, "owner");
+        assert_eq!(request.reason, "big crate");
+        assert_eq!(
+            request.overrides,
+            Overrides {
+                memory: Some(4096 * 1024 * 1024),
+                targets: None,
+                timeout: Some(Duration::from_secs(30 * 60)),
+            }
+        );
+        assert!(body.contains(&format!("#{}", request.id)));
+
+        // a second request waits for the first one to be reviewed
+        let (status, _) = submit(&env, "github_token=secret&targets=5").await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn only_owners_can_request() -> Result<()> {
+        let mut github = mockito::Server::new_async().await;
+        github
+            .mock("GET", "/user")
+            .match_header("authorization", "Bearer someone-else")
+            .with_body(r#"{"login": "someone-else", "id": 2}"#)
+            .create_async()
+            .await;
+        // the owner renamed their account and someone else took over the login
+        github
+            .mock("GET", "/user")
+            .match_header("authorization", "Bearer new-owner")
+            .with_body(r#"{"login": "owner", "id": 3}"#)
+            .create_async()
+            .await;
+        let env = env_with_github(&github).await?;
+
+        for token in ["someone-else", "new-owner"] {
+            let (status, _) = submit(&env, &format!("github_token={token}&targets=5")).await?;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{token}");
+        }
+
+        let mut conn = env.async_conn().await?;
+        assert!(requests::list(&mut conn, None).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_input() -> Result<()> {
+        let mut github = mockito::Server::new_async().await;
+        github
+            .mock("GET", "/user")
+            .with_status(401)
+            .create_async()
+            .await;
+        let env = env_with_github(&github).await?;
+
+        for (body, expected) in [
+            ("github_token=invalid&targets=5", StatusCode::UNAUTHORIZED),
+            ("github_token=&targets=5", StatusCode::UNAUTHORIZED),
+            ("github_token=secret", StatusCode::BAD_REQUEST),
+            ("github_token=secret&targets=many", StatusCode::BAD_REQUEST),
+            (
+                "github_token=secret&memory_mib=18446744073709551615",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "github_token=secret&targets=2147483648",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "github_token=secret&timeout_minutes=35791395",
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let (status, _) = submit(&env, body).await?;
+            assert_eq!(status, expected, "{body}");
+        }
+
+        Ok(())
+    }
+}
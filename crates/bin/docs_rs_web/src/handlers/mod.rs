@@ -1,15 +1,24 @@
 //! Web interface of docs.rs
 
 pub(crate) mod about;
+pub(crate) mod admin;
+pub(crate) mod api;
+pub(crate) mod api_diff;
 pub(crate) mod build_details;
 pub(crate) mod build_status;
 pub(crate) mod builds;
 pub(crate) mod crate_details;
+pub(crate) mod crates_io_events;
 pub(crate) mod features;
+pub(crate) mod feeds;
+pub(crate) mod item_search;
+pub(crate) mod limits_requests;
 pub(crate) mod releases;
+pub(crate) mod reverse_dependencies;
 pub(crate) mod rustdoc;
 pub(crate) mod sitemap;
 pub(crate) mod source;
+pub(crate) mod source_diff;
 pub(crate) mod statics;
 pub(crate) mod status;
 
@@ -3,6 +3,7 @@
 use crate::{
     Config,
     cache::CachePolicy,
+    config::SearchBackend,
     error::{AxumNope, AxumResult},
     extractors::{DbConnection, Path, rustdoc::RustdocParams},
     handlers::{axum_redirect, rustdoc::OfficialCrateDescription},
@@ -19,7 +20,7 @@ use axum::{
 };
 use base64::{Engine, engine::general_purpose::STANDARD as b64};
 use chrono::{DateTime, Utc};
-use docs_rs_build_queue::{AsyncBuildQueue, PRIORITY_CONTINUOUS, QueuedCrate};
+use docs_rs_build_queue::{AsyncBuildQueue, PRIORITY_CONTINUOUS, QueueLease, QueuedCrate};
 use docs_rs_registry_api::{self as registry_api, RegistryApi};
 use docs_rs_types::{Duration, KrateName, ReqVersion, Version};
 use docs_rs_uri::encode_url_path;
@@ -39,6 +40,8 @@ use url::form_urlencoded;
 const RELEASES_IN_HOME: i64 = 15;
 /// Releases in /releases page
 const RELEASES_IN_RELEASES: i64 = 30;
+/// Maximum page size for the crate search
+const MAX_SEARCH_RESULTS_PER_PAGE: i64 = 100;
 /// Releases in recent releases feed
 const RELEASES_IN_FEED: i64 = 150;
 
@@ -148,14 +151,223 @@ struct SearchResult {
     pub next_page: Option<String>,
 }
 
+/// The query arguments of a crate search, in the format of the crates.io search API.
+enum SearchParams<'a> {
+    Database(DatabaseSearchParams),
+    CratesIo(&'a str),
+}
+
+impl<'a> SearchParams<'a> {
+    /// Depending on the configured [`SearchBackend`], we use the full-text search
+    /// on our database, or delegate to the crates.io search API.
+    fn parse(config: &Config, query_params: &'a str) -> AxumResult<Self> {
+        Ok(match config.search_backend {
+            SearchBackend::Database => Self::Database(DatabaseSearchParams::parse(query_params)?),
+            SearchBackend::CratesIo => Self::CratesIo(query_params),
+        })
+    }
+}
+
+/// The query arguments we support in the full-text search on our database.
+struct DatabaseSearchParams {
+    query: String,
+    sort_by: String,
+    page: i64,
+    per_page: i64,
+}
+
+impl DatabaseSearchParams {
+    fn parse(query_params: &str) -> AxumResult<Self> {
+        let mut params = Self {
+            query: String::new(),
+            sort_by: String::new(),
+            page: 1,
+            per_page: RELEASES_IN_RELEASES,
+        };
+
+        for (k, v) in form_urlencoded::parse(query_params.as_bytes()) {
+            match &*k {
+                "q" => params.query = v.into_owned(),
+                "sort" => params.sort_by = v.into_owned(),
+                "page" => params.page = v.parse().unwrap_or(1).max(1),
+                "per_page" => {
+                    params.per_page = v
+                        .parse()
+                        .unwrap_or(RELEASES_IN_RELEASES)
+                        .clamp(1, MAX_SEARCH_RESULTS_PER_PAGE)
+                }
+                _ => {}
+            }
+        }
+
+        if (params.page - 1).checked_mul(params.per_page).is_none() {
+            return Err(AxumNope::BadRequest(anyhow!(
+                "page {} is too large",
+                params.page
+            )));
+        }
+
+        Ok(params)
+    }
+
+    fn offset(&self) -> i64 {
+        // can't overflow, we check this when parsing.
+        (self.page - 1) * self.per_page
+    }
+}
+
 /// Get the search results for a crate search query
-///
-/// This delegates to the crates.io search API.
 async fn get_search_results(
     conn: &mut sqlx::PgConnection,
     registry: &RegistryApi,
-    query_params: &str,
+    params: &SearchParams<'_>,
     query: &str,
+) -> Result<SearchResult, registry_api::Error> {
+    let mut search_result = match params {
+        SearchParams::Database(params) => get_database_search_results(conn, params).await?,
+        SearchParams::CratesIo(query_params) => {
+            get_crates_io_search_results(conn, registry, query_params).await?
+        }
+    };
+
+    if let Ok(krate) = query.parse::<KrateName>()
+        && let Some(desc) = super::rustdoc::DOC_RUST_LANG_ORG_REDIRECTS.get(&krate)
+    {
+        search_result
+            .results
+            .insert(0, ReleaseStatus::External(desc));
+    }
+
+    Ok(search_result)
+}
+
+/// Full-text search over crate names, and the descriptions & keywords of their
+/// latest release.
+///
+/// Takes the same query arguments as the crates.io search API, and returns
+/// pagination links in the same format.
+async fn get_database_search_results(
+    conn: &mut sqlx::PgConnection,
+    params: &DatabaseSearchParams,
+) -> Result<SearchResult, registry_api::Error> {
+    let DatabaseSearchParams {
+        query,
+        sort_by,
+        page,
+        per_page,
+    } = params;
+    let (page, per_page) = (*page, *per_page);
+
+    // WARNING: it is _crucial_ that this always be hard-coded and NEVER be user input
+    let ordering: &'static str = match sort_by.as_str() {
+        // we don't track recent downloads, so we fall back to all-time downloads.
+        "downloads" | "recent-downloads" => "downloads",
+        "recent-updates" => "recent-updates",
+        "new" => "new",
+        _ => "relevance",
+    };
+
+    let rows = sqlx::query!(
+        r#"SELECT
+               crates.name as "name: KrateName",
+               releases.version as "version: Version",
+               releases.description,
+               release_build_status.last_build_time,
+               release_build_status.build_status = 'in_progress' as "in_progress!",
+               releases.target_name,
+               releases.rustdoc_status,
+               repositories.stars as "stars?",
+               EXISTS (
+                   SELECT 1
+                   FROM releases AS all_releases
+                   WHERE
+                       all_releases.crate_id = crates.id AND
+                       all_releases.yanked = false
+               ) AS has_unyanked_releases
+
+           FROM crates
+           INNER JOIN releases ON crates.latest_version_id = releases.id
+           INNER JOIN release_build_status ON releases.id = release_build_status.rid
+           LEFT JOIN repositories ON releases.repository_id = repositories.id
+
+           WHERE
+               crates.search_vector @@ websearch_to_tsquery('english', $1::TEXT) OR
+               normalize_crate_name(crates.name) = normalize_crate_name($1::TEXT)
+
+           ORDER BY
+               -- exact name matches always come first when sorting by relevance
+               CASE WHEN $2 = 'relevance' THEN
+                   normalize_crate_name(crates.name) = normalize_crate_name($1::TEXT)
+               END DESC NULLS LAST,
+               CASE WHEN $2 = 'downloads' THEN releases.downloads END DESC NULLS LAST,
+               CASE WHEN $2 = 'recent-updates' THEN releases.release_time END DESC NULLS LAST,
+               CASE WHEN $2 = 'new' THEN (
+                   SELECT MIN(all_releases.release_time)
+                   FROM releases AS all_releases
+                   WHERE all_releases.crate_id = crates.id
+               ) END DESC NULLS LAST,
+               ts_rank_cd(crates.search_vector, websearch_to_tsquery('english', $1::TEXT)) DESC,
+               crates.name
+
+           LIMIT $3 OFFSET $4"#,
+        query,
+        ordering,
+        // one more than we show, so we know if there is a next page.
+        per_page + 1,
+        params.offset(),
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|err| anyhow!(err))?;
+
+    let page_link = |page: i64| {
+        format!(
+            "?{}",
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("q", query)
+                .append_pair("sort", sort_by)
+                .append_pair("per_page", &per_page.to_string())
+                .append_pair("page", &page.to_string())
+                .finish()
+        )
+    };
+
+    let next_page = (rows.len() as i64 > per_page).then(|| page_link(page + 1));
+    let prev_page = (page > 1).then(|| page_link(page - 1));
+
+    let results = rows
+        .into_iter()
+        .take(per_page as usize)
+        .map(|row| {
+            if row.in_progress {
+                ReleaseStatus::NotAvailable(row.name)
+            } else {
+                ReleaseStatus::Available(Release {
+                    name: row.name,
+                    version: row.version,
+                    description: row.description,
+                    build_time: row.last_build_time,
+                    target_name: row.target_name,
+                    rustdoc_status: row.rustdoc_status.unwrap_or(false),
+                    stars: row.stars.unwrap_or(0),
+                    has_unyanked_releases: row.has_unyanked_releases,
+                })
+            }
+        })
+        .collect();
+
+    Ok(SearchResult {
+        results,
+        prev_page,
+        next_page,
+    })
+}
+
+/// Get the search results for a crate search query from the crates.io search API.
+async fn get_crates_io_search_results(
+    conn: &mut sqlx::PgConnection,
+    registry: &RegistryApi,
+    query_params: &str,
 ) -> Result<SearchResult, registry_api::Error> {
     let registry_api::Search { crates, meta } = registry.search(query_params).await?;
 
@@ -228,22 +440,18 @@ async fn get_search_results(
     // start with the original names from crates.io to keep the original ranking,
     // extend with the release/build information from docs.rs
     // Crates that are not on docs.rs yet will not be returned.
-    let mut results = Vec::new();
-    if let Ok(krate) = query.parse::<KrateName>()
-        && let Some(desc) = super::rustdoc::DOC_RUST_LANG_ORG_REDIRECTS.get(&krate)
-    {
-        results.push(ReleaseStatus::External(desc));
-    }
-
     let names: Vec<KrateName> =
-        Arc::into_inner(names).expect("Arc still borrowed in `get_search_results`");
-    results.extend(names.into_iter().map(|name| {
-        if let Some(release) = crates.remove(&name) {
-            ReleaseStatus::Available(release)
-        } else {
-            ReleaseStatus::NotAvailable(name)
-        }
-    }));
+        Arc::into_inner(names).expect("Arc still borrowed in `get_crates_io_search_results`");
+    let results = names
+        .into_iter()
+        .map(|name| {
+            if let Some(release) = crates.remove(&name) {
+                ReleaseStatus::Available(release)
+            } else {
+                ReleaseStatus::NotAvailable(name)
+            }
+        })
+        .collect();
 
     Ok(SearchResult {
         results,
@@ -608,7 +816,8 @@ pub(crate) async fn search_handler(
             }
         }
 
-        get_search_results(&mut conn, &registry, query_params, "").await
+        let params = SearchParams::parse(&config, query_params)?;
+        get_search_results(&mut conn, &registry, &params, "").await
     } else if !query.is_empty() {
         let query_params: String = form_urlencoded::Serializer::new(String::new())
             .append_pair("q", &query)
@@ -616,7 +825,8 @@ pub(crate) async fn search_handler(
             .append_pair("per_page", &RELEASES_IN_RELEASES.to_string())
             .finish();
 
-        get_search_results(&mut conn, &registry, &query_params, &query).await
+        let params = SearchParams::parse(&config, &query_params)?;
+        get_search_results(&mut conn, &registry, &params, &query).await
     } else {
         return Err(AxumNope::NoResults);
     };
@@ -732,6 +942,7 @@ struct BuildQueuePage {
     queue: Vec<QueuedCrate>,
     rebuild_queue: Vec<QueuedCrate>,
     in_progress_builds: Vec<InProgressBuild>,
+    leases: Vec<QueueLease>,
     expand_rebuild_queue: bool,
     show_length_warning: bool,
 }
@@ -786,16 +997,20 @@ pub(crate) async fn build_queue_handler(
     .fetch_all(&mut *conn)
     .await?;
 
+    let leases = build_queue.leases().await?;
+
     let mut rebuild_queue = Vec::new();
     let mut queue = build_queue
         .queued_crates()
         .await?
         .into_iter()
         .filter(|krate| {
+            // use `.any` instead of `.contains` to avoid cloning name& version for the match
             !in_progress_builds.iter().any(|in_progress| {
-                // use `.any` instead of `.contains` to avoid cloning name& version for the match
                 in_progress.name == krate.name && in_progress.version == krate.version
-            })
+            }) && !leases
+                .iter()
+                .any(|lease| lease.name == krate.name && lease.version == krate.version)
         })
         .collect::<Vec<_>>();
 
@@ -819,6 +1034,7 @@ pub(crate) async fn build_queue_handler(
         queue,
         rebuild_queue,
         in_progress_builds,
+        leases,
         expand_rebuild_queue: params.expand.is_some(),
         show_length_warning,
     })
@@ -1091,6 +1307,12 @@ mod tests {
         let mut crates_io = mockito::Server::new_async().await;
 
         let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .search_backend(SearchBackend::CratesIo)
+                    .build(),
+            )
             .registry_api_config(
                 docs_rs_registry_api::Config::builder()
                     .registry_api_host(crates_io.url().parse().unwrap())
@@ -1158,6 +1380,12 @@ mod tests {
         let mut crates_io = mockito::Server::new_async().await;
 
         let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .search_backend(SearchBackend::CratesIo)
+                    .build(),
+            )
             .registry_api_config(
                 docs_rs_registry_api::Config::builder()
                     .registry_api_host(crates_io.url().parse().unwrap())
@@ -1256,6 +1484,12 @@ mod tests {
         let mut crates_io = mockito::Server::new_async().await;
 
         let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .search_backend(SearchBackend::CratesIo)
+                    .build(),
+            )
             .registry_api_config(
                 docs_rs_registry_api::Config::builder()
                     .registry_api_host(crates_io.url().parse().unwrap())
@@ -1291,6 +1525,12 @@ mod tests {
         let mut crates_io = mockito::Server::new_async().await;
 
         let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .search_backend(SearchBackend::CratesIo)
+                    .build(),
+            )
             .registry_api_config(
                 docs_rs_registry_api::Config::builder()
                     .registry_api_host(crates_io.url().parse().unwrap())
@@ -1348,6 +1588,12 @@ mod tests {
         let mut crates_io = mockito::Server::new_async().await;
 
         let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .search_backend(SearchBackend::CratesIo)
+                    .build(),
+            )
             .registry_api_config(
                 docs_rs_registry_api::Config::builder()
                     .registry_api_host(crates_io.url().parse().unwrap())
@@ -1405,6 +1651,12 @@ mod tests {
         let mut crates_io = mockito::Server::new_async().await;
 
         let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .search_backend(SearchBackend::CratesIo)
+                    .build(),
+            )
             .registry_api_config(
                 docs_rs_registry_api::Config::builder()
                     .registry_api_host(crates_io.url().parse().unwrap())
@@ -1510,6 +1762,142 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn database_search() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        env.fake_release()
+            .await
+            .name("serde_json")
+            .description("A JSON serialization file format")
+            .keywords(vec!["encoding".into(), "serde".into()])
+            .create()
+            .await?;
+        env.fake_release()
+            .await
+            .name("json")
+            .description("a simple parser")
+            .create()
+            .await?;
+        env.fake_release()
+            .await
+            .name("tokio")
+            .description("An event-driven, non-blocking I/O platform")
+            .keywords(vec!["async".into(), "io".into()])
+            .create()
+            .await?;
+        env.fake_release()
+            .await
+            .name("in_progress")
+            .description("parses json too, but is still building")
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::InProgress)
+                    .rustc_version("rustc (blabla 2022-01-01)")
+                    .docsrs_version("docs.rs 4.0.0"),
+            ])
+            .create()
+            .await?;
+
+        let web = env.web_app().await;
+
+        // exact name matches come first, `in_progress` is in the results, but not linked.
+        assert_eq!(
+            get_release_links("/releases/search?query=json", &web).await?,
+            vec!["/json/latest/json/", "/serde_json/latest/serde_json/"]
+        );
+        // keywords
+        assert_eq!(
+            get_release_links("/releases/search?query=async", &web).await?,
+            vec!["/tokio/latest/tokio/"]
+        );
+        assert_eq!(
+            get_release_links("/releases/search?query=encoding", &web).await?,
+            vec!["/serde_json/latest/serde_json/"]
+        );
+        // descriptions, with stemming
+        assert_eq!(
+            get_release_links("/releases/search?query=serialize", &web).await?,
+            vec!["/serde_json/latest/serde_json/"]
+        );
+        assert!(
+            get_release_links("/releases/search?query=nothing+matches", &web)
+                .await?
+                .is_empty()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn database_search_pagination() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        for name in ["parser_a", "parser_b", "parser_c"] {
+            env.fake_release()
+                .await
+                .name(name)
+                .description("some parser")
+                .create()
+                .await?;
+        }
+
+        let web = env.web_app().await;
+
+        let search_links = |html: String| -> Vec<String> {
+            kuchikiki::parse_html()
+                .one(html)
+                .select("a")
+                .expect("missing link")
+                .map(|el| el.attributes.borrow().get("href").unwrap().to_string())
+                .filter(|url| url.starts_with("/releases/search?"))
+                .collect()
+        };
+
+        let first_page = format!(
+            "/releases/search?paginate={}",
+            b64.encode("?q=parser&sort=relevance&per_page=2&page=1")
+        );
+        let second_page = format!(
+            "/releases/search?paginate={}",
+            b64.encode("?q=parser&sort=relevance&per_page=2&page=2")
+        );
+
+        assert_eq!(
+            get_release_links(&first_page, &web).await?,
+            vec!["/parser_a/latest/parser_a/", "/parser_b/latest/parser_b/"]
+        );
+        assert_eq!(
+            search_links(web.get(&first_page).await?.text().await?),
+            vec![second_page.clone()]
+        );
+
+        assert_eq!(
+            get_release_links(&second_page, &web).await?,
+            vec!["/parser_c/latest/parser_c/"]
+        );
+        assert_eq!(
+            search_links(web.get(&second_page).await?.text().await?),
+            vec![first_page]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn database_search_page_too_large() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let web = env.web_app().await;
+
+        let page = format!(
+            "/releases/search?paginate={}",
+            b64.encode(format!("?q=parser&per_page=100&page={}", i64::MAX))
+        );
+        assert_eq!(web.get(&page).await?.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
     async fn get_release_links(path: &str, web: &axum::Router) -> Result<Vec<String>, Error> {
         let response = web.get(path).await?;
         assert!(response.status().is_success());
@@ -1944,6 +2332,58 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_releases_queue_builder_leases() {
+        async_wrapper(|env| async move {
+            let web = env.web_app().await;
+
+            let queue = env.build_queue()?;
+            queue.add_crate(&FOO, &V1, 0).await?;
+            queue.add_crate(&BAR, &V2, 0).await?;
+
+            let mut conn = env.async_conn().await?;
+            sqlx::query!(
+                "UPDATE queue
+                 SET
+                    leased_by = 'builder-1',
+                    leased_at = NOW(),
+                    lease_expires_at = NOW() + INTERVAL '10 minutes'
+                 WHERE name = 'foo'"
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            let full = kuchikiki::parse_html().one(web.get("/releases/queue").await?.text().await?);
+
+            let leases: Vec<_> = full
+                .select(".builder-leases tbody tr")
+                .expect("missing builder lease rows")
+                .map(|row| {
+                    row.as_node()
+                        .select("td")
+                        .unwrap()
+                        .take(2)
+                        .map(|node| node.text_contents().trim().to_string())
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            assert_eq!(
+                leases,
+                vec![vec!["builder-1".to_string(), format!("foo {V1}")]]
+            );
+
+            // leased releases are not shown as queued
+            let queued_items: Vec<_> = full
+                .select(".queue-list > li > a")
+                .expect("missing queued list items")
+                .map(|node| node.text_contents().trim().to_string())
+                .collect();
+            assert_eq!(queued_items, vec![format!("bar {V2}")]);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_releases_rebuild_queue_empty() {
         async_wrapper(|env| async move {
@@ -2191,6 +2631,12 @@ mod tests {
         let mut crates_io = mockito::Server::new_async().await;
 
         let env = TestEnvironment::builder()
+            .config(
+                Config::builder()
+                    .test_config()?
+                    .search_backend(SearchBackend::CratesIo)
+                    .build(),
+            )
             .registry_api_config(
                 docs_rs_registry_api::Config::builder()
                     .registry_api_host(crates_io.url().parse().unwrap())
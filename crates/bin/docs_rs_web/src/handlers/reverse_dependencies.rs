@@ -0,0 +1,492 @@
+//! Crates that depend on a crate, grouped by their version requirement.
+//!
+//! We only look at the latest release of each dependent, so the list shows the
+//! current dependents, and not every release that ever depended on the crate.
+
+use crate::{
+    cache::CachePolicy,
+    error::{AxumNope, AxumResult},
+    extractors::{DbConnection, Path, rustdoc::RustdocParams},
+    impl_axum_webpage,
+    match_release::match_version,
+    metadata::MetaData,
+    page::templates::{RenderBrands, RenderRegular, RenderSolid, filters},
+};
+use anyhow::{Result, anyhow};
+use askama::Template;
+use axum::{Extension, Json, extract::Query, response::IntoResponse};
+use docs_rs_database::crate_details::Release;
+use docs_rs_types::{KrateName, ReqVersion, Version, VersionReq};
+use docs_rs_uri::EscapedURI;
+use futures_util::TryStreamExt as _;
+use serde::Deserialize;
+
+/// how many dependent crates we show on one page.
+const DEPENDENTS_PER_PAGE: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReverseDependenciesParams {
+    name: KrateName,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PageParams {
+    page: Option<i64>,
+}
+
+impl PageParams {
+    /// the requested page, starting at 1. Pages beyond what we can query are invalid.
+    fn page(&self) -> AxumResult<i64> {
+        let page = self.page.unwrap_or(1).max(1);
+        if (page - 1).checked_mul(DEPENDENTS_PER_PAGE).is_none() {
+            return Err(AxumNope::BadRequest(anyhow!("page {page} is too large")));
+        }
+        Ok(page)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Dependent {
+    name: KrateName,
+    /// the latest release of the dependent.
+    version: Version,
+    /// dependency kinds, like `normal`, `dev` or `build`.
+    kinds: Vec<String>,
+    optional: bool,
+}
+
+impl Dependent {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "version": self.version.to_string(),
+            "kinds": self.kinds,
+            "optional": self.optional,
+        })
+    }
+}
+
+/// Dependents with the same version requirement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DependentGroup {
+    req: String,
+    /// the newest release of the crate matching the requirement.
+    matched_version: Option<Version>,
+    dependents: Vec<Dependent>,
+}
+
+impl DependentGroup {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "req": self.req,
+            "matched_version": self.matched_version.as_ref().map(ToString::to_string),
+            "dependents": self.dependents.iter().map(Dependent::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+struct ReverseDependencies {
+    /// number of crates depending on the crate, on all pages.
+    total: i64,
+    groups: Vec<DependentGroup>,
+    page: i64,
+    has_next_page: bool,
+}
+
+#[derive(Template)]
+#[template(path = "crate/reverse_dependencies.html")]
+#[derive(Debug, Clone)]
+struct ReverseDependenciesPage {
+    metadata: MetaData,
+    total: i64,
+    groups: Vec<DependentGroup>,
+    prev_page: Option<EscapedURI>,
+    next_page: Option<EscapedURI>,
+    params: RustdocParams,
+}
+
+impl_axum_webpage! {
+    ReverseDependenciesPage,
+    cache_policy = |_| CachePolicy::LongerInCdnAndBrowser,
+}
+
+// Used in templates.
+impl ReverseDependenciesPage {
+    pub(crate) fn use_direct_platform_links(&self) -> bool {
+        true
+    }
+}
+
+fn page_url(name: &KrateName, page: i64) -> EscapedURI {
+    EscapedURI::from_path(format!("/crate/{name}/reverse-dependencies"))
+        .append_query_pair("page", page.to_string())
+}
+
+pub(crate) async fn reverse_dependencies_handler(
+    Path(ReverseDependenciesParams { name }): Path<ReverseDependenciesParams>,
+    Query(params): Query<PageParams>,
+    mut conn: DbConnection,
+) -> AxumResult<impl IntoResponse> {
+    let page = params.page()?;
+    let matched_release = match_version(&mut conn, &name, &ReqVersion::Latest)
+        .await?
+        .assume_exact_name()?;
+
+    let reverse_dependencies =
+        load_reverse_dependencies(&mut conn, &name, &matched_release.all_releases, page).await?;
+
+    let params = RustdocParams::new(name.clone())
+        .with_req_version(ReqVersion::Latest)
+        .apply_matched_release(&matched_release);
+    let metadata = MetaData::from_crate(
+        &mut conn,
+        &name,
+        &matched_release.into_version(),
+        Some(ReqVersion::Latest),
+    )
+    .await?;
+
+    let page = reverse_dependencies.page;
+    Ok(ReverseDependenciesPage {
+        metadata,
+        total: reverse_dependencies.total,
+        groups: reverse_dependencies.groups,
+        prev_page: (page > 1).then(|| page_url(&name, page - 1)),
+        next_page: reverse_dependencies
+            .has_next_page
+            .then(|| page_url(&name, page + 1)),
+        params,
+    }
+    .into_response())
+}
+
+pub(crate) async fn reverse_dependencies_json_handler(
+    Path(ReverseDependenciesParams { name }): Path<ReverseDependenciesParams>,
+    Query(params): Query<PageParams>,
+    mut conn: DbConnection,
+) -> AxumResult<impl IntoResponse> {
+    let page = params.page()?;
+    let matched_release = match_version(&mut conn, &name, &ReqVersion::Latest)
+        .await?
+        .assume_exact_name()?;
+
+    let reverse_dependencies =
+        load_reverse_dependencies(&mut conn, &name, &matched_release.all_releases, page).await?;
+
+    Ok((
+        Extension(CachePolicy::LongerInCdnAndBrowser),
+        Json(serde_json::json!({
+            "crate": name,
+            "total": reverse_dependencies.total,
+            "page": reverse_dependencies.page,
+            "per_page": DEPENDENTS_PER_PAGE,
+            "has_next_page": reverse_dependencies.has_next_page,
+            "groups": reverse_dependencies
+                .groups
+                .iter()
+                .map(DependentGroup::to_json)
+                .collect::<Vec<_>>(),
+        })),
+    ))
+}
+
+/// `page` is a valid page from [`PageParams::page`].
+///
+/// We paginate over the dependent crates, a crate can be in multiple groups
+/// when it depends on the crate with different requirements.
+async fn load_reverse_dependencies(
+    conn: &mut sqlx::PgConnection,
+    name: &KrateName,
+    releases: &[Release],
+    page: i64,
+) -> Result<ReverseDependencies> {
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(DISTINCT crates.id) as "count!"
+           FROM release_dependencies
+           INNER JOIN crates ON crates.latest_version_id = release_dependencies.release_id
+           WHERE release_dependencies.name = $1"#,
+        name as _,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let dependents: Vec<(String, Dependent)> = sqlx::query!(
+        r#"WITH dependent_crates AS (
+             SELECT DISTINCT crates.id, crates.name
+             FROM release_dependencies
+             INNER JOIN crates ON crates.latest_version_id = release_dependencies.release_id
+             WHERE release_dependencies.name = $1
+             ORDER BY crates.name, crates.id
+             LIMIT $2 OFFSET $3
+           )
+           SELECT
+             release_dependencies.req,
+             crates.name as "name: KrateName",
+             releases.version as "version: Version",
+             array_agg(DISTINCT release_dependencies.kind) as "kinds!",
+             bool_and(release_dependencies.optional) as "optional!"
+           FROM dependent_crates
+           INNER JOIN crates ON crates.id = dependent_crates.id
+           INNER JOIN release_dependencies ON release_dependencies.release_id = crates.latest_version_id
+           INNER JOIN releases ON releases.id = crates.latest_version_id
+           WHERE release_dependencies.name = $1
+           GROUP BY release_dependencies.req, crates.name, releases.version
+           ORDER BY release_dependencies.req, crates.name"#,
+        name as _,
+        DEPENDENTS_PER_PAGE,
+        (page - 1) * DEPENDENTS_PER_PAGE,
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| {
+        (
+            row.req,
+            Dependent {
+                name: row.name,
+                version: row.version,
+                kinds: row.kinds,
+                optional: row.optional,
+            },
+        )
+    })
+    .try_collect()
+    .await?;
+
+    let has_next_page = page * DEPENDENTS_PER_PAGE < total;
+
+    let mut groups: Vec<DependentGroup> = Vec::new();
+    for (req, dependent) in dependents {
+        match groups.last_mut() {
+            Some(group) if group.req == req => group.dependents.push(dependent),
+            _ => groups.push(DependentGroup {
+                matched_version: newest_matching_version(releases, &req),
+                req,
+                dependents: vec![dependent],
+            }),
+        }
+    }
+
+    Ok(ReverseDependencies {
+        total,
+        groups,
+        page,
+        has_next_page,
+    })
+}
+
+/// the newest release matching the requirement, preferring releases that aren't yanked.
+fn newest_matching_version(releases: &[Release], req: &str) -> Option<Version> {
+    let req: VersionReq = req.parse().ok()?;
+
+    releases
+        .iter()
+        .filter(|release| req.matches(&release.version))
+        .max_by_key(|release| (release.yanked != Some(true), &release.version.0))
+        .map(|release| release.version.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        AxumResponseTestExt as _, AxumRouterTestExt, TestEnvironmentExt as _, async_wrapper,
+    };
+    use docs_rs_cargo_metadata::Dependency;
+    use http::StatusCode;
+    use kuchikiki::traits::TendrilSink;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reverse_dependencies() {
+        async_wrapper(|env| async move {
+            for version in ["0.1.0", "1.0.0", "1.1.0"] {
+                env.fake_release()
+                    .await
+                    .name("foo")
+                    .version(version)
+                    .create()
+                    .await?;
+            }
+
+            for (name, version, req) in [
+                ("bar", "1.0.0", "^0.1"),
+                // only the latest release of a dependent counts
+                ("baz", "0.1.0", "^0.1"),
+                ("baz", "0.2.0", "^1.0"),
+                ("qux", "2.0.0", "^1.0"),
+            ] {
+                env.fake_release()
+                    .await
+                    .name(name)
+                    .version(version)
+                    .add_dependency(Dependency::new("foo".into(), req.parse()?))
+                    .create()
+                    .await?;
+            }
+
+            // dependents of other crates aren't listed
+            env.fake_release()
+                .await
+                .name("other")
+                .version("1.0.0")
+                .add_dependency(Dependency::new("bar".into(), "^1.0".parse()?))
+                .create()
+                .await?;
+
+            let web = env.web_app().await;
+
+            let response = web.get("/crate/foo/reverse-dependencies").await?;
+            response.assert_cache_control(CachePolicy::LongerInCdnAndBrowser, env.config());
+            let page = kuchikiki::parse_html().one(response.text().await?);
+
+            let groups: Vec<(String, Vec<String>)> = page
+                .select(".reverse-dependencies-group")
+                .unwrap()
+                .map(|group| {
+                    (
+                        group
+                            .as_node()
+                            .select_first("h3")
+                            .unwrap()
+                            .text_contents()
+                            .split_whitespace()
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        group
+                            .as_node()
+                            .select("li a")
+                            .unwrap()
+                            .map(|link| link.text_contents().trim().to_owned())
+                            .collect(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                groups,
+                vec![
+                    ("^0.1 matches 0.1.0".into(), vec!["bar 1.0.0".into()]),
+                    (
+                        "^1.0 matches 1.1.0".into(),
+                        vec!["baz 0.2.0".into(), "qux 2.0.0".into()]
+                    ),
+                ]
+            );
+
+            let value: serde_json::Value = serde_json::from_str(
+                &web.assert_success("/crate/foo/reverse-dependencies.json")
+                    .await?
+                    .text()
+                    .await?,
+            )?;
+            assert_eq!(value["total"], 3);
+            assert_eq!(value["has_next_page"], false);
+            assert_eq!(
+                value["groups"][0],
+                serde_json::json!({
+                    "req": "^0.1",
+                    "matched_version": "0.1.0",
+                    "dependents": [{
+                        "name": "bar",
+                        "version": "1.0.0",
+                        "kinds": ["normal"],
+                        "optional": false,
+                    }],
+                })
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reverse_dependencies_pagination() {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("1.0.0")
+                .create()
+                .await?;
+
+            for idx in 0..=DEPENDENTS_PER_PAGE {
+                let mut release = env
+                    .fake_release()
+                    .await
+                    .name(format!("dependent-{idx:03}").as_str())
+                    .version("1.0.0")
+                    .add_dependency(Dependency::new("foo".into(), "^1".parse()?));
+                if idx == 0 {
+                    // a crate with two requirements is still counted once
+                    release =
+                        release.add_dependency(Dependency::new("foo".into(), "^0.1".parse()?));
+                }
+                release.create().await?;
+            }
+
+            let web = env.web_app().await;
+
+            let first: serde_json::Value = serde_json::from_str(
+                &web.assert_success("/crate/foo/reverse-dependencies.json")
+                    .await?
+                    .text()
+                    .await?,
+            )?;
+            assert_eq!(first["total"], DEPENDENTS_PER_PAGE + 1);
+            assert_eq!(first["has_next_page"], true);
+            assert_eq!(first["groups"][0]["req"], "^0.1");
+            assert_eq!(
+                first["groups"][1]["dependents"].as_array().unwrap().len(),
+                DEPENDENTS_PER_PAGE as usize
+            );
+
+            let second: serde_json::Value = serde_json::from_str(
+                &web.assert_success("/crate/foo/reverse-dependencies.json?page=2")
+                    .await?
+                    .text()
+                    .await?,
+            )?;
+            assert_eq!(second["has_next_page"], false);
+            assert_eq!(
+                second["groups"][0]["dependents"],
+                serde_json::json!([{
+                    "name": format!("dependent-{DEPENDENTS_PER_PAGE:03}"),
+                    "version": "1.0.0",
+                    "kinds": ["normal"],
+                    "optional": false,
+                }])
+            );
+
+            for path in [
+                "/crate/foo/reverse-dependencies.json?page=9223372036854775807",
+                "/crate/foo/reverse-dependencies?page=9223372036854775807",
+            ] {
+                assert_eq!(web.get(path).await?.status(), StatusCode::BAD_REQUEST);
+            }
+
+            let page = kuchikiki::parse_html().one(
+                web.assert_success("/crate/foo/reverse-dependencies")
+                    .await?
+                    .text()
+                    .await?,
+            );
+            let next = page.select_first(".pagination a").unwrap();
+            assert_eq!(
+                next.attributes.borrow().get("href").unwrap(),
+                "/crate/foo/reverse-dependencies?page=2"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reverse_dependencies_unknown_crate() {
+        async_wrapper(|env| async move {
+            let web = env.web_app().await;
+            web.assert_not_found("/crate/unknown/reverse-dependencies")
+                .await?;
+            web.assert_not_found("/crate/unknown/reverse-dependencies.json")
+                .await?;
+            Ok(())
+        });
+    }
+}
@@ -5,11 +5,11 @@ use crate::{
     cache::{CachePolicy, STATIC_ASSET_CACHE_POLICY},
     error::{AxumNope, AxumResult},
     extractors::{
-        DbConnection, Path, WantedCompression,
+        AcceptedEncodings, DbConnection, Path, WantedCompression,
         rustdoc::{PageKind, RustdocParams, UrlParams},
     },
     file::StreamingFile,
-    handlers::{axum_cached_redirect, crate_details::CrateDetails},
+    handlers::{axum_cached_redirect, crate_details::CrateDetails, source::latest_build_id},
     match_release::match_version,
     metadata::MetaData,
     metrics::WebMetrics,
@@ -195,6 +195,7 @@ pub(crate) async fn rustdoc_redirector_handler(
     Extension(storage): Extension<Arc<AsyncStorage>>,
     Extension(pool): Extension<Pool>,
     if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    accepted_encodings: AcceptedEncodings,
     RawQuery(original_query): RawQuery,
 ) -> AxumResult<impl IntoResponse> {
     fn redirect_to_doc(
@@ -325,16 +326,17 @@ pub(crate) async fn rustdoc_redirector_handler(
             drop(conn);
 
             match storage
-                .stream_rustdoc_file(
+                .stream_rustdoc_file_encoded(
                     params.name(),
                     &krate.version,
                     krate.latest_build_id,
                     inner_path,
+                    &accepted_encodings,
                 )
                 .await
             {
                 Ok(blob) => Ok(StreamingFile(blob)
-                    .into_response(if_none_match.as_deref(), STATIC_ASSET_CACHE_POLICY)),
+                    .into_encoded_response(if_none_match.as_deref(), STATIC_ASSET_CACHE_POLICY)),
                 Err(err) => {
                     if !matches!(err.downcast_ref(), Some(AxumNope::ResourceNotFound))
                         && !matches!(err.downcast_ref(), Some(PathNotFoundError))
@@ -567,6 +569,7 @@ pub(crate) async fn rustdoc_html_server_handler(
     Extension(csp): Extension<Arc<Csp>>,
     RawQuery(original_query): RawQuery,
     if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    accepted_encodings: AcceptedEncodings,
     mut conn: DbConnection,
 ) -> AxumResult<AxumResponse> {
     let params = params.with_page_kind(PageKind::Rustdoc);
@@ -646,13 +649,20 @@ pub(crate) async fn rustdoc_html_server_handler(
         "try fetching from storage"
     );
 
+    // HTML pages are rewritten, so we need them decompressed.
+    // Other assets are served as they are, and can stay compressed when the client accepts it.
+    let is_html = storage_path.ends_with(".html");
+    let accepted_encodings: &[CompressionAlgorithm] =
+        if is_html { &[] } else { &accepted_encodings };
+
     // Attempt to load the given file from storage.
     let blob = match storage
-        .stream_rustdoc_file(
+        .stream_rustdoc_file_encoded(
             params.name(),
             &krate.version,
             krate.latest_build_id,
             &storage_path,
+            accepted_encodings,
         )
         .await
     {
@@ -738,15 +748,14 @@ pub(crate) async fn rustdoc_html_server_handler(
     };
 
     // Serve non-html files directly
-    if !storage_path.ends_with(".html") {
+    if !is_html {
         trace!(?storage_path, "serve asset");
 
         // default asset caching behaviour is `Cache::ForeverInCdnAndBrowser`.
         // This is an edge-case when we serve invocation specific static assets under `/latest/`:
         // https://github.com/rust-lang/docs.rs/issues/1593
-        return Ok(
-            StreamingFile(blob).into_response(if_none_match.as_deref(), STATIC_ASSET_CACHE_POLICY)
-        );
+        return Ok(StreamingFile(blob)
+            .into_encoded_response(if_none_match.as_deref(), STATIC_ASSET_CACHE_POLICY));
     }
 
     let latest_release = krate.latest_release();
@@ -1043,16 +1052,23 @@ pub(crate) async fn download_handler(
             )
         })?;
 
+    let version = &matched_release.release.version;
+    let latest_build_id = latest_build_id(&mut conn, &matched_release.name, version).await?;
+
     // NOTE: we want to give back the db connection to the pool
     // before we do the long S3 requests.
     drop(conn);
 
     params = params.apply_matched_release(&matched_release);
 
-    let version = &matched_release.release.version;
     let archive_path = rustdoc_archive_path(params.name(), version);
+    // repacked archives are in another object, and content-addressed ones don't have
+    // a ZIP archive, see `AsyncStorage::stream_archive`.
+    let archive = storage
+        .stream_archive(&archive_path, latest_build_id)
+        .await?;
 
-    let mut response = StreamingFile(storage.get_raw_stream(&archive_path).await?).into_response(
+    let mut response = StreamingFile(archive).into_response(
         if_none_match.as_deref(),
         CachePolicy::ForeverInCdn(matched_release.name.into()),
     );
@@ -1094,13 +1110,13 @@ mod test {
     use docs_rs_rustdoc_json::{
         RUSTDOC_JSON_COMPRESSION_ALGORITHMS, read_format_version_from_rustdoc_json,
     };
-    use docs_rs_storage::{decompress, testing::check_archive_consistency};
+    use docs_rs_storage::{StorageKind, decompress, testing::check_archive_consistency};
     use docs_rs_types::{
         Version,
         testing::{KRATE, V2},
     };
     use docs_rs_uri::encode_url_path;
-    use http::header::CONTENT_LENGTH;
+    use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, ETAG, VARY};
     use kuchikiki::traits::TendrilSink;
     use pretty_assertions::assert_eq;
     use reqwest::StatusCode;
@@ -3119,6 +3135,38 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn download_content_addressed() -> Result<()> {
+        let env = TestEnvironment::builder()
+            .storage_config(
+                docs_rs_storage::Config::test_config_with_kind(StorageKind::Memory)?.set(
+                    |mut cfg| {
+                        cfg.content_addressed_storage = true;
+                        cfg
+                    },
+                ),
+            )
+            .build()
+            .await?;
+
+        env.fake_release()
+            .await
+            .name("dummy")
+            .version("0.1.0")
+            .create()
+            .await?;
+
+        let web = env.web_app().await;
+        let path = "/crate/dummy/0.1.0/download";
+
+        let resp = web.assert_success(path).await?;
+        web.assert_conditional_get(path, &resp).await?;
+
+        check_archive_consistency(&web.assert_success(path).await?.bytes().await?)?;
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn download_latest_version() -> Result<()> {
         let env = TestEnvironment::new().await?;
@@ -3188,6 +3236,54 @@ mod test {
         })
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn serve_assets_with_stored_content_encoding() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let content = "some asset content ".repeat(100);
+        env.fake_release()
+            .await
+            .name("dummy")
+            .version("0.1.0")
+            .rustdoc_file_with("something.js", content.as_bytes())
+            .rustdoc_file_with("dummy/image.svg", content.as_bytes())
+            .create()
+            .await?;
+
+        let web = env.web_app().await;
+        let accept_zstd = |headers: &mut http::HeaderMap| {
+            headers.insert(ACCEPT_ENCODING, "gzip, deflate, br, zstd".parse().unwrap());
+        };
+
+        for path in ["/dummy/0.1.0/something.js", "/dummy/0.1.0/dummy/image.svg"] {
+            let resp = web.get_with_headers(path, accept_zstd).await?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers().get(CONTENT_ENCODING).unwrap(), "zstd");
+            assert_eq!(resp.headers().get(VARY).unwrap(), "Accept-Encoding");
+            let etag = resp.headers().get(ETAG).cloned();
+            let body = resp.bytes().await?;
+            assert_eq!(
+                decompress(&*body, CompressionAlgorithm::Zstd, usize::MAX)?,
+                content.as_bytes()
+            );
+
+            // without zstd support we decompress
+            let resp = web.assert_success(path).await?;
+            assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+            assert_eq!(resp.headers().get(VARY).unwrap(), "Accept-Encoding");
+            assert_ne!(resp.headers().get(ETAG).cloned(), etag);
+            assert_eq!(resp.text().await?, content);
+        }
+
+        // rustdoc pages are rewritten, so never passed through
+        let resp = web
+            .get_with_headers("/dummy/0.1.0/dummy/index.html", accept_zstd)
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[test_case("folder/file.js")]
     #[test_case("root.css")]
@@ -3,10 +3,11 @@ use crate::{
     cache::{CachePolicy, STATIC_ASSET_CACHE_POLICY},
     error::{AxumNope, AxumResult},
     extractors::{
-        DbConnection,
+        DbConnection, Path,
         rustdoc::{PageKind, RustdocParams},
     },
     file::StreamingFile,
+    handlers::source_diff,
     impl_axum_webpage,
     match_release::match_version,
     metadata::MetaData,
@@ -15,12 +16,18 @@ use crate::{
 
 use anyhow::{Context as _, Result};
 use askama::Template;
-use axum::{Extension, response::IntoResponse};
+use axum::{
+    Extension,
+    extract::Request as AxumRequest,
+    handler::Handler as _,
+    response::{IntoResponse, Response as AxumResponse},
+};
 use axum_extra::{TypedHeader, headers::HeaderMapExt};
 use docs_rs_headers::{CanonicalUrl, IfNoneMatch};
 use docs_rs_storage::{AsyncStorage, FolderEntry, PathNotFoundError, source_archive_path};
 use docs_rs_types::{BuildId, KrateName, ReqVersion, Version};
 use futures_util::TryStreamExt as _;
+use serde::Deserialize;
 use std::sync::Arc;
 use tracing::instrument;
 
@@ -89,6 +96,53 @@ impl SourcePage {
     }
 }
 
+/// The latest successful build of a release, which the source archive belongs to.
+pub(crate) async fn latest_build_id(
+    conn: &mut sqlx::PgConnection,
+    name: &KrateName,
+    version: &Version,
+) -> Result<Option<BuildId>> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT
+            (
+                SELECT id
+                FROM builds
+                WHERE
+                    builds.rid = releases.id AND
+                    builds.build_status = 'success'
+                ORDER BY build_finished DESC
+                LIMIT 1
+            ) AS "latest_build_id?: BuildId"
+         FROM releases
+         INNER JOIN crates ON releases.crate_id = crates.id
+         WHERE
+             name = $1 AND
+             version = $2"#,
+        name as _,
+        version as _,
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SourceVersionParam {
+    version: String,
+}
+
+/// Shows the source of a release, or with a `{version}` like `1.0.0...1.1.0` the
+/// changes between the source of two releases.
+pub(crate) async fn source_handler(
+    Path(SourceVersionParam { version }): Path<SourceVersionParam>,
+    request: AxumRequest,
+) -> AxumResponse {
+    if source_diff::split_version_range(&version).is_some() {
+        source_diff::source_diff_handler.call(request, ()).await
+    } else {
+        source_browser_handler.call(request, ()).await
+    }
+}
+
 #[instrument(skip(conn, storage))]
 pub(crate) async fn source_browser_handler(
     params: RustdocParams,
@@ -123,27 +177,7 @@ pub(crate) async fn source_browser_handler(
     let params = params.apply_matched_release(&matched_release);
     let version = &matched_release.release.version;
 
-    let row = sqlx::query!(
-        r#"SELECT
-            (
-                SELECT id
-                FROM builds
-                WHERE
-                    builds.rid = releases.id AND
-                    builds.build_status = 'success'
-                ORDER BY build_finished DESC
-                LIMIT 1
-            ) AS "latest_build_id?: BuildId"
-         FROM releases
-         INNER JOIN crates ON releases.crate_id = crates.id
-         WHERE
-             name = $1 AND
-             version = $2"#,
-        params.name() as _,
-        version as _,
-    )
-    .fetch_one(&mut *conn)
-    .await?;
+    let latest_build_id = latest_build_id(&mut conn, params.name(), version).await?;
 
     let inner_path = params.inner_path();
 
@@ -157,7 +191,7 @@ pub(crate) async fn source_browser_handler(
         &storage,
         params.name(),
         version,
-        row.latest_build_id,
+        latest_build_id,
         current_folder,
     )
     .await?;
@@ -178,7 +212,7 @@ pub(crate) async fn source_browser_handler(
     // skip if request is a directory
     let stream = if !params.path_is_folder() {
         match storage
-            .stream_source_file(params.name(), version, row.latest_build_id, inner_path)
+            .stream_source_file(params.name(), version, latest_build_id, inner_path)
             .await
             .context("error fetching source file")
         {
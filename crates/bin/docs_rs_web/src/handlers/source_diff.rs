@@ -0,0 +1,710 @@
+//! Compare the source of two releases of a crate.
+//!
+//! `/crate/{name}/{from}...{to}/source/` lists the files that were added, removed or
+//! changed between two releases, and `/crate/{name}/{from}...{to}/source/{path}` shows
+//! a unified diff of a single file.
+
+use crate::{
+    cache::CachePolicy,
+    error::{AxumNope, AxumResult},
+    extractors::{
+        DbConnection, Path,
+        rustdoc::{PageKind, RustdocParams},
+    },
+    handlers::source::latest_build_id,
+    impl_axum_webpage,
+    match_release::match_version,
+    metadata::MetaData,
+    page::templates::{RenderBrands, RenderRegular, RenderSolid, filters},
+    utils::highlight,
+};
+use anyhow::{Context as _, Result};
+use askama::Template;
+use axum::{Extension, response::IntoResponse};
+use docs_rs_storage::{
+    AsyncStorage, FileInfo, PathNotFoundError, SizeLimitReached, source_archive_path,
+};
+use docs_rs_types::{BuildId, KrateName, ReqVersion, Version};
+use docs_rs_uri::EscapedURI;
+use docs_rs_utils::spawn_blocking;
+use futures_util::{StreamExt as _, TryStreamExt as _, stream};
+use serde::Deserialize;
+use similar::{ChangeTag, TextDiff};
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::instrument;
+
+/// how many lines of context we show around changes.
+const CONTEXT_LINES: usize = 3;
+
+/// how many files we fetch concurrently when we have to compare their content.
+const CONCURRENT_COMPARISONS: usize = 16;
+
+/// how many bytes we fetch at most to compare files by their content, the files
+/// over this budget are shown as changed.
+const MAX_COMPARED_BYTES: u64 = 50 * 1024 * 1024;
+
+/// files we hide in the source browser too.
+const IGNORED_FILES: &[&str] = &[".cargo-ok"];
+
+/// Split a `{version}` path parameter like `1.0.0...1.1.0` into the two versions.
+pub(crate) fn split_version_range(version: &str) -> Option<(&str, &str)> {
+    version.split_once("...")
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SourceDiffParams {
+    name: KrateName,
+    version: String,
+    path: Option<String>,
+}
+
+/// A release we compare, and the build we take the source archive from.
+#[derive(Debug, Clone)]
+struct ReleaseSource {
+    req_version: ReqVersion,
+    version: Version,
+    latest_build_id: Option<BuildId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileChange {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl FileChange {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Changed => "changed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ChangedFile {
+    path: String,
+    change: FileChange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DiffLineKind {
+    Context,
+    Added,
+    Removed,
+}
+
+impl DiffLineKind {
+    pub(crate) fn css_class(&self) -> &'static str {
+        match self {
+            Self::Context => "diff-context",
+            Self::Added => "diff-added",
+            Self::Removed => "diff-removed",
+        }
+    }
+
+    pub(crate) fn marker(&self) -> &'static str {
+        match self {
+            Self::Context => " ",
+            Self::Added => "+",
+            Self::Removed => "-",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DiffLine {
+    kind: DiffLineKind,
+    old_number: Option<usize>,
+    new_number: Option<usize>,
+    /// highlighted HTML of the line.
+    html: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DiffHunk {
+    header: String,
+    lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum FileDiffContent {
+    Hunks(Vec<DiffHunk>),
+    Unchanged,
+    Binary,
+    TooLarge,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FileDiff {
+    path: String,
+    content: FileDiffContent,
+}
+
+#[derive(Template)]
+#[template(path = "crate/source_diff.html")]
+#[derive(Debug, Clone)]
+struct SourceDiffPage {
+    metadata: MetaData,
+    from: ReqVersion,
+    to: ReqVersion,
+    from_version: Version,
+    to_version: Version,
+    /// the changed files, when we show the file list.
+    files: Vec<ChangedFile>,
+    /// the diff of a single file.
+    file: Option<FileDiff>,
+    is_latest_url: bool,
+    params: RustdocParams,
+}
+
+impl_axum_webpage! {
+    SourceDiffPage,
+    cache_policy = |page| if page.is_latest_url {
+        CachePolicy::ForeverInCdn(page.metadata.name.clone().into())
+    } else {
+        CachePolicy::ForeverInCdnAndStaleInBrowser(page.metadata.name.clone().into())
+    },
+    cpu_intensive_rendering = true,
+}
+
+// Used in templates.
+impl SourceDiffPage {
+    pub(crate) fn use_direct_platform_links(&self) -> bool {
+        true
+    }
+
+    pub(crate) fn api_diff_url(&self) -> EscapedURI {
+        EscapedURI::from_path(format!(
+            "/crate/{}/{}...{}/api-diff",
+            self.metadata.name, self.from, self.to
+        ))
+    }
+
+    pub(crate) fn diff_url(&self, path: &str) -> EscapedURI {
+        EscapedURI::from_path(format!(
+            "/crate/{}/{}...{}/source/{}",
+            self.metadata.name, self.from, self.to, path
+        ))
+    }
+}
+
+#[instrument(skip(conn, storage))]
+pub(crate) async fn source_diff_handler(
+    Path(params): Path<SourceDiffParams>,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+    mut conn: DbConnection,
+) -> AxumResult<impl IntoResponse> {
+    let (from, to) = split_version_range(&params.version).ok_or(AxumNope::VersionNotFound)?;
+    let from: ReqVersion = from.parse().map_err(|_| AxumNope::VersionNotFound)?;
+    let to: ReqVersion = to.parse().map_err(|_| AxumNope::VersionNotFound)?;
+
+    let from = resolve_release(&mut conn, &params.name, from).await?;
+    let to = resolve_release(&mut conn, &params.name, to).await?;
+
+    let metadata = MetaData::from_crate(
+        &mut conn,
+        &params.name,
+        &to.version,
+        Some(to.req_version.clone()),
+    )
+    .await?;
+
+    // NOTE: we want to give back the db connection to the pool
+    // before we do the long S3 requests.
+    drop(conn);
+
+    let path = params.path.unwrap_or_default();
+    let (files, file) = if path.is_empty() || path.ends_with('/') {
+        (
+            list_changed_files(&storage, &params.name, &from, &to).await?,
+            None,
+        )
+    } else {
+        (
+            Vec::new(),
+            Some(diff_file(&storage, &params.name, &from, &to, &path).await?),
+        )
+    };
+
+    Ok(SourceDiffPage {
+        metadata,
+        is_latest_url: from.req_version.is_latest() || to.req_version.is_latest(),
+        params: RustdocParams::new(params.name)
+            .with_req_version(to.req_version.clone())
+            .with_page_kind(PageKind::Source),
+        from: from.req_version,
+        to: to.req_version,
+        from_version: from.version,
+        to_version: to.version,
+        files,
+        file,
+    }
+    .into_response())
+}
+
+async fn resolve_release(
+    conn: &mut sqlx::PgConnection,
+    name: &KrateName,
+    req_version: ReqVersion,
+) -> AxumResult<ReleaseSource> {
+    let version = match_version(&mut *conn, name, &req_version)
+        .await?
+        .assume_exact_name()?
+        .into_version();
+    let latest_build_id = latest_build_id(&mut *conn, name, &version).await?;
+
+    Ok(ReleaseSource {
+        req_version,
+        version,
+        latest_build_id,
+    })
+}
+
+async fn archive_files(
+    storage: &AsyncStorage,
+    name: &KrateName,
+    release: &ReleaseSource,
+) -> Result<BTreeMap<String, FileInfo>> {
+    let mut index = storage
+        .find_archive_index(
+            &source_archive_path(name, &release.version),
+            release.latest_build_id,
+        )
+        .await?;
+
+    index
+        .list()
+        .map_ok(|file| (file.path().to_string_lossy().into_owned(), file))
+        .try_filter(|(path, _)| std::future::ready(!IGNORED_FILES.contains(&path.as_str())))
+        .try_collect()
+        .await
+}
+
+/// List the files that were added, removed or changed between two releases.
+///
+/// Files are compared by the CRC-32 and size in the archive indexes. Older archive
+/// indexes don't have these, then we compare the file content, up to [`MAX_COMPARED_BYTES`].
+async fn list_changed_files(
+    storage: &AsyncStorage,
+    name: &KrateName,
+    from: &ReleaseSource,
+    to: &ReleaseSource,
+) -> Result<Vec<ChangedFile>> {
+    let old_files = archive_files(storage, name, from).await?;
+    let new_files = archive_files(storage, name, to).await?;
+
+    let mut changes = Vec::new();
+    let mut to_compare = Vec::new();
+
+    for (path, old) in &old_files {
+        match new_files.get(path) {
+            None => changes.push(ChangedFile {
+                path: path.clone(),
+                change: FileChange::Removed,
+            }),
+            Some(new) => match old.same_content_as(new) {
+                Some(true) => {}
+                Some(false) => changes.push(ChangedFile {
+                    path: path.clone(),
+                    change: FileChange::Changed,
+                }),
+                None => to_compare.push((path.clone(), old.stored_size() + new.stored_size())),
+            },
+        }
+    }
+
+    changes.extend(
+        new_files
+            .keys()
+            .filter(|path| !old_files.contains_key(*path))
+            .map(|path| ChangedFile {
+                path: path.clone(),
+                change: FileChange::Added,
+            }),
+    );
+
+    let mut budget = MAX_COMPARED_BYTES;
+    let (to_compare, too_many_bytes): (Vec<_>, Vec<_>) =
+        to_compare.into_iter().partition(|(_, size)| {
+            let fits = *size <= budget;
+            if fits {
+                budget -= size;
+            }
+            fits
+        });
+    changes.extend(too_many_bytes.into_iter().map(|(path, _)| ChangedFile {
+        path,
+        change: FileChange::Changed,
+    }));
+
+    let compared: Vec<_> = stream::iter(to_compare)
+        .map(|(path, _)| async move {
+            let old = fetch_source_file(storage, name, from, &path).await?;
+            let new = fetch_source_file(storage, name, to, &path).await?;
+            let changed = match (old, new) {
+                (SourceFile::Content(old), SourceFile::Content(new)) => old.content != new.content,
+                // we can't tell, so we show the file as changed
+                _ => true,
+            };
+            anyhow::Ok(changed.then_some(ChangedFile {
+                path,
+                change: FileChange::Changed,
+            }))
+        })
+        .buffer_unordered(CONCURRENT_COMPARISONS)
+        .try_collect()
+        .await?;
+    changes.extend(compared.into_iter().flatten());
+
+    changes.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+struct SourceContent {
+    content: Vec<u8>,
+    is_text: bool,
+}
+
+enum SourceFile {
+    Missing,
+    TooLarge,
+    Content(SourceContent),
+}
+
+async fn fetch_source_file(
+    storage: &AsyncStorage,
+    name: &KrateName,
+    release: &ReleaseSource,
+    path: &str,
+) -> Result<SourceFile> {
+    let stream = match storage
+        .stream_source_file(name, &release.version, release.latest_build_id, path)
+        .await
+        .context("error fetching source file")
+    {
+        Ok(stream) => stream,
+        Err(err) if err.is::<PathNotFoundError>() => return Ok(SourceFile::Missing),
+        Err(err) => return Err(err),
+    };
+
+    let is_text = stream.mime.type_() == mime::TEXT || stream.mime == mime::APPLICATION_JSON;
+    let max_file_size = storage.config().max_file_size_for(&stream.path);
+
+    match stream.materialize(max_file_size).await {
+        Ok(blob) => Ok(SourceFile::Content(SourceContent {
+            content: blob.content,
+            is_text,
+        })),
+        Err(err)
+            if err.downcast_ref::<std::io::Error>().is_some_and(|err| {
+                err.get_ref()
+                    .is_some_and(|err| err.is::<SizeLimitReached>())
+            }) =>
+        {
+            Ok(SourceFile::TooLarge)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn diff_file(
+    storage: &AsyncStorage,
+    name: &KrateName,
+    from: &ReleaseSource,
+    to: &ReleaseSource,
+    path: &str,
+) -> AxumResult<FileDiff> {
+    let old = fetch_source_file(storage, name, from, path).await?;
+    let new = fetch_source_file(storage, name, to, path).await?;
+
+    let text = |file: &SourceFile| match file {
+        SourceFile::Missing => Some(String::new()),
+        SourceFile::Content(SourceContent {
+            content,
+            is_text: true,
+        }) => Some(String::from_utf8_lossy(content).into_owned()),
+        _ => None,
+    };
+
+    let content = match (&old, &new) {
+        (SourceFile::Missing, SourceFile::Missing) => return Err(AxumNope::ResourceNotFound),
+        (SourceFile::TooLarge, _) | (_, SourceFile::TooLarge) => FileDiffContent::TooLarge,
+        _ => match (text(&old), text(&new)) {
+            (Some(old), Some(new)) if old == new => FileDiffContent::Unchanged,
+            (Some(old), Some(new)) => {
+                let file_name = path
+                    .rsplit_once('/')
+                    .map_or(path, |(_, name)| name)
+                    .to_owned();
+                FileDiffContent::Hunks(
+                    spawn_blocking(move || Ok(diff_hunks(&file_name, &old, &new))).await?,
+                )
+            }
+            _ => FileDiffContent::Binary,
+        },
+    };
+
+    Ok(FileDiff {
+        path: path.to_owned(),
+        content,
+    })
+}
+
+/// Unified diff of two versions of a file, with syntax highlighting.
+fn diff_hunks(file_name: &str, old: &str, new: &str) -> Vec<DiffHunk> {
+    let old_lines = highlight::lines_with_lang(Some(file_name), old);
+    let new_lines = highlight::lines_with_lang(Some(file_name), new);
+    let line_html = |lines: &[String], index: Option<usize>| {
+        index
+            .and_then(|index| lines.get(index))
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    let diff = TextDiff::from_lines(old, new);
+    let mut unified_diff = diff.unified_diff();
+    unified_diff.context_radius(CONTEXT_LINES);
+
+    unified_diff
+        .iter_hunks()
+        .map(|hunk| DiffHunk {
+            header: hunk.header().to_string(),
+            lines: hunk
+                .iter_changes()
+                .map(|change| {
+                    let (kind, html) = match change.tag() {
+                        ChangeTag::Equal => (
+                            DiffLineKind::Context,
+                            line_html(&old_lines, change.old_index()),
+                        ),
+                        ChangeTag::Delete => (
+                            DiffLineKind::Removed,
+                            line_html(&old_lines, change.old_index()),
+                        ),
+                        ChangeTag::Insert => (
+                            DiffLineKind::Added,
+                            line_html(&new_lines, change.new_index()),
+                        ),
+                    };
+                    DiffLine {
+                        kind,
+                        old_number: change.old_index().map(|index| index + 1),
+                        new_number: change.new_index().map(|index| index + 1),
+                        html,
+                    }
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        AxumResponseTestExt as _, AxumRouterTestExt, TestEnvironmentExt as _, async_wrapper,
+    };
+    use kuchikiki::traits::TendrilSink;
+    use pretty_assertions::assert_eq;
+
+    fn summary(hunks: &[DiffHunk]) -> Vec<String> {
+        hunks
+            .iter()
+            .flat_map(|hunk| {
+                std::iter::once(hunk.header.clone()).chain(hunk.lines.iter().map(|line| {
+                    format!(
+                        "{:>2} {:>2} {}",
+                        line.old_number.map(|n| n.to_string()).unwrap_or_default(),
+                        line.new_number.map(|n| n.to_string()).unwrap_or_default(),
+                        line.kind.marker(),
+                    )
+                }))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_hunks_with_context() {
+        let old: String = (1..=10).map(|n| format!("line {n}\n")).collect();
+        let new = old.replace("line 5\n", "line five\n");
+
+        let hunks = diff_hunks("file.txt", &old, &new);
+        assert_eq!(
+            summary(&hunks),
+            vec![
+                "@@ -2,7 +2,7 @@",
+                " 2  2  ",
+                " 3  3  ",
+                " 4  4  ",
+                " 5    -",
+                "    5 +",
+                " 6  6  ",
+                " 7  7  ",
+                " 8  8  ",
+            ]
+        );
+        assert!(hunks[0].lines[4].html.contains("line five"));
+    }
+
+    #[test]
+    fn diff_hunks_added_file() {
+        let hunks = diff_hunks("lib.rs", "", "fn main() {}\n");
+        assert_eq!(summary(&hunks), vec!["@@ -0,0 +1 @@", "    1 +"]);
+        // highlighted as rust
+        assert!(hunks[0].lines[0].html.contains("syntax-"));
+    }
+
+    #[test]
+    fn list_changed_files() {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.1.0")
+                .source_file("README.md", b"hello")
+                .source_file("src/lib.rs", b"fn old() {}\n")
+                .source_file("src/removed.rs", b"")
+                .create()
+                .await?;
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.2.0")
+                .source_file("README.md", b"hello")
+                .source_file("src/lib.rs", b"fn new() {}\n")
+                .source_file("src/added.rs", b"")
+                .create()
+                .await?;
+
+            let web = env.web_app().await;
+            let response = web.get("/crate/foo/0.1.0...0.2.0/source/").await?;
+            response.assert_cache_control(
+                CachePolicy::ForeverInCdnAndStaleInBrowser(KrateName::from_static("foo").into()),
+                env.config(),
+            );
+            let page = kuchikiki::parse_html().one(response.text().await?);
+
+            let files: Vec<_> = page
+                .select(".diff-files li")
+                .unwrap()
+                .map(|li| {
+                    let link = li.as_node().select_first("a").unwrap();
+                    (
+                        li.attributes.borrow().get("class").unwrap().to_owned(),
+                        link.text_contents(),
+                        link.attributes.borrow().get("href").unwrap().to_owned(),
+                    )
+                })
+                .collect();
+
+            assert_eq!(
+                files,
+                vec![
+                    (
+                        "diff-file-changed".into(),
+                        "Cargo.toml".into(),
+                        "/crate/foo/0.1.0...0.2.0/source/Cargo.toml".into()
+                    ),
+                    (
+                        "diff-file-added".into(),
+                        "src/added.rs".into(),
+                        "/crate/foo/0.1.0...0.2.0/source/src/added.rs".into()
+                    ),
+                    (
+                        "diff-file-changed".into(),
+                        "src/lib.rs".into(),
+                        "/crate/foo/0.1.0...0.2.0/source/src/lib.rs".into()
+                    ),
+                    (
+                        "diff-file-removed".into(),
+                        "src/removed.rs".into(),
+                        "/crate/foo/0.1.0...0.2.0/source/src/removed.rs".into()
+                    ),
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn diff_single_file() {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.1.0")
+                .source_file("src/lib.rs", b"fn old() {}\n")
+                .source_file("image.png", b"\x89PNG old")
+                .create()
+                .await?;
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.2.0")
+                .source_file("src/lib.rs", b"fn new() {}\n")
+                .source_file("image.png", b"\x89PNG new")
+                .create()
+                .await?;
+
+            let web = env.web_app().await;
+            let page = kuchikiki::parse_html().one(
+                web.assert_success("/crate/foo/0.1.0...0.2.0/source/src/lib.rs")
+                    .await?
+                    .text()
+                    .await?,
+            );
+
+            let lines: Vec<_> = page
+                .select(".diff tr:not(.diff-hunk-header)")
+                .unwrap()
+                .map(|row| {
+                    let cells: Vec<_> = row
+                        .as_node()
+                        .select("td")
+                        .unwrap()
+                        .map(|cell| cell.text_contents())
+                        .collect();
+                    (
+                        row.attributes.borrow().get("class").unwrap().to_owned(),
+                        cells.join("|"),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                lines,
+                vec![
+                    ("diff-removed".into(), "1||-fn old() {}".into()),
+                    ("diff-added".into(), "|1|+fn new() {}".into()),
+                ]
+            );
+
+            let binary = web
+                .assert_success("/crate/foo/0.1.0...0.2.0/source/image.png")
+                .await?
+                .text()
+                .await?;
+            assert!(binary.contains("This is a binary file"));
+
+            assert_eq!(
+                web.get("/crate/foo/0.1.0...0.2.0/source/missing.rs")
+                    .await?
+                    .status(),
+                404
+            );
+            assert_eq!(
+                web.get("/crate/foo/0.1.0...0.9.0/source/").await?.status(),
+                404
+            );
+
+            // the normal source browser still works
+            web.assert_success("/crate/foo/0.2.0/source/src/lib.rs")
+                .await?;
+
+            Ok(())
+        });
+    }
+}
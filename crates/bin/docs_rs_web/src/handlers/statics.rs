@@ -339,6 +339,7 @@ mod tests {
     #[test_case("/-/static/menu.js", "closeMenu")]
     #[test_case("/-/static/keyboard.js", "handleKey")]
     #[test_case("/-/static/source.js", "toggleSource")]
+    #[test_case("/-/static/build-events.js", "followBuildEvents")]
     fn js_content(path: &str, expected_content: &str) {
         async_wrapper(|env| async move {
             let web = env.web_app().await;
@@ -22,7 +22,7 @@ mod routes;
 pub(crate) mod testing;
 mod utils;
 
-pub use config::Config;
+pub use config::{Config, SearchBackend};
 pub use context::build_context;
 pub use docs_rs_build_limits::DEFAULT_MAX_TARGETS;
 pub use docs_rs_utils::{APP_USER_AGENT, BUILD_VERSION, RUSTDOC_STATIC_STORAGE_PREFIX};
@@ -2,8 +2,9 @@ use crate::{
     cache::CachePolicy,
     error::AxumNope,
     handlers::{
-        about, build_details, build_status, builds, crate_details, features, releases, rustdoc,
-        sitemap, source,
+        about, admin, api, api_diff, build_details, build_status, builds, crate_details,
+        crates_io_events, features, feeds, item_search, limits_requests, releases,
+        reverse_dependencies, rustdoc, sitemap, source,
         statics::{build_static_router, static_root_dir},
         status,
     },
@@ -148,6 +149,11 @@ pub(crate) fn build_axum_routes() -> Result<AxumRouter> {
         .route_with_tsr("/about/builds", get_internal(about::about_builds_handler))
         .route_with_tsr("/about", get_internal(about::about_handler))
         .route_with_tsr("/about/{subpage}", get_internal(about::about_handler))
+        .route("/api/v1/crates/{name}", get_internal(api::crate_handler))
+        .route(
+            "/api/v1/crates/{name}/{version}",
+            get_internal(api::release_handler),
+        )
         .route("/", get_internal(releases::home_page))
         .route_with_tsr("/releases", get_internal(releases::recent_releases_handler))
         .route_with_tsr(
@@ -194,6 +200,10 @@ pub(crate) fn build_axum_routes() -> Result<AxumRouter> {
             "/releases/feed",
             get_internal(releases::releases_feed_handler),
         )
+        .route(
+            "/releases/{owner}/feed.xml",
+            get_internal(feeds::owner_feed_handler),
+        )
         .route_with_tsr("/releases/{owner}", get_internal(releases::owner_handler))
         .route_with_tsr(
             "/releases/{owner}/{page}",
@@ -204,10 +214,30 @@ pub(crate) fn build_axum_routes() -> Result<AxumRouter> {
             get_internal(releases::activity_handler),
         )
         .route_with_tsr("/releases/search", get_internal(releases::search_handler))
+        .route(
+            "/-/search/items",
+            get_internal(item_search::item_search_handler),
+        )
+        .route(
+            "/-/search/items.json",
+            get_internal(item_search::item_search_json_handler),
+        )
         .route_with_tsr(
             "/releases/queue",
             get_internal(releases::build_queue_handler),
         )
+        .route(
+            "/crate/{name}/feed.xml",
+            get_internal(feeds::crate_feed_handler),
+        )
+        .route_with_tsr(
+            "/crate/{name}/reverse-dependencies",
+            get_internal(reverse_dependencies::reverse_dependencies_handler),
+        )
+        .route(
+            "/crate/{name}/reverse-dependencies.json",
+            get_internal(reverse_dependencies::reverse_dependencies_json_handler),
+        )
         .route_with_tsr(
             "/crate/{name}/{version}/builds",
             get_internal(builds::build_list_handler),
@@ -216,6 +246,10 @@ pub(crate) fn build_axum_routes() -> Result<AxumRouter> {
             "/crate/{name}/{version}/rebuild",
             post_internal(builds::build_trigger_rebuild_handler),
         )
+        .route(
+            "/crate/{name}/{version}/limits-request",
+            post_internal(limits_requests::limits_request_handler),
+        )
         .route(
             "/crate/{name}/{version}/status.json",
             get_internal(build_status::status_handler),
@@ -224,6 +258,10 @@ pub(crate) fn build_axum_routes() -> Result<AxumRouter> {
             "/crate/{name}/{version}/builds/{id}",
             get_internal(build_details::build_details_handler),
         )
+        .route(
+            "/crate/{name}/{version}/builds/{id}/events",
+            get_internal(build_details::build_events_handler),
+        )
         .route_with_tsr(
             "/crate/{name}/{version}/builds/{id}/{filename}",
             get_internal(build_details::build_details_handler),
@@ -234,11 +272,19 @@ pub(crate) fn build_axum_routes() -> Result<AxumRouter> {
         )
         .route_with_tsr(
             "/crate/{name}/{version}/source/",
-            get_internal(source::source_browser_handler),
+            get_internal(source::source_handler),
         )
         .route(
             "/crate/{name}/{version}/source/{*path}",
-            get_internal(source::source_browser_handler),
+            get_internal(source::source_handler),
+        )
+        .route_with_tsr(
+            "/crate/{name}/{version}/api-diff",
+            get_internal(api_diff::api_diff_handler),
+        )
+        .route(
+            "/crate/{name}/{version}/api-diff.json",
+            get_internal(api_diff::api_diff_json_handler),
         )
         .route(
             "/crate/{name}/{version}/menus/platforms/{target}/",
@@ -256,6 +302,19 @@ pub(crate) fn build_axum_routes() -> Result<AxumRouter> {
             "/crate/{name}/{version}/menus/releases/{*path}",
             get_internal(crate_details::get_all_releases),
         )
+        .route("/-/admin/audit", get_internal(admin::audit_log_handler))
+        .route(
+            "/-/admin/build-failures",
+            get_internal(admin::build_failures_handler),
+        )
+        .route(
+            "/-/admin/build-failures/rebuild",
+            post_internal(admin::rebuild_failure_cluster_handler),
+        )
+        .route(
+            "/-/crates-io/events",
+            post_internal(crates_io_events::crates_io_events_handler),
+        )
         .route(
             "/-/partial/abnormalities/",
             get_internal(status::abnormalities),
@@ -90,11 +90,54 @@ pub fn with_lang(lang: Option<&str>, code: &str, default: Option<&str>) -> Strin
     }
 }
 
+/// Highlight `code`, and return the HTML of each line.
+///
+/// Highlighted spans can cover multiple lines, like in block comments. These are closed
+/// at the end of a line and reopened on the next, so each line is valid HTML on its own.
+pub fn lines_with_lang(lang: Option<&str>, code: &str) -> Vec<String> {
+    let mut lines = split_html_lines(&with_lang(lang, code, None));
+    lines.resize(code.lines().count(), String::new());
+    lines
+}
+
+fn split_html_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open_tags: Vec<&str> = Vec::new();
+    let mut line = String::new();
+
+    let mut rest = html;
+    while let Some(pos) = rest.find(['<', '\n']) {
+        line.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if let Some(after_newline) = rest.strip_prefix('\n') {
+            line.extend(open_tags.iter().map(|_| "</span>"));
+            lines.push(std::mem::take(&mut line));
+            line.extend(open_tags.iter().copied());
+            rest = after_newline;
+        } else {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            let tag = &rest[..end];
+            if tag.starts_with("</") {
+                open_tags.pop();
+            } else {
+                open_tags.push(tag);
+            }
+            line.push_str(tag);
+            rest = &rest[end..];
+        }
+    }
+    line.push_str(rest);
+    lines.push(line);
+
+    lines
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        LimitsExceeded, PER_LINE_BYTE_LENGTH_LIMIT, TOTAL_CODE_BYTE_LENGTH_LIMIT, select_syntax,
-        try_with_lang, with_lang,
+        LimitsExceeded, PER_LINE_BYTE_LENGTH_LIMIT, TOTAL_CODE_BYTE_LENGTH_LIMIT, lines_with_lang,
+        select_syntax, split_html_lines, try_with_lang, with_lang,
     };
 
     #[test]
@@ -189,4 +232,33 @@ union!();"#,
             ],
         );
     }
+
+    #[test]
+    fn split_html_lines_reopens_spans() {
+        assert_eq!(
+            split_html_lines(
+                "<span class=\"a\">one\n<span class=\"b\">two</span>\nthree</span>\nfour"
+            ),
+            vec![
+                "<span class=\"a\">one</span>",
+                "<span class=\"a\"><span class=\"b\">two</span></span>",
+                "<span class=\"a\">three</span>",
+                "four",
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_with_lang_matches_line_count() {
+        let code = "/* a\n   multi-line comment */\nfn main() {}\n";
+        let lines = lines_with_lang(Some("rust"), code);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("multi-line comment"));
+        for line in &lines {
+            assert_eq!(
+                line.matches("<span").count(),
+                line.matches("</span>").count()
+            );
+        }
+    }
 }
@@ -0,0 +1,76 @@
+(function() {
+    function addBuildEvent(list, event) {
+        const item = document.createElement("li");
+        item.className = "build-event-" + event.status;
+
+        const time = document.createElement("span");
+        time.className = "build-event-time";
+        time.textContent = new Date(event.occurred_at).toISOString().substring(11, 19);
+        item.appendChild(time);
+
+        const phase = document.createElement("span");
+        phase.className = "build-event-phase";
+        phase.textContent = event.description;
+        item.appendChild(phase);
+
+        if (event.message) {
+            const message = document.createElement("code");
+            message.className = "build-event-message";
+            message.textContent = event.message;
+            item.appendChild(message);
+        }
+
+        const status = document.createElement("span");
+        status.className = "build-event-status";
+        status.textContent = event.status;
+        item.appendChild(status);
+
+        list.appendChild(item);
+    }
+
+    function followBuildEvents(list) {
+        const logTail = document.getElementById("build-log-tail");
+        // the first connection sends all events, including the ones
+        // that were already rendered with the page.
+        let lastEventId = 0;
+        for (const item of list.children) {
+            lastEventId = Math.max(lastEventId, parseInt(item.dataset.eventId, 10));
+        }
+
+        const source = new EventSource(list.dataset.eventsUrl);
+
+        source.addEventListener("build-event", message => {
+            const eventId = parseInt(message.lastEventId, 10);
+            if (eventId <= lastEventId) {
+                return;
+            }
+            lastEventId = eventId;
+            addBuildEvent(list, JSON.parse(message.data));
+        });
+
+        source.addEventListener("log", message => {
+            if (logTail) {
+                logTail.textContent = JSON.parse(message.data).log;
+                logTail.scrollTop = logTail.scrollHeight;
+            }
+        });
+
+        source.addEventListener("finished", () => {
+            source.close();
+            // the build logs are only available when the build is finished.
+            window.location.reload();
+        });
+
+        // the server stopped following the build, reconnecting wouldn't help.
+        source.addEventListener("timeout", () => {
+            source.close();
+        });
+    }
+
+    document.addEventListener("DOMContentLoaded", () => {
+        const list = document.getElementById("build-events");
+        if (list && list.dataset.eventsUrl) {
+            followBuildEvents(list);
+        }
+    });
+})();
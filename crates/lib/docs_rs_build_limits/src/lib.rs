@@ -2,6 +2,8 @@ pub mod blacklist;
 mod config;
 mod limits;
 mod overrides;
+pub mod requests;
+pub mod toolchain_overrides;
 
 pub use config::Config;
 pub use limits::Limits;
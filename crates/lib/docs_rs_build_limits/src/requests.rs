@@ -0,0 +1,405 @@
+//! Sandbox limit increases requested by crate owners.
+//!
+//! Owners file requests through the builds page, admins approve or reject them
+//! with the admin CLI. Approved requests are applied through [`Overrides::save`],
+//! the request keeps the previous overrides as audit trail.
+
+use crate::Overrides;
+use anyhow::{Context as _, Result, bail};
+use chrono::{DateTime, Utc};
+use docs_rs_types::KrateName;
+use futures_util::stream::TryStreamExt;
+use sqlx::Connection as _;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "override_request_status", rename_all = "snake_case")]
+pub enum RequestStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverrideRequest {
+    pub id: i32,
+    pub crate_name: KrateName,
+    /// crates.io login of the owner who filed the request.
+    pub requested_by: String,
+    pub overrides: Overrides,
+    pub reason: String,
+    pub status: RequestStatus,
+    pub created_at: DateTime<Utc>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_comment: Option<String>,
+    /// The overrides of the crate before the request was approved.
+    pub previous_overrides: Option<Overrides>,
+}
+
+/// The database columns for the limits, fails for limits that don't fit into them.
+fn columns(overrides: &Overrides) -> Result<(Option<i64>, Option<i32>, Option<i32>)> {
+    Ok((
+        overrides
+            .memory
+            .map(i64::try_from)
+            .transpose()
+            .context("memory limit too large")?,
+        overrides
+            .targets
+            .map(i32::try_from)
+            .transpose()
+            .context("target limit too large")?,
+        overrides
+            .timeout
+            .map(|d| i32::try_from(d.as_secs()))
+            .transpose()
+            .context("timeout too large")?,
+    ))
+}
+
+fn overrides(memory: Option<i64>, targets: Option<i32>, timeout: Option<i32>) -> Overrides {
+    Overrides {
+        memory: memory.map(|i| i as usize),
+        targets: targets.map(|i| i as usize),
+        timeout: timeout.map(|i| Duration::from_secs(i as u64)),
+    }
+}
+
+/// Files a request, returns its id.
+///
+/// There can only be one pending request per crate, `None` if the crate already has one.
+pub async fn create(
+    conn: &mut sqlx::PgConnection,
+    krate: &KrateName,
+    requested_by: &str,
+    overrides: Overrides,
+    reason: &str,
+) -> Result<Option<i32>> {
+    if overrides == Overrides::default() {
+        bail!("the request doesn't contain any limits");
+    }
+    let (memory, targets, timeout) = columns(&overrides)?;
+
+    // a unique index allows only one pending request per crate.
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO sandbox_override_requests (
+            crate_name, requested_by, max_memory_bytes, max_targets, timeout_seconds, reason
+         )
+         VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT (crate_name) WHERE status = 'pending' DO NOTHING
+         RETURNING id",
+        krate as _,
+        requested_by,
+        memory,
+        targets,
+        timeout,
+        reason,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+/// Lists requests, optionally only those with the given status, oldest first.
+pub async fn list(
+    conn: &mut sqlx::PgConnection,
+    status: Option<RequestStatus>,
+) -> Result<Vec<OverrideRequest>> {
+    Ok(sqlx::query!(
+        r#"SELECT
+            id,
+            crate_name as "crate_name: KrateName",
+            requested_by,
+            max_memory_bytes,
+            max_targets,
+            timeout_seconds,
+            reason,
+            status as "status: RequestStatus",
+            created_at,
+            reviewed_by,
+            reviewed_at,
+            review_comment,
+            previous_max_memory_bytes,
+            previous_max_targets,
+            previous_timeout_seconds
+         FROM sandbox_override_requests
+         WHERE $1::override_request_status IS NULL OR status = $1
+         ORDER BY id"#,
+        status as _,
+    )
+    .fetch(conn)
+    .map_ok(|row| OverrideRequest {
+        id: row.id,
+        crate_name: row.crate_name,
+        requested_by: row.requested_by,
+        overrides: overrides(row.max_memory_bytes, row.max_targets, row.timeout_seconds),
+        reason: row.reason,
+        status: row.status,
+        created_at: row.created_at,
+        review_comment: row.review_comment,
+        previous_overrides: row.reviewed_by.is_some().then(|| {
+            overrides(
+                row.previous_max_memory_bytes,
+                row.previous_max_targets,
+                row.previous_timeout_seconds,
+            )
+        }),
+        reviewed_by: row.reviewed_by,
+        reviewed_at: row.reviewed_at,
+    })
+    .try_collect()
+    .await?)
+}
+
+/// Approves a pending request, and applies the requested limits on top of the
+/// current overrides of the crate.
+///
+/// Returns the new overrides.
+pub async fn approve(
+    conn: &mut sqlx::PgConnection,
+    id: i32,
+    reviewer: &str,
+    comment: Option<&str>,
+) -> Result<Overrides> {
+    let mut transaction = conn.begin().await?;
+
+    let request = sqlx::query!(
+        r#"SELECT
+            crate_name as "crate_name: KrateName",
+            max_memory_bytes,
+            max_targets,
+            timeout_seconds
+         FROM sandbox_override_requests
+         WHERE id = $1 AND status = 'pending'
+         FOR UPDATE"#,
+        id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let Some(request) = request else {
+        bail!("there is no pending request with id {id}");
+    };
+
+    let previous = Overrides::for_crate(&mut transaction, &request.crate_name)
+        .await?
+        .unwrap_or_default();
+    let requested = overrides(
+        request.max_memory_bytes,
+        request.max_targets,
+        request.timeout_seconds,
+    );
+    let new = Overrides {
+        memory: requested.memory.or(previous.memory),
+        targets: requested.targets.or(previous.targets),
+        timeout: requested.timeout.or(previous.timeout),
+    };
+
+    Overrides::save(&mut transaction, &request.crate_name, new).await?;
+    let (previous_memory, previous_targets, previous_timeout) = columns(&previous)?;
+
+    sqlx::query!(
+        "UPDATE sandbox_override_requests
+         SET
+            status = 'approved',
+            reviewed_by = $2,
+            reviewed_at = NOW(),
+            review_comment = $3,
+            previous_max_memory_bytes = $4,
+            previous_max_targets = $5,
+            previous_timeout_seconds = $6
+         WHERE id = $1",
+        id,
+        reviewer,
+        comment,
+        previous_memory,
+        previous_targets,
+        previous_timeout,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(new)
+}
+
+/// Rejects a pending request.
+pub async fn reject(
+    conn: &mut sqlx::PgConnection,
+    id: i32,
+    reviewer: &str,
+    comment: Option<&str>,
+) -> Result<()> {
+    let result = sqlx::query!(
+        "UPDATE sandbox_override_requests
+         SET
+            status = 'rejected',
+            reviewed_by = $2,
+            reviewed_at = NOW(),
+            review_comment = $3
+         WHERE id = $1 AND status = 'pending'",
+        id,
+        reviewer,
+        comment,
+    )
+    .execute(conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        bail!("there is no pending request with id {id}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use docs_rs_config::AppConfig as _;
+    use docs_rs_database::testing::TestDatabase;
+    use docs_rs_opentelemetry::testing::TestMetrics;
+    use docs_rs_types::testing::{BAR, FOO};
+    use pretty_assertions::assert_eq;
+
+    async fn db() -> Result<TestDatabase> {
+        let test_metrics = TestMetrics::new();
+        TestDatabase::new(
+            &docs_rs_database::Config::test_config()?,
+            test_metrics.provider(),
+        )
+        .await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn approve_applies_requested_limits() -> Result<()> {
+        let db = db().await?;
+        let mut conn = db.async_conn().await?;
+
+        let current = Overrides {
+            memory: Some(1000),
+            targets: Some(2),
+            timeout: None,
+        };
+        Overrides::save(&mut conn, &FOO, current).await?;
+
+        let requested = Overrides {
+            memory: Some(2000),
+            timeout: Some(Duration::from_secs(3600)),
+            ..Overrides::default()
+        };
+        let id = create(&mut conn, &FOO, "owner", requested, "big crate")
+            .await?
+            .expect("no pending request");
+
+        // only one pending request per crate
+        assert!(
+            create(&mut conn, &FOO, "owner", requested, "again")
+                .await?
+                .is_none()
+        );
+
+        let pending = list(&mut conn, Some(RequestStatus::Pending)).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].crate_name, FOO);
+        assert_eq!(pending[0].requested_by, "owner");
+        assert_eq!(pending[0].overrides, requested);
+        assert!(pending[0].previous_overrides.is_none());
+
+        let expected = Overrides {
+            memory: Some(2000),
+            targets: Some(2),
+            timeout: Some(Duration::from_secs(3600)),
+        };
+        assert_eq!(approve(&mut conn, id, "admin", Some("ok")).await?, expected);
+        assert_eq!(Overrides::for_crate(&mut conn, &FOO).await?, Some(expected));
+
+        let requests = list(&mut conn, None).await?;
+        assert_eq!(requests[0].status, RequestStatus::Approved);
+        assert_eq!(requests[0].reviewed_by.as_deref(), Some("admin"));
+        assert_eq!(requests[0].review_comment.as_deref(), Some("ok"));
+        assert!(requests[0].reviewed_at.is_some());
+        assert_eq!(requests[0].previous_overrides, Some(current));
+
+        // requests can only be reviewed once
+        assert!(approve(&mut conn, id, "admin", None).await.is_err());
+        assert!(reject(&mut conn, id, "admin", None).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reject_keeps_overrides() -> Result<()> {
+        let db = db().await?;
+        let mut conn = db.async_conn().await?;
+
+        let requested = Overrides {
+            targets: Some(20),
+            ..Overrides::default()
+        };
+        let id = create(&mut conn, &BAR, "owner", requested, "all targets")
+            .await?
+            .expect("no pending request");
+
+        reject(&mut conn, id, "admin", Some("too many")).await?;
+        assert_eq!(Overrides::for_crate(&mut conn, &BAR).await?, None);
+
+        let requests = list(&mut conn, Some(RequestStatus::Rejected)).await?;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].review_comment.as_deref(), Some("too many"));
+        assert!(
+            list(&mut conn, Some(RequestStatus::Pending))
+                .await?
+                .is_empty()
+        );
+
+        // a new request is possible after the rejection
+        assert!(
+            create(&mut conn, &BAR, "owner", requested, "please")
+                .await?
+                .is_some()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn too_large_request() -> Result<()> {
+        let db = db().await?;
+        let mut conn = db.async_conn().await?;
+
+        for overrides in [
+            Overrides {
+                targets: Some(usize::MAX),
+                ..Overrides::default()
+            },
+            Overrides {
+                timeout: Some(Duration::from_secs(u64::MAX)),
+                ..Overrides::default()
+            },
+        ] {
+            assert!(
+                create(&mut conn, &FOO, "owner", overrides, "huge")
+                    .await
+                    .is_err()
+            );
+        }
+        assert!(list(&mut conn, None).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn empty_request() -> Result<()> {
+        let db = db().await?;
+        let mut conn = db.async_conn().await?;
+
+        assert!(
+            create(&mut conn, &FOO, "owner", Overrides::default(), "nothing")
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
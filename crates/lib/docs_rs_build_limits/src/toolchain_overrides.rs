@@ -0,0 +1,201 @@
+//! Toolchains pinned for a crate or a single release, used instead of the toolchain
+//! configured with `ConfigName::Toolchain`, for example when a nightly regressed for a crate.
+
+use anyhow::{Result, bail};
+use docs_rs_types::{KrateName, Version};
+use futures_util::stream::TryStreamExt;
+use tracing::warn;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolchainOverride {
+    pub crate_name: KrateName,
+    /// `None` when the toolchain is pinned for all releases of the crate.
+    pub version: Option<Version>,
+    pub toolchain: String,
+}
+
+/// Toolchain names are passed to rustup, we only allow what toolchain names
+/// and CI commit hashes can contain.
+fn validate_toolchain(toolchain: &str) -> Result<()> {
+    if toolchain.is_empty()
+        || !toolchain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        bail!("invalid toolchain name: {toolchain:?}");
+    }
+    Ok(())
+}
+
+pub async fn all(conn: &mut sqlx::PgConnection) -> Result<Vec<ToolchainOverride>> {
+    Ok(sqlx::query_as!(
+        ToolchainOverride,
+        r#"SELECT
+            crate_name as "crate_name: KrateName",
+            version as "version: Version",
+            toolchain
+         FROM toolchain_overrides
+         ORDER BY crate_name, version NULLS FIRST"#
+    )
+    .fetch(conn)
+    .try_collect()
+    .await?)
+}
+
+/// The toolchain pinned for a release. A toolchain pinned for the version wins over
+/// one pinned for the whole crate.
+pub async fn for_release(
+    conn: &mut sqlx::PgConnection,
+    krate: &KrateName,
+    version: &Version,
+) -> Result<Option<String>> {
+    Ok(sqlx::query_scalar!(
+        "SELECT toolchain
+         FROM toolchain_overrides
+         WHERE crate_name = $1 AND (version = $2 OR version IS NULL)
+         ORDER BY version NULLS LAST
+         LIMIT 1",
+        krate as _,
+        version as _,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+/// Pin a toolchain for a crate, or only for one release when `version` is given.
+///
+/// Returns the previously pinned toolchain.
+pub async fn save(
+    conn: &mut sqlx::PgConnection,
+    krate: &KrateName,
+    version: Option<&Version>,
+    toolchain: &str,
+) -> Result<Option<String>> {
+    validate_toolchain(toolchain)?;
+
+    if sqlx::query_scalar!("SELECT id FROM crates WHERE crates.name = $1", krate as _)
+        .fetch_optional(&mut *conn)
+        .await?
+        .is_none()
+    {
+        warn!(%krate, "pinning toolchain for unknown crate");
+    }
+
+    let previous = remove(&mut *conn, krate, version).await?;
+
+    sqlx::query!(
+        "INSERT INTO toolchain_overrides (crate_name, version, toolchain)
+         VALUES ($1, $2, $3)",
+        krate as _,
+        version as _,
+        toolchain,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(previous)
+}
+
+/// Remove the toolchain pinned for a crate, or for one release when `version` is given.
+///
+/// Returns the removed toolchain.
+pub async fn remove(
+    conn: &mut sqlx::PgConnection,
+    krate: &KrateName,
+    version: Option<&Version>,
+) -> Result<Option<String>> {
+    Ok(sqlx::query_scalar!(
+        "DELETE FROM toolchain_overrides
+         WHERE crate_name = $1 AND version IS NOT DISTINCT FROM $2
+         RETURNING toolchain",
+        krate as _,
+        version as _,
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use docs_rs_config::AppConfig as _;
+    use docs_rs_database::testing::TestDatabase;
+    use docs_rs_opentelemetry::testing::TestMetrics;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pin_for_crate_and_release() -> Result<()> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(
+            &docs_rs_database::Config::test_config()?,
+            test_metrics.provider(),
+        )
+        .await?;
+        let mut conn = db.async_conn().await?;
+
+        let krate = KrateName::from_static("foo");
+        let v1 = Version::new(1, 0, 0);
+        let v2 = Version::new(2, 0, 0);
+
+        assert_eq!(for_release(&mut conn, &krate, &v1).await?, None);
+
+        assert_eq!(
+            save(&mut conn, &krate, None, "nightly-2024-01-01").await?,
+            None
+        );
+        assert_eq!(
+            save(&mut conn, &krate, Some(&v2), "nightly-2024-02-01").await?,
+            None
+        );
+        assert_eq!(
+            for_release(&mut conn, &krate, &v1).await?.as_deref(),
+            Some("nightly-2024-01-01")
+        );
+        assert_eq!(
+            for_release(&mut conn, &krate, &v2).await?.as_deref(),
+            Some("nightly-2024-02-01")
+        );
+
+        // overwriting returns the previous toolchain
+        assert_eq!(
+            save(&mut conn, &krate, None, "nightly-2024-03-01")
+                .await?
+                .as_deref(),
+            Some("nightly-2024-01-01")
+        );
+        assert_eq!(all(&mut conn).await?.len(), 2);
+
+        assert_eq!(
+            remove(&mut conn, &krate, Some(&v2)).await?.as_deref(),
+            Some("nightly-2024-02-01")
+        );
+        assert_eq!(
+            for_release(&mut conn, &krate, &v2).await?.as_deref(),
+            Some("nightly-2024-03-01")
+        );
+        assert_eq!(remove(&mut conn, &krate, Some(&v2)).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_toolchain() -> Result<()> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(
+            &docs_rs_database::Config::test_config()?,
+            test_metrics.provider(),
+        )
+        .await?;
+        let mut conn = db.async_conn().await?;
+
+        for toolchain in ["", "nightly; rm -rf /", "nightly 2024"] {
+            assert!(
+                save(&mut conn, &KrateName::from_static("foo"), None, toolchain)
+                    .await
+                    .is_err()
+            );
+        }
+        assert!(all(&mut conn).await?.is_empty());
+
+        Ok(())
+    }
+}
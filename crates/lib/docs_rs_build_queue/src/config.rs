@@ -1,14 +1,23 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use docs_rs_config::AppConfig;
 use docs_rs_env_vars::maybe_env;
 use std::time::Duration;
 
+/// builders renew their lease every third of the lease duration, so shorter leases
+/// would mostly keep them busy renewing.
+const MIN_LEASE_DURATION: Duration = Duration::from_secs(30);
+
 #[derive(Debug)]
 pub struct Config {
     pub build_attempts: u16,
     pub deprioritize_workspace_size: u16,
     pub delay_between_build_attempts: Duration,
     pub length_warning_threshold: usize,
+    /// how long a builder keeps a queued release without renewing its lease.
+    ///
+    /// Builders renew the lease while the build is running, so this is the time
+    /// after which the release of a dead builder is picked up again.
+    pub lease_duration: Duration,
 }
 
 impl Default for Config {
@@ -18,6 +27,7 @@ impl Default for Config {
             deprioritize_workspace_size: 20,
             delay_between_build_attempts: Duration::from_secs(60),
             length_warning_threshold: 1000,
+            lease_duration: Duration::from_secs(600),
         }
     }
 }
@@ -42,6 +52,16 @@ impl AppConfig for Config {
             config.length_warning_threshold = length;
         }
 
+        if let Some(duration) = maybe_env::<u64>("DOCSRS_BUILD_QUEUE_LEASE_DURATION")? {
+            config.lease_duration = Duration::from_secs(duration);
+        }
+        if config.lease_duration < MIN_LEASE_DURATION {
+            bail!(
+                "DOCSRS_BUILD_QUEUE_LEASE_DURATION has to be at least {}s",
+                MIN_LEASE_DURATION.as_secs()
+            );
+        }
+
         Ok(config)
     }
 }
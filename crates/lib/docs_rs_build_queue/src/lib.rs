@@ -7,8 +7,11 @@ pub(crate) mod testing;
 mod types;
 
 pub use config::Config;
-pub use queue::{blocking::BuildQueue, non_blocking::AsyncBuildQueue};
-pub use types::{BuildPackageSummary, QueuedCrate};
+pub use queue::{
+    blocking::{BuildQueue, default_lease_holder},
+    non_blocking::AsyncBuildQueue,
+};
+pub use types::{BuildPackageSummary, QueueLease, QueuedCrate};
 
 pub const PRIORITY_DEFAULT: i32 = 0;
 /// Used for workspaces to avoid blocking the queue (done through the cratesfyi CLI, not used in code)
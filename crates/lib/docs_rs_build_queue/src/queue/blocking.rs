@@ -1,13 +1,14 @@
+#[cfg(test)]
+use crate::QueueLease;
 use crate::{AsyncBuildQueue, QueuedCrate, types::BuildPackageSummary};
 use anyhow::Result;
 use docs_rs_types::{KrateName, Version};
 use docs_rs_utils::Handle;
-use sqlx::Connection as _;
 #[cfg(test)]
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::runtime;
-use tracing::error;
+use tokio::{runtime, task::JoinHandle};
+use tracing::{error, warn};
 
 #[derive(Debug)]
 pub struct BuildQueue {
@@ -49,6 +50,10 @@ impl BuildQueue {
     pub(crate) fn queued_crates(&self) -> Result<Vec<QueuedCrate>> {
         self.runtime.block_on(self.inner.queued_crates())
     }
+    #[cfg(test)]
+    pub(crate) fn leases(&self) -> Result<Vec<QueueLease>> {
+        self.runtime.block_on(self.inner.leases())
+    }
 }
 
 impl BuildQueue {
@@ -59,107 +64,119 @@ impl BuildQueue {
         }
     }
 
+    /// Take the next release from the queue, and build it with `f`.
+    ///
+    /// Uses a lease holder identifying this process, see [`default_lease_holder`].
     pub fn process_next_crate(
         &self,
         f: impl FnOnce(&QueuedCrate) -> Result<BuildPackageSummary>,
     ) -> Result<Option<i32>> {
-        let mut conn = self.runtime.block_on(self.inner.db.get_async())?;
-        let mut transaction = self.runtime.block_on(conn.begin())?;
-
-        // fetch the next available crate from the queue table.
-        // We are using `SELECT FOR UPDATE` inside a transaction so
-        // the QueuedCrate is locked until we are finished with it.
-        // `SKIP LOCKED` here will enable another build-server to just
-        // skip over taken (=locked) rows and start building the first
-        // available one.
-        let to_process = match self.runtime.block_on(
-            sqlx::query_as!(
-                QueuedCrate,
-                r#"SELECT
-                    id,
-                    name as "name: KrateName",
-                    version as "version: Version",
-                    priority,
-                    attempt
-                 FROM queue
-                 WHERE
-                    last_attempt IS NULL OR last_attempt < NOW() - make_interval(secs => $1)
-                 ORDER BY priority ASC, attempt ASC, id ASC
-                 LIMIT 1
-                 FOR UPDATE SKIP LOCKED"#,
-                self.inner.config.delay_between_build_attempts.as_secs_f64(),
-            )
-            .fetch_optional(&mut *transaction),
-        )? {
-            Some(krate) => krate,
-            None => return Ok(None),
-        };
-
-        let res = f(&to_process);
+        self.process_next_crate_as(&default_lease_holder(), f)
+    }
 
-        let delete_from_queue = |conn: &mut sqlx::PgConnection| -> Result<()> {
-            self.runtime.block_on(
-                sqlx::query!("DELETE FROM queue WHERE id = $1;", to_process.id).execute(conn),
-            )?;
-            Ok(())
+    /// Take the next release from the queue, and build it with `f`.
+    ///
+    /// The release is leased to `lease_holder` while `f` is running, so other builders
+    /// skip it. The lease is renewed in the background until `f` returns. When the builder
+    /// dies mid-build, the lease expires and another builder will take the release,
+    /// without counting the lost build as a failed attempt.
+    pub fn process_next_crate_as(
+        &self,
+        lease_holder: &str,
+        f: impl FnOnce(&QueuedCrate) -> Result<BuildPackageSummary>,
+    ) -> Result<Option<i32>> {
+        let Some(to_process) = self
+            .runtime
+            .block_on(self.inner.lease_next_crate(lease_holder))?
+        else {
+            return Ok(None);
         };
 
-        let increase_attempt_or_delete_from_queue =
-            |conn: &mut sqlx::PgConnection| -> Result<Option<i32>> {
-                let potential_next_attempt = self.runtime.block_on(
-                    sqlx::query_scalar!(
-                        "UPDATE queue
-                         SET
-                            attempt = attempt + 1,
-                            last_attempt = NOW()
-                         WHERE id = $1
-                         RETURNING attempt;",
-                        to_process.id,
-                    )
-                    .fetch_one(&mut *conn),
-                )?;
-
-                if potential_next_attempt >= self.inner.config.build_attempts.into() {
-                    self.inner.queue_metrics.failed_crates_count.add(1, &[]);
-                    // exceeded max attempts, remove from queue
-                    delete_from_queue(&mut *conn)?;
-                    Ok(None)
-                } else {
-                    // keep in queue for re-attempt
-                    Ok(Some(potential_next_attempt))
-                }
-            };
-
-        let next_attempt: Option<i32>;
+        let res = {
+            let _heartbeat = self.renew_lease_in_background(to_process.id, lease_holder);
+            f(&to_process)
+        };
 
         match res {
             Ok(BuildPackageSummary {
                 should_reattempt: false,
                 successful: _,
             }) => {
-                delete_from_queue(&mut transaction)?;
-                next_attempt = None;
+                if !self
+                    .runtime
+                    .block_on(self.inner.remove_leased_crate(to_process.id, lease_holder))?
+                {
+                    warn!(
+                        name = %to_process.name,
+                        version = %to_process.version,
+                        lease_holder,
+                        "lost the lease on the queued release, another builder took it over"
+                    );
+                }
+                Ok(None)
             }
             Ok(BuildPackageSummary {
                 should_reattempt: true,
                 successful: _,
-            }) => {
-                next_attempt = increase_attempt_or_delete_from_queue(&mut transaction)?;
-            }
+            }) => self.runtime.block_on(
+                self.inner
+                    .record_failed_attempt(to_process.id, lease_holder),
+            ),
             Err(e) => {
-                next_attempt = increase_attempt_or_delete_from_queue(&mut transaction)?;
-
                 error!(
                     ?e,
                     name = %to_process.name,
                     version = %to_process.version,
                     "Failed to build package"
                 );
+
+                self.runtime.block_on(
+                    self.inner
+                        .record_failed_attempt(to_process.id, lease_holder),
+                )
             }
         }
+    }
+
+    /// Renew the lease on a queued release until the returned guard is dropped.
+    fn renew_lease_in_background(&self, id: i32, lease_holder: &str) -> AbortOnDrop {
+        let inner = self.inner.clone();
+        let lease_holder = lease_holder.to_owned();
+        let interval = inner.config.lease_duration / 3;
+
+        AbortOnDrop(self.runtime.as_handle().spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            // the first tick completes immediately, we just leased the release.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match inner.renew_lease(id, &lease_holder).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!(id, lease_holder, "lease on queued release was taken over");
+                        break;
+                    }
+                    Err(err) => warn!(?err, id, lease_holder, "failed to renew queue lease"),
+                }
+            }
+        }))
+    }
+}
 
-        self.runtime.block_on(transaction.commit())?;
-        Ok(next_attempt)
+/// Identifies this process as lease holder of queued releases.
+pub fn default_lease_holder() -> String {
+    let hostname = hostname::get()
+        .ok()
+        .and_then(|hostname| hostname.into_string().ok())
+        .unwrap_or_else(|| "unknown".into());
+    format!("{hostname}:{}", std::process::id())
+}
+
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
     }
 }
 
@@ -479,6 +496,142 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_leased_crates_are_skipped() -> Result<()> {
+        let env = BlockingTestEnv::new()?;
+        let queue = env.queue();
+
+        queue.add_crate(&FOO, &V1, 0)?;
+        queue.add_crate(&BAR, &V1, 0)?;
+
+        let mut built = Vec::new();
+        queue.process_next_crate_as("builder-1", |first| {
+            built.push(first.name.clone());
+
+            let leases = queue.leases()?;
+            assert_eq!(leases.len(), 1);
+            assert_eq!(leases[0].name, first.name);
+            assert_eq!(leases[0].leased_by, "builder-1");
+
+            // a second builder gets the other release
+            queue.process_next_crate_as("builder-2", |second| {
+                built.push(second.name.clone());
+                assert_eq!(queue.leases()?.len(), 2);
+                Ok(BuildPackageSummary::default())
+            })?;
+
+            // and nothing is left for a third builder
+            queue.process_next_crate_as("builder-3", |_| unreachable!())?;
+
+            Ok(BuildPackageSummary::default())
+        })?;
+
+        assert_eq!(built, vec![FOO, BAR]);
+        assert!(queue.leases()?.is_empty());
+        assert_eq!(queue.pending_count()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired_lease_is_reclaimed() -> Result<()> {
+        let env = BlockingTestEnv::new()?;
+        let queue = env.queue();
+
+        queue.add_crate(&KRATE, &V1, 0)?;
+
+        // simulate a builder that died mid-build
+        env.block_on_async_with_conn(async |conn| {
+            sqlx::query!(
+                "UPDATE queue
+                 SET
+                    leased_by = 'dead-builder',
+                    leased_at = NOW() - INTERVAL '1 hour',
+                    lease_expires_at = NOW() - INTERVAL '1 minute'"
+            )
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        })?;
+
+        // expired leases are not listed
+        assert!(queue.leases()?.is_empty());
+
+        let mut handled = false;
+        queue.process_next_crate_as("builder", |krate| {
+            assert_eq!(krate.name, KRATE);
+            // the lost build doesn't count as an attempt
+            assert_eq!(krate.attempt, 0);
+            handled = true;
+            Ok(BuildPackageSummary::default())
+        })?;
+        assert!(handled);
+        assert_eq!(queue.pending_count()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lost_lease_is_not_counted() -> Result<()> {
+        let env = BlockingTestEnv::new()?;
+        let queue = env.queue_with_config(Config {
+            build_attempts: 1,
+            ..Default::default()
+        });
+
+        queue.add_crate(&KRATE, &V1, 0)?;
+
+        let next_attempt = queue.process_next_crate_as("builder-1", |_| {
+            // another builder took over the expired lease in the meantime
+            env.block_on_async_with_conn(async |conn| {
+                sqlx::query!("UPDATE queue SET leased_by = 'builder-2'")
+                    .execute(&mut *conn)
+                    .await?;
+                Ok(())
+            })?;
+            anyhow::bail!("simulate a failure");
+        })?;
+        assert_eq!(next_attempt, None);
+
+        // the release is still queued, with the lease of the other builder
+        let leases = queue.leases()?;
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].leased_by, "builder-2");
+        assert_eq!(leases[0].attempt, 0);
+        assert_eq!(queue.pending_count()?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_attempt_releases_lease() -> Result<()> {
+        let env = BlockingTestEnv::new()?;
+        let queue = env.queue_with_config(Config {
+            build_attempts: 5,
+            delay_between_build_attempts: Duration::ZERO,
+            ..Default::default()
+        });
+
+        queue.add_crate(&KRATE, &V1, 0)?;
+
+        assert_eq!(
+            queue.process_next_crate_as("builder-1", |_| anyhow::bail!("simulate a failure"))?,
+            Some(1)
+        );
+        assert!(queue.leases()?.is_empty());
+
+        // another builder can retry the release right away
+        let mut handled = false;
+        queue.process_next_crate_as("builder-2", |krate| {
+            assert_eq!(krate.attempt, 1);
+            handled = true;
+            Ok(BuildPackageSummary::default())
+        })?;
+        assert!(handled);
+
+        Ok(())
+    }
+
     #[test]
     fn test_add_long_name() -> Result<()> {
         let env = BlockingTestEnv::new()?;
@@ -1,9 +1,12 @@
 use crate::{
-    Config, PRIORITY_MANUAL_FROM_CRATES_IO, QueuedCrate, metrics, priority::PrioritiesCache,
+    Config, PRIORITY_BROKEN_RUSTDOC, PRIORITY_MANUAL_FROM_CRATES_IO, QueueLease, QueuedCrate,
+    metrics, priority::PrioritiesCache,
 };
 use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
 use docs_rs_database::{
     Pool,
+    build_failures::{self, FailureSignature},
     service_config::{Abnormality, ConfigName, get_config, set_config},
 };
 use docs_rs_opentelemetry::AnyMeterProvider;
@@ -11,6 +14,7 @@ use docs_rs_types::{KrateName, Version};
 use docs_rs_uri::EscapedURI;
 use futures_util::TryStreamExt as _;
 use std::{collections::HashMap, sync::Arc};
+use tracing::warn;
 
 #[derive(Debug)]
 pub struct AsyncBuildQueue {
@@ -113,6 +117,31 @@ impl AsyncBuildQueue {
         Ok(())
     }
 
+    /// Queue rebuilds for the releases that failed with this signature since `since`,
+    /// and weren't successfully built again afterwards.
+    ///
+    /// Returns the number of queued releases, releases that are already queued are skipped.
+    pub async fn queue_failure_cluster_rebuilds(
+        &self,
+        signature: &FailureSignature,
+        since: DateTime<Utc>,
+    ) -> Result<usize> {
+        let releases = {
+            let mut conn = self.db.get_async().await?;
+            build_failures::cluster_releases(&mut conn, signature, since).await?
+        };
+
+        let mut queued = 0;
+        for (name, version) in releases {
+            if !self.has_build_queued(&name, &version).await? {
+                self.add_crate(&name, &version, PRIORITY_BROKEN_RUSTDOC)
+                    .await?;
+                queued += 1;
+            }
+        }
+        Ok(queued)
+    }
+
     pub async fn pending_count(&self) -> Result<usize> {
         Ok(self
             .pending_count_by_priority()
@@ -284,6 +313,159 @@ impl AsyncBuildQueue {
     }
 }
 
+/// Leases of queued releases.
+///
+/// A builder takes a release from the queue by leasing it, and renews the lease while
+/// the build is running. When a builder dies mid-build, the lease expires and the
+/// release can be taken by the next builder.
+impl AsyncBuildQueue {
+    /// Lease the next release from the queue to `leased_by`.
+    ///
+    /// Skips releases with an active lease, and releases that were attempted too recently.
+    pub(crate) async fn lease_next_crate(&self, leased_by: &str) -> Result<Option<QueuedCrate>> {
+        let mut conn = self.db.get_async().await?;
+
+        // `SKIP LOCKED` lets concurrent builders skip the row another builder is just leasing.
+        Ok(sqlx::query_as!(
+            QueuedCrate,
+            r#"UPDATE queue
+             SET
+                leased_by = $1,
+                leased_at = NOW(),
+                lease_expires_at = NOW() + make_interval(secs => $2)
+             WHERE id = (
+                SELECT id
+                FROM queue
+                WHERE
+                    (last_attempt IS NULL OR last_attempt < NOW() - make_interval(secs => $3)) AND
+                    (lease_expires_at IS NULL OR lease_expires_at < NOW())
+                ORDER BY priority ASC, attempt ASC, id ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+             )
+             RETURNING
+                id,
+                name as "name: KrateName",
+                version as "version: Version",
+                priority,
+                attempt"#,
+            leased_by,
+            self.config.lease_duration.as_secs_f64(),
+            self.config.delay_between_build_attempts.as_secs_f64(),
+        )
+        .fetch_optional(&mut *conn)
+        .await?)
+    }
+
+    /// Renew the lease of a queued release.
+    ///
+    /// Returns `false` when `leased_by` doesn't hold the lease anymore.
+    pub(crate) async fn renew_lease(&self, id: i32, leased_by: &str) -> Result<bool> {
+        let mut conn = self.db.get_async().await?;
+
+        Ok(sqlx::query!(
+            "UPDATE queue
+             SET lease_expires_at = NOW() + make_interval(secs => $3)
+             WHERE id = $1 AND leased_by = $2",
+            id,
+            leased_by,
+            self.config.lease_duration.as_secs_f64(),
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Remove a leased release from the queue after it was built.
+    ///
+    /// Returns `false` when `leased_by` doesn't hold the lease anymore.
+    pub(crate) async fn remove_leased_crate(&self, id: i32, leased_by: &str) -> Result<bool> {
+        let mut conn = self.db.get_async().await?;
+
+        Ok(sqlx::query!(
+            "DELETE FROM queue WHERE id = $1 AND leased_by = $2",
+            id,
+            leased_by,
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Count a failed build attempt of a leased release, and release the lease.
+    ///
+    /// Removes the release from the queue when it ran out of attempts.
+    /// Returns the next attempt, or `None` when the release was removed, or when
+    /// `leased_by` doesn't hold the lease anymore.
+    pub(crate) async fn record_failed_attempt(
+        &self,
+        id: i32,
+        leased_by: &str,
+    ) -> Result<Option<i32>> {
+        let mut conn = self.db.get_async().await?;
+
+        let Some(attempt) = sqlx::query_scalar!(
+            "UPDATE queue
+             SET
+                attempt = attempt + 1,
+                last_attempt = NOW(),
+                leased_by = NULL,
+                leased_at = NULL,
+                lease_expires_at = NULL
+             WHERE id = $1 AND leased_by = $2
+             RETURNING attempt",
+            id,
+            leased_by,
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        else {
+            warn!(
+                id,
+                leased_by, "lost the lease on the queued release, not counting the attempt"
+            );
+            return Ok(None);
+        };
+
+        if attempt >= self.config.build_attempts.into() {
+            self.queue_metrics.failed_crates_count.add(1, &[]);
+            // exceeded max attempts, remove from queue
+            sqlx::query!("DELETE FROM queue WHERE id = $1", id)
+                .execute(&mut *conn)
+                .await?;
+            Ok(None)
+        } else {
+            // keep in queue for re-attempt
+            Ok(Some(attempt))
+        }
+    }
+
+    /// The active leases, oldest first.
+    pub async fn leases(&self) -> Result<Vec<QueueLease>> {
+        let mut conn = self.db.get_async().await?;
+
+        Ok(sqlx::query_as!(
+            QueueLease,
+            r#"SELECT
+                name as "name: KrateName",
+                version as "version: Version",
+                attempt,
+                leased_by as "leased_by!",
+                leased_at as "leased_at!",
+                lease_expires_at as "lease_expires_at!"
+             FROM queue
+             WHERE
+                leased_by IS NOT NULL AND
+                lease_expires_at >= NOW()
+             ORDER BY leased_at ASC, id ASC"#,
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+}
+
 /// Locking functions.
 impl AsyncBuildQueue {
     /// Checks for the lock and returns whether it currently exists.
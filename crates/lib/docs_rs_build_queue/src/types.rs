@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use docs_rs_types::{KrateName, Version};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -9,6 +10,17 @@ pub struct QueuedCrate {
     pub attempt: i32,
 }
 
+/// A queued release that is currently leased by a builder.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct QueueLease {
+    pub name: KrateName,
+    pub version: Version,
+    pub attempt: i32,
+    pub leased_by: String,
+    pub leased_at: DateTime<Utc>,
+    pub lease_expires_at: DateTime<Utc>,
+}
+
 #[derive(Debug)]
 pub struct BuildPackageSummary {
     pub successful: bool,
@@ -57,6 +57,61 @@ impl IndexChangeV1 {
             _ => None,
         }
     }
+
+    /// The kind of change, as it's named in the wire format.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IndexChangeV1::Added(_) => "added",
+            IndexChangeV1::Unyanked(_) => "unyanked",
+            IndexChangeV1::Yanked(_) => "yanked",
+            IndexChangeV1::CrateDeleted { .. } => "crate_deleted",
+            IndexChangeV1::VersionDeleted(_) => "version_deleted",
+        }
+    }
+
+    /// The name of the crate this change is about.
+    pub fn crate_name(&self) -> &str {
+        match self {
+            IndexChangeV1::Added(v)
+            | IndexChangeV1::Unyanked(v)
+            | IndexChangeV1::Yanked(v)
+            | IndexChangeV1::VersionDeleted(v) => &v.name,
+            IndexChangeV1::CrateDeleted { name } => name,
+        }
+    }
+
+    /// The crate version this change is about, `None` when the whole crate was deleted.
+    pub fn crate_version(&self) -> Option<&CrateVersion> {
+        match self {
+            IndexChangeV1::Added(v)
+            | IndexChangeV1::Unyanked(v)
+            | IndexChangeV1::Yanked(v)
+            | IndexChangeV1::VersionDeleted(v) => Some(v),
+            IndexChangeV1::CrateDeleted { .. } => None,
+        }
+    }
+
+    /// Reassemble a change from the output of [`Self::kind`], [`Self::crate_name`]
+    /// and [`Self::crate_version`].
+    ///
+    /// Returns `None` for unknown kinds, or when the version is missing.
+    pub fn from_parts(kind: &str, name: String, version: Option<String>) -> Option<Self> {
+        if kind == "crate_deleted" {
+            return Some(IndexChangeV1::CrateDeleted { name });
+        }
+
+        let crate_version = CrateVersion {
+            name,
+            version: version?,
+        };
+        Some(match kind {
+            "added" => IndexChangeV1::Added(crate_version),
+            "unyanked" => IndexChangeV1::Unyanked(crate_version),
+            "yanked" => IndexChangeV1::Yanked(crate_version),
+            "version_deleted" => IndexChangeV1::VersionDeleted(crate_version),
+            _ => return None,
+        })
+    }
 }
 
 impl fmt::Display for IndexChangeV1 {
@@ -194,6 +249,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn change_kind_matches_wire_format_and_roundtrips() {
+        let crate_version = crate_version();
+
+        for change in [
+            IndexChangeV1::Added(crate_version.clone()),
+            IndexChangeV1::Unyanked(crate_version.clone()),
+            IndexChangeV1::Yanked(crate_version.clone()),
+            IndexChangeV1::CrateDeleted {
+                name: "old-crate".into(),
+            },
+            IndexChangeV1::VersionDeleted(crate_version.clone()),
+        ] {
+            assert_eq!(
+                serde_json::to_value(&change).unwrap()["type"],
+                change.kind()
+            );
+
+            assert_eq!(
+                IndexChangeV1::from_parts(
+                    change.kind(),
+                    change.crate_name().to_owned(),
+                    change.crate_version().map(|v| v.version.clone()),
+                ),
+                Some(change)
+            );
+        }
+
+        assert_eq!(
+            IndexChangeV1::from_parts("added", "clap".into(), None),
+            None
+        );
+        assert_eq!(
+            IndexChangeV1::from_parts("renamed", "clap".into(), Some("4.5.0".into())),
+            None
+        );
+    }
+
     #[test]
     fn event_serializes_with_minimum_metadata() {
         let event = event(IndexChangeV1::CrateDeleted {
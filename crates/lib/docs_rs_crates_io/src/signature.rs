@@ -0,0 +1,72 @@
+//! Signing of event payloads sent from crates.io to docs.rs.
+//!
+//! The sender computes an HMAC-SHA256 over the raw request body with a shared secret,
+//! and sends it hex-encoded in the [`SIGNATURE_HEADER`], prefixed with `sha256=`.
+
+use hmac::{Hmac, KeyInit as _, Mac as _};
+use sha2::Sha256;
+
+/// The HTTP header containing the signature of the request body.
+pub const SIGNATURE_HEADER: &str = "x-docsrs-signature";
+
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(secret: &[u8], body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac
+}
+
+/// Generate the signature header value for a request body.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    format!(
+        "{SIGNATURE_PREFIX}{}",
+        hex::encode(mac(secret, body).finalize().into_bytes())
+    )
+}
+
+/// Check if the signature header value matches the request body.
+///
+/// The comparison is done in constant time.
+pub fn verify(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let Some(signature) = signature.strip_prefix(SIGNATURE_PREFIX) else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    mac(secret, body).verify_slice(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const BODY: &[u8] = br#"{"id":"evt_123"}"#;
+
+    #[test]
+    fn sign_and_verify() {
+        let signature = sign(SECRET, BODY);
+        assert!(signature.starts_with("sha256="));
+        assert!(verify(SECRET, BODY, &signature));
+    }
+
+    #[test]
+    fn verify_rejects_invalid_signatures() {
+        let signature = sign(SECRET, BODY);
+
+        assert!(!verify(b"other secret", BODY, &signature));
+        assert!(!verify(SECRET, br#"{"id":"evt_124"}"#, &signature));
+        assert!(!verify(
+            SECRET,
+            BODY,
+            signature.trim_start_matches("sha256=")
+        ));
+        assert!(!verify(SECRET, BODY, "sha256=not-hex"));
+        assert!(!verify(SECRET, BODY, ""));
+    }
+}
@@ -0,0 +1 @@
+DROP TABLE crates_io_events;
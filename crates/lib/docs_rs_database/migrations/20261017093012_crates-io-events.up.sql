@@ -0,0 +1,18 @@
+CREATE TABLE crates_io_events (
+    id TEXT PRIMARY KEY,
+    occurred_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    received_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    version TEXT,
+    attempts INTEGER DEFAULT 0 NOT NULL,
+    last_error TEXT,
+    processed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX crates_io_events_pending_idx
+    ON crates_io_events USING btree (occurred_at)
+    WHERE processed_at IS NULL;
+
+CREATE INDEX crates_io_events_release_idx
+    ON crates_io_events USING btree (name, version, kind);
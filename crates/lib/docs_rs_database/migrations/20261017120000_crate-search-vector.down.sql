@@ -0,0 +1,3 @@
+DROP INDEX crates_search_vector_idx;
+ALTER TABLE crates DROP COLUMN search_vector;
+DROP FUNCTION crate_search_vector;
@@ -0,0 +1,29 @@
+-- Full-text search document for a crate, built from its name and the
+-- description & keywords of its latest release.
+-- Word separators in crate names are split so `serde_json` also matches `json`.
+CREATE FUNCTION crate_search_vector(name TEXT, description TEXT, keywords JSON) RETURNS tsvector
+    LANGUAGE sql IMMUTABLE
+    AS $$
+        SELECT
+            setweight(to_tsvector('english', name || ' ' || translate(name, '-_', '  ')), 'A') ||
+            setweight(to_tsvector('english', COALESCE(
+                CASE WHEN json_typeof(keywords) = 'array' THEN
+                    (SELECT string_agg(keyword, ' ') FROM json_array_elements_text(keywords) AS keyword)
+                END,
+                ''
+            )), 'B') ||
+            setweight(to_tsvector('english', COALESCE(description, '')), 'C');
+    $$;
+
+ALTER TABLE crates ADD COLUMN search_vector tsvector;
+
+UPDATE crates
+SET search_vector = crate_search_vector(crates.name, releases.description, releases.keywords)
+FROM releases
+WHERE releases.id = crates.latest_version_id;
+
+UPDATE crates
+SET search_vector = crate_search_vector(crates.name, NULL, NULL)
+WHERE search_vector IS NULL;
+
+CREATE INDEX crates_search_vector_idx ON crates USING gin (search_vector);
@@ -0,0 +1,2 @@
+ALTER TABLE releases DROP COLUMN items_indexed_at;
+DROP TABLE release_items;
@@ -0,0 +1,15 @@
+-- Public items of a release, extracted from its rustdoc JSON.
+-- Only the latest release of each crate is indexed.
+CREATE TABLE release_items (
+    release_id INT NOT NULL REFERENCES releases(id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    signature TEXT
+);
+
+CREATE INDEX release_items_release_id_idx ON release_items (release_id);
+CREATE INDEX release_items_name_idx ON release_items (lower(name) text_pattern_ops);
+
+-- NULL when the items of the release still have to be (re-)indexed.
+ALTER TABLE releases ADD COLUMN items_indexed_at TIMESTAMPTZ;
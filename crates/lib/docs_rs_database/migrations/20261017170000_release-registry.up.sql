@@ -0,0 +1,2 @@
+-- The registry a release was fetched from.
+ALTER TABLE releases ADD COLUMN registry TEXT NOT NULL DEFAULT 'crates-io';
@@ -0,0 +1,4 @@
+ALTER TABLE builds DROP COLUMN log_tail;
+DROP TABLE build_events;
+DROP TYPE build_event_status;
+DROP TYPE build_phase;
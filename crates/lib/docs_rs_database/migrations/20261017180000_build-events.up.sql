@@ -0,0 +1,32 @@
+CREATE TYPE build_phase AS ENUM (
+    'blacklist_check',
+    'fetch',
+    'source_upload',
+    'default_target_build',
+    'lockfile_regeneration',
+    'target_build',
+    'doc_upload',
+    'log_upload',
+    'finish_release'
+);
+
+CREATE TYPE build_event_status AS ENUM (
+    'started',
+    'finished',
+    'failed'
+);
+
+CREATE TABLE build_events (
+    id BIGSERIAL PRIMARY KEY,
+    build_id INTEGER NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    phase build_phase NOT NULL,
+    status build_event_status NOT NULL,
+    -- additional information, like the target of a target build.
+    message TEXT
+);
+
+CREATE INDEX build_events_build_id_idx ON build_events (build_id, id);
+
+-- the end of the log of the currently running cargo command, while the build is in progress.
+ALTER TABLE builds ADD COLUMN log_tail TEXT;
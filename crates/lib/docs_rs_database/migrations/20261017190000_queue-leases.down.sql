@@ -0,0 +1,4 @@
+ALTER TABLE queue
+    DROP COLUMN leased_by,
+    DROP COLUMN leased_at,
+    DROP COLUMN lease_expires_at;
@@ -0,0 +1,7 @@
+-- A builder takes a queued release by leasing it. The lease is renewed while the build
+-- is running, so releases of builders that died mid-build can be taken by other builders
+-- once the lease expired.
+ALTER TABLE queue
+    ADD COLUMN leased_by TEXT,
+    ADD COLUMN leased_at TIMESTAMPTZ,
+    ADD COLUMN lease_expires_at TIMESTAMPTZ;
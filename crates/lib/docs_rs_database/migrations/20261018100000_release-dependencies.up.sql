@@ -0,0 +1,12 @@
+-- The dependencies of each release, normalized from `releases.dependencies`
+-- so we can look up the dependents of a crate.
+CREATE TABLE release_dependencies (
+    release_id INT NOT NULL REFERENCES releases(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    req TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    optional BOOLEAN NOT NULL
+);
+
+CREATE INDEX release_dependencies_release_id_idx ON release_dependencies (release_id);
+CREATE INDEX release_dependencies_name_idx ON release_dependencies (name);
@@ -0,0 +1,2 @@
+-- when a release was yanked, for the yank entries in the Atom feeds.
+ALTER TABLE releases ADD COLUMN yanked_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,40 @@
+-- Outgoing webhooks for build lifecycle events.
+-- Subscriptions without a crate name receive the events of all crates.
+-- The secrets the payloads are signed with are derived from a configured key, not stored.
+CREATE TABLE webhook_subscriptions (
+    id SERIAL PRIMARY KEY,
+    crate_name TEXT,
+    url TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX webhook_subscriptions_crate_name_idx ON webhook_subscriptions (crate_name);
+
+-- Every delivery attempt, successful or not.
+CREATE TABLE webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    subscription_id INT NOT NULL REFERENCES webhook_subscriptions(id) ON DELETE CASCADE,
+    build_id INT NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    attempt INT NOT NULL,
+    status_code INT,
+    error TEXT,
+    attempted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX webhook_deliveries_subscription_id_idx ON webhook_deliveries (subscription_id, attempted_at DESC);
+
+-- Deliveries that still need an attempt, so retries survive restarts.
+-- Every attempt is still recorded in `webhook_deliveries`.
+CREATE TABLE webhook_pending_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    subscription_id INT NOT NULL REFERENCES webhook_subscriptions(id) ON DELETE CASCADE,
+    build_id INT NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX webhook_pending_deliveries_next_attempt_at_idx ON webhook_pending_deliveries (next_attempt_at);
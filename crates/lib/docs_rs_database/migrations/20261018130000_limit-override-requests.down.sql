@@ -0,0 +1,2 @@
+DROP TABLE sandbox_override_requests;
+DROP TYPE override_request_status;
@@ -0,0 +1,29 @@
+CREATE TYPE override_request_status AS ENUM ('pending', 'approved', 'rejected');
+
+-- Sandbox limit increases requested by crate owners, reviewed by admins.
+-- Approved requests are applied to `sandbox_overrides`, the previous overrides
+-- are kept here for the audit trail.
+CREATE TABLE sandbox_override_requests (
+    id SERIAL PRIMARY KEY,
+    crate_name TEXT NOT NULL,
+    requested_by TEXT NOT NULL,
+    max_memory_bytes BIGINT,
+    max_targets INT,
+    timeout_seconds INT,
+    reason TEXT NOT NULL,
+    status override_request_status NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    reviewed_by TEXT,
+    reviewed_at TIMESTAMP WITH TIME ZONE,
+    review_comment TEXT,
+    previous_max_memory_bytes BIGINT,
+    previous_max_targets INT,
+    previous_timeout_seconds INT
+);
+
+CREATE INDEX sandbox_override_requests_crate_name_idx ON sandbox_override_requests (crate_name);
+CREATE INDEX sandbox_override_requests_status_idx ON sandbox_override_requests (status);
+-- Only one pending request per crate, also for concurrent requests.
+CREATE UNIQUE INDEX sandbox_override_requests_pending_idx
+    ON sandbox_override_requests (crate_name)
+    WHERE status = 'pending';
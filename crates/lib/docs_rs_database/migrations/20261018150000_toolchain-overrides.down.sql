@@ -0,0 +1,2 @@
+ALTER TABLE builds DROP COLUMN pinned_toolchain;
+DROP TABLE toolchain_overrides;
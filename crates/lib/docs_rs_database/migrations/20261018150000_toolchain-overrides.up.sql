@@ -0,0 +1,17 @@
+-- Toolchains pinned for a crate, or for a single release when `version` is set,
+-- used instead of the globally configured toolchain.
+CREATE TABLE toolchain_overrides (
+    id SERIAL PRIMARY KEY,
+    crate_name TEXT NOT NULL,
+    version TEXT,
+    toolchain TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX toolchain_overrides_crate_idx
+    ON toolchain_overrides (crate_name) WHERE version IS NULL;
+CREATE UNIQUE INDEX toolchain_overrides_release_idx
+    ON toolchain_overrides (crate_name, version) WHERE version IS NOT NULL;
+
+-- the pinned toolchain a build used, NULL for the global toolchain.
+ALTER TABLE builds ADD COLUMN pinned_toolchain TEXT;
@@ -0,0 +1,11 @@
+-- The first compiler error of each failed build, extracted from the build logs,
+-- to group failures by their error.
+-- `message` is NULL when we couldn't find a compiler error in the logs.
+CREATE TABLE build_failures (
+    build_id INT PRIMARY KEY REFERENCES builds(id) ON DELETE CASCADE,
+    error_code TEXT,
+    message TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX build_failures_signature_idx ON build_failures (message, error_code);
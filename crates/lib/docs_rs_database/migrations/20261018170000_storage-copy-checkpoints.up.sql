@@ -0,0 +1,12 @@
+-- Progress of `docs_rs_admin storage copy`, so interrupted copies can be resumed.
+-- Objects are copied in the order of their paths, `last_path` is the last object of
+-- the last batch that was completely copied.
+CREATE TABLE storage_copy_checkpoints (
+    name TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    last_path TEXT NOT NULL,
+    copied_objects BIGINT NOT NULL,
+    copied_bytes BIGINT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (name, prefix)
+);
@@ -0,0 +1,9 @@
+-- Progress of `docs_rs_admin storage verify`, so interrupted runs can be resumed.
+-- Releases are verified in the order of their crate name and id, `crate_filter` is
+-- the crate the run is limited to, or an empty string for all crates.
+CREATE TABLE storage_verify_checkpoints (
+    crate_filter TEXT PRIMARY KEY,
+    last_crate_name TEXT NOT NULL,
+    last_release_id INT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
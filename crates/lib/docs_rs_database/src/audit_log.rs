@@ -0,0 +1,186 @@
+//! Record of the administrative changes to docs.rs, like blacklisting crates, changing
+//! limits or priorities, or locking the build queue.
+//!
+//! Entries are written by `docs_rs_admin` and by [`set_config`](crate::service_config::set_config)
+//! / [`remove_config`](crate::service_config::remove_config), and shown on `/-/admin/audit`.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt as _;
+use serde::Serialize;
+use serde_json::Value;
+
+/// The person or service making a change, taken from the environment of the process.
+pub fn current_actor() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".into())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub actor: String,
+    /// what was done, like `blacklist.add` or `config.set`.
+    pub action: String,
+    /// what the action was done to, like a crate name or a config name.
+    pub target: Option<String>,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Filters for [`list`], all given filters have to match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLogFilter {
+    pub actor: Option<String>,
+    /// matches the action itself, and all actions in its namespace,
+    /// so `limits` matches `limits.set` and `limits.remove`.
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: i64,
+}
+
+impl Default for AuditLogFilter {
+    fn default() -> Self {
+        Self {
+            actor: None,
+            action: None,
+            target: None,
+            since: None,
+            limit: 100,
+        }
+    }
+}
+
+/// Record an administrative action. `Value::Null` values are stored as "no value".
+pub async fn record(
+    conn: &mut sqlx::PgConnection,
+    actor: &str,
+    action: &str,
+    target: Option<&str>,
+    old_value: Value,
+    new_value: Value,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO admin_audit_log (actor, action, target, old_value, new_value)
+         VALUES ($1, $2, $3, $4, $5)",
+        actor,
+        action,
+        target,
+        (!old_value.is_null()).then_some(old_value),
+        (!new_value.is_null()).then_some(new_value),
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// The matching entries, newest first.
+pub async fn list(
+    conn: &mut sqlx::PgConnection,
+    filter: &AuditLogFilter,
+) -> Result<Vec<AuditLogEntry>> {
+    Ok(sqlx::query_as!(
+        AuditLogEntry,
+        r#"SELECT id, actor, action, target, old_value, new_value, created_at
+           FROM admin_audit_log
+           WHERE
+               ($1::TEXT IS NULL OR actor = $1) AND
+               ($2::TEXT IS NULL OR action = $2 OR action LIKE $2 || '.%') AND
+               ($3::TEXT IS NULL OR target = $3) AND
+               ($4::TIMESTAMPTZ IS NULL OR created_at >= $4)
+           ORDER BY created_at DESC, id DESC
+           LIMIT $5"#,
+        filter.actor,
+        filter.action,
+        filter.target,
+        filter.since,
+        filter.limit,
+    )
+    .fetch(conn)
+    .try_collect()
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, testing::TestDatabase};
+    use docs_rs_config::AppConfig as _;
+    use docs_rs_opentelemetry::testing::TestMetrics;
+    use serde_json::json;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn record_and_filter() -> Result<()> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(&Config::test_config()?, test_metrics.provider()).await?;
+        let mut conn = db.async_conn().await?;
+
+        record(
+            &mut conn,
+            "alice",
+            "blacklist.add",
+            Some("foo"),
+            Value::Null,
+            json!(true),
+        )
+        .await?;
+        record(
+            &mut conn,
+            "bob",
+            "limits.set",
+            Some("foo"),
+            json!({"memory": null}),
+            json!({"memory": 1024}),
+        )
+        .await?;
+        record(&mut conn, "bob", "limitsx", None, Value::Null, Value::Null).await?;
+
+        let all = list(&mut conn, &AuditLogFilter::default()).await?;
+        assert_eq!(
+            all.iter().map(|e| e.action.as_str()).collect::<Vec<_>>(),
+            ["limitsx", "limits.set", "blacklist.add"]
+        );
+        assert_eq!(all[2].actor, "alice");
+        assert_eq!(all[2].old_value, None);
+        assert_eq!(all[2].new_value, Some(json!(true)));
+        assert_eq!(all[1].old_value, Some(json!({"memory": null})));
+
+        let by_action = list(
+            &mut conn,
+            &AuditLogFilter {
+                action: Some("limits".into()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(by_action.len(), 1);
+        assert_eq!(by_action[0].action, "limits.set");
+
+        let by_actor_and_target = list(
+            &mut conn,
+            &AuditLogFilter {
+                actor: Some("bob".into()),
+                target: Some("foo".into()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(by_actor_and_target.len(), 1);
+
+        let limited = list(
+            &mut conn,
+            &AuditLogFilter {
+                limit: 1,
+                since: Some(Utc::now() - chrono::Duration::hours(1)),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(limited.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+//! Structured timeline of the phases of a build, and the tail of the log
+//! of the currently running cargo command.
+//!
+//! Both are written by the builder while the build is running, so the web server can
+//! show the progress of a build.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use docs_rs_types::BuildId;
+use serde::Serialize;
+
+/// how much of the end of the in-progress build log we store.
+pub const LOG_TAIL_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "build_phase", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum BuildPhase {
+    BlacklistCheck,
+    Fetch,
+    SourceUpload,
+    DefaultTargetBuild,
+    LockfileRegeneration,
+    TargetBuild,
+    DocUpload,
+    LogUpload,
+    FinishRelease,
+}
+
+impl BuildPhase {
+    /// human readable name of the phase
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::BlacklistCheck => "blacklist check",
+            Self::Fetch => "fetch crate",
+            Self::SourceUpload => "upload sources",
+            Self::DefaultTargetBuild => "build default target",
+            Self::LockfileRegeneration => "regenerate lockfile",
+            Self::TargetBuild => "build target",
+            Self::DocUpload => "upload documentation",
+            Self::LogUpload => "upload build logs",
+            Self::FinishRelease => "finish release",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "build_event_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum BuildEventStatus {
+    Started,
+    Finished,
+    Failed,
+}
+
+impl BuildEventStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Started => "started",
+            Self::Finished => "finished",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BuildEvent {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub phase: BuildPhase,
+    pub status: BuildEventStatus,
+    /// additional information, like the target of a target build.
+    pub message: Option<String>,
+}
+
+pub async fn add_build_event(
+    conn: &mut sqlx::PgConnection,
+    build_id: BuildId,
+    phase: BuildPhase,
+    status: BuildEventStatus,
+    message: Option<&str>,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO build_events (build_id, phase, status, message)
+         VALUES ($1, $2, $3, $4)",
+        build_id.0,
+        phase as BuildPhase,
+        status as BuildEventStatus,
+        message,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Fetch the events of a build, optionally only the ones after the event with
+/// the ID `after`.
+pub async fn get_build_events(
+    conn: &mut sqlx::PgConnection,
+    build_id: BuildId,
+    after: Option<i64>,
+) -> Result<Vec<BuildEvent>> {
+    Ok(sqlx::query_as!(
+        BuildEvent,
+        r#"SELECT
+             id,
+             occurred_at,
+             phase as "phase: BuildPhase",
+             status as "status: BuildEventStatus",
+             message
+         FROM build_events
+         WHERE
+             build_id = $1 AND
+             id > $2
+         ORDER BY id"#,
+        build_id.0,
+        after.unwrap_or(0),
+    )
+    .fetch_all(&mut *conn)
+    .await?)
+}
+
+/// Store the end of the log of the currently running cargo command.
+pub async fn set_build_log_tail(
+    conn: &mut sqlx::PgConnection,
+    build_id: BuildId,
+    log: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE builds SET log_tail = $2 WHERE id = $1",
+        build_id.0,
+        log_tail(log),
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// The last `LOG_TAIL_SIZE` bytes of a log, starting at a line boundary if possible.
+pub fn log_tail(log: &str) -> &str {
+    if log.len() <= LOG_TAIL_SIZE {
+        return log;
+    }
+
+    let mut start = log.len() - LOG_TAIL_SIZE;
+    while !log.is_char_boundary(start) {
+        start += 1;
+    }
+    let tail = &log[start..];
+    match tail.find('\n') {
+        Some(newline) => &tail[newline + 1..],
+        None => tail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Config,
+        releases::{initialize_build, initialize_crate, initialize_release},
+        testing::TestDatabase,
+    };
+    use docs_rs_config::AppConfig as _;
+    use docs_rs_opentelemetry::testing::TestMetrics;
+    use docs_rs_types::testing::{KRATE, V1};
+
+    #[test]
+    fn test_log_tail() {
+        assert_eq!(log_tail("short log\n"), "short log\n");
+
+        let log = format!("{}\nlast line\n", "a".repeat(LOG_TAIL_SIZE));
+        assert_eq!(log_tail(&log), "last line\n");
+
+        // never cut inside of a character
+        let log = "ä".repeat(LOG_TAIL_SIZE);
+        let tail = log_tail(&log);
+        assert!(tail.len() <= LOG_TAIL_SIZE);
+        assert!(tail.chars().all(|c| c == 'ä'));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_build_events() -> Result<()> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(&Config::test_config()?, test_metrics.provider()).await?;
+
+        let mut conn = db.async_conn().await?;
+        let crate_id = initialize_crate(&mut conn, &KRATE).await?;
+        let release_id = initialize_release(&mut conn, crate_id, &V1).await?;
+        let build_id = initialize_build(&mut conn, release_id).await?;
+
+        assert!(
+            get_build_events(&mut conn, build_id, None)
+                .await?
+                .is_empty()
+        );
+
+        add_build_event(
+            &mut conn,
+            build_id,
+            BuildPhase::Fetch,
+            BuildEventStatus::Started,
+            None,
+        )
+        .await?;
+        add_build_event(
+            &mut conn,
+            build_id,
+            BuildPhase::Fetch,
+            BuildEventStatus::Finished,
+            None,
+        )
+        .await?;
+        add_build_event(
+            &mut conn,
+            build_id,
+            BuildPhase::TargetBuild,
+            BuildEventStatus::Failed,
+            Some("x86_64-apple-darwin"),
+        )
+        .await?;
+
+        let events = get_build_events(&mut conn, build_id, None).await?;
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.phase, event.status, event.message.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (BuildPhase::Fetch, BuildEventStatus::Started, None),
+                (BuildPhase::Fetch, BuildEventStatus::Finished, None),
+                (
+                    BuildPhase::TargetBuild,
+                    BuildEventStatus::Failed,
+                    Some("x86_64-apple-darwin")
+                ),
+            ]
+        );
+
+        let after = get_build_events(&mut conn, build_id, Some(events[1].id)).await?;
+        assert_eq!(after, events[2..]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_build_log_tail() -> Result<()> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(&Config::test_config()?, test_metrics.provider()).await?;
+
+        let mut conn = db.async_conn().await?;
+        let crate_id = initialize_crate(&mut conn, &KRATE).await?;
+        let release_id = initialize_release(&mut conn, crate_id, &V1).await?;
+        let build_id = initialize_build(&mut conn, release_id).await?;
+
+        set_build_log_tail(&mut conn, build_id, "Compiling krate v1.0.0\n").await?;
+
+        assert_eq!(
+            sqlx::query_scalar!("SELECT log_tail FROM builds WHERE id = $1", build_id.0)
+                .fetch_one(&mut *conn)
+                .await?
+                .as_deref(),
+            Some("Compiling krate v1.0.0\n")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+//! The first compiler error of failed builds, used to group failures across all builds,
+//! for example to find the crates a broken nightly failed to build.
+//!
+//! The errors are extracted from the build logs by the `docs_rs_watcher` background job.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use docs_rs_types::{BuildId, KrateName, Version};
+use futures_util::TryStreamExt as _;
+use std::fmt;
+
+/// messages are cut after this many characters.
+const MAX_MESSAGE_LENGTH: usize = 500;
+
+/// the longest time window for failure clusters, in days.
+pub const MAX_FAILURE_CLUSTER_DAYS: u32 = 10 * 365;
+
+/// The summaries cargo and rustdoc print after the actual errors.
+const SUMMARY_PREFIXES: &[&str] = &[
+    "could not compile",
+    "could not document",
+    "aborting due to",
+    "Compilation failed, aborting rustdoc",
+];
+
+/// A compiler error, like `error[E0432]: unresolved import `foo``.
+///
+/// Failures with the same signature form a cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailureSignature {
+    pub error_code: Option<String>,
+    pub message: String,
+}
+
+impl FailureSignature {
+    /// Parse a single line of compiler output, `None` if it isn't an error.
+    pub fn from_line(line: &str) -> Option<Self> {
+        let rest = line.trim().strip_prefix("error")?;
+
+        let (error_code, rest) = match rest.strip_prefix('[') {
+            Some(rest) => {
+                let (code, rest) = rest.split_once(']')?;
+                if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return None;
+                }
+                (Some(code.to_owned()), rest)
+            }
+            None => (None, rest),
+        };
+
+        let message = rest.strip_prefix(':')?.trim();
+        if message.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            error_code,
+            message: message.chars().take(MAX_MESSAGE_LENGTH).collect(),
+        })
+    }
+
+    /// Find the first compiler error in a build log.
+    pub fn from_build_log(log: &str) -> Option<Self> {
+        log.lines()
+            .map(strip_log_prefixes)
+            .filter_map(Self::from_line)
+            .find(|signature| {
+                !SUMMARY_PREFIXES
+                    .iter()
+                    .any(|prefix| signature.message.starts_with(prefix))
+            })
+    }
+}
+
+impl fmt::Display for FailureSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error_code {
+            Some(code) => write!(f, "error[{code}]: {}", self.message),
+            None => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// Build logs contain the log level and the stream the line was printed to,
+/// like `[INFO] [stderr] error: ...`.
+fn strip_log_prefixes(mut line: &str) -> &str {
+    for prefix in [
+        "[INFO] ",
+        "[WARN] ",
+        "[ERROR] ",
+        "[DEBUG] ",
+        "[stderr] ",
+        "[stdout] ",
+    ] {
+        line = line.strip_prefix(prefix).unwrap_or(line);
+    }
+    line
+}
+
+/// Store the first compiler error of a failed build, `None` when the logs didn't contain one.
+pub async fn record(
+    conn: &mut sqlx::PgConnection,
+    build_id: BuildId,
+    signature: Option<&FailureSignature>,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO build_failures (build_id, error_code, message)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (build_id) DO UPDATE
+         SET error_code = EXCLUDED.error_code, message = EXCLUDED.message",
+        build_id as _,
+        signature.and_then(|s| s.error_code.as_deref()),
+        signature.map(|s| s.message.as_str()),
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailureCluster {
+    pub signature: FailureSignature,
+    pub builds: i64,
+    pub crates: i64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Failed builds finished since `since`, grouped by their first compiler error,
+/// biggest clusters first.
+pub async fn clusters(
+    conn: &mut sqlx::PgConnection,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<FailureCluster>> {
+    Ok(sqlx::query!(
+        r#"SELECT
+             build_failures.error_code,
+             build_failures.message as "message!",
+             COUNT(*) as "builds!",
+             COUNT(DISTINCT releases.crate_id) as "crates!",
+             MIN(builds.build_finished) as "first_seen!",
+             MAX(builds.build_finished) as "last_seen!"
+         FROM build_failures
+         INNER JOIN builds ON builds.id = build_failures.build_id
+         INNER JOIN releases ON releases.id = builds.rid
+         WHERE
+             build_failures.message IS NOT NULL AND
+             builds.build_finished >= $1
+         GROUP BY build_failures.message, build_failures.error_code
+         ORDER BY COUNT(*) DESC, build_failures.message
+         LIMIT $2"#,
+        since,
+        limit,
+    )
+    .fetch(conn)
+    .map_ok(|row| FailureCluster {
+        signature: FailureSignature {
+            error_code: row.error_code,
+            message: row.message,
+        },
+        builds: row.builds,
+        crates: row.crates,
+        first_seen: row.first_seen,
+        last_seen: row.last_seen,
+    })
+    .try_collect()
+    .await?)
+}
+
+/// The releases that failed with this signature since `since`, and weren't
+/// successfully built again afterwards.
+pub async fn cluster_releases(
+    conn: &mut sqlx::PgConnection,
+    signature: &FailureSignature,
+    since: DateTime<Utc>,
+) -> Result<Vec<(KrateName, Version)>> {
+    Ok(sqlx::query!(
+        r#"SELECT DISTINCT
+             crates.name as "name: KrateName",
+             releases.version as "version: Version"
+         FROM build_failures
+         INNER JOIN builds ON builds.id = build_failures.build_id
+         INNER JOIN releases ON releases.id = builds.rid
+         INNER JOIN crates ON crates.id = releases.crate_id
+         INNER JOIN release_build_status ON release_build_status.rid = releases.id
+         WHERE
+             build_failures.message = $1 AND
+             build_failures.error_code IS NOT DISTINCT FROM $2 AND
+             builds.build_finished >= $3 AND
+             release_build_status.build_status != 'success'
+         ORDER BY 1, 2"#,
+        signature.message,
+        signature.error_code,
+        since,
+    )
+    .fetch(conn)
+    .map_ok(|row| (row.name, row.version))
+    .try_collect()
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_error_from_build_log() {
+        let log = "\
+[INFO] running `Command { std: \"docker\" \"create\" }`
+[INFO] [stderr]    Compiling foo v0.1.0 (/opt/rustwide/workdir)
+[INFO] [stderr] warning: unused import: `bar`
+[INFO] [stderr] error[E0432]: unresolved import `std::simd::Simd`
+[INFO] [stderr]  --> src/lib.rs:1:5
+[INFO] [stderr] error: cannot find macro `baz` in this scope
+[INFO] [stderr] error: could not compile `foo` (lib) due to 2 previous errors
+";
+        assert_eq!(
+            FailureSignature::from_build_log(log),
+            Some(FailureSignature {
+                error_code: Some("E0432".into()),
+                message: "unresolved import `std::simd::Simd`".into(),
+            })
+        );
+
+        let only_summary = "[INFO] [stderr] error: could not document `foo`\n";
+        assert_eq!(FailureSignature::from_build_log(only_summary), None);
+        assert_eq!(FailureSignature::from_build_log(""), None);
+    }
+
+    #[test]
+    fn signature_roundtrip() {
+        for line in [
+            "error[E0658]: use of unstable library feature `foo`",
+            "error: internal compiler error: unexpected panic",
+        ] {
+            let signature = FailureSignature::from_line(line).unwrap();
+            assert_eq!(signature.to_string(), line);
+        }
+
+        for line in ["errors: 2", "error[]: foo", "error:", "warning: foo"] {
+            assert_eq!(FailureSignature::from_line(line), None, "{line}");
+        }
+    }
+}
@@ -107,9 +107,16 @@ pub async fn update_latest_version_id(
 ) -> Result<()> {
     let releases = releases_for_crate(conn, crate_id).await?;
 
+    // the search vector is based on the latest release, so we update it here too.
     sqlx::query!(
         "UPDATE crates
-         SET latest_version_id = $2
+         SET
+            latest_version_id = $2,
+            search_vector = crate_search_vector(
+                crates.name,
+                (SELECT description FROM releases WHERE id = $2),
+                (SELECT keywords FROM releases WHERE id = $2)
+            )
          WHERE id = $1",
         crate_id.0,
         latest_release(&releases).map(|release| release.id.0),
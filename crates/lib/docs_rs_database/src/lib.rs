@@ -1,9 +1,13 @@
+pub mod audit_log;
+pub mod build_events;
+pub mod build_failures;
 mod config;
 pub mod crate_details;
 mod errors;
 mod metrics;
 mod migrations;
 mod pool;
+pub mod release_dependencies;
 pub mod releases;
 pub mod service_config;
 #[cfg(any(test, feature = "testing"))]
@@ -0,0 +1,182 @@
+//! Normalized dependencies of releases, for reverse-dependency lookups.
+//!
+//! `releases.dependencies` stays the source for the dependency list of a single
+//! release, `release_dependencies` lets us find the dependents of a crate.
+
+use anyhow::Result;
+use docs_rs_cargo_metadata::{ReleaseDependency, ReleaseDependencyList};
+use docs_rs_types::ReleaseId;
+use tracing::{info, warn};
+
+/// how many releases we backfill in one batch.
+const BACKFILL_BATCH_SIZE: i64 = 1000;
+
+/// Replace the normalized dependencies of a release.
+pub async fn update_release_dependencies(
+    conn: &mut sqlx::PgConnection,
+    release_id: ReleaseId,
+    dependencies: &[ReleaseDependency],
+) -> Result<()> {
+    let mut names = Vec::with_capacity(dependencies.len());
+    let mut reqs = Vec::with_capacity(dependencies.len());
+    let mut kinds = Vec::with_capacity(dependencies.len());
+    let mut optionals = Vec::with_capacity(dependencies.len());
+    for dependency in dependencies {
+        names.push(dependency.name.clone());
+        reqs.push(dependency.req.to_string());
+        kinds.push(dependency.kind.clone().unwrap_or_else(|| "normal".into()));
+        optionals.push(dependency.optional);
+    }
+
+    let mut transaction = sqlx::Acquire::begin(&mut *conn).await?;
+
+    sqlx::query!(
+        "DELETE FROM release_dependencies WHERE release_id = $1",
+        release_id.0
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO release_dependencies (release_id, name, req, kind, optional)
+         SELECT $1, name, req, kind, optional
+         FROM UNNEST($2::TEXT[], $3::TEXT[], $4::TEXT[], $5::BOOLEAN[])
+            AS t(name, req, kind, optional)",
+        release_id.0,
+        &names,
+        &reqs,
+        &kinds,
+        &optionals,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Fill `release_dependencies` from `releases.dependencies` for all releases.
+///
+/// Can be run again, the dependencies of each release are replaced.
+/// Returns the number of releases that were handled.
+pub async fn backfill_release_dependencies(conn: &mut sqlx::PgConnection) -> Result<u64> {
+    let mut last_id = 0;
+    let mut count = 0;
+
+    loop {
+        let rows = sqlx::query!(
+            r#"SELECT id as "id: ReleaseId", dependencies as "dependencies!"
+               FROM releases
+               WHERE id > $1 AND dependencies IS NOT NULL
+               ORDER BY id
+               LIMIT $2"#,
+            last_id,
+            BACKFILL_BATCH_SIZE,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let Some(last_row) = rows.last() else {
+            break;
+        };
+        last_id = last_row.id.0;
+
+        for row in rows {
+            match serde_json::from_value::<ReleaseDependencyList>(row.dependencies) {
+                Ok(dependencies) => {
+                    update_release_dependencies(&mut *conn, row.id, &dependencies).await?;
+                    count += 1;
+                }
+                Err(err) => warn!(?err, release_id = %row.id, "invalid dependencies"),
+            }
+        }
+
+        info!(count, "backfilled release dependencies");
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Config,
+        releases::{initialize_crate, initialize_release},
+        testing::TestDatabase,
+    };
+    use docs_rs_cargo_metadata::Dependency;
+    use docs_rs_config::AppConfig as _;
+    use docs_rs_opentelemetry::testing::TestMetrics;
+    use docs_rs_types::testing::{KRATE, V1, V2};
+
+    async fn stored_dependencies(
+        conn: &mut sqlx::PgConnection,
+        release_id: ReleaseId,
+    ) -> Result<Vec<(String, String, String, bool)>> {
+        Ok(sqlx::query!(
+            "SELECT name, req, kind, optional
+             FROM release_dependencies
+             WHERE release_id = $1
+             ORDER BY name, kind",
+            release_id.0
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|row| (row.name, row.req, row.kind, row.optional))
+        .collect())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_update_and_backfill() -> Result<()> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(&Config::test_config()?, test_metrics.provider()).await?;
+
+        let mut conn = db.async_conn().await?;
+        let crate_id = initialize_crate(&mut conn, &KRATE).await?;
+        let release_id = initialize_release(&mut conn, crate_id, &V1).await?;
+
+        let dependencies: Vec<ReleaseDependency> = vec![
+            Dependency::new("serde".into(), "^1.0".parse()?).into(),
+            Dependency::new("rand".into(), "0.8".parse()?)
+                .set_optional(true)
+                .into(),
+        ];
+        update_release_dependencies(&mut conn, release_id, &dependencies).await?;
+
+        let expected = vec![
+            ("rand".into(), "^0.8".into(), "normal".into(), true),
+            ("serde".into(), "^1.0".into(), "normal".into(), false),
+        ];
+        assert_eq!(stored_dependencies(&mut conn, release_id).await?, expected);
+
+        // replaces the existing dependencies
+        update_release_dependencies(&mut conn, release_id, &dependencies[..1]).await?;
+        assert_eq!(
+            stored_dependencies(&mut conn, release_id).await?,
+            expected[1..]
+        );
+
+        // a release stored before we had the table, in the old JSON format
+        let old_release_id = initialize_release(&mut conn, crate_id, &V2).await?;
+        sqlx::query!(
+            "UPDATE releases SET dependencies = $2 WHERE id = $1",
+            old_release_id.0,
+            serde_json::json!([["libc", "^0.2"], ["cc", "^1", "build"]]),
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        assert_eq!(backfill_release_dependencies(&mut conn).await?, 1);
+        assert_eq!(
+            stored_dependencies(&mut conn, old_release_id).await?,
+            vec![
+                ("cc".into(), "^1".into(), "build".into(), false),
+                ("libc".into(), "^0.2".into(), "normal".into(), false),
+            ]
+        );
+
+        Ok(())
+    }
+}
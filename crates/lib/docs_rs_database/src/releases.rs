@@ -1,4 +1,6 @@
-use crate::crate_details::update_latest_version_id;
+use crate::{
+    crate_details::update_latest_version_id, release_dependencies::update_release_dependencies,
+};
 use anyhow::{Context, Result, anyhow};
 use docs_rs_cargo_metadata::{MetadataPackage, ReleaseDependencyList};
 use docs_rs_registry_api::{CrateData, CrateOwner, ReleaseData};
@@ -56,6 +58,7 @@ pub async fn finish_release(
                dependencies = $3,
                target_name = $4,
                yanked = $5,
+               yanked_at = CASE WHEN $5 THEN COALESCE(yanked_at, NOW()) END,
                rustdoc_status = $6,
                test_status = $7,
                license = $8,
@@ -73,7 +76,8 @@ pub async fn finish_release(
                default_target = $20,
                features = $21,
                repository_id = $22,
-               source_size = $23
+               source_size = $23,
+               items_indexed_at = NULL
            WHERE id = $1"#,
         release_id.0,
         registry_data.release_time,
@@ -106,6 +110,7 @@ pub async fn finish_release(
         return Err(anyhow!("Failed to update release"));
     }
 
+    update_release_dependencies(&mut *conn, release_id, &dependencies).await?;
     add_keywords_into_database(conn, metadata_pkg, release_id).await?;
     add_compression_into_database(conn, compression_algorithms.into_iter(), release_id).await?;
 
@@ -254,7 +259,8 @@ where
              rustc_nightly_date = $7,
              build_finished = NOW(),
              error_kind = $8,
-             memory_peak = $9
+             memory_peak = $9,
+             log_tail = NULL
          WHERE
             id = $10
          RETURNING rid as "rid: ReleaseId" "#,
@@ -292,7 +298,8 @@ where
          SET
              build_status = $1,
              errors = $2,
-             error_kind = $3
+             error_kind = $3,
+             log_tail = NULL
          WHERE id = $4
          RETURNING rid as "rid: ReleaseId" "#,
         BuildStatus::Failure as BuildStatus,
@@ -347,6 +354,23 @@ pub async fn initialize_release(
     Ok(release_id)
 }
 
+/// Remember which registry a release was fetched from.
+pub async fn set_release_registry(
+    conn: &mut sqlx::PgConnection,
+    release_id: ReleaseId,
+    registry: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE releases SET registry = $2 WHERE id = $1",
+        release_id.0,
+        registry,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
 pub async fn initialize_build(
     conn: &mut sqlx::PgConnection,
     release_id: ReleaseId,
@@ -384,6 +408,23 @@ pub async fn initialize_build(
     Ok(build_id)
 }
 
+/// Record that a build uses a toolchain pinned for the crate or release.
+pub async fn set_build_pinned_toolchain(
+    conn: &mut sqlx::PgConnection,
+    build_id: BuildId,
+    toolchain: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE builds SET pinned_toolchain = $2 WHERE id = $1",
+        build_id as _,
+        toolchain,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
 /// Reads features and converts them to Vec<Feature> with default being first
 fn get_features(pkg: &MetadataPackage) -> Vec<Feature> {
     let mut features = Vec::with_capacity(pkg.features.len());
@@ -1302,6 +1343,26 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_set_release_registry() -> Result<()> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(&Config::test_config()?, test_metrics.provider()).await?;
+
+        let mut conn = db.async_conn().await?;
+        let crate_id = initialize_crate(&mut conn, &KRATE).await?;
+        let release_id = initialize_release(&mut conn, crate_id, &V1).await?;
+
+        let registry =
+            || sqlx::query_scalar!("SELECT registry FROM releases WHERE id = $1", release_id.0);
+
+        assert_eq!(registry().fetch_one(&mut *conn).await?, "crates-io");
+
+        set_release_registry(&mut conn, release_id, "company").await?;
+        assert_eq!(registry().fetch_one(&mut *conn).await?, "company");
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_initialize_build() -> Result<()> {
         let test_metrics = TestMetrics::new();
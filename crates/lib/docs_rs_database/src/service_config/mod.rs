@@ -1,18 +1,37 @@
 mod abnormalities;
+mod toolchain_window;
 
+use crate::audit_log;
 use anyhow::Result;
 use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use sqlx::Connection as _;
 
 pub use abnormalities::Abnormality;
+pub use toolchain_window::ToolchainWindow;
 
-#[derive(strum::IntoStaticStr)]
+#[derive(Debug, Clone, Copy, strum::IntoStaticStr)]
 #[strum(serialize_all = "snake_case")]
 pub enum ConfigName {
     RustcVersion,
     LastSeenIndexReference,
+    LastSeenSparseIndexUpdate,
     QueueLocked,
     Toolchain,
     Abnormality,
+    /// the nightlies crates can request, see [`ToolchainWindow`].
+    ToolchainWindow,
+}
+
+impl ConfigName {
+    /// Changes to settings are recorded in the audit log, the values our services
+    /// keep updating by themselves are not.
+    fn is_audited(&self) -> bool {
+        matches!(
+            self,
+            Self::QueueLocked | Self::Toolchain | Self::Abnormality | Self::ToolchainWindow
+        )
+    }
 }
 
 pub async fn set_config(
@@ -20,24 +39,62 @@ pub async fn set_config(
     name: ConfigName,
     value: impl Serialize,
 ) -> anyhow::Result<()> {
+    let audited = name.is_audited();
     let name: &'static str = name.into();
-    sqlx::query!(
-        "INSERT INTO config (name, value)
+    let value = serde_json::to_value(value)?;
+
+    let mut transaction = conn.begin().await?;
+    let old_value = sqlx::query_scalar!(
+        "WITH old AS (SELECT value FROM config WHERE name = $1)
+         INSERT INTO config (name, value)
          VALUES ($1, $2)
-         ON CONFLICT (name) DO UPDATE SET value = $2;",
+         ON CONFLICT (name) DO UPDATE SET value = $2
+         RETURNING (SELECT value FROM old) AS old_value;",
         name,
-        &serde_json::to_value(value)?,
+        &value,
     )
-    .execute(conn)
+    .fetch_one(&mut *transaction)
     .await?;
+
+    if audited && old_value.as_ref() != Some(&value) {
+        audit_log::record(
+            &mut transaction,
+            &audit_log::current_actor(),
+            "config.set",
+            Some(name),
+            old_value.unwrap_or_default(),
+            value,
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
     Ok(())
 }
 
 pub async fn remove_config(conn: &mut sqlx::PgConnection, name: ConfigName) -> anyhow::Result<()> {
+    let audited = name.is_audited();
     let name: &'static str = name.into();
-    sqlx::query!("DELETE FROM config WHERE name = $1;", name)
-        .execute(conn)
+
+    let mut transaction = conn.begin().await?;
+    let old_value =
+        sqlx::query_scalar!("DELETE FROM config WHERE name = $1 RETURNING value;", name)
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+    if audited && let Some(old_value) = old_value {
+        audit_log::record(
+            &mut transaction,
+            &audit_log::current_actor(),
+            "config.remove",
+            Some(name),
+            old_value,
+            Value::Null,
+        )
         .await?;
+    }
+
+    transaction.commit().await?;
     Ok(())
 }
 
@@ -69,7 +126,9 @@ mod tests {
     #[test_case(ConfigName::RustcVersion, "rustc_version")]
     #[test_case(ConfigName::QueueLocked, "queue_locked")]
     #[test_case(ConfigName::LastSeenIndexReference, "last_seen_index_reference")]
+    #[test_case(ConfigName::LastSeenSparseIndexUpdate, "last_seen_sparse_index_update")]
     #[test_case(ConfigName::Abnormality, "abnormality")]
+    #[test_case(ConfigName::ToolchainWindow, "toolchain_window")]
     fn test_configname_variants(variant: ConfigName, expected: &'static str) {
         let name: &'static str = variant.into();
         assert_eq!(name, expected);
@@ -169,4 +228,54 @@ mod tests {
         );
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_setting_changes_are_audited() -> anyhow::Result<()> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(&Config::test_config()?, test_metrics.provider()).await?;
+
+        let mut conn = db.async_conn().await?;
+
+        set_config(&mut conn, ConfigName::RustcVersion, "rustc 1.0.0").await?;
+        set_config(&mut conn, ConfigName::Toolchain, "nightly").await?;
+        // unchanged values are not recorded
+        set_config(&mut conn, ConfigName::Toolchain, "nightly").await?;
+        set_config(&mut conn, ConfigName::Toolchain, "stable").await?;
+        remove_config(&mut conn, ConfigName::Toolchain).await?;
+        remove_config(&mut conn, ConfigName::Toolchain).await?;
+
+        let entries = audit_log::list(&mut conn, &Default::default()).await?;
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (
+                    entry.action.as_str(),
+                    entry.target.as_deref(),
+                    entry.old_value.clone(),
+                    entry.new_value.clone(),
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "config.remove",
+                    Some("toolchain"),
+                    Some(Value::from("stable")),
+                    None
+                ),
+                (
+                    "config.set",
+                    Some("toolchain"),
+                    Some(Value::from("nightly")),
+                    Some(Value::from("stable"))
+                ),
+                (
+                    "config.set",
+                    Some("toolchain"),
+                    None,
+                    Some(Value::from("nightly"))
+                ),
+            ]
+        );
+        Ok(())
+    }
 }
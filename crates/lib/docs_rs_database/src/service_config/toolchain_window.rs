@@ -0,0 +1,18 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// The nightlies crates can request with `toolchain` in `[package.metadata.docs.rs]`,
+/// by the date of the nightly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolchainWindow {
+    pub oldest: NaiveDate,
+    /// `None` allows all nightlies since `oldest`.
+    #[serde(default)]
+    pub newest: Option<NaiveDate>,
+}
+
+impl ToolchainWindow {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        date >= self.oldest && self.newest.is_none_or(|newest| date <= newest)
+    }
+}
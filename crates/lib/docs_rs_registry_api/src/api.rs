@@ -1,8 +1,13 @@
 use crate::{
     Config,
     error::{Error, Result},
-    models::{ApiErrors, CrateData, CrateOwner, OwnerKind, ReleaseData, Search, SearchResponse},
+    models::{
+        ApiErrors, CrateData, CrateOwner, OwnerKind, RecentlyUpdatedCrates, ReleaseData, Search,
+        SearchResponse, UpdatedCrate,
+    },
+    registry::Registry,
 };
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use docs_rs_types::{KrateName, Version};
 use docs_rs_utils::APP_USER_AGENT;
@@ -18,19 +23,26 @@ use url::Url;
 
 #[derive(Debug)]
 pub struct RegistryApi {
-    api_base: Url,
+    registry: Registry,
     client: ClientWithMiddleware,
 }
 
 impl RegistryApi {
     pub fn from_config(config: &Config) -> Result<Self> {
-        Self::new(
-            config.registry_api_host.clone(),
-            config.crates_io_api_call_retries,
-        )
+        Self::with_registry(config.registry(), config.crates_io_api_call_retries)
     }
 
     pub fn new(api_base: Url, max_retries: u32) -> Result<Self> {
+        Self::with_registry(
+            Registry {
+                api_base,
+                ..Registry::crates_io()
+            },
+            max_retries,
+        )
+    }
+
+    pub fn with_registry(registry: Registry, max_retries: u32) -> Result<Self> {
         let headers = [
             (USER_AGENT, HeaderValue::from_static(APP_USER_AGENT)),
             (ACCEPT, HeaderValue::from_static("application/json")),
@@ -49,7 +61,38 @@ impl RegistryApi {
         ))
         .build();
 
-        Ok(Self { api_base, client })
+        Ok(Self { registry, client })
+    }
+
+    /// The registry this API talks to.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Download the `.crate` file of a release from the registry.
+    #[instrument(skip(self))]
+    pub async fn download_crate(&self, name: &KrateName, version: &Version) -> Result<Bytes> {
+        let url = self
+            .registry
+            .download_url(name, version)
+            .map_err(|_| Error::InvalidApiUrl)?;
+
+        let response = self
+            .client
+            .get(url)
+            .header(ACCEPT, HeaderValue::from_static("*/*"))
+            .send()
+            .await?;
+
+        if let Err(err) = response.error_for_status_ref() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(Error::HttpError(err.into(), text));
+        }
+
+        Ok(response
+            .bytes()
+            .await
+            .map_err(reqwest_middleware::Error::Reqwest)?)
     }
 
     /// Make a request to crates.io, parse the response as JSON.
@@ -107,7 +150,7 @@ impl RegistryApi {
         version: &Version,
     ) -> Result<Option<ReleaseData>> {
         let url = {
-            let mut url = self.api_base.clone();
+            let mut url = self.registry.api_base.clone();
             url.path_segments_mut()
                 .map_err(|_| Error::InvalidApiUrl)?
                 .extend(&["api", "v1", "crates", name.as_str(), "versions"]);
@@ -154,7 +197,7 @@ impl RegistryApi {
     /// Fetch owners from the registry's API
     async fn get_owners(&self, name: &KrateName) -> Result<Vec<CrateOwner>> {
         let url = {
-            let mut url = self.api_base.clone();
+            let mut url = self.registry.api_base.clone();
             url.path_segments_mut()
                 .map_err(|()| Error::InvalidApiUrl)?
                 .extend(&["api", "v1", "crates", name.as_str(), "owners"]);
@@ -198,10 +241,71 @@ impl RegistryApi {
         Ok(result)
     }
 
+    /// Fetch the crates that were updated after `since`, most recently updated first.
+    ///
+    /// Follows the pagination of the API until we reach crates that were updated
+    /// before `since`, for at most `max_pages` pages. The result tells if we got
+    /// there.
+    #[instrument(skip(self))]
+    pub async fn recently_updated_crates(
+        &self,
+        since: DateTime<Utc>,
+        max_pages: usize,
+    ) -> Result<RecentlyUpdatedCrates> {
+        let mut url = self.registry.api_base.clone();
+        url.path_segments_mut()
+            .map_err(|()| Error::InvalidApiUrl)?
+            .extend(&["api", "v1", "crates"]);
+        url.set_query(Some("sort=recent-updates&per_page=100"));
+
+        #[derive(Deserialize)]
+        struct Response {
+            crates: Vec<UpdatedCrate>,
+            #[serde(default)]
+            meta: Meta,
+        }
+
+        #[derive(Deserialize, Default)]
+        struct Meta {
+            next_page: Option<String>,
+        }
+
+        let mut result = Vec::new();
+        let mut complete = false;
+        for _ in 0..max_pages {
+            let response: Response = self.request(&url).await?;
+
+            let page_size = response.crates.len();
+            let before = result.len();
+            result.extend(
+                response
+                    .crates
+                    .into_iter()
+                    .take_while(|krate| krate.updated_at > since),
+            );
+            let reached_since = result.len() - before < page_size;
+
+            match response.meta.next_page {
+                Some(next_page) if page_size > 0 && !reached_since => {
+                    url.set_query(Some(next_page.trim_start_matches('?')));
+                }
+                _ => {
+                    complete = true;
+                    break;
+                }
+            }
+        }
+
+        Ok(RecentlyUpdatedCrates {
+            crates: result,
+            complete,
+        })
+    }
+
     /// Fetch crates from the registry's API.
     pub async fn search(&self, query_params: &str) -> Result<Search> {
         let url = {
-            let mut url = self.api_base.clone();
+            let mut url = self.registry.api_base.clone();
             url.path_segments_mut()
                 .map_err(|()| Error::InvalidApiUrl)?
                 .extend(&["api", "v1", "crates"]);
@@ -510,4 +614,109 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_download_crate_from_alternative_registry() -> Result<()> {
+        let mut registry_server = mockito::Server::new_async().await;
+
+        let _m = registry_server
+            .mock("GET", "/api/v1/crates/krate/1.0.0/download")
+            .with_status(200)
+            .with_body("crate content")
+            .create_async()
+            .await;
+
+        let api = RegistryApi::with_registry(
+            Registry {
+                name: "company".into(),
+                index_url: format!("sparse+{}/index/", registry_server.url()),
+                api_base: registry_server.url().parse().unwrap(),
+                download_url_template: format!("{}/api/v1/crates", registry_server.url()),
+            },
+            0,
+        )?;
+
+        assert_eq!(
+            api.download_crate(&KRATE, &V1).await?.as_ref(),
+            b"crate content"
+        );
+
+        let err = api.download_crate(&KRATE, &V2).await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::NOT_IMPLEMENTED));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recently_updated_crates() -> Result<()> {
+        let mut crates_io_api = mockito::Server::new_async().await;
+
+        let updated = |name: &str, minute: u32| UpdatedCrate {
+            name: name.parse().unwrap(),
+            updated_at: format!("2024-01-01T10:{minute:02}:00Z").parse().unwrap(),
+        };
+
+        let _first = crates_io_api
+            .mock("GET", "/api/v1/crates?sort=recent-updates&per_page=100")
+            .with_status(200)
+            .with_header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .with_body(
+                serde_json::to_vec(&serde_json::json!({
+                    "crates": [updated("foo", 30), updated("bar", 20)],
+                    "meta": { "next_page": "?sort=recent-updates&per_page=100&page=2" },
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+        let _second = crates_io_api
+            .mock(
+                "GET",
+                "/api/v1/crates?sort=recent-updates&per_page=100&page=2",
+            )
+            .with_status(200)
+            .with_header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .with_body(
+                serde_json::to_vec(&serde_json::json!({
+                    "crates": [updated("baz", 10), updated("old", 0)],
+                    "meta": { "next_page": "?sort=recent-updates&per_page=100&page=3" },
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let api = RegistryApi::new(crates_io_api.url().parse().unwrap(), 0)?;
+
+        assert_eq!(
+            api.recently_updated_crates("2024-01-01T10:05:00Z".parse().unwrap(), 10)
+                .await?,
+            RecentlyUpdatedCrates {
+                crates: vec![updated("foo", 30), updated("bar", 20), updated("baz", 10)],
+                complete: true,
+            }
+        );
+
+        // the page limit is respected, and we didn't reach `since`
+        assert_eq!(
+            api.recently_updated_crates("2024-01-01T10:05:00Z".parse().unwrap(), 1)
+                .await?,
+            RecentlyUpdatedCrates {
+                crates: vec![updated("foo", 30), updated("bar", 20)],
+                complete: false,
+            }
+        );
+
+        // when we reach `since` we don't fetch more pages
+        assert_eq!(
+            api.recently_updated_crates("2024-01-01T10:25:00Z".parse().unwrap(), 10)
+                .await?,
+            RecentlyUpdatedCrates {
+                crates: vec![updated("foo", 30)],
+                complete: true,
+            }
+        );
+
+        Ok(())
+    }
 }
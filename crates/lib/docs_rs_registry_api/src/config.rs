@@ -1,4 +1,5 @@
-use anyhow::Result;
+use crate::registry::{CRATES_IO, Registry, check_download_url_template};
+use anyhow::{Context as _, Result};
 use docs_rs_config::AppConfig;
 use docs_rs_env_vars::maybe_env;
 use url::Url;
@@ -8,6 +9,16 @@ pub struct Config {
     #[builder(default =  "https://crates.io".parse().unwrap())]
     pub registry_api_host: Url,
 
+    /// name of the registry we build documentation for.
+    #[builder(default = CRATES_IO.into())]
+    pub registry_name: String,
+
+    /// index URL of the registry, defaults to the crates.io index.
+    pub registry_index_url: Option<String>,
+
+    /// download URL template for `.crate` files, defaults to the crates.io CDN.
+    pub registry_download_url: Option<String>,
+
     // amount of retries for external API calls, mostly crates.io
     #[builder(default = 3)]
     pub crates_io_api_call_retries: u32,
@@ -15,9 +26,38 @@ pub struct Config {
 
 impl AppConfig for Config {
     fn from_environment() -> Result<Self> {
-        Ok(Self::builder()
+        let config = Self::builder()
             .maybe_crates_io_api_call_retries(maybe_env("DOCSRS_CRATESIO_API_CALL_RETRIES")?)
             .maybe_registry_api_host(maybe_env("DOCSRS_REGISTRY_API_HOST")?)
-            .build())
+            .maybe_registry_name(maybe_env("DOCSRS_REGISTRY_NAME")?)
+            .maybe_registry_index_url(maybe_env("REGISTRY_URL")?)
+            .maybe_registry_download_url(maybe_env("DOCSRS_REGISTRY_DOWNLOAD_URL")?)
+            .build();
+
+        if let Some(template) = &config.registry_download_url {
+            check_download_url_template(template)
+                .context("invalid DOCSRS_REGISTRY_DOWNLOAD_URL")?;
+        }
+
+        Ok(config)
+    }
+}
+
+impl Config {
+    /// The registry configured here, unset values fall back to crates.io.
+    pub fn registry(&self) -> Registry {
+        let crates_io = Registry::crates_io();
+        Registry {
+            name: self.registry_name.clone(),
+            index_url: self
+                .registry_index_url
+                .clone()
+                .unwrap_or(crates_io.index_url),
+            api_base: self.registry_api_host.clone(),
+            download_url_template: self
+                .registry_download_url
+                .clone()
+                .unwrap_or(crates_io.download_url_template),
+        }
     }
 }
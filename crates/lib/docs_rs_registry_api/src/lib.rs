@@ -2,8 +2,12 @@ mod api;
 mod config;
 mod error;
 mod models;
+mod registry;
 
 pub use api::RegistryApi;
 pub use config::Config;
 pub use error::Error;
-pub use models::{CrateData, CrateOwner, OwnerKind, ReleaseData, Search};
+pub use models::{
+    CrateData, CrateOwner, OwnerKind, RecentlyUpdatedCrates, ReleaseData, Search, UpdatedCrate,
+};
+pub use registry::{CRATES_IO, Registry};
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use docs_rs_types::KrateName;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -71,6 +72,23 @@ pub struct SearchMeta {
     pub prev_page: Option<String>,
 }
 
+/// A crate from the list of recently updated crates.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(test, derive(Serialize))]
+pub struct UpdatedCrate {
+    pub name: KrateName,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The crates that were updated after some point in time, most recently updated first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentlyUpdatedCrates {
+    pub crates: Vec<UpdatedCrate>,
+    /// did we reach that point in time? If not, the page limit stopped us,
+    /// and crates updated before the oldest crate in `crates` are missing.
+    pub complete: bool,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Search {
     pub crates: Vec<SearchCrate>,
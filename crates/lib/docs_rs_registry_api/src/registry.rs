@@ -0,0 +1,198 @@
+use anyhow::{Result, bail};
+use docs_rs_types::{KrateName, Version};
+use url::Url;
+
+/// name of the default registry, how it's stored in `releases.registry`.
+pub const CRATES_IO: &str = "crates-io";
+
+const CRATES_IO_INDEX_URL: &str = "https://github.com/rust-lang/crates.io-index";
+const CRATES_IO_DOWNLOAD_URL: &str =
+    "https://static.crates.io/crates/{crate}/{crate}-{version}.crate";
+/// we download crates without looking at the index, so we don't know their checksum.
+const SHA256_CHECKSUM_MARKER: &str = "{sha256-checksum}";
+
+/// A Cargo registry we build documentation for.
+///
+/// By default this is crates.io, but it can also be an alternative registry
+/// with a crates.io-compatible web API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registry {
+    /// short name of the registry, stored with each release.
+    pub name: String,
+    /// URL of the registry index, a git repository or a `sparse+` URL.
+    pub index_url: String,
+    /// base URL of the registry web API, like `https://crates.io`.
+    pub api_base: Url,
+    /// where to download `.crate` files from.
+    ///
+    /// Uses the same format as the `dl` key in the `config.json` of a registry index,
+    /// see <https://doc.rust-lang.org/cargo/reference/registry-index.html#index-configuration>,
+    /// except for the `{sha256-checksum}` marker.
+    pub download_url_template: String,
+}
+
+impl Registry {
+    pub fn crates_io() -> Self {
+        Self {
+            name: CRATES_IO.into(),
+            index_url: CRATES_IO_INDEX_URL.into(),
+            api_base: "https://crates.io".parse().unwrap(),
+            download_url_template: CRATES_IO_DOWNLOAD_URL.into(),
+        }
+    }
+
+    pub fn is_crates_io(&self) -> bool {
+        self.name == CRATES_IO
+    }
+
+    /// URL to download the `.crate` file of a release.
+    pub fn download_url(
+        &self,
+        name: &KrateName,
+        version: &Version,
+    ) -> Result<Url, url::ParseError> {
+        const MARKERS: &[&str] = &["{crate}", "{version}", "{prefix}", "{lowerprefix}"];
+
+        let template = &self.download_url_template;
+        if !MARKERS.iter().any(|marker| template.contains(marker)) {
+            return format!(
+                "{}/{name}/{version}/download",
+                template.trim_end_matches('/')
+            )
+            .parse();
+        }
+
+        let prefix = index_prefix(name.as_str());
+        template
+            .replace("{crate}", name.as_str())
+            .replace("{version}", &version.to_string())
+            .replace("{prefix}", &prefix)
+            .replace("{lowerprefix}", &prefix.to_lowercase())
+            .parse()
+    }
+
+    /// URL of the page of a crate in the registry web UI.
+    pub fn crate_url(&self, name: &KrateName) -> Url {
+        let mut url = self.api_base.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().extend(&["crates", name.as_str()]);
+        }
+        url
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::crates_io()
+    }
+}
+
+/// Check that we can fill in all markers of a download URL template.
+pub(crate) fn check_download_url_template(template: &str) -> Result<()> {
+    if template.contains(SHA256_CHECKSUM_MARKER) {
+        bail!("the {SHA256_CHECKSUM_MARKER} marker in the download URL template is not supported");
+    }
+    Ok(())
+}
+
+/// the directory prefix of a crate in a registry index, like `se/rd` for `serde`.
+fn index_prefix(name: &str) -> String {
+    match name.len() {
+        1 => "1".into(),
+        2 => "2".into(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn registry(download_url_template: &str) -> Registry {
+        Registry {
+            name: "company".into(),
+            index_url: "sparse+https://registry.example.com/index/".into(),
+            api_base: "https://registry.example.com".parse().unwrap(),
+            download_url_template: download_url_template.into(),
+        }
+    }
+
+    #[test_case("a", "1")]
+    #[test_case("ab", "2")]
+    #[test_case("abc", "3/a")]
+    #[test_case("Serde", "Se/rd")]
+    fn prefix(name: &str, expected: &str) {
+        assert_eq!(index_prefix(name), expected);
+    }
+
+    #[test]
+    fn crates_io_download_url() {
+        assert_eq!(
+            Registry::crates_io()
+                .download_url(&"serde".parse().unwrap(), &"1.0.0".parse().unwrap())
+                .unwrap()
+                .as_str(),
+            "https://static.crates.io/crates/serde/serde-1.0.0.crate"
+        );
+    }
+
+    #[test_case(
+        "https://registry.example.com/api/v1/crates",
+        "https://registry.example.com/api/v1/crates/Serde/1.0.0/download"
+    )]
+    #[test_case(
+        "https://registry.example.com/api/v1/crates/",
+        "https://registry.example.com/api/v1/crates/Serde/1.0.0/download";
+        "trailing slash"
+    )]
+    #[test_case(
+        "https://registry.example.com/{lowerprefix}/{crate}/{version}.crate",
+        "https://registry.example.com/se/rd/Serde/1.0.0.crate"
+    )]
+    #[test_case(
+        "https://registry.example.com/{prefix}/{crate}-{version}.crate",
+        "https://registry.example.com/Se/rd/Serde-1.0.0.crate"
+    )]
+    fn download_url_template(template: &str, expected: &str) {
+        assert_eq!(
+            registry(template)
+                .download_url(&"Serde".parse().unwrap(), &"1.0.0".parse().unwrap())
+                .unwrap()
+                .as_str(),
+            expected
+        );
+    }
+
+    #[test]
+    fn download_url_template_with_checksum() {
+        assert!(check_download_url_template(CRATES_IO_DOWNLOAD_URL).is_ok());
+        assert!(
+            check_download_url_template(
+                "https://registry.example.com/{crate}/{version}/{sha256-checksum}.crate"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn crate_url() {
+        assert_eq!(
+            Registry::crates_io()
+                .crate_url(&"serde".parse().unwrap())
+                .as_str(),
+            "https://crates.io/crates/serde"
+        );
+        assert_eq!(
+            registry("").crate_url(&"serde".parse().unwrap()).as_str(),
+            "https://registry.example.com/crates/serde"
+        );
+    }
+
+    #[test]
+    fn is_crates_io() {
+        assert!(Registry::crates_io().is_crates_io());
+        assert!(!registry("").is_crates_io());
+    }
+}
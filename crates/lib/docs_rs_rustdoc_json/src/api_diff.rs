@@ -0,0 +1,574 @@
+//! Compare the public API of two releases of a crate, based on their rustdoc JSON.
+//!
+//! On top of the items from [`extract_public_items`](crate::extract_public_items)
+//! we also look at the methods and the trait implementations of the public types
+//! and traits, since these are a big part of the API of most crates.
+
+use crate::items::{
+    ItemKind, ItemSummary, RustdocJson, public_items, render_generic_params, render_path,
+    render_signature,
+};
+use anyhow::Result;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::Read,
+};
+
+/// The kinds of items we compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiItemKind {
+    Item(ItemKind),
+    Method,
+    TraitImpl,
+}
+
+impl fmt::Display for ApiItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Item(kind) => kind.fmt(f),
+            Self::Method => f.write_str("method"),
+            Self::TraitImpl => f.write_str("trait_impl"),
+        }
+    }
+}
+
+/// A part of the public API of a crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiItem {
+    /// identifies the item in both releases, like `krate::Foo`, `krate::Foo::new`
+    /// or `impl Clone for krate::Foo`.
+    ///
+    /// Methods from impl blocks with different generics can share a path.
+    pub path: String,
+    pub kind: ApiItemKind,
+    /// short signature for functions, methods, constants, statics & type aliases.
+    pub signature: Option<String>,
+}
+
+/// The public API of a release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicApi {
+    /// format version of the rustdoc JSON we read the API from.
+    pub format_version: u16,
+    /// API items, sorted by path & signature.
+    pub items: Vec<ApiItem>,
+}
+
+/// An item that exists in both releases, but changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedApiItem {
+    pub old: ApiItem,
+    pub new: ApiItem,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiChanges {
+    pub added: Vec<ApiItem>,
+    pub removed: Vec<ApiItem>,
+    pub changed: Vec<ChangedApiItem>,
+}
+
+impl ApiChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiDiff {
+    /// The releases were documented with different rustdoc JSON format versions.
+    ///
+    /// The rendered signatures depend on the format, so we can't reliably compare them.
+    Incomparable {
+        old_format_version: u16,
+        new_format_version: u16,
+    },
+    Changes(ApiChanges),
+}
+
+/// read the public API of the documented crate from its rustdoc JSON.
+pub fn extract_public_api(reader: impl Read) -> Result<PublicApi> {
+    let rustdoc_json = RustdocJson::from_reader(reader)?;
+
+    let mut items: Vec<ApiItem> = public_items(&rustdoc_json)
+        .into_iter()
+        .map(|item| ApiItem {
+            path: item.path,
+            kind: ApiItemKind::Item(item.kind),
+            signature: item.signature,
+        })
+        .collect();
+
+    let public_paths: HashSet<&str> = items.iter().map(|item| item.path.as_str()).collect();
+
+    // the public types & traits of the crate, by id.
+    let local_types: HashMap<&str, String> = rustdoc_json
+        .paths
+        .iter()
+        .filter(|(_, summary)| summary.crate_id == 0)
+        .filter(|(_, summary)| {
+            matches!(summary.kind.as_str(), "struct" | "enum" | "union" | "trait")
+        })
+        .map(|(id, summary)| (id.as_str(), summary.path.join("::")))
+        .filter(|(_, path)| public_paths.contains(path.as_str()))
+        .collect();
+
+    let mut extra_items = Vec::new();
+
+    for (id, item) in &rustdoc_json.index {
+        if let Some(trait_) = item.inner.get("trait") {
+            // trait items don't have their own visibility.
+            let Some(trait_path) = local_types.get(id.as_str()) else {
+                continue;
+            };
+            extra_items.extend(methods(&rustdoc_json, trait_path, &trait_["items"], false));
+        } else if let Some(impl_) = item.inner.get("impl") {
+            // blanket impls come from the crate that defines the trait.
+            if !impl_["blanket_impl"].is_null() {
+                continue;
+            }
+
+            let self_type = &impl_["for"]["resolved_path"];
+            let Some(type_path) = id_key(&self_type["id"])
+                .as_deref()
+                .and_then(|id| local_types.get(id))
+            else {
+                continue;
+            };
+
+            if impl_["trait"].is_null() {
+                extra_items.extend(methods(&rustdoc_json, type_path, &impl_["items"], true));
+            } else {
+                let negative = impl_["is_negative"]
+                    .as_bool()
+                    .or(impl_["negative"].as_bool())
+                    .unwrap_or(false);
+                extra_items.push(ApiItem {
+                    path: format!(
+                        "impl{} {}{} for {}",
+                        render_generic_params(&impl_["generics"]),
+                        if negative { "!" } else { "" },
+                        render_full_path(&impl_["trait"], &rustdoc_json.paths),
+                        render_full_path(self_type, &rustdoc_json.paths),
+                    ),
+                    kind: ApiItemKind::TraitImpl,
+                    signature: None,
+                });
+            }
+        }
+    }
+
+    // the same method name can exist in multiple impl blocks with different
+    // generics, sorting by signature keeps the result stable.
+    extra_items.sort_unstable_by(|a, b| (&a.path, &a.signature).cmp(&(&b.path, &b.signature)));
+    extra_items.dedup();
+    extra_items.retain(|item| !public_paths.contains(item.path.as_str()));
+
+    items.extend(extra_items);
+    items.sort_unstable_by(|a, b| (&a.path, &a.signature).cmp(&(&b.path, &b.signature)));
+
+    Ok(PublicApi {
+        format_version: rustdoc_json.format_version,
+        items,
+    })
+}
+
+/// the methods from the item ids of an impl block or trait.
+fn methods(
+    rustdoc_json: &RustdocJson,
+    parent_path: &str,
+    ids: &Value,
+    only_public: bool,
+) -> Vec<ApiItem> {
+    ids.as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|id| rustdoc_json.index.get(&id_key(id)?))
+        .filter(|item| !only_public || item.visibility == "public")
+        .filter(|item| item.inner.get("function").is_some())
+        .filter_map(|item| {
+            let name = item.name.as_deref()?;
+            Some(ApiItem {
+                path: format!("{parent_path}::{name}"),
+                kind: ApiItemKind::Method,
+                signature: render_signature(name, ItemKind::Function, &item.inner),
+            })
+        })
+        .collect()
+}
+
+/// ids are strings in older format versions, and numbers in newer ones.
+fn id_key(id: &Value) -> Option<String> {
+    match id {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// render a path with the full path of the item it points to, when we know it.
+///
+/// This way the result doesn't depend on how the item was imported.
+fn render_full_path(path: &Value, paths: &HashMap<String, ItemSummary>) -> String {
+    let Some(summary) = id_key(&path["id"]).and_then(|id| paths.get(&id)) else {
+        return render_path(path);
+    };
+
+    let mut path = path.clone();
+    if let Some(path) = path.as_object_mut() {
+        path.insert("path".into(), summary.path.join("::").into());
+    }
+    render_path(&path)
+}
+
+/// Compare the public API of two releases.
+pub fn diff_public_api(old: &PublicApi, new: &PublicApi) -> ApiDiff {
+    if old.format_version != new.format_version {
+        return ApiDiff::Incomparable {
+            old_format_version: old.format_version,
+            new_format_version: new.format_version,
+        };
+    }
+
+    let old_items = items_by_path(&old.items);
+    let new_items = items_by_path(&new.items);
+
+    let mut changes = ApiChanges::default();
+
+    for (path, old_group) in &old_items {
+        let new_group = new_items.get(path).map(Vec::as_slice).unwrap_or_default();
+
+        let removed: Vec<&ApiItem> = old_group
+            .iter()
+            .filter(|item| !new_group.contains(item))
+            .copied()
+            .collect();
+        let added: Vec<&ApiItem> = new_group
+            .iter()
+            .filter(|item| !old_group.contains(item))
+            .copied()
+            .collect();
+
+        // when only one item with this path changed, we can show it as a change.
+        // Otherwise we can't tell which old item became which new one.
+        if let ([old_item], [new_item]) = (removed.as_slice(), added.as_slice()) {
+            changes.changed.push(ChangedApiItem {
+                old: (*old_item).clone(),
+                new: (*new_item).clone(),
+            });
+        } else {
+            changes.removed.extend(removed.into_iter().cloned());
+            changes.added.extend(added.into_iter().cloned());
+        }
+    }
+
+    changes.added.extend(
+        new_items
+            .iter()
+            .filter(|(path, _)| !old_items.contains_key(*path))
+            .flat_map(|(_, group)| group.iter().copied().cloned()),
+    );
+    changes
+        .added
+        .sort_by(|a, b| (&a.path, &a.signature).cmp(&(&b.path, &b.signature)));
+
+    ApiDiff::Changes(changes)
+}
+
+/// the items of a release, grouped by their path.
+fn items_by_path(items: &[ApiItem]) -> BTreeMap<&str, Vec<&ApiItem>> {
+    let mut result: BTreeMap<&str, Vec<&ApiItem>> = BTreeMap::new();
+    for item in items {
+        result.entry(item.path.as_str()).or_default().push(item);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn function(name: &str, inputs: Value, output: Value) -> Value {
+        json!({
+            "name": name,
+            "visibility": "public",
+            "inner": { "function": {
+                "sig": { "inputs": inputs, "output": output, "is_c_variadic": false },
+                "generics": { "params": [], "where_predicates": [] },
+                "header": { "is_const": false, "is_unsafe": false, "is_async": false },
+            } },
+        })
+    }
+
+    fn rustdoc_json(format_version: u16, new_signature: bool) -> Value {
+        let new_inputs = if new_signature {
+            json!([["capacity", { "primitive": "usize" }]])
+        } else {
+            json!([])
+        };
+
+        json!({
+            "format_version": format_version,
+            "root": 0,
+            "index": {
+                "0": { "name": "krate", "visibility": "public", "inner": { "module": {} } },
+                "1": { "name": "Foo", "visibility": "public", "inner": { "struct": {} } },
+                "2": { "name": "Speak", "visibility": "public", "inner": { "trait": {
+                    "items": [3],
+                } } },
+                "3": function(
+                    "speak",
+                    json!([["self", { "borrowed_ref": {
+                        "lifetime": null,
+                        "is_mutable": false,
+                        "type": { "generic": "Self" },
+                    } }]]),
+                    json!({ "primitive": "str" }),
+                ),
+                "4": { "name": null, "visibility": "default", "inner": { "impl": {
+                    "generics": { "params": [], "where_predicates": [] },
+                    "trait": null,
+                    "for": { "resolved_path": { "path": "Foo", "id": 1, "args": null } },
+                    "items": [5, 6],
+                    "is_negative": false,
+                    "is_synthetic": false,
+                    "blanket_impl": null,
+                } } },
+                "5": function(
+                    "new",
+                    new_inputs,
+                    json!({ "generic": "Self" }),
+                ),
+                "6": {
+                    "name": "internal",
+                    "visibility": "crate",
+                    "inner": { "function": {
+                        "sig": { "inputs": [], "output": null },
+                        "generics": { "params": [] },
+                        "header": {},
+                    } },
+                },
+                "7": { "name": null, "visibility": "default", "inner": { "impl": {
+                    "generics": { "params": [], "where_predicates": [] },
+                    "trait": { "path": "Clone", "id": 20, "args": null },
+                    "for": { "resolved_path": { "path": "Foo", "id": 1, "args": null } },
+                    "items": [],
+                    "is_negative": false,
+                    "is_synthetic": false,
+                    "blanket_impl": null,
+                } } },
+                "8": { "name": null, "visibility": "default", "inner": { "impl": {
+                    "generics": { "params": [{ "name": "T", "kind": { "type": {} } }] },
+                    "trait": { "path": "Into", "id": 21, "args": null },
+                    "for": { "generic": "T" },
+                    "items": [],
+                    "is_negative": false,
+                    "is_synthetic": false,
+                    "blanket_impl": { "generic": "T" },
+                } } },
+            },
+            "paths": {
+                "0": { "crate_id": 0, "path": ["krate"], "kind": "module" },
+                "1": { "crate_id": 0, "path": ["krate", "Foo"], "kind": "struct" },
+                "2": { "crate_id": 0, "path": ["krate", "Speak"], "kind": "trait" },
+                "20": { "crate_id": 1, "path": ["core", "clone", "Clone"], "kind": "trait" },
+                "21": { "crate_id": 1, "path": ["core", "convert", "Into"], "kind": "trait" },
+            },
+        })
+    }
+
+    fn public_api(json: Value) -> PublicApi {
+        extract_public_api(serde_json::to_vec(&json).unwrap().as_slice()).unwrap()
+    }
+
+    fn item(path: &str, kind: ApiItemKind, signature: Option<&str>) -> ApiItem {
+        ApiItem {
+            path: path.into(),
+            kind,
+            signature: signature.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn extract_api() {
+        assert_eq!(
+            public_api(rustdoc_json(45, false)),
+            PublicApi {
+                format_version: 45,
+                items: vec![
+                    item(
+                        "impl core::clone::Clone for krate::Foo",
+                        ApiItemKind::TraitImpl,
+                        None
+                    ),
+                    item("krate", ApiItemKind::Item(ItemKind::Module), None),
+                    item("krate::Foo", ApiItemKind::Item(ItemKind::Struct), None),
+                    item(
+                        "krate::Foo::new",
+                        ApiItemKind::Method,
+                        Some("fn new() -> Self")
+                    ),
+                    item("krate::Speak", ApiItemKind::Item(ItemKind::Trait), None),
+                    item(
+                        "krate::Speak::speak",
+                        ApiItemKind::Method,
+                        Some("fn speak(&self) -> str")
+                    ),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn extract_api_with_string_ids() {
+        // older format versions use strings for the ids.
+        let json = json!({
+            "format_version": 20,
+            "index": {
+                "0:1": { "name": "Foo", "visibility": "public", "inner": { "struct": {} } },
+                "0:2": { "name": null, "visibility": "default", "inner": { "impl": {
+                    "generics": { "params": [] },
+                    "trait": { "name": "Debug", "id": "1:5", "args": null },
+                    "for": { "resolved_path": { "name": "Foo", "id": "0:1", "args": null } },
+                    "items": [],
+                    "negative": false,
+                    "synthetic": false,
+                    "blanket_impl": null,
+                } } },
+            },
+            "paths": {
+                "0:1": { "crate_id": 0, "path": ["krate", "Foo"], "kind": "struct" },
+                "1:5": { "crate_id": 1, "path": ["core", "fmt", "Debug"], "kind": "trait" },
+            },
+        });
+
+        assert_eq!(
+            public_api(json).items,
+            vec![
+                item(
+                    "impl core::fmt::Debug for krate::Foo",
+                    ApiItemKind::TraitImpl,
+                    None
+                ),
+                item("krate::Foo", ApiItemKind::Item(ItemKind::Struct), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_api() {
+        let old = public_api(rustdoc_json(45, false));
+
+        let mut new_json = rustdoc_json(45, true);
+        new_json["index"]
+            .as_object_mut()
+            .unwrap()
+            .remove("7")
+            .unwrap();
+        new_json["index"]["9"] = json!({
+            "name": "Bar", "visibility": "public", "inner": { "enum": {} },
+        });
+        new_json["paths"]["9"] = json!({ "crate_id": 0, "path": ["krate", "Bar"], "kind": "enum" });
+        let new = public_api(new_json);
+
+        assert_eq!(
+            diff_public_api(&old, &new),
+            ApiDiff::Changes(ApiChanges {
+                added: vec![item("krate::Bar", ApiItemKind::Item(ItemKind::Enum), None)],
+                removed: vec![item(
+                    "impl core::clone::Clone for krate::Foo",
+                    ApiItemKind::TraitImpl,
+                    None
+                )],
+                changed: vec![ChangedApiItem {
+                    old: item(
+                        "krate::Foo::new",
+                        ApiItemKind::Method,
+                        Some("fn new() -> Self")
+                    ),
+                    new: item(
+                        "krate::Foo::new",
+                        ApiItemKind::Method,
+                        Some("fn new(capacity: usize) -> Self")
+                    ),
+                }],
+            })
+        );
+
+        assert_eq!(
+            diff_public_api(&old, &old),
+            ApiDiff::Changes(ApiChanges::default())
+        );
+    }
+
+    #[test]
+    fn methods_with_the_same_path() {
+        // a second impl block for `Foo`, with another `new` method.
+        let rustdoc_json = |inputs: Value| {
+            let mut json = rustdoc_json(45, false);
+            let mut impl_ = json["index"]["4"].clone();
+            impl_["inner"]["impl"]["items"] = json!([11]);
+            json["index"]["10"] = impl_;
+            json["index"]["11"] = function("new", inputs, json!({ "generic": "Self" }));
+            json
+        };
+
+        let old = public_api(rustdoc_json(json!([["value", { "primitive": "u8" }]])));
+        assert_eq!(
+            old.items
+                .iter()
+                .filter(|item| item.path == "krate::Foo::new")
+                .collect::<Vec<_>>(),
+            vec![
+                &item(
+                    "krate::Foo::new",
+                    ApiItemKind::Method,
+                    Some("fn new() -> Self")
+                ),
+                &item(
+                    "krate::Foo::new",
+                    ApiItemKind::Method,
+                    Some("fn new(value: u8) -> Self")
+                ),
+            ]
+        );
+
+        let new = public_api(rustdoc_json(json!([["value", { "primitive": "u16" }]])));
+        assert_eq!(
+            diff_public_api(&old, &new),
+            ApiDiff::Changes(ApiChanges {
+                changed: vec![ChangedApiItem {
+                    old: item(
+                        "krate::Foo::new",
+                        ApiItemKind::Method,
+                        Some("fn new(value: u8) -> Self")
+                    ),
+                    new: item(
+                        "krate::Foo::new",
+                        ApiItemKind::Method,
+                        Some("fn new(value: u16) -> Self")
+                    ),
+                }],
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn diff_api_with_different_format_versions() {
+        assert_eq!(
+            diff_public_api(
+                &public_api(rustdoc_json(45, false)),
+                &public_api(rustdoc_json(46, false))
+            ),
+            ApiDiff::Incomparable {
+                old_format_version: 45,
+                new_format_version: 46,
+            }
+        );
+    }
+}
@@ -0,0 +1,593 @@
+//! Extract the public items of a crate from its rustdoc JSON output.
+//!
+//! We only need a small part of the (big) rustdoc JSON, and we want to support
+//! multiple format versions, so we don't use `rustdoc-types` here, and instead
+//! work on the raw JSON values.
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{BufReader, Read},
+};
+
+/// The kinds of items we index.
+///
+/// These are the items that get their own page in the rustdoc output.
+#[derive(strum::Display, strum::EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ItemKind {
+    Module,
+    Struct,
+    Enum,
+    Union,
+    Trait,
+    TraitAlias,
+    Function,
+    TypeAlias,
+    Constant,
+    Static,
+    Macro,
+    ProcAttribute,
+    ProcDerive,
+    Primitive,
+}
+
+impl ItemKind {
+    /// parse the kind from the `paths` section of the rustdoc JSON.
+    fn from_rustdoc(kind: &str) -> Option<Self> {
+        match kind {
+            // older format versions
+            "typedef" => Some(Self::TypeAlias),
+            kind => kind.parse().ok(),
+        }
+    }
+
+    /// The prefix rustdoc uses for the page of this item kind,
+    /// like `struct` in `struct.Foo.html`.
+    fn rustdoc_page_prefix(&self) -> &'static str {
+        match self {
+            Self::Module => "index",
+            Self::Struct => "struct",
+            Self::Enum => "enum",
+            Self::Union => "union",
+            Self::Trait => "trait",
+            Self::TraitAlias => "traitalias",
+            Self::Function => "fn",
+            Self::TypeAlias => "type",
+            Self::Constant => "constant",
+            Self::Static => "static",
+            Self::Macro => "macro",
+            Self::ProcAttribute => "attr",
+            Self::ProcDerive => "derive",
+            Self::Primitive => "primitive",
+        }
+    }
+}
+
+/// A public item of a crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicItem {
+    /// full path to the item, starting with the crate, like `tokio::io::AsyncSeek`.
+    pub path: String,
+    pub kind: ItemKind,
+    /// short signature for functions, constants, statics & type aliases.
+    pub signature: Option<String>,
+}
+
+impl PublicItem {
+    pub fn name(&self) -> &str {
+        self.path.rsplit("::").next().unwrap_or(&self.path)
+    }
+}
+
+/// path to the rustdoc page of an item, relative to the documentation root.
+///
+/// `path` is the full item path, starting with the crate, like `tokio::io::AsyncSeek`.
+pub fn rustdoc_page_path(path: &str, kind: ItemKind) -> String {
+    let mut components: Vec<&str> = path.split("::").collect();
+
+    if kind == ItemKind::Module {
+        return format!("{}/index.html", components.join("/"));
+    }
+
+    let name = components.pop().unwrap_or_default();
+    components.push("");
+    format!(
+        "{}{}.{}.html",
+        components.join("/"),
+        kind.rustdoc_page_prefix(),
+        name
+    )
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RustdocJson {
+    pub(crate) format_version: u16,
+    pub(crate) index: HashMap<String, IndexItem>,
+    pub(crate) paths: HashMap<String, ItemSummary>,
+}
+
+impl RustdocJson {
+    pub(crate) fn from_reader(reader: impl Read) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(reader))?)
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct IndexItem {
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+    #[serde(default)]
+    pub(crate) visibility: Value,
+    #[serde(default)]
+    pub(crate) inner: Value,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ItemSummary {
+    pub(crate) crate_id: u32,
+    pub(crate) path: Vec<String>,
+    pub(crate) kind: String,
+}
+
+/// read all public items of the documented crate from its rustdoc JSON.
+///
+/// Items are sorted by path.
+pub fn extract_public_items(reader: impl Read) -> Result<Vec<PublicItem>> {
+    Ok(public_items(&RustdocJson::from_reader(reader)?))
+}
+
+pub(crate) fn public_items(rustdoc_json: &RustdocJson) -> Vec<PublicItem> {
+    // `paths` contains the canonical paths for all items we can link to,
+    // `crate_id` 0 is the crate we documented.
+    let mut seen = BTreeSet::new();
+    let mut items: Vec<PublicItem> = rustdoc_json
+        .paths
+        .iter()
+        .filter(|(_, summary)| summary.crate_id == 0)
+        .filter_map(|(id, summary)| {
+            let kind = ItemKind::from_rustdoc(&summary.kind)?;
+            let item = rustdoc_json.index.get(id)?;
+            if item.visibility != "public" {
+                return None;
+            }
+
+            let path = summary.path.join("::");
+            if !seen.insert(path.clone()) {
+                return None;
+            }
+
+            Some(PublicItem {
+                signature: render_signature(summary.path.last()?, kind, &item.inner),
+                path,
+                kind,
+            })
+        })
+        .collect();
+
+    items.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    items
+}
+
+pub(crate) fn render_signature(name: &str, kind: ItemKind, inner: &Value) -> Option<String> {
+    match kind {
+        ItemKind::Function => {
+            let function = &inner["function"];
+            // renamed from `decl` to `sig` in newer format versions
+            let sig = function.get("sig").or_else(|| function.get("decl"))?;
+
+            let mut result = String::new();
+            let header = &function["header"];
+            for (flag, old_flag, keyword) in [
+                ("is_const", "const", "const "),
+                ("is_async", "async", "async "),
+                ("is_unsafe", "unsafe", "unsafe "),
+            ] {
+                if header[flag].as_bool().or(header[old_flag].as_bool()) == Some(true) {
+                    result.push_str(keyword);
+                }
+            }
+            result.push_str("fn ");
+            result.push_str(name);
+            result.push_str(&render_generic_params(&function["generics"]));
+            result.push('(');
+            let inputs = sig["inputs"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+            result.push_str(
+                &inputs
+                    .iter()
+                    .map(|input| match input[0].as_str() {
+                        Some("self") => render_self(&input[1]),
+                        Some(name) => format!("{name}: {}", render_type(&input[1])),
+                        None => render_type(&input[1]),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+            result.push(')');
+            if !sig["output"].is_null() {
+                result.push_str(" -> ");
+                result.push_str(&render_type(&sig["output"]));
+            }
+            Some(result)
+        }
+        ItemKind::Constant => Some(format!(
+            "const {name}: {}",
+            render_type(&inner["constant"]["type"])
+        )),
+        ItemKind::Static => {
+            let static_ = &inner["static"];
+            Some(format!(
+                "static {}{name}: {}",
+                if is_mutable(static_) { "mut " } else { "" },
+                render_type(&static_["type"])
+            ))
+        }
+        ItemKind::TypeAlias => {
+            let alias = inner.get("type_alias").or_else(|| inner.get("typedef"))?;
+            Some(format!(
+                "type {name}{} = {}",
+                render_generic_params(&alias["generics"]),
+                render_type(&alias["type"])
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// render the `self` argument of a method in its short form.
+fn render_self(ty: &Value) -> String {
+    if let Some(reference) = ty.get("borrowed_ref") {
+        let mutable = is_mutable(reference);
+        match reference["lifetime"].as_str() {
+            Some(lifetime) => format!("&{lifetime} {}self", if mutable { "mut " } else { "" }),
+            None => format!("&{}self", if mutable { "mut " } else { "" }),
+        }
+    } else if ty.get("generic").and_then(Value::as_str) == Some("Self") {
+        "self".into()
+    } else {
+        format!("self: {}", render_type(ty))
+    }
+}
+
+fn is_mutable(value: &Value) -> bool {
+    value["is_mutable"]
+        .as_bool()
+        .or(value["mutable"].as_bool())
+        .unwrap_or(false)
+}
+
+/// render generic parameters, skipping the synthetic ones from `impl Trait` arguments.
+pub(crate) fn render_generic_params(generics: &Value) -> String {
+    let params: Vec<&str> = generics["params"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+        .iter()
+        .filter(|param| {
+            param["kind"]["type"]["is_synthetic"].as_bool() != Some(true)
+                && param["kind"]["type"]["synthetic"].as_bool() != Some(true)
+        })
+        .filter_map(|param| param["name"].as_str())
+        .collect();
+
+    if params.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", params.join(", "))
+    }
+}
+
+pub(crate) fn render_path(path: &Value) -> String {
+    // `name` was renamed to `path` in newer format versions
+    let name = path["path"]
+        .as_str()
+        .or(path["name"].as_str())
+        .unwrap_or("_");
+
+    let args = &path["args"];
+    if let Some(angle_bracketed) = args.get("angle_bracketed") {
+        let args: Vec<String> = angle_bracketed["args"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+            .iter()
+            .map(|arg| {
+                if let Some(ty) = arg.get("type") {
+                    render_type(ty)
+                } else if let Some(lifetime) = arg.get("lifetime").and_then(Value::as_str) {
+                    lifetime.to_owned()
+                } else {
+                    "_".to_owned()
+                }
+            })
+            .collect();
+        if args.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{name}<{}>", args.join(", "))
+        }
+    } else if let Some(parenthesized) = args.get("parenthesized") {
+        let inputs: Vec<String> = parenthesized["inputs"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+            .iter()
+            .map(render_type)
+            .collect();
+        let mut result = format!("{name}({})", inputs.join(", "));
+        if !parenthesized["output"].is_null() {
+            result.push_str(" -> ");
+            result.push_str(&render_type(&parenthesized["output"]));
+        }
+        result
+    } else {
+        name.to_owned()
+    }
+}
+
+fn render_bounds(bounds: &Value) -> String {
+    bounds
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+        .iter()
+        .map(|bound| {
+            if let Some(trait_bound) = bound.get("trait_bound") {
+                render_path(&trait_bound["trait"])
+            } else if let Some(lifetime) = bound.get("outlives").and_then(Value::as_str) {
+                lifetime.to_owned()
+            } else {
+                "_".to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// render a type in a Rust-like syntax.
+///
+/// This is only meant for display, unknown parts are rendered as `_`.
+pub(crate) fn render_type(ty: &Value) -> String {
+    let Some((kind, value)) = ty.as_object().and_then(|ty| ty.iter().next()) else {
+        return "_".into();
+    };
+
+    match kind.as_str() {
+        "resolved_path" => render_path(value),
+        "generic" | "primitive" => value.as_str().unwrap_or("_").to_owned(),
+        "borrowed_ref" => {
+            let mut result = String::from("&");
+            if let Some(lifetime) = value["lifetime"].as_str() {
+                result.push_str(lifetime);
+                result.push(' ');
+            }
+            if is_mutable(value) {
+                result.push_str("mut ");
+            }
+            result.push_str(&render_type(&value["type"]));
+            result
+        }
+        "raw_pointer" => format!(
+            "*{} {}",
+            if is_mutable(value) { "mut" } else { "const" },
+            render_type(&value["type"])
+        ),
+        "slice" => format!("[{}]", render_type(value)),
+        "array" => format!(
+            "[{}; {}]",
+            render_type(&value["type"]),
+            value["len"].as_str().unwrap_or("_")
+        ),
+        "tuple" => {
+            let types: Vec<String> = value
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or(&[])
+                .iter()
+                .map(render_type)
+                .collect();
+            if types.len() == 1 {
+                format!("({},)", types[0])
+            } else {
+                format!("({})", types.join(", "))
+            }
+        }
+        "impl_trait" => format!("impl {}", render_bounds(value)),
+        "dyn_trait" => {
+            let mut bounds: Vec<String> = value["traits"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or(&[])
+                .iter()
+                .map(|bound| render_path(&bound["trait"]))
+                .collect();
+            if let Some(lifetime) = value["lifetime"].as_str() {
+                bounds.push(lifetime.to_owned());
+            }
+            format!("dyn {}", bounds.join(" + "))
+        }
+        "qualified_path" => {
+            let self_type = render_type(&value["self_type"]);
+            let name = value["name"].as_str().unwrap_or("_");
+            if value["trait"].is_null() {
+                format!("{self_type}::{name}")
+            } else {
+                format!("<{self_type} as {}>::{name}", render_path(&value["trait"]))
+            }
+        }
+        _ => "_".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn rustdoc_json() -> Value {
+        json!({
+            "format_version": 45,
+            "root": 0,
+            "index": {
+                "0": { "name": "krate", "visibility": "public", "inner": { "module": {} } },
+                "1": { "name": "io", "visibility": "public", "inner": { "module": {} } },
+                "2": { "name": "AsyncSeek", "visibility": "public", "inner": { "trait": {} } },
+                "3": {
+                    "name": "read_to_string",
+                    "visibility": "public",
+                    "inner": { "function": {
+                        "sig": {
+                            "inputs": [
+                                ["reader", { "borrowed_ref": {
+                                    "lifetime": null,
+                                    "is_mutable": true,
+                                    "type": { "generic": "R" },
+                                } }],
+                                ["limit", { "resolved_path": {
+                                    "path": "Option",
+                                    "id": 10,
+                                    "args": { "angle_bracketed": {
+                                        "args": [{ "type": { "primitive": "usize" } }],
+                                        "constraints": [],
+                                    } },
+                                } }],
+                            ],
+                            "output": { "resolved_path": {
+                                "path": "io::Result",
+                                "id": 11,
+                                "args": { "angle_bracketed": {
+                                    "args": [{ "type": { "resolved_path": {
+                                        "path": "String",
+                                        "id": 12,
+                                        "args": null,
+                                    } } }],
+                                    "constraints": [],
+                                } },
+                            } },
+                            "is_c_variadic": false,
+                        },
+                        "generics": {
+                            "params": [{ "name": "R", "kind": { "type": {
+                                "bounds": [],
+                                "default": null,
+                                "is_synthetic": false,
+                            } } }],
+                            "where_predicates": [],
+                        },
+                        "header": { "is_const": false, "is_unsafe": false, "is_async": true },
+                    } },
+                },
+                "4": { "name": "MAX", "visibility": "public", "inner": { "constant": {
+                    "type": { "primitive": "u64" },
+                } } },
+                "5": { "name": "Private", "visibility": "crate", "inner": { "struct": {} } },
+                "6": { "name": "Result", "visibility": "public", "inner": { "type_alias": {
+                    "type": { "resolved_path": {
+                        "path": "std::result::Result",
+                        "id": 13,
+                        "args": { "angle_bracketed": {
+                            "args": [
+                                { "type": { "generic": "T" } },
+                                { "type": { "resolved_path": { "path": "Error", "id": 14 } } },
+                            ],
+                            "constraints": [],
+                        } },
+                    } },
+                    "generics": { "params": [{ "name": "T", "kind": { "type": {} } }] },
+                } } },
+            },
+            "paths": {
+                "0": { "crate_id": 0, "path": ["krate"], "kind": "module" },
+                "1": { "crate_id": 0, "path": ["krate", "io"], "kind": "module" },
+                "2": { "crate_id": 0, "path": ["krate", "io", "AsyncSeek"], "kind": "trait" },
+                "3": { "crate_id": 0, "path": ["krate", "io", "read_to_string"], "kind": "function" },
+                "4": { "crate_id": 0, "path": ["krate", "MAX"], "kind": "constant" },
+                "5": { "crate_id": 0, "path": ["krate", "Private"], "kind": "struct" },
+                "6": { "crate_id": 0, "path": ["krate", "io", "Result"], "kind": "type_alias" },
+                "10": { "crate_id": 1, "path": ["core", "option", "Option"], "kind": "enum" },
+            },
+        })
+    }
+
+    #[test]
+    fn extract_items() {
+        let items =
+            extract_public_items(serde_json::to_vec(&rustdoc_json()).unwrap().as_slice()).unwrap();
+
+        assert_eq!(
+            items,
+            vec![
+                PublicItem {
+                    path: "krate".into(),
+                    kind: ItemKind::Module,
+                    signature: None,
+                },
+                PublicItem {
+                    path: "krate::MAX".into(),
+                    kind: ItemKind::Constant,
+                    signature: Some("const MAX: u64".into()),
+                },
+                PublicItem {
+                    path: "krate::io".into(),
+                    kind: ItemKind::Module,
+                    signature: None,
+                },
+                PublicItem {
+                    path: "krate::io::AsyncSeek".into(),
+                    kind: ItemKind::Trait,
+                    signature: None,
+                },
+                PublicItem {
+                    path: "krate::io::Result".into(),
+                    kind: ItemKind::TypeAlias,
+                    signature: Some("type Result<T> = std::result::Result<T, Error>".into()),
+                },
+                PublicItem {
+                    path: "krate::io::read_to_string".into(),
+                    kind: ItemKind::Function,
+                    signature: Some(
+                        "async fn read_to_string<R>(reader: &mut R, limit: Option<usize>) \
+                         -> io::Result<String>"
+                            .into()
+                    ),
+                },
+            ]
+        );
+        assert_eq!(items[3].name(), "AsyncSeek");
+    }
+
+    #[test]
+    fn extract_items_from_minimal_json() {
+        assert!(
+            extract_public_items(&br#"{"format_version": 42, "index": {}, "paths": {}}"#[..])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test_case("krate", ItemKind::Module, "krate/index.html")]
+    #[test_case("krate::io", ItemKind::Module, "krate/io/index.html")]
+    #[test_case(
+        "krate::io::AsyncSeek",
+        ItemKind::Trait,
+        "krate/io/trait.AsyncSeek.html"
+    )]
+    #[test_case("krate::read", ItemKind::Function, "krate/fn.read.html")]
+    #[test_case("krate::Result", ItemKind::TypeAlias, "krate/type.Result.html")]
+    #[test_case("krate::vec", ItemKind::Macro, "krate/macro.vec.html")]
+    fn page_path(path: &str, kind: ItemKind, expected: &str) {
+        assert_eq!(rustdoc_page_path(path, kind), expected);
+    }
+
+    #[test_case("typedef", Some(ItemKind::TypeAlias))]
+    #[test_case("type_alias", Some(ItemKind::TypeAlias))]
+    #[test_case("proc_derive", Some(ItemKind::ProcDerive))]
+    #[test_case("struct_field", None)]
+    #[test_case("impl", None)]
+    fn kind_from_rustdoc(kind: &str, expected: Option<ItemKind>) {
+        assert_eq!(ItemKind::from_rustdoc(kind), expected);
+    }
+}
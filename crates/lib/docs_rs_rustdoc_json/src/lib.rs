@@ -1,8 +1,17 @@
+mod api_diff;
+mod items;
+
 use anyhow::Result;
 use docs_rs_types::CompressionAlgorithm;
 use serde::Deserialize;
 use std::{io::BufReader, num::ParseIntError, str::FromStr};
 
+pub use api_diff::{
+    ApiChanges, ApiDiff, ApiItem, ApiItemKind, ChangedApiItem, PublicApi, diff_public_api,
+    extract_public_api,
+};
+pub use items::{ItemKind, PublicItem, extract_public_items, rustdoc_page_path};
+
 pub const RUSTDOC_JSON_COMPRESSION_ALGORITHMS: &[CompressionAlgorithm] =
     &[CompressionAlgorithm::Zstd, CompressionAlgorithm::Gzip];
 
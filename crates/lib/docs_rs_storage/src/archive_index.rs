@@ -4,6 +4,7 @@ use crate::{
 };
 use anyhow::{Context as _, Result, anyhow, bail};
 use async_stream::try_stream;
+use chrono::{DateTime, Utc};
 use docs_rs_mimes::detect_mime;
 use docs_rs_opentelemetry::AnyMeterProvider;
 use docs_rs_types::{BuildId, CompressionAlgorithm};
@@ -25,7 +26,7 @@ use std::{
         Arc,
         atomic::{AtomicU64, Ordering},
     },
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 use tokio::{
     fs,
@@ -138,7 +139,19 @@ impl Metrics {
 pub struct FileInfo {
     path: PathBuf,
     range: FileRange,
-    compression: CompressionAlgorithm,
+    /// `None` for files stored without compression.
+    compression: Option<CompressionAlgorithm>,
+    /// CRC-32 and uncompressed size of the file, from the zip directory.
+    ///
+    /// Only set in archive indexes created after we started storing them.
+    crc32: Option<u32>,
+    size: Option<u64>,
+    /// The content-addressed blob with the file, the range is inside this blob.
+    ///
+    /// `None` for files inside the archive itself.
+    blob: Option<String>,
+    /// The object with the ZIP archive, see [`Index::archive`].
+    archive: Option<String>,
 }
 
 pub(crate) struct Entry {
@@ -201,6 +214,22 @@ pub(crate) trait Downloader {
         &'a self,
         remote_index_path: &'a str,
     ) -> Pin<Box<dyn Future<Output = Result<StreamingBlob>> + Send + 'a>>;
+
+    /// when the remote archive index was last modified.
+    fn archive_index_modified<'a>(
+        &'a self,
+        remote_index_path: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<DateTime<Utc>>> + Send + 'a>>
+    where
+        Self: Sync,
+    {
+        Box::pin(async move {
+            Ok(self
+                .fetch_archive_index(remote_index_path)
+                .await?
+                .date_updated)
+        })
+    }
 }
 
 impl Cache {
@@ -485,9 +514,22 @@ impl Cache {
         downloader: &D,
     ) -> Result<Index> {
         let local_index_path = self.local_index_path(archive_path, latest_build_id);
+        let remote_index_path = format!("{archive_path}.{ARCHIVE_INDEX_FILE_EXTENSION}");
 
         // fast path: try to use whatever is there, no locking
         let force_redownload = match Index::open(&local_index_path).await {
+            Ok(_)
+                if self
+                    .is_outdated(&local_index_path, &remote_index_path, downloader)
+                    .await =>
+            {
+                debug!(
+                    archive_path,
+                    "remote archive index changed, will download it again."
+                );
+                self.purge(archive_path, latest_build_id).await?;
+                false
+            }
             Ok(index) => {
                 // Keep moka's recency/frequency view in sync with successful fast-path
                 // file lookups so TTI and admission decisions reflect real usage.
@@ -510,8 +552,6 @@ impl Cache {
             }
         };
 
-        let remote_index_path = format!("{archive_path}.{ARCHIVE_INDEX_FILE_EXTENSION}");
-
         // moka will coalesce all concurrent calls to try_get_with_by_ref with the same key
         // into a single call to the async closure.
         // https://docs.rs/moka/0.12.14/moka/future/struct.Cache.html#concurrent-calls-on-the-same-key
@@ -584,6 +624,60 @@ impl Cache {
         Index::open(local_index_path).await
     }
 
+    /// Whether the remote archive index was modified after we downloaded our local copy.
+    ///
+    /// The cache key only changes with a new build, but an archive can also be replaced,
+    /// see `AsyncStorage::repack_archive`. We only ask the remote storage every
+    /// `revalidate_after`, and remember when we did in the modification time of the
+    /// local file.
+    ///
+    /// We note the check before we ask, so concurrent requests for the same index don't
+    /// ask too. When we can't ask, we keep using our local copy until the next check.
+    async fn is_outdated<D: Downloader + Sync>(
+        &self,
+        local_index_path: &Path,
+        remote_index_path: &str,
+        downloader: &D,
+    ) -> bool {
+        let checked_at = match fs::metadata(local_index_path)
+            .await
+            .and_then(|metadata| metadata.modified())
+        {
+            Ok(checked_at) => checked_at,
+            Err(err) => {
+                warn!(
+                    ?err,
+                    ?local_index_path,
+                    "can't read local archive index metadata"
+                );
+                return false;
+            }
+        };
+        if checked_at.elapsed().unwrap_or_default() < self.config.revalidate_after {
+            return false;
+        }
+
+        if let Err(err) = touch(local_index_path).await {
+            warn!(
+                ?err,
+                ?local_index_path,
+                "can't update local archive index metadata"
+            );
+            return false;
+        }
+
+        match downloader.archive_index_modified(remote_index_path).await {
+            Ok(modified) => modified > DateTime::<Utc>::from(checked_at),
+            Err(err) => {
+                warn!(
+                    ?err,
+                    remote_index_path, "can't revalidate archive index, using the local copy"
+                );
+                false
+            }
+        }
+    }
+
     /// Find the file metadata needed to fetch a certain path inside a remote archive.
     /// Will try to use a local cache of the index file, and otherwise download it
     /// from storage.
@@ -681,12 +775,68 @@ impl Drop for Cache {
 }
 
 impl FileInfo {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
     pub(crate) fn range(&self) -> FileRange {
         self.range.clone()
     }
-    pub(crate) fn compression(&self) -> CompressionAlgorithm {
+    pub(crate) fn compression(&self) -> Option<CompressionAlgorithm> {
         self.compression
     }
+    pub fn crc32(&self) -> Option<u32> {
+        self.crc32
+    }
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+    /// How many bytes we fetch from the storage for this file, it might be compressed.
+    pub fn stored_size(&self) -> u64 {
+        self.range.end() - self.range.start() + 1
+    }
+    pub(crate) fn blob(&self) -> Option<&str> {
+        self.blob.as_deref()
+    }
+
+    /// The object the range is in, for a file in the archive at `archive_path`.
+    pub(crate) fn object<'a>(&'a self, archive_path: &'a str) -> &'a str {
+        self.blob
+            .as_deref()
+            .or(self.archive.as_deref())
+            .unwrap_or(archive_path)
+    }
+
+    /// If the file has the same content as `other`, based on the CRC-32 and size.
+    ///
+    /// `None` when one of the archive indexes doesn't have this metadata.
+    pub fn same_content_as(&self, other: &FileInfo) -> Option<bool> {
+        Some(self.crc32? == other.crc32? && self.size? == other.size?)
+    }
+
+    /// If both describe the same file at the same place.
+    ///
+    /// The CRC-32 and size are only compared when both archive indexes have them.
+    pub(crate) fn same_entry_as(&self, other: &FileInfo) -> bool {
+        self.path == other.path
+            && self.range == other.range
+            && self.compression == other.compression
+            && self.blob == other.blob
+            && self.same_content_as(other).unwrap_or(true)
+    }
+}
+
+impl From<IndexEntry> for FileInfo {
+    fn from(entry: IndexEntry) -> Self {
+        FileInfo {
+            path: entry.path.into(),
+            range: entry.start..=entry.end,
+            compression: entry.compression,
+            crc32: Some(entry.crc32),
+            size: Some(entry.size),
+            blob: entry.blob,
+            archive: None,
+        }
+    }
 }
 
 /// creates a new empty SQLite database, and returns a configured connection
@@ -728,18 +878,40 @@ async fn sqlite_open<P: AsRef<Path>>(path: P) -> Result<sqlx::SqliteConnection>
         .map_err(Into::into)
 }
 
-/// create an archive index based on a zipfile.
-///
-/// Will delete the destination file if it already exists.
-#[instrument(skip(zipfile))]
-pub(crate) async fn create<R, P>(zipfile: R, destination: P) -> Result<R>
-where
-    R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
-    P: AsRef<Path> + std::fmt::Debug,
-{
-    let mut conn = sqlite_create(destination).await?;
-    let mut tx = conn.begin().await?;
+/// set the modification time of a file to now.
+async fn touch(path: &Path) -> io::Result<()> {
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .await?
+        .into_std()
+        .await
+        .set_modified(SystemTime::now())
+}
+
+fn compression_from_raw(compression_raw: Option<i32>) -> Result<Option<CompressionAlgorithm>> {
+    compression_raw
+        .map(|raw| {
+            raw.try_into()
+                .map_err(|value| anyhow!("invalid compression algorithm '{value}' in database"))
+        })
+        .transpose()
+}
+
+/// one file, as we store it in the archive index.
+pub(crate) struct IndexEntry {
+    pub(crate) path: String,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    /// `None` for stored entries.
+    pub(crate) compression: Option<CompressionAlgorithm>,
+    pub(crate) crc32: u32,
+    pub(crate) size: u64,
+    /// `None` when the file is inside the archive.
+    pub(crate) blob: Option<String>,
+}
 
+async fn create_files_table(conn: &mut sqlx::SqliteConnection) -> Result<()> {
     sqlx::query(
         r#"
             CREATE TABLE files (
@@ -747,33 +919,121 @@ where
                 path TEXT UNIQUE,
                 start INTEGER,
                 end INTEGER,
-                compression INTEGER
+                compression INTEGER,
+                crc32 INTEGER,
+                size INTEGER,
+                blob TEXT
             );
         "#,
     )
-    .execute(&mut *tx)
+    .execute(conn)
     .await?;
+    Ok(())
+}
+
+/// for an archive that isn't stored at its archive path, see [`Index::archive`].
+async fn create_archive_table(conn: &mut sqlx::SqliteConnection, object: &str) -> Result<()> {
+    sqlx::query("CREATE TABLE archive (object TEXT NOT NULL);")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("INSERT INTO archive (object) VALUES (?);")
+        .bind(object)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+async fn insert_entries(
+    conn: &mut sqlx::SqliteConnection,
+    entries: impl IntoIterator<Item = IndexEntry>,
+) -> Result<()> {
+    let mut insert_stmt = QueryBuilder::<Sqlite>::new(
+        "INSERT INTO files (path, start, end, compression, crc32, size, blob) ",
+    );
+    insert_stmt.push_values(entries, |mut b, entry| {
+        b.push_bind(entry.path)
+            .push_bind(entry.start as i64)
+            .push_bind(entry.end as i64)
+            .push_bind(entry.compression.map(|alg| alg as i32))
+            .push_bind(entry.crc32 as i64)
+            .push_bind(entry.size as i64)
+            .push_bind(entry.blob);
+    });
+    insert_stmt.build().persistent(false).execute(conn).await?;
+    Ok(())
+}
+
+async fn finish_index(mut conn: sqlx::SqliteConnection) -> Result<()> {
+    sqlx::query("CREATE INDEX idx_files_path ON files (path);")
+        .execute(&mut conn)
+        .await?;
+    sqlx::query("VACUUM").execute(&mut conn).await?;
+    Ok(())
+}
+
+/// The archive index entry for the file at `index` in a ZIP archive.
+///
+/// Reads the local header of the file, for the start of its data.
+pub(crate) fn zip_index_entry<R>(
+    archive: &mut zip::ZipArchive<R>,
+    index: usize,
+) -> Result<IndexEntry>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    let entry = archive.by_index(index)?;
+
+    let start = entry
+        .data_start()
+        .ok_or_else(|| anyhow!("missing data_start in zip directory"))?;
+    let end = start + entry.compressed_size() - 1;
+    let compression = match entry.compression() {
+        zip::CompressionMethod::Stored => None,
+        zip::CompressionMethod::Bzip2 => Some(CompressionAlgorithm::Bzip2),
+        zip::CompressionMethod::Deflated => Some(CompressionAlgorithm::Deflate),
+        zip::CompressionMethod::Zstd => Some(CompressionAlgorithm::Zstd),
+        c => bail!("unsupported compression algorithm {} in zip-file", c),
+    };
+
+    Ok(IndexEntry {
+        path: entry.name().to_string(),
+        start,
+        end,
+        compression,
+        crc32: entry.crc32(),
+        size: entry.size(),
+        blob: None,
+    })
+}
+
+/// create an archive index based on a zipfile.
+///
+/// `archive` is the object the zipfile is stored in, when it's not the archive path,
+/// see [`Index::archive`].
+///
+/// Will delete the destination file if it already exists.
+#[instrument(skip(zipfile))]
+pub(crate) async fn create<R, P>(zipfile: R, destination: P, archive: Option<&str>) -> Result<R>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut conn = sqlite_create(destination).await?;
+    let mut tx = conn.begin().await?;
+
+    create_files_table(&mut tx).await?;
+    if let Some(archive) = archive {
+        create_archive_table(&mut tx, archive).await?;
+    }
 
-    let (tx_entries, mut rx_entries) = mpsc::channel::<(String, u64, u64, i32)>(1000);
+    let (tx_entries, mut rx_entries) = mpsc::channel::<IndexEntry>(1000);
 
     let zip_task = spawn_blocking(move || {
         let mut bridge = SyncIoBridge::new(zipfile);
         let mut archive = zip::ZipArchive::new(&mut bridge)?;
         for i in 0..archive.len() {
-            let entry = archive.by_index(i)?;
-
-            let start = entry
-                .data_start()
-                .ok_or_else(|| anyhow!("missing data_start in zip directory"))?;
-            let end = start + entry.compressed_size() - 1;
-            let compression_raw = match entry.compression() {
-                zip::CompressionMethod::Bzip2 => CompressionAlgorithm::Bzip2 as i32,
-                zip::CompressionMethod::Deflated => CompressionAlgorithm::Deflate as i32,
-                c => bail!("unsupported compression algorithm {} in zip-file", c),
-            };
-
             tx_entries
-                .blocking_send((entry.name().to_string(), start, end, compression_raw))
+                .blocking_send(zip_index_entry(&mut archive, i)?)
                 .map_err(|_| anyhow!("archive index receiver dropped"))?;
         }
         drop(archive);
@@ -787,41 +1047,52 @@ where
         if received == 0 {
             break;
         }
-        let mut insert_stmt =
-            QueryBuilder::<Sqlite>::new("INSERT INTO files (path, start, end, compression) ");
-        insert_stmt.push_values(
-            chunk.drain(..),
-            |mut b, (path, start, end, compression_raw)| {
-                b.push_bind(path)
-                    .push_bind(start as i64)
-                    .push_bind(end as i64)
-                    .push_bind(compression_raw);
-            },
-        );
-        insert_stmt
-            .build()
-            .persistent(false)
-            .execute(&mut *tx)
-            .await?;
+        insert_entries(&mut tx, chunk.drain(..)).await?;
     }
 
     let zipfile = zip_task.await?;
 
-    sqlx::query("CREATE INDEX idx_files_path ON files (path);")
-        .execute(&mut *tx)
-        .await?;
-
     // Commit the transaction before VACUUM (VACUUM cannot run inside a transaction)
     tx.commit().await?;
-
-    // VACUUM outside the transaction
-    sqlx::query("VACUUM").execute(&mut conn).await?;
+    finish_index(conn).await?;
 
     Ok(zipfile)
 }
 
+/// create an archive index from its entries, for files stored as content-addressed
+/// blobs, or read from a ZIP archive with [`zip_index_entry`].
+///
+/// `archive` like in [`create`].
+///
+/// Will delete the destination file if it already exists.
+#[instrument(skip(entries))]
+pub(crate) async fn create_from_entries<P>(
+    entries: Vec<IndexEntry>,
+    destination: P,
+    archive: Option<&str>,
+) -> Result<()>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut conn = sqlite_create(destination).await?;
+    let mut tx = conn.begin().await?;
+
+    create_files_table(&mut tx).await?;
+    if let Some(archive) = archive {
+        create_archive_table(&mut tx, archive).await?;
+    }
+    let mut entries = entries.into_iter().peekable();
+    while entries.peek().is_some() {
+        insert_entries(&mut tx, entries.by_ref().take(1000)).await?;
+    }
+
+    tx.commit().await?;
+    finish_index(conn).await
+}
+
 pub struct Index {
     conn: sqlx::SqliteConnection,
+    archive: Option<String>,
 }
 
 impl Index {
@@ -830,8 +1101,46 @@ impl Index {
         P: AsRef<Path>,
     {
         let archive_index_path = archive_index_path.as_ref().to_path_buf();
-        let conn = sqlite_open(&archive_index_path).await?;
-        Ok(Self { conn })
+        let mut conn = sqlite_open(&archive_index_path).await?;
+
+        // only indexes of replaced archives have this table.
+        let has_archive_table: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'archive')",
+        )
+        .fetch_one(&mut conn)
+        .await
+        .context("error fetching SQLite schema")?;
+        let archive = if has_archive_table {
+            sqlx::query_scalar("SELECT object FROM archive")
+                .fetch_optional(&mut conn)
+                .await
+                .context("error fetching SQLite data")?
+        } else {
+            None
+        };
+
+        Ok(Self { conn, archive })
+    }
+
+    /// The object with the ZIP archive, when it's not stored at the archive path.
+    ///
+    /// A replaced archive is uploaded to a new object, and only its new index points
+    /// to it, see `AsyncStorage::repack_archive`. Web servers with the old index keep
+    /// reading the old archive, which matches it.
+    pub fn archive(&self) -> Option<&str> {
+        self.archive.as_deref()
+    }
+
+    /// Whether the files are in content-addressed blobs instead of a ZIP archive,
+    /// see `Config::content_addressed_storage`.
+    ///
+    /// An archive index either has a blob for all files, or for none.
+    pub async fn is_content_addressed(&mut self) -> Result<bool> {
+        let mut files = Box::pin(self.list());
+        Ok(files
+            .try_next()
+            .await?
+            .is_some_and(|info| info.blob().is_some()))
     }
 
     #[instrument(skip(self))]
@@ -849,28 +1158,30 @@ impl Index {
             .to_str()
             .ok_or_else(|| anyhow!("non-UTF-8 path in archive index lookup"))?;
 
-        // now actually find the entry in the index
-        let row = sqlx::query(
-            "SELECT start, end, compression
-                FROM files
-                WHERE path = ?",
-        )
-        .bind(search_str)
-        .fetch_optional(&mut self.conn)
-        .await
-        .context("error fetching SQLite data")?;
+        // now actually find the entry in the index.
+        // `*` because older archive indexes don't have the CRC-32, size and blob columns.
+        let row = sqlx::query("SELECT * FROM files WHERE path = ?")
+            .bind(search_str)
+            .fetch_optional(&mut self.conn)
+            .await
+            .context("error fetching SQLite data")?;
 
         let file_info = if let Some(row) = row {
-            let start: u64 = row.try_get(0)?;
-            let end: u64 = row.try_get(1)?;
-            let compression_raw: i32 = row.try_get(2)?;
+            let start: u64 = row.try_get("start")?;
+            let end: u64 = row.try_get("end")?;
+            let compression_raw: Option<i32> = row.try_get("compression")?;
+            let crc32: Option<i64> = row.try_get("crc32").unwrap_or_default();
+            let size: Option<i64> = row.try_get("size").unwrap_or_default();
+            let blob: Option<String> = row.try_get("blob").unwrap_or_default();
 
             Some(FileInfo {
                 path: search_for.to_path_buf(),
                 range: start..=end,
-                compression: compression_raw.try_into().map_err(|value| {
-                    anyhow!("invalid compression algorithm '{value}' in database")
-                })?,
+                compression: compression_from_raw(compression_raw)?,
+                crc32: crc32.map(|crc32| crc32 as u32),
+                size: size.map(|size| size as u64),
+                blob,
+                archive: self.archive.clone(),
             })
         } else {
             None
@@ -879,27 +1190,53 @@ impl Index {
         Ok(file_info)
     }
 
+    /// list all files in the archive.
+    ///
+    /// Includes the CRC-32 and size of the files, when the archive index has them.
     pub fn list(&mut self) -> impl Stream<Item = Result<FileInfo>> + '_ {
         try_stream! {
-            let mut rows = sqlx::query(
-                "SELECT path, start, end, compression FROM files"
-            )
-            .fetch(&mut self.conn);
+            // older archive indexes don't have the CRC-32, size and blob columns.
+            let columns: Vec<String> =
+                sqlx::query_scalar("SELECT name FROM pragma_table_info('files')")
+                    .fetch_all(&mut self.conn)
+                    .await
+                    .context("error fetching SQLite schema")?;
+            let column = |name: &'static str| {
+                if columns.iter().any(|column| column == name) {
+                    name
+                } else {
+                    "NULL"
+                }
+            };
+
+            let query = format!(
+                "SELECT path, start, end, compression, {}, {}, {} FROM files",
+                column("crc32"),
+                column("size"),
+                column("blob"),
+            );
+            // only contains our own column names.
+            let mut rows = sqlx::query(sqlx::AssertSqlSafe(query)).fetch(&mut self.conn);
 
             while let Some(row) = rows.try_next().await.context("error fetching SQLite data")? {
                 let path: String = row.try_get(0)?;
                 let start: u64 = row.try_get(1)?;
                 let end: u64 = row.try_get(2)?;
-                let compression_raw: i32 = row.try_get(3)?;
+                let compression_raw: Option<i32> = row.try_get(3)?;
+                let crc32: Option<i64> = row.try_get(4)?;
+                let size: Option<i64> = row.try_get(5)?;
+                let blob: Option<String> = row.try_get(6)?;
                 let path = PathBuf::from(path);
                 debug_assert!(path.is_relative());
 
                 yield FileInfo {
                     path,
                     range: start..=end,
-                    compression: compression_raw.try_into().map_err(|value| {
-                        anyhow!("invalid compression algorithm '{value}' in database")
-                    })?,
+                    compression: compression_from_raw(compression_raw)?,
+                    crc32: crc32.map(|crc32| crc32 as u32),
+                    size: size.map(|size| size as u64),
+                    blob,
+                    archive: self.archive.clone(),
                 };
             }
         }
@@ -989,6 +1326,7 @@ mod tests {
     use docs_rs_opentelemetry::testing::TestMetrics;
     use sqlx::error::DatabaseError as _;
     use std::{collections::HashMap, io::Cursor, ops::Deref, pin::Pin, sync::Arc};
+    use test_case::test_case;
     use zip::write::SimpleFileOptions;
 
     /// Creates a test archive from a list of (path, content) pairs.
@@ -1034,7 +1372,10 @@ mod tests {
                     format!("testfile{i}"),
                     SimpleFileOptions::default()
                         .compression_method(compression)
-                        .compression_level(Some(1)),
+                        // stored files don't have a compression level
+                        .compression_level(
+                            (compression != zip::CompressionMethod::Stored).then_some(1),
+                        ),
                 )?;
                 archive.write_all(&objectcontent)?;
             }
@@ -1049,6 +1390,8 @@ mod tests {
         indices: HashMap<String, Vec<u8>>,
         download_count: std::sync::Mutex<HashMap<String, usize>>,
         delay: Option<std::time::Duration>,
+        /// last modification of all remote indexes.
+        modified: DateTime<Utc>,
     }
 
     impl FakeDownloader {
@@ -1057,6 +1400,7 @@ mod tests {
                 indices: HashMap::new(),
                 download_count: std::sync::Mutex::new(HashMap::new()),
                 delay: None,
+                modified: DateTime::<Utc>::MIN_UTC,
             }
         }
 
@@ -1105,6 +1449,13 @@ mod tests {
                 })
             })
         }
+
+        fn archive_index_modified<'a>(
+            &'a self,
+            _remote_index_path: &'a str,
+        ) -> Pin<Box<dyn Future<Output = Result<DateTime<Utc>>> + Send + 'a>> {
+            Box::pin(async move { Ok(self.modified) })
+        }
     }
 
     struct FlakyDownloader {
@@ -1165,7 +1516,7 @@ mod tests {
     async fn create_index_bytes(file_count: u32) -> Result<Vec<u8>> {
         let tf = create_test_archive(file_count).await?;
         let tempfile = tempfile::NamedTempFile::new()?.into_temp_path();
-        create(tf, &tempfile).await?;
+        create(tf, &tempfile, None).await?;
         fs::read(&tempfile).await.map_err(Into::into)
     }
 
@@ -1200,12 +1551,14 @@ mod tests {
         let tf = create_test_archive(1).await?;
 
         let tempfile = tempfile::NamedTempFile::new()?.into_temp_path();
-        create(tf, &tempfile).await?;
+        create(tf, &tempfile, None).await?;
 
         let mut index = Index::open(&tempfile).await?;
         let fi = index.find("testfile0").await?.unwrap();
 
-        assert_eq!(fi.compression, CompressionAlgorithm::Deflate);
+        assert_eq!(fi.compression, Some(CompressionAlgorithm::Deflate));
+        assert!(fi.crc32().is_some());
+        assert!(fi.size().is_some());
 
         assert!(index.find("some_other_file",).await?.is_none());
         Ok(())
@@ -1216,23 +1569,44 @@ mod tests {
         let tf = create_test_archive_with_compression(1, zip::CompressionMethod::Bzip2).await?;
 
         let tempfile = tempfile::NamedTempFile::new()?.into_temp_path();
-        create(tf, &tempfile).await?;
+        create(tf, &tempfile, None).await?;
 
         let mut index = Index::open(&tempfile).await?;
         let fi = index.find("testfile0").await?.unwrap();
 
-        assert_eq!(fi.compression, CompressionAlgorithm::Bzip2);
+        assert_eq!(fi.compression, Some(CompressionAlgorithm::Bzip2));
 
         assert!(index.find("some_other_file").await?.is_none());
         Ok(())
     }
 
+    #[tokio::test]
+    #[test_case(zip::CompressionMethod::Zstd, Some(CompressionAlgorithm::Zstd))]
+    #[test_case(zip::CompressionMethod::Stored, None)]
+    async fn index_create_zstd_and_stored(
+        method: zip::CompressionMethod,
+        expected: Option<CompressionAlgorithm>,
+    ) -> Result<()> {
+        let tf = create_test_archive_with_compression(1, method).await?;
+
+        let tempfile = tempfile::NamedTempFile::new()?.into_temp_path();
+        create(tf, &tempfile, None).await?;
+
+        let mut index = Index::open(&tempfile).await?;
+        let fi = index.find("testfile0").await?.unwrap();
+        assert_eq!(fi.compression, expected);
+
+        let entries: Vec<FileInfo> = index.list().try_collect().await?;
+        assert_eq!(entries[0].compression, expected);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn empty_archive() -> Result<()> {
         let tf = create_test_archive(0).await?;
 
         let tempfile = tempfile::NamedTempFile::new()?.into_temp_path();
-        create(tf, &tempfile).await?;
+        create(tf, &tempfile, None).await?;
 
         let mut conn = sqlite_open(&tempfile).await?;
 
@@ -1250,7 +1624,7 @@ mod tests {
         let tf = create_test_archive(100_000).await?;
 
         let tempfile = tempfile::NamedTempFile::new()?.into_temp_path();
-        create(tf, &tempfile).await?;
+        create(tf, &tempfile, None).await?;
 
         let mut conn = sqlite_open(&tempfile).await?;
 
@@ -1326,6 +1700,87 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn find_redownloads_when_remote_index_changed() -> Result<()> {
+        let mut config = ArchiveIndexCacheConfig::test_config()?;
+        config.revalidate_after = Duration::ZERO;
+        let meter_provider = TestMetrics::new();
+        let cache = Cache::new_with_backfill(Arc::new(config), meter_provider.provider()).await?;
+        const LATEST_BUILD_ID: Option<BuildId> = Some(BuildId(7));
+        const ARCHIVE_NAME: &str = "test.zip";
+        const FILE_IN_ARCHIVE: &str = "testfile0";
+
+        let remote_index_path = format!("{ARCHIVE_NAME}.{ARCHIVE_INDEX_FILE_EXTENSION}");
+        let cache_file = cache.local_index_path(ARCHIVE_NAME, LATEST_BUILD_ID);
+        fs::create_dir_all(cache_file.parent().unwrap()).await?;
+        fs::write(&cache_file, create_index_bytes(1).await?).await?;
+
+        let mut downloader = FakeDownloader::new();
+        downloader
+            .indices
+            .insert(remote_index_path.clone(), create_index_bytes(2).await?);
+
+        // unchanged remote index
+        assert!(
+            cache
+                .find(ARCHIVE_NAME, LATEST_BUILD_ID, FILE_IN_ARCHIVE, &downloader)
+                .await?
+                .is_some()
+        );
+        assert_eq!(downloader.download_count(&remote_index_path), 0);
+
+        // replaced after we downloaded ours
+        downloader.modified = Utc::now() + chrono::Duration::hours(1);
+        assert!(
+            cache
+                .find(ARCHIVE_NAME, LATEST_BUILD_ID, "testfile1", &downloader)
+                .await?
+                .is_some()
+        );
+        assert_eq!(downloader.download_count(&remote_index_path), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_uses_local_copy_when_revalidation_fails() -> Result<()> {
+        let mut config = ArchiveIndexCacheConfig::test_config()?;
+        config.revalidate_after = Duration::from_secs(60);
+        let meter_provider = TestMetrics::new();
+        let cache = Cache::new_with_backfill(Arc::new(config), meter_provider.provider()).await?;
+        const LATEST_BUILD_ID: Option<BuildId> = Some(BuildId(7));
+        const ARCHIVE_NAME: &str = "test.zip";
+
+        let remote_index_path = format!("{ARCHIVE_NAME}.{ARCHIVE_INDEX_FILE_EXTENSION}");
+        let cache_file = cache.local_index_path(ARCHIVE_NAME, LATEST_BUILD_ID);
+        fs::create_dir_all(cache_file.parent().unwrap()).await?;
+        fs::write(&cache_file, create_index_bytes(1).await?).await?;
+        touch_at(&cache_file, SystemTime::now() - Duration::from_secs(120))?;
+
+        // every request to the remote storage fails.
+        let downloader = FlakyDownloader::new(remote_index_path, Vec::new(), usize::MAX);
+        for _ in 0..3 {
+            assert!(
+                cache
+                    .find(ARCHIVE_NAME, LATEST_BUILD_ID, "testfile0", &downloader)
+                    .await?
+                    .is_some()
+            );
+        }
+        // only the first request revalidated, the next one is only after `revalidate_after`.
+        assert_eq!(downloader.fetch_count(), 1);
+
+        Ok(())
+    }
+
+    fn touch_at(path: &Path, time: SystemTime) -> Result<()> {
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(time)?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn find_downloads_when_local_cache_missing() -> Result<()> {
         let cache = test_cache().await?;
@@ -1752,7 +2207,7 @@ mod tests {
     async fn index_from_entries(entries: Vec<(&'static str, &'static [u8])>) -> Result<Index> {
         let archive = create_archive_from_entries(entries).await?;
         let tmp = tempfile::NamedTempFile::new()?.into_temp_path();
-        create(archive, &tmp).await?;
+        create(archive, &tmp, None).await?;
 
         Index::open(&tmp).await
     }
@@ -1947,7 +2402,66 @@ mod tests {
         // (set by create_archive_from_entries).
         let fi = &entries[0];
         assert!(!fi.range().is_empty());
-        assert_eq!(fi.compression(), CompressionAlgorithm::Bzip2);
+        assert_eq!(fi.compression(), Some(CompressionAlgorithm::Bzip2));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_includes_checksum_and_size() -> Result<()> {
+        let mut index = index_from_entries(vec![
+            ("a.txt", b"hello"),
+            ("b.txt", b"hello"),
+            ("c.txt", b"world"),
+        ])
+        .await?;
+        let mut entries: Vec<FileInfo> = index.list().try_collect().await?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(entries[0].crc32(), Some(crc32fast::hash(b"hello")));
+        assert_eq!(entries[0].size(), Some(5));
+
+        assert_eq!(entries[0].same_content_as(&entries[1]), Some(true));
+        assert_eq!(entries[0].same_content_as(&entries[2]), Some(false));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_index_without_checksums() -> Result<()> {
+        // archive index created before we stored CRC-32 and size
+        let tmp = tempfile::NamedTempFile::new()?.into_temp_path();
+        let mut conn = sqlite_create(&tmp).await?;
+        sqlx::query(
+            "CREATE TABLE files (
+                id INTEGER PRIMARY KEY,
+                path TEXT UNIQUE,
+                start INTEGER,
+                end INTEGER,
+                compression INTEGER
+            )",
+        )
+        .execute(&mut conn)
+        .await?;
+        sqlx::query("INSERT INTO files (path, start, end, compression) VALUES (?, 0, 10, ?)")
+            .bind("file.txt")
+            .bind(CompressionAlgorithm::Bzip2 as i32)
+            .execute(&mut conn)
+            .await?;
+        drop(conn);
+
+        let mut index = Index::open(&tmp).await?;
+        let entries: Vec<FileInfo> = index.list().try_collect().await?;
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path(), Path::new("file.txt"));
+        assert_eq!(entries[0].crc32(), None);
+        assert_eq!(entries[0].same_content_as(&entries[0]), None);
+
+        let entry = index.find("file.txt").await?.unwrap();
+        assert_eq!(entry.range(), 0..=10);
+        assert_eq!(entry.crc32(), None);
+        assert_eq!(entry.size(), None);
 
         Ok(())
     }
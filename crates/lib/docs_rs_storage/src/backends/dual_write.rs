@@ -0,0 +1,195 @@
+use crate::{
+    backends::{StorageBackend, StorageBackendMethods},
+    blob::{ObjectVersion, StreamUpload, StreamingBlob},
+    errors::PathNotFoundError,
+    types::FileRange,
+};
+use anyhow::Result;
+use async_stream::try_stream;
+use futures_util::{TryStreamExt as _, stream::BoxStream};
+use std::{cmp::Ordering, future::Future};
+use tracing::error;
+
+/// Storage for the migration window between two backends.
+///
+/// Writes and deletes go to both backends, reads prefer the new backend and fall back
+/// to the old one for objects that weren't copied yet.
+///
+/// Writes and deletes reach the old backend first. So when a copy from the old backend
+/// sees that an object didn't change after uploading it, a concurrent write will still
+/// reach the new backend after the copy, see `AsyncStorage::copy_object_to`.
+///
+/// When a write succeeds in the old backend but fails in the new one, the write fails,
+/// and we log the path so it can be repaired, see [`diverged`].
+pub(crate) struct DualWriteBackend {
+    new: Box<StorageBackend>,
+    old: Box<StorageBackend>,
+}
+
+impl DualWriteBackend {
+    pub(crate) fn new(new: StorageBackend, old: StorageBackend) -> Self {
+        Self {
+            new: Box::new(new),
+            old: Box::new(old),
+        }
+    }
+}
+
+// The futures of the inner backends can contain our futures again, so we box them, and
+// declare that ours are `Send` so the compiler doesn't have to look into them for that.
+// With `async fn` we couldn't declare that.
+#[allow(refining_impl_trait, clippy::manual_async_fn)]
+impl StorageBackendMethods for DualWriteBackend {
+    fn exists(&self, path: &str) -> impl Future<Output = Result<bool>> + Send {
+        async move {
+            Ok(Box::pin(self.new.exists(path)).await? || Box::pin(self.old.exists(path)).await?)
+        }
+    }
+
+    fn get_stream(
+        &self,
+        path: &str,
+        range: Option<FileRange>,
+    ) -> impl Future<Output = Result<StreamingBlob>> + Send {
+        async move {
+            match Box::pin(self.new.get_stream(path, range.clone())).await {
+                Err(err) if err.is::<PathNotFoundError>() => {
+                    Box::pin(self.old.get_stream(path, range)).await
+                }
+                result => result,
+            }
+        }
+    }
+
+    fn version(&self, path: &str) -> impl Future<Output = Result<ObjectVersion>> + Send {
+        async move {
+            match Box::pin(self.new.version(path)).await {
+                Err(err) if err.is::<PathNotFoundError>() => Box::pin(self.old.version(path)).await,
+                result => result,
+            }
+        }
+    }
+
+    fn upload_stream(&self, upload: StreamUpload) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let path = upload.path.clone();
+            Box::pin(self.old.upload_stream(upload.clone())).await?;
+            if let Err(err) = Box::pin(self.new.upload_stream(upload)).await {
+                // without an outdated object in the new backend, reads see the one we
+                // just wrote to the old backend.
+                let removed = Box::pin(self.new.delete_object(&path)).await.is_ok();
+                diverged("upload", &path, removed, &err);
+                return Err(err);
+            }
+            Ok(())
+        }
+    }
+
+    /// Merges the sorted listings of both backends.
+    fn list_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Future<Output = BoxStream<'a, Result<String>>> + Send {
+        async move {
+            let keys: BoxStream<'a, Result<String>> = Box::pin(try_stream! {
+                let mut new_keys = Box::pin(self.new.list_prefix(prefix)).await;
+                let mut old_keys = Box::pin(self.old.list_prefix(prefix)).await;
+
+                let mut next_new = new_keys.try_next().await?;
+                let mut next_old = old_keys.try_next().await?;
+                loop {
+                    let key = match (next_new.take(), next_old.take()) {
+                        (None, None) => break,
+                        (Some(new), None) => {
+                            next_new = new_keys.try_next().await?;
+                            new
+                        }
+                        (None, Some(old)) => {
+                            next_old = old_keys.try_next().await?;
+                            old
+                        }
+                        (Some(new), Some(old)) => match new.cmp(&old) {
+                            Ordering::Less => {
+                                next_old = Some(old);
+                                next_new = new_keys.try_next().await?;
+                                new
+                            }
+                            Ordering::Greater => {
+                                next_new = Some(new);
+                                next_old = old_keys.try_next().await?;
+                                old
+                            }
+                            Ordering::Equal => {
+                                next_new = new_keys.try_next().await?;
+                                next_old = old_keys.try_next().await?;
+                                new
+                            }
+                        },
+                    };
+                    yield key;
+                }
+            });
+            keys
+        }
+    }
+
+    fn delete_prefix(&self, prefix: &str) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            Box::pin(self.old.delete_prefix(prefix)).await?;
+            Box::pin(self.new.delete_prefix(prefix))
+                .await
+                .inspect_err(|err| diverged("delete prefix", prefix, false, err))
+        }
+    }
+
+    fn delete_object(&self, path: &str) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            Box::pin(self.old.delete_object(path)).await?;
+            Box::pin(self.new.delete_object(path))
+                .await
+                .inspect_err(|err| diverged("delete", path, false, err))
+        }
+    }
+
+    /// Copies in both backends, objects that weren't copied to the new backend yet
+    /// are only copied in the old one.
+    fn copy_object(&self, from: &str, to: &str) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let copied_old = copied(Box::pin(self.old.copy_object(from, to)).await)?;
+            let copied_new =
+                copied(Box::pin(self.new.copy_object(from, to)).await).inspect_err(|err| {
+                    diverged("copy", to, false, err);
+                })?;
+            if copied_old || copied_new {
+                Ok(())
+            } else {
+                Err(PathNotFoundError.into())
+            }
+        }
+    }
+}
+
+/// Log a write that only reached the old backend, `repaired` when the new backend
+/// doesn't have an outdated object anymore.
+///
+/// Otherwise reads see an outdated or deleted object in the new backend, until the path
+/// is written or deleted again. Uploads can also be copied again with
+/// `docs_rs_admin storage copy --restart --prefix <path>`.
+fn diverged(operation: &str, path: &str, repaired: bool, err: &anyhow::Error) {
+    error!(
+        operation,
+        path,
+        repaired,
+        ?err,
+        "write to the new storage failed, the storages diverged"
+    );
+}
+
+/// `false` when the object to copy doesn't exist.
+fn copied(result: Result<()>) -> Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(err) if err.is::<PathNotFoundError>() => Ok(false),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,465 @@
+use crate::{
+    Config,
+    backends::StorageBackendMethods,
+    blob::{ObjectVersion, StreamUpload, StreamUploadSource, StreamingBlob},
+    errors::PathNotFoundError,
+    metrics::StorageMetrics,
+    types::FileRange,
+};
+use anyhow::{Context as _, Error, Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use docs_rs_headers::{ETag, ETagComputer, compute_etag};
+use docs_rs_types::CompressionAlgorithm;
+use docs_rs_utils::spawn_blocking;
+use futures_util::{
+    future,
+    stream::{self, BoxStream},
+};
+use headers::Header as _;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, SeekFrom, Write as _},
+    path::{Component, Path, PathBuf},
+};
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncSeekExt as _, BufReader},
+};
+use walkdir::WalkDir;
+
+/// subdirectory of the storage root containing the stored objects.
+const OBJECTS_DIR: &str = "objects";
+/// subdirectory of the storage root for in-progress uploads.
+///
+/// Needs to be on the same filesystem as `OBJECTS_DIR` so we can atomically
+/// rename finished uploads into place.
+const TEMP_DIR: &str = "tmp";
+
+/// Metadata we store in front of the object content.
+///
+/// Each object is a single file on disk, starting with this header serialized as
+/// one line of JSON, followed by the raw (possibly compressed) content.
+/// Keeping both in one file means a single `rename` makes an upload visible,
+/// including its metadata.
+#[derive(Debug, Serialize, Deserialize)]
+struct ObjectHeader {
+    mime: String,
+    compression: Option<CompressionAlgorithm>,
+    etag: String,
+}
+
+impl ObjectHeader {
+    fn new(upload: &StreamUpload, etag: &ETag) -> Result<Self> {
+        let mut values = Vec::with_capacity(1);
+        etag.encode(&mut values);
+        let etag = values
+            .first()
+            .context("ETag didn't encode into a header value")?
+            .to_str()?
+            .to_owned();
+
+        Ok(Self {
+            mime: upload.mime.to_string(),
+            compression: upload.compression,
+            etag,
+        })
+    }
+
+    fn to_line(&self) -> Result<Vec<u8>> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        Ok(line)
+    }
+}
+
+struct OpenObject {
+    header: ObjectHeader,
+    /// positioned at the start of the content.
+    reader: BufReader<fs::File>,
+    /// where the content starts in the file.
+    header_len: u64,
+    content_length: u64,
+    date_updated: DateTime<Utc>,
+}
+
+/// convert i/o errors that mean "this object doesn't exist" into `PathNotFoundError`.
+fn convert_io_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory | io::ErrorKind::InvalidFilename => {
+            PathNotFoundError.into()
+        }
+        _ => err.into(),
+    }
+}
+
+/// Local filesystem storage backend.
+///
+/// Meant for single-machine deployments where running an S3-compatible service
+/// would be overkill.
+///
+/// Like with S3 keys, paths are `/`-separated, but one path can't be both
+/// an object and the prefix of another object (`foo` and `foo/bar`).
+pub(crate) struct FilesystemBackend {
+    objects_root: PathBuf,
+    temp_root: PathBuf,
+    otel_metrics: StorageMetrics,
+}
+
+impl FilesystemBackend {
+    pub(crate) async fn new(config: &Config, otel_metrics: StorageMetrics) -> Result<Self> {
+        let objects_root = config.local_storage_path.join(OBJECTS_DIR);
+        let temp_root = config.local_storage_path.join(TEMP_DIR);
+
+        fs::create_dir_all(&objects_root)
+            .await
+            .with_context(|| format!("failed to create {}", objects_root.display()))?;
+        fs::create_dir_all(&temp_root)
+            .await
+            .with_context(|| format!("failed to create {}", temp_root.display()))?;
+
+        Ok(Self {
+            objects_root,
+            temp_root,
+            otel_metrics,
+        })
+    }
+
+    /// local path for a storage path.
+    ///
+    /// Only plain relative paths are valid, so nobody can escape the storage root.
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path);
+        if path.is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+
+        Some(self.objects_root.join(relative))
+    }
+
+    /// open an object & read its header.
+    async fn open(&self, path: &str) -> Result<OpenObject> {
+        let local_path = self.local_path(path).ok_or(PathNotFoundError)?;
+
+        let file = fs::File::open(&local_path)
+            .await
+            .map_err(convert_io_error)?;
+        let metadata = file.metadata().await?;
+        if !metadata.is_file() {
+            return Err(PathNotFoundError.into());
+        }
+
+        let mut reader = BufReader::new(file);
+        let mut header_line = Vec::new();
+        let header_len = reader.read_until(b'\n', &mut header_line).await? as u64;
+        let header: ObjectHeader = serde_json::from_slice(&header_line)
+            .with_context(|| format!("invalid object header in {}", local_path.display()))?;
+
+        let content_length = metadata
+            .len()
+            .checked_sub(header_len)
+            .ok_or_else(|| anyhow!("object {} is truncated", local_path.display()))?;
+
+        Ok(OpenObject {
+            header,
+            reader,
+            header_len,
+            content_length,
+            date_updated: metadata.modified()?.into(),
+        })
+    }
+
+    /// list all keys starting with `prefix`, sorted.
+    async fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        // only walk the deepest directory that can contain matching keys.
+        let walk_root = match prefix.rsplit_once('/') {
+            Some((dir, _)) => match self.local_path(dir) {
+                Some(path) => path,
+                None => return Ok(Vec::new()),
+            },
+            None => self.objects_root.clone(),
+        };
+
+        let objects_root = self.objects_root.clone();
+        let prefix = prefix.to_owned();
+
+        spawn_blocking(move || {
+            if !walk_root.is_dir() {
+                return Ok(Vec::new());
+            }
+
+            let mut keys = Vec::new();
+            for entry in WalkDir::new(&walk_root) {
+                let entry = entry?;
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+
+                let key = entry
+                    .path()
+                    .strip_prefix(&objects_root)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+
+                if key.starts_with(&prefix) {
+                    keys.push(key);
+                }
+            }
+
+            keys.sort_unstable();
+            Ok(keys)
+        })
+        .await
+    }
+}
+
+impl StorageBackendMethods for FilesystemBackend {
+    async fn exists(&self, path: &str) -> Result<bool> {
+        let Some(local_path) = self.local_path(path) else {
+            return Ok(false);
+        };
+
+        match fs::metadata(&local_path).await.map_err(convert_io_error) {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(err) if err.is::<PathNotFoundError>() => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn get_stream(&self, path: &str, range: Option<FileRange>) -> Result<StreamingBlob> {
+        let OpenObject {
+            header,
+            mut reader,
+            header_len,
+            content_length,
+            date_updated,
+        } = self.open(path).await?;
+
+        let etag = header.etag.parse::<ETag>().ok();
+
+        let (start, length, etag) = if let Some(range) = range {
+            if range.start() > range.end() || *range.end() >= content_length {
+                bail!("invalid range");
+            }
+
+            // same approach as the S3 backend, derive an ETag for the range
+            // from the ETag of the full object.
+            (
+                *range.start(),
+                range.end() - range.start() + 1,
+                Some(compute_etag(format!(
+                    "{}-{}-{}",
+                    header.etag,
+                    range.start(),
+                    range.end()
+                ))),
+            )
+        } else {
+            (0, content_length, etag)
+        };
+
+        if start > 0 {
+            reader.seek(SeekFrom::Start(header_len + start)).await?;
+        }
+
+        Ok(StreamingBlob {
+            path: path.into(),
+            mime: header
+                .mime
+                .parse()
+                .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            date_updated,
+            etag,
+            compression: header.compression,
+            content_length: Some(length as usize),
+            content: Box::new(reader.take(length)),
+        })
+    }
+
+    async fn version(&self, path: &str) -> Result<ObjectVersion> {
+        let object = self.open(path).await?;
+        Ok(ObjectVersion {
+            date_updated: object.date_updated,
+            etag: object.header.etag.parse().ok(),
+            size: Some(object.content_length),
+        })
+    }
+
+    async fn upload_stream(&self, upload: StreamUpload) -> Result<()> {
+        let target = self
+            .local_path(&upload.path)
+            .ok_or_else(|| anyhow!("invalid storage path: {:?}", upload.path))?;
+        let temp_root = self.temp_root.clone();
+
+        spawn_blocking(move || {
+            use std::fs;
+
+            let etag = match &upload.source {
+                StreamUploadSource::Bytes(bytes) => compute_etag(bytes),
+                StreamUploadSource::File(local_path) => {
+                    let mut computer = ETagComputer::new();
+                    io::copy(&mut fs::File::open(local_path)?, &mut computer)?;
+                    computer.finalize()
+                }
+            };
+            let header = ObjectHeader::new(&upload, &etag)?;
+
+            let mut temp_file = tempfile::NamedTempFile::new_in(&temp_root)?;
+            {
+                let mut writer = io::BufWriter::new(temp_file.as_file_mut());
+                writer.write_all(&header.to_line()?)?;
+                match &upload.source {
+                    StreamUploadSource::Bytes(bytes) => writer.write_all(bytes)?,
+                    StreamUploadSource::File(local_path) => {
+                        io::copy(&mut fs::File::open(local_path)?, &mut writer)?;
+                    }
+                }
+                writer.flush()?;
+            }
+            temp_file.as_file().sync_all()?;
+
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            temp_file
+                .persist(&target)
+                .with_context(|| format!("failed to move upload to {}", target.display()))?;
+
+            Ok(())
+        })
+        .await?;
+
+        self.otel_metrics.uploaded_files.add(1, &[]);
+        Ok(())
+    }
+
+    async fn list_prefix<'a>(&'a self, prefix: &'a str) -> BoxStream<'a, Result<String>> {
+        match self.list_keys(prefix).await {
+            Ok(keys) => Box::pin(stream::iter(keys.into_iter().map(Ok))),
+            Err(err) => Box::pin(stream::once(future::ready(Err(err)))),
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        let keys = self.list_keys(prefix).await?;
+        self.delete_keys(keys).await
+    }
+
+    async fn delete_object(&self, path: &str) -> Result<()> {
+        if self.local_path(path).is_none() {
+            return Ok(());
+        }
+        self.delete_keys(vec![path.to_owned()]).await
+    }
+
+    async fn copy_object(&self, from: &str, to: &str) -> Result<()> {
+        let source = self.local_path(from).ok_or(PathNotFoundError)?;
+        let target = self
+            .local_path(to)
+            .ok_or_else(|| anyhow!("invalid storage path: {to:?}"))?;
+        let temp_root = self.temp_root.clone();
+
+        spawn_blocking(move || {
+            use std::fs;
+
+            // the object header is part of the file, so a copy of the file is a copy
+            // of the object.
+            let temp_file = tempfile::NamedTempFile::new_in(&temp_root)?;
+            fs::copy(&source, temp_file.path()).map_err(convert_io_error)?;
+
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            temp_file
+                .persist(&target)
+                .with_context(|| format!("failed to move copy to {}", target.display()))?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl FilesystemBackend {
+    /// delete the objects with these keys, ignoring missing ones.
+    async fn delete_keys(&self, keys: Vec<String>) -> Result<()> {
+        let objects_root = self.objects_root.clone();
+
+        spawn_blocking(move || {
+            use std::fs;
+
+            for key in keys {
+                let path = objects_root.join(&key);
+                match fs::remove_file(&path) {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => {
+                        return Err(err).with_context(|| format!("failed to delete {key}"));
+                    }
+                }
+
+                // clean up directories that became empty, `remove_dir` fails
+                // on the first one that still has content.
+                let mut dir = path.parent();
+                while let Some(current) = dir
+                    && current != objects_root
+                    && fs::remove_dir(current).is_ok()
+                {
+                    dir = current.parent();
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use docs_rs_config::AppConfig as _;
+    use docs_rs_opentelemetry::testing::TestMetrics;
+    use test_case::test_case;
+
+    #[test_case(""; "empty")]
+    #[test_case("/etc/passwd"; "absolute")]
+    #[test_case("../outside.txt"; "parent")]
+    #[test_case("foo/../../outside.txt"; "nested parent")]
+    #[test_case("./foo.txt"; "current dir")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_invalid_paths_stay_inside_root(path: &str) -> Result<()> {
+        let config = Config::test_config()?;
+        let metrics = TestMetrics::new();
+        let backend =
+            FilesystemBackend::new(&config, StorageMetrics::new(metrics.provider())).await?;
+
+        assert!(backend.local_path(path).is_none());
+        assert!(!backend.exists(path).await?);
+        assert!(
+            backend
+                .get_stream(path, None)
+                .await
+                .unwrap_err()
+                .is::<PathNotFoundError>()
+        );
+        assert!(
+            backend
+                .upload_stream(StreamUpload {
+                    path: path.into(),
+                    mime: mime::TEXT_PLAIN,
+                    source: StreamUploadSource::Bytes("data".into()),
+                    compression: None,
+                })
+                .await
+                .is_err()
+        );
+
+        fs::remove_dir_all(&config.local_storage_path).await?;
+        Ok(())
+    }
+}
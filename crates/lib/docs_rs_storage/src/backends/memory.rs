@@ -1,7 +1,7 @@
 use crate::{
     Blob,
     backends::StorageBackendMethods,
-    blob::{StreamUpload, StreamUploadSource, StreamingBlob},
+    blob::{ObjectVersion, StreamUpload, StreamUploadSource, StreamingBlob},
     errors::PathNotFoundError,
     metrics::StorageMetrics,
     types::FileRange,
@@ -48,6 +48,15 @@ impl StorageBackendMethods for MemoryBackend {
         Ok(blob.into())
     }
 
+    async fn version(&self, path: &str) -> Result<ObjectVersion> {
+        let blob = self.objects.get(path).ok_or(PathNotFoundError)?;
+        Ok(ObjectVersion {
+            date_updated: blob.date_updated,
+            etag: blob.etag.clone(),
+            size: Some(blob.content.len() as u64),
+        })
+    }
+
     async fn upload_stream(&self, upload: StreamUpload) -> Result<()> {
         let StreamUpload {
             path,
@@ -96,4 +105,17 @@ impl StorageBackendMethods for MemoryBackend {
         self.objects.retain(|key, _| !key.starts_with(prefix));
         Ok(())
     }
+
+    async fn delete_object(&self, path: &str) -> Result<()> {
+        self.objects.remove(path);
+        Ok(())
+    }
+
+    async fn copy_object(&self, from: &str, to: &str) -> Result<()> {
+        let mut blob = self.objects.get(from).ok_or(PathNotFoundError)?.clone();
+        blob.path = to.to_owned();
+        blob.date_updated = Utc::now();
+        self.objects.insert(blob.path.clone(), blob);
+        Ok(())
+    }
 }
@@ -1,23 +1,74 @@
+pub(crate) mod dual_write;
+pub(crate) mod filesystem;
 #[cfg(any(test, feature = "testing"))]
 pub(crate) mod memory;
 pub(crate) mod s3;
 
-use crate::{StreamingBlob, blob::StreamUpload, types::FileRange};
+use crate::{
+    Config, StreamingBlob,
+    blob::{ObjectVersion, StreamUpload},
+    metrics::StorageMetrics,
+    types::FileRange,
+    types::StorageKind,
+};
 use anyhow::Result;
+use docs_rs_opentelemetry::AnyMeterProvider;
 use futures_util::stream::BoxStream;
 
 pub(crate) trait StorageBackendMethods {
     async fn exists(&self, path: &str) -> Result<bool>;
     async fn get_stream(&self, path: &str, range: Option<FileRange>) -> Result<StreamingBlob>;
+    /// The version of the object at `path`, without fetching it.
+    async fn version(&self, path: &str) -> Result<ObjectVersion>;
     async fn upload_stream(&self, upload: StreamUpload) -> Result<()>;
     async fn list_prefix<'a>(&'a self, prefix: &'a str) -> BoxStream<'a, Result<String>>;
     async fn delete_prefix(&self, prefix: &str) -> Result<()>;
+    /// Delete the object at exactly `path`, unlike `delete_prefix`. Missing objects are ignored.
+    async fn delete_object(&self, path: &str) -> Result<()>;
+    /// Copy the object at `from` to `to` inside the storage, without downloading it.
+    async fn copy_object(&self, from: &str, to: &str) -> Result<()>;
 }
 
 pub(crate) enum StorageBackend {
     #[cfg(any(test, feature = "testing"))]
     Memory(memory::MemoryBackend),
     S3(s3::S3Backend),
+    Filesystem(filesystem::FilesystemBackend),
+    DualWrite(dual_write::DualWriteBackend),
+}
+
+impl StorageBackend {
+    /// The backend for `config`, writing to both storages during a migration,
+    /// see `Config::migrate_from`.
+    pub(crate) async fn new(
+        config: &Config,
+        otel_meter_provider: &AnyMeterProvider,
+    ) -> Result<Self> {
+        let backend = Self::for_kind(config, StorageMetrics::new(otel_meter_provider)).await?;
+
+        Ok(match &config.migrate_from {
+            Some(location) => Self::DualWrite(dual_write::DualWriteBackend::new(
+                backend,
+                Self::for_kind(
+                    &config.with_location(location),
+                    StorageMetrics::new(otel_meter_provider),
+                )
+                .await?,
+            )),
+            None => backend,
+        })
+    }
+
+    async fn for_kind(config: &Config, metrics: StorageMetrics) -> Result<Self> {
+        Ok(match config.storage_backend {
+            #[cfg(any(test, feature = "testing"))]
+            StorageKind::Memory => Self::Memory(memory::MemoryBackend::new(metrics)),
+            StorageKind::S3 => Self::S3(s3::S3Backend::new(config, metrics).await?),
+            StorageKind::Filesystem => {
+                Self::Filesystem(filesystem::FilesystemBackend::new(config, metrics).await?)
+            }
+        })
+    }
 }
 
 macro_rules! call_inner {
@@ -26,6 +77,8 @@ macro_rules! call_inner {
             #[cfg(any(test, feature = "testing"))]
             StorageBackend::Memory(backend) => backend.$method($($args),*).await,
             StorageBackend::S3(backend) => backend.$method($($args),*).await,
+            StorageBackend::Filesystem(backend) => backend.$method($($args),*).await,
+            StorageBackend::DualWrite(backend) => backend.$method($($args),*).await,
         }
     }};
 }
@@ -39,6 +92,10 @@ impl StorageBackendMethods for StorageBackend {
         call_inner!(self, get_stream(path, range))
     }
 
+    async fn version(&self, path: &str) -> Result<ObjectVersion> {
+        call_inner!(self, version(path))
+    }
+
     async fn upload_stream(&self, upload: StreamUpload) -> Result<()> {
         call_inner!(self, upload_stream(upload))
     }
@@ -50,4 +107,12 @@ impl StorageBackendMethods for StorageBackend {
     async fn delete_prefix(&self, prefix: &str) -> Result<()> {
         call_inner!(self, delete_prefix(prefix))
     }
+
+    async fn delete_object(&self, path: &str) -> Result<()> {
+        call_inner!(self, delete_object(path))
+    }
+
+    async fn copy_object(&self, from: &str, to: &str) -> Result<()> {
+        call_inner!(self, copy_object(from, to))
+    }
 }
@@ -1,7 +1,7 @@
 use crate::{
     Config,
     backends::StorageBackendMethods,
-    blob::{StreamUpload, StreamUploadSource, StreamingBlob},
+    blob::{ObjectVersion, StreamUpload, StreamUploadSource, StreamingBlob},
     crc32_for_path,
     errors::PathNotFoundError,
     metrics::StorageMetrics,
@@ -26,6 +26,7 @@ use docs_rs_types::CompressionAlgorithm;
 use docs_rs_utils::{retry_backoff, spawn_blocking};
 use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use mime::Mime;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
 use std::path::Path;
 use tokio::{fs, time};
 use tracing::{error, warn};
@@ -66,6 +67,13 @@ const S3_UPLOAD_BUFFER_SIZE: usize = 1024 * 1024; // 1 MiB
 const S3_MULTIPART_UPLOAD_THRESHOLD: u64 = 100 * 1024 * 1024; // 100 MiB
 const S3_MULTIPART_PART_SIZE: u64 = S3_MULTIPART_UPLOAD_THRESHOLD; // 100 MiB
 const S3_DELETE_OBJECTS_LIMIT: usize = 1000;
+// the source of a copy is URL-encoded, except for the separators of the key.
+const COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
 
 trait S3ResultExt<T> {
     fn convert_errors(self) -> anyhow::Result<T>;
@@ -389,6 +397,26 @@ impl StorageBackendMethods for S3Backend {
         }
     }
 
+    async fn version(&self, path: &str) -> Result<ObjectVersion, Error> {
+        let res = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(path)
+            .send()
+            .await
+            .convert_errors()?;
+
+        Ok(ObjectVersion {
+            date_updated: res
+                .last_modified
+                .and_then(|dt| dt.to_chrono_utc().ok())
+                .context("missing last modified date")?,
+            etag: res.e_tag.and_then(|etag| etag.parse().ok()),
+            size: res.content_length.and_then(|length| length.try_into().ok()),
+        })
+    }
+
     async fn get_stream(
         &self,
         path: &str,
@@ -558,6 +586,26 @@ impl StorageBackendMethods for S3Backend {
             .try_for_each(|batch| self.delete_batch_with_retry(batch))
             .await
     }
+
+    async fn delete_object(&self, path: &str) -> Result<(), Error> {
+        self.delete_batch_with_retry(vec![path.to_owned()]).await
+    }
+
+    async fn copy_object(&self, from: &str, to: &str) -> Result<(), Error> {
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(format!(
+                "{}/{}",
+                self.bucket,
+                utf8_percent_encode(from, COPY_SOURCE_ENCODE_SET)
+            ))
+            .key(to)
+            .send()
+            .await
+            .convert_errors()?;
+        Ok(())
+    }
 }
 
 impl S3Backend {
@@ -8,6 +8,7 @@ use mime::Mime;
 use std::{fmt, io::Cursor, path::PathBuf};
 use tokio::io::{self, AsyncBufRead, AsyncBufReadExt};
 
+#[derive(Clone)]
 pub enum StreamUploadSource {
     Bytes(Bytes),
     File(PathBuf),
@@ -21,6 +22,7 @@ pub enum StreamUploadSource {
 /// We can add support for streams with unknown size, but this would mean
 /// using an intermediate fixed-size buffer and multipart uploads for these cases, which
 /// has other downsides.
+#[derive(Clone)]
 pub struct StreamUpload {
     pub path: String,
     pub mime: Mime,
@@ -82,6 +84,14 @@ impl From<BlobUpload> for Blob {
     }
 }
 
+/// When an object was last written, its ETag and stored size, to notice that it changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ObjectVersion {
+    pub(crate) date_updated: DateTime<Utc>,
+    pub(crate) etag: Option<ETag>,
+    pub(crate) size: Option<u64>,
+}
+
 pub struct StreamingBlob {
     pub path: String,
     pub mime: Mime,
@@ -111,6 +121,15 @@ impl fmt::Debug for StreamingBlob {
 }
 
 impl StreamingBlob {
+    /// the version of the object, only for streams of the full object.
+    pub(crate) fn version(&self) -> ObjectVersion {
+        ObjectVersion {
+            date_updated: self.date_updated,
+            etag: self.etag.clone(),
+            size: self.content_length.map(|length| length as u64),
+        }
+    }
+
     /// wrap the content stream in a streaming decompressor according to the
     /// algorithm found in `compression` attribute.
     pub async fn decompress(mut self) -> Result<Self, io::Error> {
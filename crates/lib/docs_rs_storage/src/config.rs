@@ -1,4 +1,4 @@
-use crate::types::StorageKind;
+use crate::types::{StorageKind, StorageLocation};
 use docs_rs_config::AppConfig;
 use docs_rs_env_vars::{env, maybe_env, require_env};
 use std::{
@@ -28,6 +28,10 @@ pub struct ArchiveIndexCacheConfig {
     // TTL for the local index cache
     pub ttl: Duration,
 
+    // how often we check if the remote index changed since we downloaded it,
+    // for archives that are replaced in place, like when repacking them.
+    pub revalidate_after: Duration,
+
     // expected number of entries in the local archive cache.
     // Makes server restarts faster by preallocating some data structures.
     // General numbers (as of 2025-12):
@@ -59,6 +63,10 @@ impl AppConfig for ArchiveIndexCacheConfig {
                 "DOCSRS_ARCHIVE_INDEX_CACHE_TTL",
                 24 * 60 * 60, // 24 hours
             )?),
+            revalidate_after: Duration::from_secs(env(
+                "DOCSRS_ARCHIVE_INDEX_CACHE_REVALIDATE_AFTER",
+                10 * 60, // 10 minutes
+            )?),
             expected_count: env("DOCSRS_ARCHIVE_INDEX_EXPECTED_COUNT", 100_000usize)?,
         })
     }
@@ -73,7 +81,7 @@ impl AppConfig for ArchiveIndexCacheConfig {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     // Storage params
     pub storage_backend: StorageKind,
@@ -86,6 +94,9 @@ pub struct Config {
     pub s3_region: String,
     pub s3_endpoint: Option<String>,
 
+    // Filesystem params
+    pub local_storage_path: PathBuf,
+
     // DO NOT CONFIGURE THIS THROUGH AN ENVIRONMENT VARIABLE!
     // Accidentally turning this on outside of the test suite might cause data loss in the
     // production environment.
@@ -101,11 +112,26 @@ pub struct Config {
 
     // How much we want to parallelize file uploads / downloads.
     pub network_parallelism: usize,
+
+    // Store the files of new archives once per content in `blobs/`, instead of in a
+    // ZIP archive per release. The archive index maps the paths to these blobs.
+    // Rustdoc archive downloads aren't available for releases stored like this.
+    pub content_addressed_storage: bool,
+
+    // Write archive indexes that web servers from before this setting can't read: with
+    // files stored without compression, and for archives replaced by repacking them.
+    // Only enable this once all web servers are updated.
+    pub new_archive_index_format: bool,
+
+    // While migrating to this storage: also write everything to this old storage, and
+    // read what isn't copied yet from it. See `docs_rs_admin storage copy`.
+    pub migrate_from: Option<StorageLocation>,
 }
 
 impl AppConfig for Config {
     fn from_environment() -> anyhow::Result<Self> {
         let cores = std::thread::available_parallelism()?.get();
+        let prefix: PathBuf = require_env("DOCSRS_PREFIX")?;
 
         Ok(Self {
             storage_backend: env("DOCSRS_STORAGE_BACKEND", StorageKind::default())?,
@@ -113,12 +139,19 @@ impl AppConfig for Config {
             s3_bucket: env("DOCSRS_S3_BUCKET", "rust-docs-rs".to_string())?,
             s3_region: env("S3_REGION", "us-west-1".to_string())?,
             s3_endpoint: maybe_env("S3_ENDPOINT")?,
+            local_storage_path: ensure_absolute_path(env(
+                "DOCSRS_LOCAL_STORAGE_PATH",
+                prefix.join("storage"),
+            )?)?,
             archive_index_cache: Arc::new(ArchiveIndexCacheConfig::from_environment()?),
             max_file_size: env("DOCSRS_MAX_FILE_SIZE", 50 * 1024 * 1024)?,
             max_file_size_html: env("DOCSRS_MAX_FILE_SIZE_HTML", 50 * 1024 * 1024)?,
             #[cfg(any(test, feature = "testing"))]
             s3_bucket_is_temporary: false,
             network_parallelism: env("DOCSRS_NETWORK_PARALLELISM", 8usize.min(cores))?.max(1),
+            content_addressed_storage: env("DOCSRS_CONTENT_ADDRESSED_STORAGE", false)?,
+            new_archive_index_format: env("DOCSRS_NEW_ARCHIVE_INDEX_FORMAT", false)?,
+            migrate_from: maybe_env("DOCSRS_STORAGE_MIGRATE_FROM")?,
         })
     }
 
@@ -137,6 +170,27 @@ impl Config {
         f(self)
     }
 
+    /// The same config for the storage at `location`, without a migration.
+    pub fn with_location(&self, location: &StorageLocation) -> Self {
+        let mut config = self.clone();
+        match location {
+            StorageLocation::S3 { bucket } => {
+                config.storage_backend = StorageKind::S3;
+                config.s3_bucket = bucket.clone();
+                #[cfg(any(test, feature = "testing"))]
+                {
+                    config.s3_bucket_is_temporary = false;
+                }
+            }
+            StorageLocation::Filesystem { path } => {
+                config.storage_backend = StorageKind::Filesystem;
+                config.local_storage_path = path.clone();
+            }
+        }
+        config.migrate_from = None;
+        config
+    }
+
     pub fn max_file_size_for(&self, path: impl AsRef<Path>) -> usize {
         static HTML: &str = "html";
 
@@ -155,11 +209,16 @@ impl Config {
         config.storage_backend = kind;
 
         config.archive_index_cache = Arc::new(ArchiveIndexCacheConfig::test_config()?);
+        config.new_archive_index_format = true;
 
         // Use a temporary S3 bucket, only used when storage_kind is set to S3 in env or later.
         config.s3_bucket = format!("docsrs-test-bucket-{}", rand::random::<u64>());
         config.s3_bucket_is_temporary = true;
 
+        // Same for the local storage directory, only used with the filesystem backend.
+        config.local_storage_path =
+            std::env::temp_dir().join(format!("docsrs-test-storage-{}", rand::random::<u64>()));
+
         Ok(config)
     }
 }
@@ -5,3 +5,7 @@ pub struct SizeLimitReached;
 #[derive(Debug, thiserror::Error)]
 #[error("path not found")]
 pub struct PathNotFoundError;
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid storage location {0}, expected s3://<bucket> or file://<path>")]
+pub struct InvalidStorageLocation(pub(crate) String);
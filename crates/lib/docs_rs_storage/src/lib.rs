@@ -13,15 +13,16 @@ pub mod testing;
 pub(crate) mod types;
 pub(crate) mod utils;
 
+pub use archive_index::FileInfo;
 pub use blob::{Blob, BlobUpload, StreamingBlob};
 pub use compression::{compress, compress_async, decompress};
 pub use config::Config;
-pub use errors::{PathNotFoundError, SizeLimitReached};
+pub use errors::{InvalidStorageLocation, PathNotFoundError, SizeLimitReached};
 pub use file::{FileEntry, FolderEntry};
-pub use result::ArchiveStatistics;
+pub use result::{ArchiveProblem, ArchiveStatistics, ArchiveVerification, RepackStatistics};
 pub use storage::blocking::Storage;
 pub use storage::non_blocking::AsyncStorage;
-pub use types::StorageKind;
+pub use types::{StorageKind, StorageLocation};
 pub use utils::{
     crc32::crc32_for_path,
     file_list::get_file_list,
@@ -1,9 +1,10 @@
 use docs_rs_types::CompressionAlgorithm;
+use std::fmt;
 
 /// When we create an zip archive for source or rustdoc files,
 /// we collect some statistics we need.
 pub struct ArchiveStatistics {
-    /// used compression algorithm
+    /// used compression algorithm, some files might also be stored uncompressed.
     pub alg: CompressionAlgorithm,
     /// original size of all files
     pub original_size: u64,
@@ -20,3 +21,61 @@ impl ArchiveStatistics {
         }
     }
 }
+
+/// Sizes of an archive before and after we re-compressed its files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RepackStatistics {
+    /// file count in the archive.
+    pub file_count: u64,
+    /// size of the existing archive
+    pub old_size: u64,
+    /// size of the repacked archive
+    pub new_size: u64,
+}
+
+/// A problem we found when verifying an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveProblem {
+    /// The ZIP archive is missing, and the archive index doesn't point to
+    /// content-addressed blobs instead.
+    MissingArchive,
+    /// We can't read the directory of the ZIP archive.
+    UnreadableArchive,
+    MissingIndex,
+    /// The archive index doesn't match the directory of the ZIP archive.
+    IndexMismatch,
+    /// The content of this file can't be read, or doesn't match its CRC-32.
+    CorruptFile(String),
+    /// The content-addressed blob with this path is missing.
+    MissingBlob(String),
+}
+
+impl ArchiveProblem {
+    /// If a new archive index, created from the ZIP archive, fixes the problem.
+    pub fn fixed_by_new_index(&self) -> bool {
+        matches!(self, Self::MissingIndex | Self::IndexMismatch)
+    }
+}
+
+impl fmt::Display for ArchiveProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingArchive => write!(f, "archive missing"),
+            Self::UnreadableArchive => write!(f, "archive unreadable"),
+            Self::MissingIndex => write!(f, "archive index missing"),
+            Self::IndexMismatch => write!(f, "archive index doesn't match the archive"),
+            Self::CorruptFile(path) => write!(f, "corrupt file {path}"),
+            Self::MissingBlob(path) => write!(f, "blob {path} missing"),
+        }
+    }
+}
+
+/// The result of verifying an archive.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ArchiveVerification {
+    /// number of files we checked the CRC-32 of.
+    pub checked_files: u64,
+    pub problems: Vec<ArchiveProblem>,
+    /// if we replaced the archive index with one created from the ZIP archive.
+    pub index_repaired: bool,
+}
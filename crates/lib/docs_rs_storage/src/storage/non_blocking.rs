@@ -1,41 +1,71 @@
-#[cfg(any(test, feature = "testing"))]
-use crate::backends::memory::MemoryBackend;
 use crate::{
     Config,
-    archive_index::{self, ARCHIVE_INDEX_FILE_EXTENSION, Index},
-    backends::{StorageBackend, StorageBackendMethods, s3::S3Backend},
+    archive_index::{self, ARCHIVE_INDEX_FILE_EXTENSION, FileInfo, Index, IndexEntry},
+    backends::{StorageBackend, StorageBackendMethods},
     blob::{Blob, StreamUpload, StreamUploadSource, StreamingBlob},
     compression::{compress, compress_async},
     errors::PathNotFoundError,
     file::FileEntry,
-    metrics::StorageMetrics,
-    result::ArchiveStatistics,
-    types::{FileRange, StorageKind},
+    result::{ArchiveProblem, ArchiveStatistics, ArchiveVerification, RepackStatistics},
+    types::FileRange,
     utils::{
         file_list::{get_file_list, walk_dir_recursive},
-        storage_path::{rustdoc_archive_path, source_archive_path},
+        storage_path::{
+            CONTENT_BLOB_PREFIX, CONTENT_BLOB_REFS_PREFIX, RELEASED_BLOB_PREFIX, content_blob_path,
+            content_blob_ref_path, content_blob_refs_prefix, released_blob_path,
+            rustdoc_archive_path, source_archive_path,
+        },
     },
 };
 use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
+use docs_rs_headers::{ETag, compute_etag};
 use docs_rs_mimes::{self as mimes, detect_mime};
 use docs_rs_opentelemetry::AnyMeterProvider;
 use docs_rs_types::{BuildId, CompressionAlgorithm, KrateName, Version};
 use docs_rs_utils::spawn_blocking;
-use futures_util::{TryStreamExt as _, future, stream::BoxStream};
+use futures_util::{
+    TryStreamExt as _,
+    future::{self, BoxFuture},
+    stream::{self, BoxStream},
+};
+use headers::Header as _;
+use sha2::{Digest as _, Sha256};
 use std::{
+    collections::HashSet,
     fmt,
+    future::Future,
     io::{Cursor, Write as _},
-    path::Path,
+    path::{Path, PathBuf},
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    fs, io,
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _},
+    sync::{mpsc, oneshot},
 };
-use tokio::{fs, io, io::AsyncWriteExt as _};
 use tokio_util::bytes::Bytes;
 use tracing::{info_span, instrument, trace, warn};
 
 /// buffer size when writing zip files.
 pub(crate) const ZIP_BUFFER_SIZE: usize = 1024 * 1024;
 
+/// gzip member header without any optional fields, modification time or OS.
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0xff];
+
+/// magic bytes at the start of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// compression level for zstd compressed files in archives, same as for single blobs.
+const ARCHIVE_ZSTD_LEVEL: i64 = 9;
+
+/// file types that are already compressed, we store these uncompressed in archives.
+const STORED_FILE_EXTENSIONS: &[&str] = &[
+    "avif", "br", "bz2", "gif", "gz", "jpeg", "jpg", "png", "webp", "woff", "woff2", "xz", "zip",
+    "zst",
+];
+
 pub struct AsyncStorage {
     backend: StorageBackend,
     config: Arc<Config>,
@@ -44,8 +74,6 @@ pub struct AsyncStorage {
 
 impl AsyncStorage {
     pub async fn new(config: Arc<Config>, otel_meter_provider: &AnyMeterProvider) -> Result<Self> {
-        let metrics = StorageMetrics::new(otel_meter_provider);
-
         Ok(Self {
             archive_index_cache: archive_index::Cache::new(
                 config.archive_index_cache.clone(),
@@ -53,11 +81,7 @@ impl AsyncStorage {
             )
             .await
             .context("initialize archive index cache")?,
-            backend: match config.storage_backend {
-                #[cfg(any(test, feature = "testing"))]
-                StorageKind::Memory => StorageBackend::Memory(MemoryBackend::new(metrics)),
-                StorageKind::S3 => StorageBackend::S3(S3Backend::new(&config, metrics).await?),
-            },
+            backend: StorageBackend::new(&config, otel_meter_provider).await?,
             config,
         })
     }
@@ -101,6 +125,27 @@ impl AsyncStorage {
             .await
     }
 
+    /// Like [`Self::stream_rustdoc_file`], but keeps the file compressed when its
+    /// stored compression is in `accepted`, see [`Self::stream_from_archive_encoded`].
+    #[instrument(skip(self))]
+    pub async fn stream_rustdoc_file_encoded(
+        &self,
+        name: &KrateName,
+        version: &Version,
+        latest_build_id: Option<BuildId>,
+        path: &str,
+        accepted: &[CompressionAlgorithm],
+    ) -> Result<StreamingBlob> {
+        trace!("fetch encoded rustdoc file");
+        self.stream_from_archive_encoded(
+            &rustdoc_archive_path(name, version),
+            latest_build_id,
+            path,
+            accepted,
+        )
+        .await
+    }
+
     #[instrument(skip(self))]
     pub async fn fetch_source_file(
         &self,
@@ -208,12 +253,82 @@ impl AsyncStorage {
             .await
     }
 
+    /// get a range inside an archive or content-addressed blob without decompressing it,
+    /// for a file that is served with `encoding` as content-encoding.
+    ///
+    /// ZIP archives contain raw deflate streams, which HTTP doesn't have an encoding for.
+    /// We turn these into a gzip stream by adding the gzip header and the trailer with
+    /// the CRC-32 and size from the zip directory.
+    async fn get_encoded_range_stream(
+        &self,
+        object_path: &str,
+        info: &FileInfo,
+        encoding: CompressionAlgorithm,
+    ) -> Result<StreamingBlob> {
+        let mut stream = self
+            .backend
+            .get_stream(object_path, Some(info.range()))
+            .await?;
+
+        match (info.compression(), encoding) {
+            (Some(CompressionAlgorithm::Deflate), CompressionAlgorithm::Gzip) => {
+                let (Some(crc32), Some(size)) = (info.crc32(), info.size()) else {
+                    anyhow::bail!("missing CRC-32 or size to build a gzip stream");
+                };
+                // the gzip trailer contains the size modulo 2^32.
+                let mut trailer = crc32.to_le_bytes().to_vec();
+                trailer.extend_from_slice(&(size as u32).to_le_bytes());
+
+                stream.content_length = stream
+                    .content_length
+                    .map(|len| GZIP_HEADER.len() + len + trailer.len());
+                stream.content = Box::new(
+                    Cursor::new(&GZIP_HEADER[..])
+                        .chain(stream.content)
+                        .chain(Cursor::new(trailer)),
+                );
+            }
+            (Some(CompressionAlgorithm::Zstd), CompressionAlgorithm::Zstd) => {
+                // Same as the early decompression in `StreamingBlob::decompress`, we want
+                // to find a wrong range here, and not when the client decompresses.
+                if !stream.content.fill_buf().await?.starts_with(&ZSTD_MAGIC) {
+                    anyhow::bail!("zstd magic bytes not found in archive range");
+                }
+            }
+            (compression, encoding) => debug_assert_eq!(compression, Some(encoding)),
+        }
+
+        stream.compression = Some(encoding);
+        // different representations of the same file need different ETags.
+        stream.etag = stream.etag.map(|etag| etag_for_encoding(&etag, encoding));
+        Ok(stream)
+    }
+
     #[instrument(skip(self))]
     pub async fn stream_from_archive(
         &self,
         archive_path: &str,
         latest_build_id: Option<BuildId>,
         path: &str,
+    ) -> Result<StreamingBlob> {
+        self.stream_from_archive_encoded(archive_path, latest_build_id, path, &[])
+            .await
+    }
+
+    /// Fetch a file from an archive, and only decompress it when its stored
+    /// compression isn't in `accepted`.
+    ///
+    /// `compression` of the returned blob is the encoding of the stream, `None` when
+    /// it was decompressed.
+    /// Deflate compressed files are returned gzip encoded, see
+    /// [`Self::get_encoded_range_stream`].
+    #[instrument(skip(self))]
+    pub async fn stream_from_archive_encoded(
+        &self,
+        archive_path: &str,
+        latest_build_id: Option<BuildId>,
+        path: &str,
+        accepted: &[CompressionAlgorithm],
     ) -> Result<StreamingBlob> {
         for attempt in 0..2 {
             let info = self
@@ -222,12 +337,25 @@ impl AsyncStorage {
                 .await?
                 .ok_or(PathNotFoundError)?;
 
-            match self
-                .get_range_stream(archive_path, info.range(), Some(info.compression()))
-                .await
-            {
+            // content-addressed files are in their own blob, and replaced archives are
+            // in a new object.
+            let object_path = info.object(archive_path);
+
+            let encoding = passthrough_encoding(&info, accepted);
+            let stream = match encoding {
+                Some(encoding) => {
+                    self.get_encoded_range_stream(object_path, &info, encoding)
+                        .await
+                }
+                None => {
+                    self.get_range_stream(object_path, info.range(), info.compression())
+                        .await
+                }
+            };
+
+            match stream {
                 Ok(stream) => {
-                    debug_assert_eq!(stream.compression, None);
+                    debug_assert_eq!(stream.compression, encoding);
                     return Ok(StreamingBlob {
                         path: format!("{archive_path}/{path}"),
                         mime: detect_mime(path),
@@ -235,7 +363,7 @@ impl AsyncStorage {
                         etag: stream.etag,
                         content: stream.content,
                         content_length: stream.content_length,
-                        compression: None,
+                        compression: encoding,
                     });
                 }
                 Err(err) if attempt == 0 => {
@@ -271,6 +399,10 @@ impl AsyncStorage {
         unreachable!("stream_from_archive retry loop exited unexpectedly");
     }
 
+    /// Store all files in `root_dir` in an archive, or as content-addressed blobs
+    /// with `Config::content_addressed_storage`.
+    ///
+    /// Both create an archive index at `{archive_path}.index`.
     #[instrument(skip(self))]
     pub async fn store_all_in_archive(
         &self,
@@ -278,6 +410,14 @@ impl AsyncStorage {
         root_dir: impl AsRef<Path> + fmt::Debug,
     ) -> Result<ArchiveStatistics> {
         let root_dir = root_dir.as_ref();
+        // blobs the archive we replace references, which we might not need any more.
+        let replaced_blobs = self.referenced_blobs(archive_path).await?;
+
+        if self.config.content_addressed_storage {
+            return self
+                .store_all_as_blobs(archive_path, root_dir, replaced_blobs)
+                .await;
+        }
 
         // Keep the TempPath guards alive until after both uploads complete; dropping them earlier
         // would delete the files while S3 is still reading from them.
@@ -289,8 +429,9 @@ impl AsyncStorage {
                 let archive_path = archive_path.to_owned();
                 let root_dir = root_dir.to_owned();
                 let zip_path = zip_path.clone();
+                let store_compressed = self.config.new_archive_index_format;
 
-                let mut stats = ArchiveStatistics::new(CompressionAlgorithm::Deflate);
+                let mut stats = ArchiveStatistics::new(CompressionAlgorithm::Zstd);
 
                 move || {
                     // We are only using the `zip` library to create the archives and the matching
@@ -302,17 +443,15 @@ impl AsyncStorage {
                     // For decompression we are sharing the compression algorithms defined in
                     // `storage::compression`. So every new algorithm to be used inside ZIP archives
                     // also has to be added as supported algorithm for storage compression, together
-                    // with a mapping in `storage::archive_index::Index::new_from_zip`.
+                    // with a mapping in `storage::archive_index::create`.
+                    //
+                    // Which algorithm we use depends on the file type, see `archive_file_options`.
 
 
                     {
                         let _span =
                             info_span!("create_zip_archive", %archive_path, root_dir=%root_dir.display()).entered();
 
-                        let options = zip::write::SimpleFileOptions::default()
-                            .compression_method(zip::CompressionMethod::Deflated)
-                            .compression_level(Some(6));
-
                         // rustdoc archives can become a couple of GiB big, so we better use a tempfile.
                         let zip_file = fs::File::create(&zip_path)?;
                         let mut zip = zip::ZipWriter::new(io::BufWriter::with_capacity(ZIP_BUFFER_SIZE, zip_file));
@@ -320,7 +459,7 @@ impl AsyncStorage {
                             let file_path = file_path?;
 
                             let mut file = fs::File::open(root_dir.join(&file_path))?;
-                            zip.start_file(file_path.to_str().unwrap(), options)?;
+                            zip.start_file(file_path.to_str().unwrap(), archive_file_options(&file_path, store_compressed))?;
                             io::copy(&mut file, &mut zip)?;
 
                         stats.file_count +=1 ;
@@ -336,54 +475,1009 @@ impl AsyncStorage {
             })
             .await?;
 
-        let remote_index_path = format!("{}.{ARCHIVE_INDEX_FILE_EXTENSION}", archive_path);
-        let index_compression_alg = CompressionAlgorithm::default();
+        self.upload_archive(archive_path, archive_path, &zip_path)
+            .await?;
+        self.delete_replaced_archives(archive_path, Some(archive_path))
+            .await?;
+        self.release_blobs(archive_path, replaced_blobs).await?;
 
-        let compressed_index_temp_path = tempfile::NamedTempFile::new()?.into_temp_path();
-        let compressed_index_path = compressed_index_temp_path.to_path_buf();
-        {
-            let _span = info_span!("create_archive_index", %remote_index_path).entered();
+        Ok(stats)
+    }
+
+    /// Store every file in `root_dir` once per content as blob, see
+    /// `Config::content_addressed_storage`, and create the archive index that maps
+    /// the paths to these blobs.
+    ///
+    /// Each blob has a marker object for every archive that references it, so we
+    /// know when we can delete it, see [`Self::release_blobs`].
+    async fn store_all_as_blobs(
+        &self,
+        archive_path: &str,
+        root_dir: &Path,
+        replaced_blobs: HashSet<String>,
+    ) -> Result<ArchiveStatistics> {
+        let mut stats = ArchiveStatistics::new(CompressionAlgorithm::Zstd);
+
+        // We already reference the blobs of the archive we replace, and only need
+        // to reference and upload each new blob once.
+        let known_blobs = Mutex::new(replaced_blobs.clone());
+
+        let file_paths = get_file_list(root_dir).collect::<Result<Vec<_>, _>>()?;
+        let entries: Vec<IndexEntry> =
+            stream::iter(file_paths.into_iter().map(Ok::<_, anyhow::Error>))
+                .map_ok(|file_path| {
+                    let known_blobs = &known_blobs;
+                    async move {
+                        let local_path = root_dir.join(&file_path);
+                        let path = file_path
+                            .to_str()
+                            .context("non-UTF-8 path in archive")?
+                            .to_owned();
+
+                        // files can be big, so we read them in chunks instead of loading them.
+                        let (hash, crc32, size) = spawn_blocking({
+                            let local_path = local_path.clone();
+                            move || {
+                                use std::io::Read as _;
+
+                                let mut file = std::fs::File::open(&local_path)?;
+                                let mut sha256 = Sha256::new();
+                                let mut crc32 = crc32fast::Hasher::new();
+                                let mut size = 0;
+                                let mut buf = vec![0; 64 * 1024];
+                                loop {
+                                    let len = file.read(&mut buf)?;
+                                    if len == 0 {
+                                        break;
+                                    }
+                                    sha256.update(&buf[..len]);
+                                    crc32.update(&buf[..len]);
+                                    size += len as u64;
+                                }
+                                Ok((hex::encode(sha256.finalize()), crc32.finalize(), size))
+                            }
+                        })
+                        .await?;
+
+                        // an empty range can't be in the index, so we always compress empty files.
+                        let compression = if size == 0 {
+                            Some(CompressionAlgorithm::Zstd)
+                        } else {
+                            archive_file_compression(
+                                &file_path,
+                                self.config.new_archive_index_format,
+                            )
+                        };
+                        // Keep the TempPath guard alive until after the upload.
+                        let compressed_temp_path = match compression {
+                            Some(alg) => {
+                                let temp_path = tempfile::NamedTempFile::new()?.into_temp_path();
+                                compress_async(
+                                    io::BufReader::new(fs::File::open(&local_path).await?),
+                                    io::BufWriter::new(fs::File::create(&temp_path).await?),
+                                    alg,
+                                )
+                                .await?;
+                                Some(temp_path)
+                            }
+                            None => None,
+                        };
+                        let (data_path, stored_size) = match &compressed_temp_path {
+                            Some(temp_path) => (
+                                temp_path.to_path_buf(),
+                                fs::metadata(temp_path).await?.len(),
+                            ),
+                            None => (local_path, size),
+                        };
+
+                        let blob_path = content_blob_path(&hash, compression);
+                        let is_new = known_blobs.lock().unwrap().insert(blob_path.clone());
+                        if is_new {
+                            // add our reference before we look for the blob, so a concurrent
+                            // `release_blobs` either sees it, or deleted the blob before we look.
+                            self.backend
+                                .upload_stream(StreamUpload {
+                                    path: content_blob_ref_path(&blob_path, archive_path),
+                                    mime: mime::APPLICATION_OCTET_STREAM,
+                                    source: StreamUploadSource::Bytes(Bytes::new()),
+                                    compression: None,
+                                })
+                                .await?;
+                        }
+                        if is_new && !self.backend.exists(&blob_path).await? {
+                            self.backend
+                                .upload_stream(StreamUpload {
+                                    path: blob_path.clone(),
+                                    mime: mime::APPLICATION_OCTET_STREAM,
+                                    source: StreamUploadSource::File(data_path),
+                                    compression,
+                                })
+                                .await?;
+                        }
+
+                        Ok(IndexEntry {
+                            path,
+                            start: 0,
+                            end: stored_size - 1,
+                            compression,
+                            crc32,
+                            size,
+                            blob: Some(blob_path),
+                        })
+                    }
+                })
+                .try_buffer_unordered(self.config.network_parallelism)
+                .try_collect()
+                .await?;
+
+        for entry in &entries {
+            stats.file_count += 1;
+            stats.original_size += entry.size;
+        }
+        let blobs: HashSet<String> = entries
+            .iter()
+            .filter_map(|entry| entry.blob.clone())
+            .collect();
+
+        let local_index_path = tempfile::NamedTempFile::new()?.into_temp_path();
+        archive_index::create_from_entries(entries, &local_index_path, None).await?;
+
+        self.upload_archive_index(archive_path, &local_index_path)
+            .await?;
+        // the ZIP archive of an earlier build would otherwise be left behind. We only
+        // delete it now, so there is always an index for the archive.
+        self.delete_replaced_archives(archive_path, None).await?;
+
+        self.release_blobs(
+            archive_path,
+            replaced_blobs
+                .into_iter()
+                .filter(|blob| !blobs.contains(blob))
+                .collect(),
+        )
+        .await?;
+
+        Ok(stats)
+    }
+
+    /// The content-addressed blobs the archive index of `archive_path` references.
+    ///
+    /// Empty when the archive doesn't exist, or is a ZIP archive.
+    ///
+    /// Boxed like [`Self::release_blobs`], the futures of its callers would otherwise
+    /// nest too deep for the compiler.
+    fn referenced_blobs<'a>(
+        &'a self,
+        archive_path: &'a str,
+    ) -> BoxFuture<'a, Result<HashSet<String>>> {
+        Box::pin(async move {
+            // archive indexes can be big, so we don't download them when there can't
+            // be any blobs.
+            if !self.might_have_blobs().await? || self.backend.exists(archive_path).await? {
+                return Ok(HashSet::new());
+            }
 
             let local_index_path = tempfile::NamedTempFile::new()?.into_temp_path();
+            if !self
+                .download_archive_index_to(archive_path, &local_index_path)
+                .await?
+            {
+                return Ok(HashSet::new());
+            }
+
+            let mut index = Index::open(&local_index_path).await?;
+            index
+                .list()
+                .try_filter_map(|info| future::ready(Ok(info.blob().map(ToOwned::to_owned))))
+                .try_collect()
+                .await
+        })
+    }
+
+    /// `false` when no archive can reference content-addressed blobs, because they
+    /// are disabled and there are no references left from when they were enabled.
+    async fn might_have_blobs(&self) -> Result<bool> {
+        Ok(self.config.content_addressed_storage
+            || self.is_referenced(CONTENT_BLOB_REFS_PREFIX).await?)
+    }
+
+    /// Whether there is any blob reference under `refs_prefix`.
+    async fn is_referenced(&self, refs_prefix: &str) -> Result<bool> {
+        Ok(self
+            .backend
+            .list_prefix(refs_prefix)
+            .await
+            .try_next()
+            .await?
+            .is_some())
+    }
+
+    /// Write the content of `stream` into a local file.
+    async fn download_to(&self, mut stream: StreamingBlob, local_path: &Path) -> Result<()> {
+        let mut file = fs::File::create(local_path).await?;
+        io::copy(&mut stream.content, &mut file).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Download and decompress the archive index of `archive_path`.
+    ///
+    /// `false` when there is no archive index.
+    async fn download_archive_index_to(
+        &self,
+        archive_path: &str,
+        local_path: &Path,
+    ) -> Result<bool> {
+        let remote_index_path = format!("{archive_path}.{ARCHIVE_INDEX_FILE_EXTENSION}");
+        match self.get_stream(&remote_index_path).await {
+            Ok(stream) => {
+                self.download_to(stream, local_path).await?;
+                Ok(true)
+            }
+            Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Check that the archive at `archive_path` and its index exist and match, and
+    /// verify the CRC-32 of up to `crc_samples` files in it.
+    ///
+    /// For content-addressed archives we check that all blobs exist instead.
+    ///
+    /// With `repair`, a missing or mismatching archive index is replaced with one
+    /// created from the ZIP archive.
+    #[instrument(skip(self))]
+    pub async fn verify_archive(
+        &self,
+        archive_path: &str,
+        latest_build_id: Option<BuildId>,
+        crc_samples: usize,
+        repair: bool,
+    ) -> Result<ArchiveVerification> {
+        let local_index_path = tempfile::NamedTempFile::new()?.into_temp_path();
+        let has_index = self
+            .download_archive_index_to(archive_path, &local_index_path)
+            .await?;
+        let mut object = archive_path.to_owned();
+        let files: Option<Vec<FileInfo>> = if has_index {
+            let mut index = Index::open(&local_index_path).await?;
+            if let Some(archive) = index.archive() {
+                object = archive.to_owned();
+            }
+            Some(index.list().try_collect().await?)
+        } else {
+            None
+        };
+
+        if self.backend.exists(&object).await? {
+            return self
+                .verify_zip_archive(
+                    archive_path,
+                    &object,
+                    latest_build_id,
+                    files,
+                    crc_samples,
+                    repair,
+                )
+                .await;
+        }
+
+        let mut result = ArchiveVerification::default();
+        match files {
+            None => {
+                result.problems.push(ArchiveProblem::MissingArchive);
+                result.problems.push(ArchiveProblem::MissingIndex);
+            }
+            Some(files) if !files.is_empty() && files.iter().all(|info| info.blob().is_some()) => {
+                self.verify_blobs(&files, crc_samples, &mut result).await?;
+            }
+            Some(_) => result.problems.push(ArchiveProblem::MissingArchive),
+        }
+        Ok(result)
+    }
+
+    /// see [`Self::verify_archive`], `files` is the content of the existing
+    /// archive index, `None` when it's missing. The ZIP archive is in `object`.
+    ///
+    /// We only read the central directory of the ZIP archive, and the sampled files,
+    /// with range requests. The file names in the central directory and the sampled
+    /// files have to match the archive index.
+    async fn verify_zip_archive(
+        &self,
+        archive_path: &str,
+        object: &str,
+        latest_build_id: Option<BuildId>,
+        files: Option<Vec<FileInfo>>,
+        crc_samples: usize,
+        repair: bool,
+    ) -> Result<ArchiveVerification> {
+        let mut result = ArchiveVerification::default();
+        if files.is_none() {
+            result.problems.push(ArchiveProblem::MissingIndex);
+        }
+
+        let read = self
+            .read_with_range_requests(object, |reader| {
+                spawn_blocking(move || {
+                    let mut archive = zip::ZipArchive::new(reader)?;
+                    let mut paths: Vec<PathBuf> = archive.file_names().map(PathBuf::from).collect();
+                    paths.sort_unstable();
+
+                    // the sampled files, as in the archive index, and if their content
+                    // matches the CRC-32, `None` for directories.
+                    let mut samples = Vec::new();
+                    for i in sample_indexes(archive.len(), crc_samples) {
+                        let index_entry = archive_index::zip_index_entry(&mut archive, i)?;
+                        let mut entry = archive.by_index(i)?;
+                        let intact = (!entry.is_dir()).then(|| {
+                            let mut hasher = crc32fast::Hasher::new();
+                            let read = std::io::copy(&mut entry, &mut HashWriter(&mut hasher));
+                            read.is_ok() && hasher.finalize() == entry.crc32()
+                        });
+                        samples.push((FileInfo::from(index_entry), intact));
+                    }
+                    Ok((paths, samples))
+                })
+            })
+            .await?;
+        let (paths, samples) = match read {
+            Ok(read) => read,
+            Err(err) => {
+                warn!(?err, archive_path, "can't read archive");
+                result.problems.push(ArchiveProblem::UnreadableArchive);
+                return Ok(result);
+            }
+        };
+
+        if let Some(mut files) = files {
+            files.sort_by(|a, b| a.path().cmp(b.path()));
+            let same_paths = files.iter().map(FileInfo::path).eq(paths.iter());
+            let same_samples = samples.iter().all(|(info, _)| {
+                files
+                    .binary_search_by(|file| file.path().cmp(info.path()))
+                    .is_ok_and(|i| files[i].same_entry_as(info))
+            });
+            if !same_paths || !same_samples {
+                result.problems.push(ArchiveProblem::IndexMismatch);
+            }
+        }
+
+        for (info, intact) in samples {
+            let Some(intact) = intact else {
+                continue;
+            };
+            if !intact {
+                result.problems.push(ArchiveProblem::CorruptFile(
+                    info.path().to_string_lossy().into_owned(),
+                ));
+            }
+            result.checked_files += 1;
+        }
+
+        if repair
+            && result
+                .problems
+                .iter()
+                .any(ArchiveProblem::fixed_by_new_index)
+        {
+            // the new index needs the local headers of all files.
+            let entries = self
+                .read_with_range_requests(object, |reader| {
+                    spawn_blocking(move || {
+                        let mut archive = zip::ZipArchive::new(reader)?;
+                        (0..archive.len())
+                            .map(|i| archive_index::zip_index_entry(&mut archive, i))
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .await??;
+
+            let new_index_path = tempfile::NamedTempFile::new()?.into_temp_path();
+            archive_index::create_from_entries(
+                entries,
+                &new_index_path,
+                (object != archive_path).then_some(object),
+            )
+            .await?;
+            self.upload_archive_index(archive_path, &new_index_path)
+                .await?;
+            self.archive_index_cache
+                .purge(archive_path, latest_build_id)
+                .await?;
+            result.index_repaired = true;
+        }
+
+        Ok(result)
+    }
+
+    /// Run `read` with a blocking reader for the object at `path`, that fetches the
+    /// parts it reads with range requests.
+    ///
+    /// Errors from fetching the object are returned as the outer error, the result of
+    /// `read` as the inner one.
+    async fn read_with_range_requests<T, F, Fut>(&self, path: &str, read: F) -> Result<Result<T>>
+    where
+        F: FnOnce(RangeReader) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let size = self
+            .backend
+            .version(path)
+            .await?
+            .size
+            .with_context(|| format!("unknown size of {path}"))?;
+
+        let (requests, mut rx_requests) = mpsc::channel::<RangeRequest>(1);
+        let reader = RangeReader {
+            requests,
+            size,
+            position: 0,
+            block_start: 0,
+            block: Vec::new(),
+        };
+
+        let mut fetch_error = None;
+        // ends when `read` drops the reader.
+        let serve = async {
+            while let Some((range, response)) = rx_requests.recv().await {
+                let content = async {
+                    let mut stream = self.backend.get_stream(path, Some(range)).await?;
+                    let mut content = Vec::new();
+                    stream.content.read_to_end(&mut content).await?;
+                    Ok::<_, anyhow::Error>(content)
+                }
+                .await;
+                let _ = response.send(content.map_err(|err| {
+                    let io_err = std::io::Error::other(err.to_string());
+                    fetch_error.get_or_insert(err);
+                    io_err
+                }));
+            }
+        };
+
+        let (result, ()) = future::join(read(reader), serve).await;
+        match fetch_error {
+            Some(err) => Err(err),
+            None => Ok(result),
+        }
+    }
+
+    /// check that the content-addressed blobs of these files exist, and verify
+    /// the CRC-32 of up to `crc_samples` of the files.
+    async fn verify_blobs(
+        &self,
+        files: &[FileInfo],
+        crc_samples: usize,
+        result: &mut ArchiveVerification,
+    ) -> Result<()> {
+        let blobs: HashSet<&str> = files.iter().filter_map(FileInfo::blob).collect();
+        let missing_blobs: HashSet<&str> =
+            stream::iter(blobs.into_iter().map(Ok::<_, anyhow::Error>))
+                .map_ok(|blob_path| async move {
+                    Ok((!self.backend.exists(blob_path).await?).then_some(blob_path))
+                })
+                .try_buffer_unordered(self.config.network_parallelism)
+                .try_filter_map(|blob_path| future::ready(Ok(blob_path)))
+                .try_collect()
+                .await?;
+        result.problems.extend(
+            missing_blobs
+                .iter()
+                .map(|blob_path| ArchiveProblem::MissingBlob((*blob_path).to_owned())),
+        );
+
+        for i in sample_indexes(files.len(), crc_samples) {
+            let info = &files[i];
+            let Some(blob_path) = info.blob() else {
+                continue;
+            };
+            if missing_blobs.contains(blob_path) {
+                continue;
+            }
+
+            let content = match self
+                .get_range_stream(blob_path, info.range(), info.compression())
+                .await
+            {
+                Ok(stream) => stream.materialize(usize::MAX).await.ok(),
+                Err(_) => None,
+            };
+            if content.is_none_or(|blob| Some(crc32fast::hash(&blob.content)) != info.crc32()) {
+                result.problems.push(ArchiveProblem::CorruptFile(
+                    info.path().to_string_lossy().into_owned(),
+                ));
+            }
+            result.checked_files += 1;
+        }
+        Ok(())
+    }
+
+    /// Content-addressed blobs no archive references any more.
+    ///
+    /// These can be left behind when deleting an archive failed after removing its
+    /// references, see [`Self::release_blobs`]. Includes the copies of blobs that an
+    /// interrupted release left behind.
+    #[instrument(skip(self))]
+    pub async fn unreferenced_blobs(&self) -> Result<Vec<String>> {
+        // references are at `blob-refs/{blob_path}/{archive_path}`, and blob paths have
+        // a fixed depth of 4 path segments.
+        let referenced: HashSet<String> = self
+            .backend
+            .list_prefix(CONTENT_BLOB_REFS_PREFIX)
+            .await
+            .map_ok(|ref_path| {
+                ref_path
+                    .trim_start_matches(CONTENT_BLOB_REFS_PREFIX)
+                    .splitn(5, '/')
+                    .take(4)
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .try_collect()
+            .await?;
+
+        let mut unreferenced: Vec<String> = self
+            .backend
+            .list_prefix(CONTENT_BLOB_PREFIX)
+            .await
+            .try_filter(|blob_path| future::ready(!referenced.contains(blob_path)))
+            .try_collect()
+            .await?;
+        unreferenced.extend(
+            self.backend
+                .list_prefix(RELEASED_BLOB_PREFIX)
+                .await
+                .try_collect::<Vec<_>>()
+                .await?,
+        );
+        Ok(unreferenced)
+    }
+
+    /// Remove the references from the archive at `archive_path` to these
+    /// content-addressed blobs, and delete the blobs that aren't referenced any more.
+    ///
+    /// A new archive adds its reference before it checks if the blob exists, see
+    /// [`Self::store_all_as_blobs`]. It can do both between our check for references
+    /// and the delete, so we keep a copy of the blob in the storage, check again after
+    /// deleting it, and restore it when it's referenced now. Archives adding their
+    /// reference after that will find the blob missing and upload it again.
+    fn release_blobs<'a>(
+        &'a self,
+        archive_path: &'a str,
+        blobs: HashSet<String>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            stream::iter(blobs.into_iter().map(Ok::<_, anyhow::Error>))
+                .try_for_each_concurrent(self.config.network_parallelism, |blob_path| async move {
+                    self.release_blob(archive_path, &blob_path).await
+                })
+                .await
+        })
+    }
+
+    /// Release one blob, see [`Self::release_blobs`].
+    async fn release_blob(&self, archive_path: &str, blob_path: &str) -> Result<()> {
+        self.backend
+            .delete_object(&content_blob_ref_path(blob_path, archive_path))
+            .await?;
+
+        let refs_prefix = content_blob_refs_prefix(blob_path);
+        if self.is_referenced(&refs_prefix).await? {
+            return Ok(());
+        }
+
+        let released_path = released_blob_path(blob_path);
+        match self.backend.copy_object(blob_path, &released_path).await {
+            Ok(()) => {}
+            // deleted by someone else.
+            Err(err) if err.is::<PathNotFoundError>() => return Ok(()),
+            Err(err) => return Err(err),
+        }
+
+        trace!(blob_path, "deleting unreferenced blob");
+        self.backend.delete_object(blob_path).await?;
+
+        if self.is_referenced(&refs_prefix).await? {
+            trace!(blob_path, "restoring blob that was referenced again");
+            self.backend.copy_object(&released_path, blob_path).await?;
+        }
+        self.backend.delete_object(&released_path).await
+    }
+
+    /// Create the archive index for a local ZIP archive, and upload both.
+    ///
+    /// The archive is uploaded to `object`, see [`Index::archive`]. We upload the index
+    /// after the archive, so the index never points to a missing or different archive.
+    async fn upload_archive(
+        &self,
+        archive_path: &str,
+        object: &str,
+        zip_path: &Path,
+    ) -> Result<()> {
+        let local_index_path = tempfile::NamedTempFile::new()?.into_temp_path();
+        {
+            let _span = info_span!("create_archive_index", %archive_path).entered();
 
             archive_index::create(
                 io::BufReader::new(fs::File::open(&zip_path).await?),
                 &local_index_path,
+                (object != archive_path).then_some(object),
             )
             .await?;
+        }
+
+        self.backend
+            .upload_stream(StreamUpload {
+                path: object.to_string(),
+                mime: mimes::APPLICATION_ZIP.clone(),
+                source: StreamUploadSource::File(zip_path.to_path_buf()),
+                compression: None,
+            })
+            .await?;
+        self.upload_archive_index(archive_path, &local_index_path)
+            .await
+    }
+
+    /// Delete the ZIP archives of `archive_path` except `current`: the one at the archive
+    /// path itself, and the objects of replaced archives, see [`Self::repack_archive`].
+    ///
+    /// Web servers with an outdated archive index that still points to one of these
+    /// download the index again when they can't fetch the file, see
+    /// [`Self::stream_from_archive_encoded`].
+    async fn delete_replaced_archives(
+        &self,
+        archive_path: &str,
+        current: Option<&str>,
+    ) -> Result<()> {
+        let remote_index_path = format!("{archive_path}.{ARCHIVE_INDEX_FILE_EXTENSION}");
+        let mut objects: Vec<String> = self
+            .list_prefix(&format!("{archive_path}."))
+            .await
+            .try_filter(|path| future::ready(*path != remote_index_path))
+            .try_collect()
+            .await?;
+        objects.push(archive_path.to_owned());
 
+        for object in objects {
+            if Some(object.as_str()) != current {
+                self.backend.delete_object(&object).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The object with the ZIP archive of `archive_path`, see [`Index::archive`].
+    ///
+    /// `None` for content-addressed archives, which don't have a ZIP archive.
+    pub async fn archive_object(
+        &self,
+        archive_path: &str,
+        latest_build_id: Option<BuildId>,
+    ) -> Result<Option<String>> {
+        let mut index = self
+            .find_archive_index(archive_path, latest_build_id)
+            .await?;
+        if index.is_content_addressed().await? {
+            return Ok(None);
+        }
+        Ok(Some(index.archive().unwrap_or(archive_path).to_owned()))
+    }
+
+    /// Stream the ZIP archive of `archive_path`, for downloads.
+    ///
+    /// For content-addressed archives we create the ZIP archive from the blobs.
+    #[instrument(skip(self))]
+    pub async fn stream_archive(
+        &self,
+        archive_path: &str,
+        latest_build_id: Option<BuildId>,
+    ) -> Result<StreamingBlob> {
+        match self.archive_object(archive_path, latest_build_id).await? {
+            Some(object) => self.get_raw_stream(&object).await,
+            None => self.zip_blobs(archive_path, latest_build_id).await,
+        }
+    }
+
+    /// Create a ZIP archive with the files of a content-addressed archive.
+    ///
+    /// The files are compressed like in the ZIP archives we store.
+    async fn zip_blobs(
+        &self,
+        archive_path: &str,
+        latest_build_id: Option<BuildId>,
+    ) -> Result<StreamingBlob> {
+        let mut index = self
+            .find_archive_index(archive_path, latest_build_id)
+            .await?;
+        let files: Vec<FileInfo> = index.list().try_collect().await?;
+        let store_compressed = self.config.new_archive_index_format;
+        // the files only change with the archive index.
+        let index_version = self
+            .backend
+            .version(&format!("{archive_path}.{ARCHIVE_INDEX_FILE_EXTENSION}"))
+            .await?;
+
+        // rustdoc archives can become a couple of GiB big, so we better use a tempfile.
+        let mut zip = zip::ZipWriter::new(std::io::BufWriter::with_capacity(
+            ZIP_BUFFER_SIZE,
+            tempfile::tempfile()?,
+        ));
+
+        // we fetch the next files while we add one to the archive, but keep their order.
+        let mut contents = stream::iter(files.into_iter().map(Ok::<_, anyhow::Error>))
+            .map_ok(|info| async move {
+                let blob_path = info.blob().context("file without blob in archive")?;
+                let content = self
+                    .get_range_stream(blob_path, info.range(), info.compression())
+                    .await?
+                    .materialize(usize::MAX)
+                    .await?
+                    .content;
+                Ok((info.path().to_owned(), content))
+            })
+            .try_buffered(self.config.network_parallelism);
+
+        while let Some((path, content)) = contents.try_next().await? {
+            zip = spawn_blocking(move || {
+                zip.start_file(
+                    path.to_str().context("non-UTF-8 path in archive")?,
+                    archive_file_options(&path, store_compressed),
+                )?;
+                zip.write_all(&content)?;
+                Ok(zip)
+            })
+            .await?;
+        }
+
+        let zip_file = spawn_blocking(move || {
+            use std::io::Seek as _;
+
+            let mut zip_file = zip.finish()?.into_inner()?;
+            zip_file.rewind()?;
+            Ok(zip_file)
+        })
+        .await?;
+        let content_length = zip_file.metadata()?.len();
+
+        Ok(StreamingBlob {
+            path: archive_path.to_owned(),
+            mime: mimes::APPLICATION_ZIP.clone(),
+            date_updated: index_version.date_updated,
+            etag: index_version.etag,
+            compression: None,
+            content_length: Some(content_length as usize),
+            content: Box::new(io::BufReader::new(fs::File::from_std(zip_file))),
+        })
+    }
+
+    /// Compress and upload a local archive index for the archive at `archive_path`.
+    async fn upload_archive_index(
+        &self,
+        archive_path: &str,
+        local_index_path: &Path,
+    ) -> Result<()> {
+        let remote_index_path = format!("{}.{ARCHIVE_INDEX_FILE_EXTENSION}", archive_path);
+        let index_compression_alg = CompressionAlgorithm::default();
+
+        let compressed_index_temp_path = tempfile::NamedTempFile::new()?.into_temp_path();
+        let compressed_index_path = compressed_index_temp_path.to_path_buf();
+        {
             // compressed index can become up to a couple 100 MiB big, so rather use a tempfile.
             let mut compressed_index_file = fs::File::create(&compressed_index_path).await?;
-            {
-                let mut compressed_index_writer = io::BufWriter::new(&mut compressed_index_file);
-                compress_async(
-                    &mut io::BufReader::new(fs::File::open(&local_index_path).await?),
-                    &mut compressed_index_writer,
-                    index_compression_alg,
-                )
-                .await?;
-                compressed_index_writer.flush().await?;
-            }
+            let mut compressed_index_writer = io::BufWriter::new(&mut compressed_index_file);
+            compress_async(
+                &mut io::BufReader::new(fs::File::open(local_index_path).await?),
+                &mut compressed_index_writer,
+                index_compression_alg,
+            )
+            .await?;
+            compressed_index_writer.flush().await?;
         }
 
-        tokio::try_join!(
-            self.backend.upload_stream(StreamUpload {
-                path: archive_path.to_string(),
-                mime: mimes::APPLICATION_ZIP.clone(),
-                source: StreamUploadSource::File(zip_path),
-                compression: None,
-            }),
-            self.backend.upload_stream(StreamUpload {
+        self.backend
+            .upload_stream(StreamUpload {
                 path: remote_index_path,
                 mime: mime::APPLICATION_OCTET_STREAM,
                 source: StreamUploadSource::File(compressed_index_path),
                 compression: Some(index_compression_alg),
             })
-        )?;
+            .await
+    }
+
+    /// Re-compress all files in an existing archive like we would for new archives,
+    /// see `archive_file_options`.
+    ///
+    /// Only with `replace` the repacked archive and its index replace the existing ones,
+    /// otherwise this only measures the difference in size.
+    ///
+    /// The repacked archive is uploaded to a new object, and uploading its index switches
+    /// over to it, see [`Index::archive`]. Web servers notice the new index when they
+    /// revalidate their local copy, see `ArchiveIndexCacheConfig::revalidate_after`. Until
+    /// then they read the old archive, and when it's deleted already, they download the
+    /// new index. Responses from the CDN have to be invalidated by the caller.
+    #[instrument(skip(self))]
+    pub async fn repack_archive(
+        &self,
+        archive_path: &str,
+        latest_build_id: Option<BuildId>,
+        replace: bool,
+    ) -> Result<RepackStatistics> {
+        if replace && !self.config.new_archive_index_format {
+            anyhow::bail!(
+                "replacing archives needs the new archive index format, see DOCSRS_NEW_ARCHIVE_INDEX_FORMAT"
+            );
+        }
+
+        // Keep the TempPath guards alive until after the upload, see `store_all_in_archive`.
+        let old_zip_temp_path = tempfile::NamedTempFile::new()?.into_temp_path();
+        let new_zip_temp_path = tempfile::NamedTempFile::new()?.into_temp_path();
+
+        // the archive might have been replaced before.
+        let old_index_temp_path = tempfile::NamedTempFile::new()?.into_temp_path();
+        if !self
+            .download_archive_index_to(archive_path, &old_index_temp_path)
+            .await?
+        {
+            return Err(PathNotFoundError.into());
+        }
+        let old_object = Index::open(&old_index_temp_path)
+            .await?
+            .archive()
+            .unwrap_or(archive_path)
+            .to_owned();
+
+        self.download_to(self.get_raw_stream(&old_object).await?, &old_zip_temp_path)
+            .await?;
+
+        let stats = spawn_blocking({
+            use std::{fs, io};
+            let archive_path = archive_path.to_owned();
+            let old_zip_path = old_zip_temp_path.to_path_buf();
+            let new_zip_path = new_zip_temp_path.to_path_buf();
+            let store_compressed = self.config.new_archive_index_format;
+
+            move || {
+                let _span = info_span!("repack_zip_archive", %archive_path).entered();
+
+                let mut archive =
+                    zip::ZipArchive::new(io::BufReader::new(fs::File::open(&old_zip_path)?))?;
+                let mut zip = zip::ZipWriter::new(io::BufWriter::with_capacity(
+                    ZIP_BUFFER_SIZE,
+                    fs::File::create(&new_zip_path)?,
+                ));
+
+                let mut file_count = 0;
+                for i in 0..archive.len() {
+                    let mut entry = archive.by_index(i)?;
+                    if entry.is_dir() {
+                        zip.raw_copy_file(entry)?;
+                        continue;
+                    }
+
+                    let name = entry.name().to_owned();
+                    zip.start_file(
+                        &name,
+                        archive_file_options(Path::new(&name), store_compressed),
+                    )?;
+                    io::copy(&mut entry, &mut zip)?;
+                    file_count += 1;
+                }
+
+                let mut zip_file = zip.finish()?.into_inner()?;
+                zip_file.flush()?;
+
+                Ok(RepackStatistics {
+                    file_count,
+                    old_size: fs::metadata(&old_zip_path)?.len(),
+                    new_size: fs::metadata(&new_zip_path)?.len(),
+                })
+            }
+        })
+        .await?;
+
+        if replace {
+            let new_object = format!("{archive_path}.{}", Utc::now().timestamp_millis());
+            self.upload_archive(archive_path, &new_object, &new_zip_temp_path)
+                .await?;
+            self.delete_replaced_archives(archive_path, Some(&new_object))
+                .await?;
+            // at least our own cached index shouldn't be outdated.
+            self.archive_index_cache
+                .purge(archive_path, latest_build_id)
+                .await?;
+        }
 
         Ok(stats)
     }
 
+    /// Copy an object to another storage as it is stored, without decompressing it.
+    ///
+    /// Returns the stored size, `None` when the object doesn't exist (any more).
+    ///
+    /// During a migration with `Config::migrate_from` the servers write to both storages
+    /// while we copy, see `DualWriteBackend`. When the object in this storage changed
+    /// after we read it, a newer version might have reached `destination` before our
+    /// upload, so we copy it again. Deleted objects are deleted from `destination` too.
+    #[instrument(skip(self, destination))]
+    pub async fn copy_object_to(
+        &self,
+        path: &str,
+        destination: &AsyncStorage,
+    ) -> Result<Option<u64>> {
+        loop {
+            let stream = match self.get_raw_stream(path).await {
+                Ok(stream) => stream,
+                Err(err) if err.is::<PathNotFoundError>() => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            let version = stream.version();
+            let mime = stream.mime.clone();
+            let compression = stream.compression;
+
+            // objects like rustdoc archives can be a couple of GiB big.
+            let local_path = tempfile::NamedTempFile::new()?.into_temp_path();
+            self.download_to(stream, &local_path).await?;
+            let size = fs::metadata(&local_path).await?.len();
+
+            destination
+                .backend
+                .upload_stream(StreamUpload {
+                    path: path.to_owned(),
+                    mime,
+                    source: StreamUploadSource::File(local_path.to_path_buf()),
+                    compression,
+                })
+                .await?;
+
+            match self.backend.version(path).await {
+                Ok(current) if current == version => return Ok(Some(size)),
+                Ok(_) => trace!(path, "object changed while copying it, copying it again"),
+                Err(err) if err.is::<PathNotFoundError>() => {
+                    destination.backend.delete_object(path).await?;
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Check that `destination` has the same object at `path`, with the same compression
+    /// and the same stored bytes.
+    #[instrument(skip(self, destination))]
+    pub async fn verify_object_copy(&self, path: &str, destination: &AsyncStorage) -> Result<bool> {
+        let Some(copy) = destination.raw_checksum(path).await? else {
+            return Ok(false);
+        };
+        Ok(self.raw_checksum(path).await? == Some(copy))
+    }
+
+    /// The compression, stored size and CRC-32 of an object, `None` when it doesn't exist.
+    async fn raw_checksum(
+        &self,
+        path: &str,
+    ) -> Result<Option<(Option<CompressionAlgorithm>, u64, u32)>> {
+        let mut stream = match self.get_raw_stream(path).await {
+            Ok(stream) => stream,
+            Err(err) if err.is::<PathNotFoundError>() => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size = 0;
+        loop {
+            let buf = stream.content.fill_buf().await?;
+            if buf.is_empty() {
+                break;
+            }
+            hasher.update(buf);
+            let len = buf.len();
+            size += len as u64;
+            stream.content.consume(len);
+        }
+
+        Ok(Some((stream.compression, size, hasher.finalize())))
+    }
+
     /// Store all files in `root_dir` into the backend under `prefix`.
     #[instrument(skip(self))]
     pub async fn store_all(
@@ -505,9 +1599,39 @@ impl AsyncStorage {
         self.backend.list_prefix(prefix).await
     }
 
+    /// Delete all objects under `prefix`.
+    ///
+    /// The content-addressed blobs of deleted archives are garbage collected,
+    /// see [`Self::release_blobs`].
     #[instrument(skip(self))]
     pub async fn delete_prefix(&self, prefix: &str) -> Result<()> {
-        self.backend.delete_prefix(prefix).await
+        if !self.might_have_blobs().await? {
+            return self.backend.delete_prefix(prefix).await;
+        }
+
+        let index_suffix = format!(".{ARCHIVE_INDEX_FILE_EXTENSION}");
+        let archive_paths: Vec<String> = self
+            .backend
+            .list_prefix(prefix)
+            .await
+            .try_filter_map(|path| {
+                future::ready(Ok(path.strip_suffix(&index_suffix).map(ToOwned::to_owned)))
+            })
+            .try_collect()
+            .await?;
+
+        let mut released = Vec::with_capacity(archive_paths.len());
+        for archive_path in archive_paths {
+            let blobs = self.referenced_blobs(&archive_path).await?;
+            released.push((archive_path, blobs));
+        }
+
+        self.backend.delete_prefix(prefix).await?;
+
+        for (archive_path, blobs) in released {
+            self.release_blobs(&archive_path, blobs).await?;
+        }
+        Ok(())
     }
 
     // We're using `&self` instead of consuming `self` or creating a Drop impl because during tests
@@ -529,6 +1653,13 @@ impl archive_index::Downloader for AsyncStorage {
     ) -> Pin<Box<dyn Future<Output = Result<StreamingBlob>> + Send + 'a>> {
         Box::pin(self.get_stream(remote_index_path))
     }
+
+    fn archive_index_modified<'a>(
+        &'a self,
+        remote_index_path: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<DateTime<Utc>>> + Send + 'a>> {
+        Box::pin(async move { Ok(self.backend.version(remote_index_path).await?.date_updated) })
+    }
 }
 
 impl fmt::Debug for AsyncStorage {
@@ -537,10 +1668,153 @@ impl fmt::Debug for AsyncStorage {
             #[cfg(any(test, feature = "testing"))]
             StorageBackend::Memory(_) => write!(f, "memory-backed storage"),
             StorageBackend::S3(_) => write!(f, "S3-backed storage"),
+            StorageBackend::Filesystem(_) => write!(f, "filesystem-backed storage"),
+            StorageBackend::DualWrite(_) => write!(f, "storage writing to two backends"),
         }
     }
 }
 
+/// The compression we use for a file in an archive, depending on its file type.
+///
+/// Files in formats that are already compressed are stored as they are (`None`),
+/// everything else is compressed with zstd.
+/// Without `Config::new_archive_index_format` we compress all files, older web servers
+/// can't read stored files from archive indexes.
+fn archive_file_compression(path: &Path, store_compressed: bool) -> Option<CompressionAlgorithm> {
+    let already_compressed = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| STORED_FILE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+
+    (!(store_compressed && already_compressed)).then_some(CompressionAlgorithm::Zstd)
+}
+
+/// The ZIP options for a file in an archive, see [`archive_file_compression`].
+fn archive_file_options(path: &Path, store_compressed: bool) -> zip::write::SimpleFileOptions {
+    let options = zip::write::SimpleFileOptions::default();
+
+    match archive_file_compression(path, store_compressed) {
+        None => options.compression_method(zip::CompressionMethod::Stored),
+        Some(_) => options
+            .compression_method(zip::CompressionMethod::Zstd)
+            .compression_level(Some(ARCHIVE_ZSTD_LEVEL)),
+    }
+}
+
+/// up to `samples` random indexes into a list with `len` items, in ascending order.
+///
+/// Different runs check different files, so repeated verifications cover more of an archive.
+fn sample_indexes(len: usize, samples: usize) -> Vec<usize> {
+    let mut indexes = rand::seq::index::sample(&mut rand::rng(), len, samples.min(len)).into_vec();
+    indexes.sort_unstable();
+    indexes
+}
+
+/// how much of an object a [`RangeReader`] fetches at once.
+const RANGE_READER_BLOCK_SIZE: u64 = 256 * 1024;
+
+/// a range of an object a [`RangeReader`] wants, and where to send it.
+type RangeRequest = (FileRange, oneshot::Sender<std::io::Result<Vec<u8>>>);
+
+/// A blocking reader for an object in storage, see
+/// [`AsyncStorage::read_with_range_requests`].
+///
+/// Fetches aligned blocks of the object, and keeps the last one.
+struct RangeReader {
+    requests: mpsc::Sender<RangeRequest>,
+    size: u64,
+    position: u64,
+    block_start: u64,
+    block: Vec<u8>,
+}
+
+impl std::io::Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_end = self.block_start + self.block.len() as u64;
+        if !(self.block_start..block_end).contains(&self.position) {
+            let start = self.position - self.position % RANGE_READER_BLOCK_SIZE;
+            let end = (start + RANGE_READER_BLOCK_SIZE).min(self.size) - 1;
+            let (response, rx_response) = oneshot::channel();
+            self.requests
+                .blocking_send((start..=end, response))
+                .map_err(|_| std::io::Error::other("range request receiver dropped"))?;
+            self.block = rx_response
+                .blocking_recv()
+                .map_err(|_| std::io::Error::other("range request sender dropped"))??;
+            self.block_start = start;
+        }
+
+        let offset = (self.position - self.block_start) as usize;
+        let available = &self.block[offset.min(self.block.len())..];
+        let len = buf.len().min(available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl std::io::Seek for RangeReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            std::io::SeekFrom::Start(offset) => Some(offset),
+            std::io::SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            std::io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// feeds everything written into a CRC-32 hasher.
+struct HashWriter<'a>(&'a mut crc32fast::Hasher);
+
+impl std::io::Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The encoding we can serve a file from an archive with, without decompressing it.
+fn passthrough_encoding(
+    info: &FileInfo,
+    accepted: &[CompressionAlgorithm],
+) -> Option<CompressionAlgorithm> {
+    let encoding = match info.compression()? {
+        // we need the CRC-32 & size for the gzip trailer, only newer archive indexes have them.
+        CompressionAlgorithm::Deflate if info.crc32().is_some() && info.size().is_some() => {
+            CompressionAlgorithm::Gzip
+        }
+        CompressionAlgorithm::Deflate => return None,
+        compression => compression,
+    };
+    accepted.contains(&encoding).then_some(encoding)
+}
+
+/// derive the ETag of an encoded representation from the ETag of the original content.
+fn etag_for_encoding(etag: &ETag, encoding: CompressionAlgorithm) -> ETag {
+    let mut values = Vec::with_capacity(1);
+    etag.encode(&mut values);
+    let etag = values
+        .first()
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    compute_etag(format!("{etag}-{}", encoding.file_extension()))
+}
+
 /// Backend tests are a set of tests executed on all the supportedootorage backends. They ensure
 /// docs.rs behaves the same no matter the storage backend currently used.
 ///
@@ -552,7 +1826,9 @@ impl fmt::Debug for AsyncStorage {
 mod backend_tests {
     use super::*;
     use crate::blob::BlobUpload;
-    use crate::{PathNotFoundError, errors::SizeLimitReached};
+    use crate::{
+        PathNotFoundError, errors::SizeLimitReached, testing::TestStorage, types::StorageKind,
+    };
     use docs_rs_headers::compute_etag;
     use docs_rs_opentelemetry::testing::TestMetrics;
 
@@ -830,7 +2106,7 @@ mod backend_tests {
                 .await?
         );
 
-        assert_eq!(stats.alg, CompressionAlgorithm::Deflate);
+        assert_eq!(stats.alg, CompressionAlgorithm::Zstd);
         assert_eq!(stats.file_count, files.len() as u64);
 
         // delete the existing index to test the download of it
@@ -881,6 +2157,646 @@ mod backend_tests {
         Ok(())
     }
 
+    /// upload a ZIP archive where all files are compressed with `method`.
+    async fn upload_test_archive(
+        storage: &AsyncStorage,
+        archive_path: &str,
+        files: &[(&str, &[u8])],
+        method: zip::CompressionMethod,
+    ) -> Result<()> {
+        let zip_path = tempfile::NamedTempFile::new()?.into_temp_path();
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path)?);
+            for (name, content) in files {
+                zip.start_file(
+                    *name,
+                    zip::write::SimpleFileOptions::default().compression_method(method),
+                )?;
+                zip.write_all(content)?;
+            }
+            zip.finish()?;
+        }
+        storage
+            .upload_archive(archive_path, archive_path, &zip_path)
+            .await
+    }
+
+    async fn test_stream_from_archive_encoded(storage: &AsyncStorage) -> Result<()> {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-archive-test")
+            .tempdir()?;
+        let content = "some compressible data ".repeat(100);
+        fs::write(dir.path().join("main.js"), &content).await?;
+        fs::write(dir.path().join("image.png"), &content).await?;
+
+        const ARCHIVE_PATH: &str = "folder/encoded.zip";
+        storage
+            .store_all_in_archive(ARCHIVE_PATH, dir.path())
+            .await?;
+
+        let plain = storage
+            .stream_from_archive(ARCHIVE_PATH, None, "main.js")
+            .await?;
+        assert_eq!(plain.compression, None);
+        let plain_etag = plain.etag.clone();
+
+        // zstd entries can't be served as gzip
+        let blob = storage
+            .stream_from_archive_encoded(
+                ARCHIVE_PATH,
+                None,
+                "main.js",
+                &[CompressionAlgorithm::Gzip],
+            )
+            .await?;
+        assert_eq!(blob.compression, None);
+        assert_eq!(
+            blob.materialize(usize::MAX).await?.content,
+            content.as_bytes()
+        );
+
+        let blob = storage
+            .stream_from_archive_encoded(
+                ARCHIVE_PATH,
+                None,
+                "main.js",
+                &[CompressionAlgorithm::Gzip, CompressionAlgorithm::Zstd],
+            )
+            .await?;
+        assert_eq!(blob.compression, Some(CompressionAlgorithm::Zstd));
+        assert_eq!(blob.mime, "text/javascript");
+        assert!(blob.etag.is_some());
+        assert_ne!(blob.etag, plain_etag);
+
+        let content_length = blob.content_length;
+        let blob = blob.materialize(usize::MAX).await?;
+        assert_eq!(content_length, Some(blob.content.len()));
+        assert!(blob.content.len() < content.len());
+        assert_eq!(
+            crate::decompress(&*blob.content, CompressionAlgorithm::Zstd, usize::MAX)?,
+            content.as_bytes()
+        );
+
+        // stored files are never encoded
+        let blob = storage
+            .stream_from_archive_encoded(
+                ARCHIVE_PATH,
+                None,
+                "image.png",
+                &[CompressionAlgorithm::Gzip, CompressionAlgorithm::Zstd],
+            )
+            .await?;
+        assert_eq!(blob.compression, None);
+        assert_eq!(
+            blob.materialize(usize::MAX).await?.content,
+            content.as_bytes()
+        );
+
+        Ok(())
+    }
+
+    async fn test_stream_deflate_from_archive_as_gzip(storage: &AsyncStorage) -> Result<()> {
+        let content = "some compressible data ".repeat(100);
+        const ARCHIVE_PATH: &str = "folder/deflate.zip";
+        upload_test_archive(
+            storage,
+            ARCHIVE_PATH,
+            &[("main.js", content.as_bytes())],
+            zip::CompressionMethod::Deflated,
+        )
+        .await?;
+
+        // raw deflate streams can't be served as they are
+        let blob = storage
+            .stream_from_archive_encoded(
+                ARCHIVE_PATH,
+                None,
+                "main.js",
+                &[CompressionAlgorithm::Deflate, CompressionAlgorithm::Zstd],
+            )
+            .await?;
+        assert_eq!(blob.compression, None);
+        assert_eq!(
+            blob.materialize(usize::MAX).await?.content,
+            content.as_bytes()
+        );
+
+        let blob = storage
+            .stream_from_archive_encoded(
+                ARCHIVE_PATH,
+                None,
+                "main.js",
+                &[CompressionAlgorithm::Gzip],
+            )
+            .await?;
+        assert_eq!(blob.compression, Some(CompressionAlgorithm::Gzip));
+
+        let content_length = blob.content_length;
+        let blob = blob.materialize(usize::MAX).await?;
+        assert_eq!(content_length, Some(blob.content.len()));
+        assert_eq!(
+            crate::decompress(&*blob.content, CompressionAlgorithm::Gzip, usize::MAX)?,
+            content.as_bytes()
+        );
+
+        Ok(())
+    }
+
+    async fn test_repack_archive(storage: &AsyncStorage) -> Result<()> {
+        let content = "some compressible data ".repeat(1000);
+        const ARCHIVE_PATH: &str = "folder/repack.zip";
+        upload_test_archive(
+            storage,
+            ARCHIVE_PATH,
+            &[
+                ("index.html", content.as_bytes()),
+                ("image.png", content.as_bytes()),
+            ],
+            zip::CompressionMethod::Bzip2,
+        )
+        .await?;
+
+        let compression = async |path| -> Result<_> {
+            Ok(storage
+                .archive_index_cache
+                .find(ARCHIVE_PATH, None, path, storage)
+                .await?
+                .unwrap()
+                .compression())
+        };
+
+        // only measure
+        let stats = storage.repack_archive(ARCHIVE_PATH, None, false).await?;
+        assert_eq!(stats.file_count, 2);
+        assert!(stats.old_size > 0 && stats.new_size > 0);
+        assert_eq!(
+            compression("index.html").await?,
+            Some(CompressionAlgorithm::Bzip2)
+        );
+
+        // a web server with the index from before the repack.
+        const STALE_BUILD_ID: Option<BuildId> = Some(BuildId(42));
+        storage
+            .find_archive_index(ARCHIVE_PATH, STALE_BUILD_ID)
+            .await?;
+
+        assert_eq!(
+            storage.repack_archive(ARCHIVE_PATH, None, true).await?,
+            stats
+        );
+        assert_eq!(
+            compression("index.html").await?,
+            Some(CompressionAlgorithm::Zstd)
+        );
+        assert_eq!(compression("image.png").await?, None);
+
+        // the repacked archive is in a new object, and the old one is gone.
+        let object = storage
+            .archive_object(ARCHIVE_PATH, None)
+            .await?
+            .expect("ZIP archive");
+        assert_ne!(object, ARCHIVE_PATH);
+        assert!(storage.exists(&object).await?);
+        assert!(!storage.exists(ARCHIVE_PATH).await?);
+
+        for build_id in [None, STALE_BUILD_ID] {
+            for path in ["index.html", "image.png"] {
+                let blob = storage
+                    .get_from_archive(ARCHIVE_PATH, build_id, path)
+                    .await?;
+                assert_eq!(blob.content, content.as_bytes());
+            }
+        }
+
+        // repacking again replaces the repacked archive.
+        storage.repack_archive(ARCHIVE_PATH, None, true).await?;
+        let mut expected = vec![
+            format!("{ARCHIVE_PATH}.{ARCHIVE_INDEX_FILE_EXTENSION}"),
+            storage
+                .archive_object(ARCHIVE_PATH, None)
+                .await?
+                .expect("ZIP archive"),
+        ];
+        expected.sort();
+        assert_eq!(list_sorted(storage, ARCHIVE_PATH).await?, expected);
+        let blob = storage
+            .get_from_archive(ARCHIVE_PATH, None, "index.html")
+            .await?;
+        assert_eq!(blob.content, content.as_bytes());
+
+        // new builds replace the repacked archive.
+        let dir = create_test_dir(&[("index.html", "new")]).await?;
+        storage
+            .store_all_in_archive(ARCHIVE_PATH, dir.path())
+            .await?;
+        assert_eq!(
+            list_sorted(storage, ARCHIVE_PATH).await?,
+            vec![
+                ARCHIVE_PATH.to_owned(),
+                format!("{ARCHIVE_PATH}.{ARCHIVE_INDEX_FILE_EXTENSION}"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replace_needs_new_archive_index_format() -> Result<()> {
+        let storage = TestStorage::from_config(
+            Arc::new(
+                Config::test_config_with_kind(StorageKind::Memory)?.set(|mut config| {
+                    config.new_archive_index_format = false;
+                    config
+                }),
+            ),
+            TestMetrics::new().provider(),
+        )
+        .await?;
+        const ARCHIVE_PATH: &str = "folder/repack.zip";
+        upload_test_archive(
+            &storage,
+            ARCHIVE_PATH,
+            &[("image.png", b"content")],
+            zip::CompressionMethod::Bzip2,
+        )
+        .await?;
+
+        assert!(
+            storage
+                .repack_archive(ARCHIVE_PATH, None, true)
+                .await
+                .is_err()
+        );
+        storage.repack_archive(ARCHIVE_PATH, None, false).await?;
+
+        // without it, we also compress files in compressed formats.
+        assert_eq!(
+            archive_file_compression(Path::new("image.png"), false),
+            Some(CompressionAlgorithm::Zstd)
+        );
+        assert_eq!(archive_file_compression(Path::new("image.png"), true), None);
+
+        Ok(())
+    }
+
+    async fn test_copy_object(storage: &AsyncStorage) -> Result<()> {
+        let destination =
+            TestStorage::from_kind(StorageKind::Memory, TestMetrics::new().provider()).await?;
+
+        let content = "some compressible data ".repeat(100);
+        storage
+            .store_one("folder/compressed.txt", content.clone())
+            .await?;
+        storage
+            .store_one_uncompressed("folder/stored.png", content.clone())
+            .await?;
+
+        for path in ["folder/compressed.txt", "folder/stored.png"] {
+            assert!(!storage.verify_object_copy(path, &destination).await?);
+
+            let size = storage
+                .copy_object_to(path, &destination)
+                .await?
+                .expect("the object exists");
+            assert!(storage.verify_object_copy(path, &destination).await?);
+
+            let original = storage.get_raw_stream(path).await?;
+            let copy = destination.get_raw_stream(path).await?;
+            assert_eq!(copy.compression, original.compression);
+            assert_eq!(copy.content_length, Some(size as usize));
+            assert_eq!(
+                destination.get(path, usize::MAX).await?.content,
+                content.as_bytes()
+            );
+        }
+
+        destination
+            .store_one_uncompressed("folder/stored.png", "changed")
+            .await?;
+        assert!(
+            !storage
+                .verify_object_copy("folder/stored.png", &destination)
+                .await?
+        );
+
+        // deleted after listing it.
+        assert_eq!(
+            storage
+                .copy_object_to("folder/missing.txt", &destination)
+                .await?,
+            None
+        );
+        assert!(!destination.exists("folder/missing.txt").await?);
+
+        Ok(())
+    }
+
+    async fn test_dual_write(storage: &AsyncStorage, old: &AsyncStorage) -> Result<()> {
+        storage.store_one("both.txt", "both").await?;
+        old.store_one("old.txt", "old").await?;
+
+        // writes go to both storages, and reads fall back to the old one.
+        for (path, content) in [("both.txt", "both"), ("old.txt", "old")] {
+            assert!(storage.exists(path).await?);
+            assert_eq!(
+                storage.get(path, usize::MAX).await?.content,
+                content.as_bytes()
+            );
+        }
+        assert_eq!(old.get("both.txt", usize::MAX).await?.content, b"both");
+
+        storage.store_one("new.txt", "new").await?;
+        old.delete_prefix("new.txt").await?;
+        storage.store_one("other/file.txt", "other").await?;
+
+        assert_eq!(
+            list_sorted(storage, "").await?,
+            vec!["both.txt", "new.txt", "old.txt", "other/file.txt"]
+        );
+        let listed: Vec<_> = storage.list_prefix("").await.try_collect().await?;
+        assert_eq!(listed, list_sorted(storage, "").await?);
+
+        storage.delete_prefix("o").await?;
+        assert_eq!(list_sorted(storage, "").await?, vec!["both.txt", "new.txt"]);
+        assert_eq!(list_sorted(old, "").await?, vec!["both.txt"]);
+
+        Ok(())
+    }
+
+    /// write `files` into a new temporary directory.
+    async fn create_test_dir(files: &[(&str, &str)]) -> Result<tempfile::TempDir> {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-content-addressed-test")
+            .tempdir()?;
+        for (file, content) in files {
+            let path = dir.path().join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(path, content).await?;
+        }
+        Ok(dir)
+    }
+
+    async fn list_sorted(storage: &AsyncStorage, prefix: &str) -> Result<Vec<String>> {
+        let mut paths: Vec<String> = storage.list_prefix(prefix).await.try_collect().await?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    async fn test_store_all_content_addressed(storage: &AsyncStorage) -> Result<()> {
+        use std::io::Read as _;
+
+        let shared = "function shared() {} ".repeat(100);
+        let dir_a = create_test_dir(&[
+            ("shared.js", &shared),
+            ("index.html", "a"),
+            ("image.png", "not really a png"),
+        ])
+        .await?;
+        let dir_b = create_test_dir(&[("shared.js", &shared), ("index.html", "b")]).await?;
+
+        let stats = storage.store_all_in_archive("a.zip", dir_a.path()).await?;
+        assert_eq!(stats.file_count, 3);
+        storage.store_all_in_archive("b.zip", dir_b.path()).await?;
+
+        // only the index, no ZIP archive.
+        assert_eq!(list_sorted(storage, "a.zip").await?, vec!["a.zip.index"]);
+        // `shared.js` is only stored once.
+        assert_eq!(list_sorted(storage, "blobs/").await?.len(), 4);
+
+        for (archive_path, path, content) in [
+            ("a.zip", "shared.js", shared.as_str()),
+            ("a.zip", "index.html", "a"),
+            ("a.zip", "image.png", "not really a png"),
+            ("b.zip", "shared.js", shared.as_str()),
+            ("b.zip", "index.html", "b"),
+        ] {
+            let blob = storage.get_from_archive(archive_path, None, path).await?;
+            assert_eq!(blob.content, content.as_bytes(), "{archive_path}/{path}");
+            assert_eq!(blob.path, format!("{archive_path}/{path}"));
+        }
+
+        let stream = storage
+            .stream_from_archive_encoded("b.zip", None, "shared.js", &[CompressionAlgorithm::Zstd])
+            .await?;
+        assert_eq!(stream.compression, Some(CompressionAlgorithm::Zstd));
+        let blob = stream.decompress().await?.materialize(usize::MAX).await?;
+        assert_eq!(blob.content, shared.as_bytes());
+
+        // downloads get a ZIP archive created from the blobs.
+        let blob = storage
+            .stream_archive("a.zip", None)
+            .await?
+            .materialize(usize::MAX)
+            .await?;
+        let mut zip = zip::ZipArchive::new(Cursor::new(blob.content))?;
+        assert_eq!(zip.len(), 3);
+        let mut content = String::new();
+        zip.by_name("shared.js")?.read_to_string(&mut content)?;
+        assert_eq!(content, shared);
+
+        // the shared blob is still referenced by `b.zip`.
+        storage.delete_prefix("a.zip").await?;
+        assert_eq!(list_sorted(storage, "blobs/").await?.len(), 2);
+        let blob = storage.get_from_archive("b.zip", None, "shared.js").await?;
+        assert_eq!(blob.content, shared.as_bytes());
+
+        storage.delete_prefix("b.zip").await?;
+        assert!(list_sorted(storage, "blobs/").await?.is_empty());
+        assert!(list_sorted(storage, "blob-refs/").await?.is_empty());
+        assert!(list_sorted(storage, "released-blobs/").await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_replace_content_addressed(storage: &AsyncStorage) -> Result<()> {
+        // a ZIP archive from before we switched to blobs.
+        upload_test_archive(
+            storage,
+            "folder/test.zip",
+            &[("lib.rs", b"zip")],
+            zip::CompressionMethod::Stored,
+        )
+        .await?;
+
+        let dir = create_test_dir(&[("lib.rs", "old"), ("main.rs", "same")]).await?;
+        storage
+            .store_all_in_archive("folder/test.zip", dir.path())
+            .await?;
+        // only the ZIP archive is deleted, not the new index.
+        assert_eq!(
+            list_sorted(storage, "folder/").await?,
+            vec!["folder/test.zip.index"]
+        );
+        let old_blobs = list_sorted(storage, "blobs/").await?;
+
+        let dir = create_test_dir(&[("lib.rs", "new"), ("main.rs", "same")]).await?;
+        storage
+            .store_all_in_archive("folder/test.zip", dir.path())
+            .await?;
+        let new_blobs = list_sorted(storage, "blobs/").await?;
+
+        // the blob with the old content is gone, the unchanged one is kept.
+        assert_eq!(new_blobs.len(), 2);
+        assert_eq!(
+            old_blobs
+                .iter()
+                .filter(|blob| new_blobs.contains(blob))
+                .count(),
+            1
+        );
+
+        let blob = storage
+            .get_from_archive("folder/test.zip", None, "lib.rs")
+            .await?;
+        assert_eq!(blob.content, b"new");
+
+        Ok(())
+    }
+
+    async fn test_verify_archive(storage: &AsyncStorage) -> Result<()> {
+        const ARCHIVE_PATH: &str = "folder/verify.zip";
+        upload_test_archive(
+            storage,
+            ARCHIVE_PATH,
+            &[("a.txt", b"first file"), ("b.txt", b"second file")],
+            zip::CompressionMethod::Stored,
+        )
+        .await?;
+
+        let result = storage
+            .verify_archive(ARCHIVE_PATH, None, 10, false)
+            .await?;
+        assert_eq!(result.checked_files, 2);
+        assert!(result.problems.is_empty());
+
+        // flip a byte inside the first file
+        let mut zip = storage
+            .get_raw_stream(ARCHIVE_PATH)
+            .await?
+            .materialize(usize::MAX)
+            .await?
+            .content;
+        let pos = zip
+            .windows(10)
+            .position(|window| window == b"first file")
+            .unwrap();
+        zip[pos] = b'F';
+        storage.store_one_uncompressed(ARCHIVE_PATH, zip).await?;
+        storage
+            .delete_prefix(&format!("{ARCHIVE_PATH}.{ARCHIVE_INDEX_FILE_EXTENSION}"))
+            .await?;
+
+        let result = storage.verify_archive(ARCHIVE_PATH, None, 10, true).await?;
+        assert_eq!(
+            result.problems,
+            vec![
+                ArchiveProblem::MissingIndex,
+                ArchiveProblem::CorruptFile("a.txt".into())
+            ]
+        );
+        assert!(result.index_repaired);
+
+        // the new index matches the archive
+        let result = storage.verify_archive(ARCHIVE_PATH, None, 0, false).await?;
+        assert_eq!(result.checked_files, 0);
+        assert!(result.problems.is_empty());
+
+        let result = storage
+            .verify_archive("missing.zip", None, 10, true)
+            .await?;
+        assert_eq!(
+            result.problems,
+            vec![ArchiveProblem::MissingArchive, ArchiveProblem::MissingIndex]
+        );
+        assert!(!result.index_repaired);
+
+        Ok(())
+    }
+
+    async fn test_read_with_range_requests(storage: &AsyncStorage) -> Result<()> {
+        use std::io::{Read as _, Seek as _, SeekFrom};
+
+        let content: Vec<u8> = (0..RANGE_READER_BLOCK_SIZE * 2 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        storage
+            .store_one_uncompressed("folder/object", content.clone())
+            .await?;
+
+        let (tail, across_blocks, all) = storage
+            .read_with_range_requests("folder/object", |mut reader| {
+                spawn_blocking(move || {
+                    let mut tail = [0; 10];
+                    reader.seek(SeekFrom::End(-10))?;
+                    reader.read_exact(&mut tail)?;
+
+                    let mut across_blocks = [0; 10];
+                    reader.seek(SeekFrom::Start(RANGE_READER_BLOCK_SIZE - 5))?;
+                    reader.read_exact(&mut across_blocks)?;
+
+                    assert!(reader.seek(SeekFrom::Current(-1000)).is_ok());
+                    assert!(reader.seek(SeekFrom::Start(0)).is_ok());
+                    assert!(reader.seek(SeekFrom::Current(-1)).is_err());
+
+                    let mut all = Vec::new();
+                    reader.seek(SeekFrom::Start(0))?;
+                    reader.read_to_end(&mut all)?;
+                    Ok((tail, across_blocks, all))
+                })
+            })
+            .await??;
+
+        let len = content.len();
+        assert_eq!(tail, content[len - 10..]);
+        let block = RANGE_READER_BLOCK_SIZE as usize;
+        assert_eq!(across_blocks, content[block - 5..block + 5]);
+        assert_eq!(all, content);
+
+        assert!(
+            storage
+                .read_with_range_requests("folder/missing", |_| async { Ok(()) })
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    async fn test_verify_content_addressed(storage: &AsyncStorage) -> Result<()> {
+        let dir =
+            create_test_dir(&[("lib.rs", "fn lib() {}"), ("main.rs", "fn main() {}")]).await?;
+        storage
+            .store_all_in_archive("folder/test.zip", dir.path())
+            .await?;
+
+        let result = storage
+            .verify_archive("folder/test.zip", None, 10, false)
+            .await?;
+        assert_eq!(result.checked_files, 2);
+        assert!(result.problems.is_empty());
+        assert!(storage.unreferenced_blobs().await?.is_empty());
+
+        let blobs = list_sorted(storage, CONTENT_BLOB_PREFIX).await?;
+        storage.backend.delete_prefix(&blobs[0]).await?;
+        let result = storage
+            .verify_archive("folder/test.zip", None, 10, false)
+            .await?;
+        assert_eq!(result.checked_files, 1);
+        assert_eq!(
+            result.problems,
+            vec![ArchiveProblem::MissingBlob(blobs[0].clone())]
+        );
+
+        // a blob without references
+        let stray_blob = content_blob_path(&"0".repeat(64), None);
+        storage.store_one_uncompressed(&stray_blob, "stray").await?;
+        assert_eq!(storage.unreferenced_blobs().await?, vec![stray_blob]);
+
+        Ok(())
+    }
+
     async fn test_store_all(storage: &AsyncStorage, metrics: &TestMetrics) -> Result<()> {
         let dir = tempfile::Builder::new()
             .prefix("docs.rs-upload-test")
@@ -953,7 +2869,7 @@ mod backend_tests {
     }
 
     async fn test_s3_large_file_upload_uses_multipart(storage: &AsyncStorage) -> Result<()> {
-        if matches!(storage.config.storage_backend, StorageKind::Memory) {
+        if !matches!(storage.config.storage_backend, StorageKind::S3) {
             return Ok(());
         }
 
@@ -1068,6 +2984,8 @@ mod backend_tests {
             backends { $($backend:ident => $config:expr,)* }
             tests $tests:tt
             tests_with_metrics $tests_with_metrics:tt
+            tests_content_addressed $tests_content_addressed:tt
+            tests_dual_write $tests_dual_write:tt
         ) => {
             $(
                 mod $backend {
@@ -1081,9 +2999,36 @@ mod backend_tests {
                         Ok((storage, metrics))
                     }
 
+                    async fn get_content_addressed_storage() -> anyhow::Result<(TestStorage, TestMetrics)> {
+                        let metrics = TestMetrics::new();
+                        let config = crate::Config::test_config_with_kind($config)?.set(|mut config| {
+                            config.content_addressed_storage = true;
+                            config
+                        });
+                        let storage =
+                            TestStorage::from_config(std::sync::Arc::new(config), metrics.provider()).await?;
+                        Ok((storage, metrics))
+                    }
 
                     backend_tests!(@tests $tests);
                     backend_tests!(@tests_with_metrics $tests_with_metrics);
+                    /// storage migrating from an old filesystem storage, which is returned too.
+                    async fn get_dual_write_storage() -> anyhow::Result<(TestStorage, TestStorage)> {
+                        let metrics = TestMetrics::new();
+                        let old = TestStorage::from_kind(StorageKind::Filesystem, metrics.provider()).await?;
+                        let config = crate::Config::test_config_with_kind($config)?.set(|mut config| {
+                            config.migrate_from = Some(crate::StorageLocation::Filesystem {
+                                path: old.config().local_storage_path.clone(),
+                            });
+                            config
+                        });
+                        let storage =
+                            TestStorage::from_config(std::sync::Arc::new(config), metrics.provider()).await?;
+                        Ok((storage, old))
+                    }
+
+                    backend_tests!(@tests_content_addressed $tests_content_addressed);
+                    backend_tests!(@tests_dual_write $tests_dual_write);
                 }
             )*
         };
@@ -1105,12 +3050,31 @@ mod backend_tests {
                 }
             )*
         };
+        (@tests_content_addressed { $($test:ident,)* }) => {
+            $(
+                #[tokio::test(flavor = "multi_thread")]
+                async fn $test() -> anyhow::Result<()> {
+                    let (storage, _metrics) = get_content_addressed_storage().await?;
+                    super::$test(&storage).await
+                }
+            )*
+        };
+        (@tests_dual_write { $($test:ident,)* }) => {
+            $(
+                #[tokio::test(flavor = "multi_thread")]
+                async fn $test() -> anyhow::Result<()> {
+                    let (storage, old) = get_dual_write_storage().await?;
+                    super::$test(&storage, &old).await
+                }
+            )*
+        };
     }
 
     backend_tests! {
         backends {
             s3 => StorageKind::S3,
             memory => StorageKind::Memory,
+            filesystem => StorageKind::Filesystem,
         }
 
         tests {
@@ -1125,6 +3089,12 @@ mod backend_tests {
             test_delete_prefix_without_matches,
             test_delete_percent,
             test_exists_without_remote_archive,
+            test_stream_from_archive_encoded,
+            test_stream_deflate_from_archive_as_gzip,
+            test_repack_archive,
+            test_verify_archive,
+            test_read_with_range_requests,
+            test_copy_object,
             test_s3_large_file_upload_uses_multipart,
         }
 
@@ -1133,5 +3103,15 @@ mod backend_tests {
             test_store_all,
             test_store_all_in_archive,
         }
+
+        tests_content_addressed {
+            test_store_all_content_addressed,
+            test_replace_content_addressed,
+            test_verify_content_addressed,
+        }
+
+        tests_dual_write {
+            test_dual_write,
+        }
     }
 }
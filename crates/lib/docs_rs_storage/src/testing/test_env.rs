@@ -67,5 +67,9 @@ impl Drop for TestStorage {
         if self.config.archive_index_cache.path.exists() {
             std::fs::remove_dir_all(&self.config.archive_index_cache.path).unwrap();
         }
+
+        if self.config.local_storage_path.exists() {
+            std::fs::remove_dir_all(&self.config.local_storage_path).unwrap();
+        }
     }
 }
@@ -1,4 +1,5 @@
-use std::ops::RangeInclusive;
+use crate::errors::InvalidStorageLocation;
+use std::{fmt, ops::RangeInclusive, path::PathBuf, str::FromStr};
 use strum::EnumString;
 
 pub type FileRange = RangeInclusive<u64>;
@@ -9,6 +10,7 @@ pub enum StorageKind {
     #[cfg(any(test, feature = "testing"))]
     Memory,
     S3,
+    Filesystem,
 }
 
 impl Default for StorageKind {
@@ -19,3 +21,73 @@ impl Default for StorageKind {
         return StorageKind::S3;
     }
 }
+
+/// A storage backend and where it keeps its objects, like `s3://rust-docs-rs`
+/// or `file:///srv/docs.rs/storage`.
+///
+/// Everything else, like the S3 region and endpoint, comes from the main `Config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageLocation {
+    S3 { bucket: String },
+    Filesystem { path: PathBuf },
+}
+
+impl FromStr for StorageLocation {
+    type Err = InvalidStorageLocation;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(bucket) = s.strip_prefix("s3://") {
+            let bucket = bucket.trim_end_matches('/');
+            if !bucket.is_empty() && !bucket.contains('/') {
+                return Ok(Self::S3 {
+                    bucket: bucket.to_owned(),
+                });
+            }
+        } else if let Some(path) = s.strip_prefix("file://")
+            && !path.is_empty()
+        {
+            return Ok(Self::Filesystem { path: path.into() });
+        }
+        Err(InvalidStorageLocation(s.to_owned()))
+    }
+}
+
+impl fmt::Display for StorageLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::S3 { bucket } => write!(f, "s3://{bucket}"),
+            Self::Filesystem { path } => write!(f, "file://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_storage_location() {
+        for (input, expected) in [
+            (
+                "s3://rust-docs-rs",
+                StorageLocation::S3 {
+                    bucket: "rust-docs-rs".into(),
+                },
+            ),
+            (
+                "file:///srv/storage",
+                StorageLocation::Filesystem {
+                    path: "/srv/storage".into(),
+                },
+            ),
+        ] {
+            let location: StorageLocation = input.parse().unwrap();
+            assert_eq!(location, expected);
+            assert_eq!(location.to_string(), input);
+        }
+
+        for input in ["rust-docs-rs", "s3://", "s3://bucket/prefix", "file://"] {
+            assert!(input.parse::<StorageLocation>().is_err(), "{input}");
+        }
+    }
+}
@@ -27,3 +27,34 @@ pub fn rustdoc_json_path(
 pub fn source_archive_path(name: &KrateName, version: &Version) -> String {
     format!("sources/{name}/{version}.zip")
 }
+
+pub(crate) const CONTENT_BLOB_PREFIX: &str = "blobs/";
+
+/// content-addressed blob of a file with this SHA-256 hash, see `Config::content_addressed_storage`.
+///
+/// The hash has a fixed length, so a blob path is never the prefix of another blob path.
+pub(crate) fn content_blob_path(hash: &str, compression: Option<CompressionAlgorithm>) -> String {
+    let compression = compression.map_or("stored", |alg| alg.file_extension());
+    format!("{CONTENT_BLOB_PREFIX}{compression}/{}/{hash}", &hash[..2])
+}
+
+pub(crate) const CONTENT_BLOB_REFS_PREFIX: &str = "blob-refs/";
+
+/// prefix for all references to a content-addressed blob.
+pub(crate) fn content_blob_refs_prefix(blob_path: &str) -> String {
+    format!("{CONTENT_BLOB_REFS_PREFIX}{blob_path}/")
+}
+
+/// marker object that the archive at `archive_path` references a content-addressed blob.
+///
+/// A blob can be deleted when there are no references left.
+pub(crate) fn content_blob_ref_path(blob_path: &str, archive_path: &str) -> String {
+    format!("{}{archive_path}", content_blob_refs_prefix(blob_path))
+}
+
+pub(crate) const RELEASED_BLOB_PREFIX: &str = "released-blobs/";
+
+/// copy of a content-addressed blob while we delete it, in case it's referenced again.
+pub(crate) fn released_blob_path(blob_path: &str) -> String {
+    format!("{RELEASED_BLOB_PREFIX}{blob_path}")
+}
@@ -166,6 +166,11 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub fn keywords(mut self, new: Vec<String>) -> Self {
+        self.package.keywords = new;
+        self
+    }
+
     pub fn add_dependency(mut self, dependency: Dependency) -> Self {
         self.package.dependencies.push(dependency);
         self
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use docs_rs_config::AppConfig;
+use docs_rs_env_vars::{env, maybe_env};
+use std::time::Duration;
+use url::Url;
+
+#[derive(Debug)]
+pub struct Config {
+    /// Base URL for the build & log links in the payloads.
+    pub base_url: Url,
+
+    /// How often we try to deliver an event before giving up.
+    pub max_attempts: u32,
+
+    /// Timeout for a single delivery attempt.
+    pub timeout: Duration,
+
+    /// Key the secrets of the subscriptions are derived from, see
+    /// [`subscriptions::secret`](crate::subscriptions::secret).
+    ///
+    /// Without it we can't sign payloads, pending deliveries wait until it's set.
+    pub secret_key: Option<String>,
+}
+
+impl AppConfig for Config {
+    fn from_environment() -> Result<Self> {
+        Ok(Self {
+            base_url: env(
+                "DOCSRS_WEBHOOK_BASE_URL",
+                "https://docs.rs".parse().unwrap(),
+            )?,
+            max_attempts: env("DOCSRS_WEBHOOK_MAX_ATTEMPTS", 5)?,
+            timeout: Duration::from_secs(env("DOCSRS_WEBHOOK_TIMEOUT", 10)?),
+            secret_key: maybe_env("DOCSRS_WEBHOOK_SECRET_KEY")?,
+        })
+    }
+
+    #[cfg(test)]
+    fn test_config() -> Result<Self> {
+        let mut config = Self::from_environment()?;
+        config.max_attempts = 2;
+        config.secret_key = Some("test key".into());
+        Ok(config)
+    }
+}
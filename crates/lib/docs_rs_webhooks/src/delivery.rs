@@ -0,0 +1,468 @@
+use crate::{
+    Config,
+    subscriptions::{self, PendingDelivery},
+};
+use anyhow::{Context as _, Result, bail};
+use docs_rs_crates_io::signature::{SIGNATURE_HEADER, sign};
+use docs_rs_database::Pool;
+use docs_rs_types::{BuildId, BuildStatus, KrateName, Version};
+use docs_rs_utils::{APP_USER_AGENT, retry_backoff};
+use reqwest::header::CONTENT_TYPE;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::{error, instrument, warn};
+
+/// The HTTP header containing the event name of the payload.
+pub const EVENT_HEADER: &str = "x-docsrs-event";
+
+/// how many pending deliveries we attempt in one run of [`Webhooks::deliver_pending`].
+const MAX_DELIVERIES_PER_RUN: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    BuildStarted,
+    BuildFinished,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BuildStarted => "build.started",
+            Self::BuildFinished => "build.finished",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    client: reqwest::Client,
+    config: Arc<Config>,
+}
+
+impl Webhooks {
+    pub fn new(config: Arc<Config>) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .user_agent(APP_USER_AGENT)
+                .timeout(config.timeout)
+                .build()?,
+            config,
+        })
+    }
+
+    /// Sends the event for a build to all subscribers of its crate.
+    ///
+    /// The deliveries are stored as pending first. The first attempt runs in a background
+    /// task, so it doesn't hold up the caller, failed attempts are retried by
+    /// [`Webhooks::deliver_pending`].
+    #[instrument(skip(self, pool))]
+    pub async fn notify(&self, pool: &Pool, event: WebhookEvent, build_id: BuildId) -> Result<()> {
+        let mut conn = pool.get_async().await?;
+        let ids = self.queue(&mut conn, event, build_id).await?;
+        drop(conn);
+
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let webhooks = self.clone();
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if let Err(err) = webhooks.deliver(&pool, Some(&ids)).await {
+                error!(?err, %build_id, "error delivering webhooks");
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Attempt the pending deliveries that are due, returns how many succeeded.
+    ///
+    /// Every attempt is recorded in the delivery log. Can run concurrently in multiple
+    /// processes, each delivery is only attempted by one of them.
+    pub async fn deliver_pending(&self, pool: &Pool) -> Result<usize> {
+        self.deliver(pool, None).await
+    }
+
+    /// Store a pending delivery of the event to every subscriber of the crate.
+    pub(crate) async fn queue(
+        &self,
+        conn: &mut sqlx::PgConnection,
+        event: WebhookEvent,
+        build_id: BuildId,
+    ) -> Result<Vec<i64>> {
+        let (name, payload) = self.payload(&mut *conn, event, build_id).await?;
+        Ok(
+            subscriptions::queue_deliveries(conn, &name, build_id, event.as_str(), &payload)
+                .await?,
+        )
+    }
+
+    /// Build the JSON payload for an event, returns it together with the crate name.
+    pub(crate) async fn payload(
+        &self,
+        conn: &mut sqlx::PgConnection,
+        event: WebhookEvent,
+        build_id: BuildId,
+    ) -> Result<(KrateName, serde_json::Value)> {
+        let build = sqlx::query!(
+            r#"SELECT
+                crates.name as "name: KrateName",
+                releases.version as "version: Version",
+                builds.build_status as "build_status: BuildStatus",
+                builds.rustc_version,
+                builds.docsrs_version,
+                builds.error_kind,
+                builds.build_started,
+                builds.build_finished,
+                ARRAY(
+                    SELECT log_filename
+                    FROM builds_logs
+                    WHERE builds_logs.build_id = builds.id AND log_filename IS NOT NULL
+                    ORDER BY log_filename
+                ) as "log_filenames!: Vec<String>"
+             FROM builds
+             INNER JOIN releases ON releases.id = builds.rid
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE builds.id = $1"#,
+            build_id as _,
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .with_context(|| format!("could not load build {build_id}"))?;
+
+        let build_url = self
+            .config
+            .base_url
+            .join(&format!(
+                "crate/{}/{}/builds/{}",
+                build.name, build.version, build_id
+            ))?
+            .to_string();
+
+        let log_urls: Vec<_> = build
+            .log_filenames
+            .iter()
+            .map(|filename| format!("{build_url}/{filename}"))
+            .collect();
+
+        let payload = json!({
+            "event": event.as_str(),
+            "crate": {
+                "name": build.name,
+                "version": build.version,
+            },
+            "build": {
+                "id": build_id,
+                "status": build.build_status,
+                "rustc_version": build.rustc_version,
+                "docsrs_version": build.docsrs_version,
+                "error_kind": build.error_kind,
+                "started_at": build.build_started,
+                "finished_at": build.build_finished,
+                "url": build_url,
+                "log_urls": log_urls,
+            },
+        });
+
+        Ok((build.name, payload))
+    }
+
+    /// Attempt the due pending deliveries, or only the given ones.
+    ///
+    /// Deliveries are claimed one at a time, so the lease only has to cover one attempt.
+    async fn deliver(&self, pool: &Pool, ids: Option<&[i64]>) -> Result<usize> {
+        let Some(secret_key) = &self.config.secret_key else {
+            bail!("can't sign webhook payloads, DOCSRS_WEBHOOK_SECRET_KEY is not set");
+        };
+
+        let mut delivered = 0;
+        for _ in 0..MAX_DELIVERIES_PER_RUN {
+            let mut conn = pool.get_async().await?;
+            let Some(delivery) = subscriptions::claim_pending_delivery(
+                &mut conn,
+                ids,
+                // enough time to finish an attempt, and to record it.
+                self.config.timeout * 2,
+            )
+            .await?
+            else {
+                break;
+            };
+            drop(conn);
+
+            if self.attempt(pool, secret_key, &delivery).await? {
+                delivered += 1;
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Make one attempt to deliver a pending delivery.
+    ///
+    /// The attempt is recorded in the delivery log. Failed deliveries are scheduled
+    /// for a retry with backoff, until we reach `max_attempts`. Returns if the
+    /// delivery succeeded.
+    async fn attempt(
+        &self,
+        pool: &Pool,
+        secret_key: &str,
+        delivery: &PendingDelivery,
+    ) -> Result<bool> {
+        let body = serde_json::to_vec(&delivery.payload)?;
+        let secret = subscriptions::secret(secret_key, delivery.subscription_id);
+        let signature = sign(secret.as_bytes(), &body);
+        let attempt = delivery.attempts as u32 + 1;
+
+        let result = self
+            .client
+            .post(&delivery.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .header(EVENT_HEADER, &delivery.event)
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        let (status_code, error) = match &result {
+            Ok(response) => (Some(response.status().as_u16()), None),
+            Err(err) => (err.status().map(|s| s.as_u16()), Some(err.to_string())),
+        };
+
+        let mut conn = pool.get_async().await?;
+        subscriptions::log_delivery(
+            &mut conn,
+            delivery.subscription_id,
+            delivery.build_id,
+            &delivery.event,
+            &delivery.payload,
+            attempt,
+            status_code,
+            error.as_deref(),
+        )
+        .await?;
+
+        if result.is_ok() {
+            subscriptions::remove_pending_delivery(&mut conn, delivery.id).await?;
+            return Ok(true);
+        }
+
+        if attempt < self.config.max_attempts {
+            let backoff = retry_backoff(attempt);
+            warn!(
+                delivery.subscription_id,
+                attempt,
+                ?error,
+                "webhook delivery failed, will try again after {}s",
+                backoff.as_secs(),
+            );
+            subscriptions::reschedule_pending_delivery(
+                &mut conn,
+                delivery.id,
+                attempt as i32,
+                backoff,
+            )
+            .await?;
+        } else {
+            warn!(
+                delivery.subscription_id,
+                attempt,
+                ?error,
+                "webhook delivery failed, giving up"
+            );
+            subscriptions::remove_pending_delivery(&mut conn, delivery.id).await?;
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscriptions::Subscription;
+    use docs_rs_config::AppConfig as _;
+    use docs_rs_crates_io::signature::verify;
+    use docs_rs_database::{
+        releases::{
+            add_build_logs, finish_build, initialize_build, initialize_crate, initialize_release,
+        },
+        testing::TestDatabase,
+    };
+    use docs_rs_opentelemetry::testing::TestMetrics;
+    use docs_rs_types::{
+        SimpleBuildError,
+        testing::{FOO, V1},
+    };
+    use pretty_assertions::assert_eq;
+    use url::Url;
+
+    async fn test_db() -> Result<TestDatabase> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(
+            &docs_rs_database::Config::test_config()?,
+            test_metrics.provider(),
+        )
+        .await?;
+        Ok(db)
+    }
+
+    async fn failed_build(conn: &mut sqlx::PgConnection) -> Result<BuildId> {
+        let crate_id = initialize_crate(&mut *conn, &FOO).await?;
+        let release_id = initialize_release(&mut *conn, crate_id, &V1).await?;
+        let build_id = initialize_build(&mut *conn, release_id).await?;
+        finish_build(
+            &mut *conn,
+            build_id,
+            "rustc 1.0.0",
+            "docsrs 1.0.0",
+            BuildStatus::Failure,
+            None,
+            None,
+            Some(&SimpleBuildError("oops".into())),
+        )
+        .await?;
+        add_build_logs(
+            &mut *conn,
+            build_id,
+            vec![("x86_64-unknown-linux-gnu.txt".into(), false)],
+        )
+        .await?;
+        Ok(build_id)
+    }
+
+    async fn pending_deliveries(conn: &mut sqlx::PgConnection) -> Result<i64> {
+        Ok(
+            sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM webhook_pending_deliveries"#)
+                .fetch_one(conn)
+                .await?,
+        )
+    }
+
+    async fn subscription(conn: &mut sqlx::PgConnection, url: &str) -> Result<Subscription> {
+        let url: Url = url.parse()?;
+        subscriptions::add(&mut *conn, Some(&FOO), &url).await?;
+        Ok(subscriptions::list(&mut *conn).await?.remove(0))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_payload() -> Result<()> {
+        let db = test_db().await?;
+        let mut conn = db.async_conn().await?;
+        let build_id = failed_build(&mut conn).await?;
+
+        let webhooks = Webhooks::new(Arc::new(Config::test_config()?))?;
+        let (name, payload) = webhooks
+            .payload(&mut conn, WebhookEvent::BuildFinished, build_id)
+            .await?;
+
+        let build_url = format!("https://docs.rs/crate/foo/1.0.0/builds/{build_id}");
+        assert_eq!(name, FOO);
+        assert_eq!(payload["event"], "build.finished");
+        assert_eq!(payload["crate"], json!({"name": "foo", "version": "1.0.0"}));
+        assert_eq!(payload["build"]["id"], build_id.0);
+        assert_eq!(payload["build"]["status"], "failure");
+        assert_eq!(payload["build"]["rustc_version"], "rustc 1.0.0");
+        assert_eq!(payload["build"]["docsrs_version"], "docsrs 1.0.0");
+        assert_eq!(payload["build"]["error_kind"], "SimpleBuildError");
+        assert_eq!(payload["build"]["url"], build_url.as_str());
+        assert_eq!(
+            payload["build"]["log_urls"],
+            json!([format!("{build_url}/x86_64-unknown-linux-gnu.txt")])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deliver_signed_payload() -> Result<()> {
+        let db = test_db().await?;
+        let mut conn = db.async_conn().await?;
+        let build_id = failed_build(&mut conn).await?;
+
+        let mut server = mockito::Server::new_async().await;
+        let subscription = subscription(&mut conn, &format!("{}/hook", server.url())).await?;
+
+        let secret = subscriptions::secret("test key", subscription.id);
+        let m = server
+            .mock("POST", "/hook")
+            .match_header(EVENT_HEADER, "build.finished")
+            .match_request(move |request| {
+                let signature = request.header(SIGNATURE_HEADER)[0].to_str().unwrap();
+                verify(secret.as_bytes(), request.body().unwrap(), signature)
+            })
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let webhooks = Webhooks::new(Arc::new(Config::test_config()?))?;
+        let ids = webhooks
+            .queue(&mut conn, WebhookEvent::BuildFinished, build_id)
+            .await?;
+        assert_eq!(ids.len(), 1);
+        assert_eq!(webhooks.deliver(db.pool(), Some(&ids)).await?, 1);
+        m.assert_async().await;
+
+        let deliveries = subscriptions::deliveries(&mut conn, subscription.id, 10).await?;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].build_id, build_id);
+        assert_eq!(deliveries[0].event, "build.finished");
+        assert_eq!(deliveries[0].attempt, 1);
+        assert_eq!(deliveries[0].status_code, Some(200));
+        assert!(deliveries[0].error.is_none());
+
+        // nothing left to deliver
+        assert_eq!(pending_deliveries(&mut conn).await?, 0);
+        assert_eq!(webhooks.deliver_pending(db.pool()).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn retry_failed_delivery() -> Result<()> {
+        let db = test_db().await?;
+        let mut conn = db.async_conn().await?;
+        let build_id = failed_build(&mut conn).await?;
+
+        let mut server = mockito::Server::new_async().await;
+        let subscription = subscription(&mut conn, &format!("{}/hook", server.url())).await?;
+
+        let m = server
+            .mock("POST", "/hook")
+            .with_status(500)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let webhooks = Webhooks::new(Arc::new(Config::test_config()?))?;
+        webhooks
+            .queue(&mut conn, WebhookEvent::BuildStarted, build_id)
+            .await?;
+        assert_eq!(webhooks.deliver_pending(db.pool()).await?, 0);
+
+        // the retry waits for the backoff
+        assert_eq!(pending_deliveries(&mut conn).await?, 1);
+        assert_eq!(webhooks.deliver_pending(db.pool()).await?, 0);
+
+        sqlx::query!("UPDATE webhook_pending_deliveries SET next_attempt_at = NOW()")
+            .execute(&mut *conn)
+            .await?;
+        assert_eq!(webhooks.deliver_pending(db.pool()).await?, 0);
+        m.assert_async().await;
+
+        // we give up after `max_attempts`
+        assert_eq!(pending_deliveries(&mut conn).await?, 0);
+
+        let deliveries = subscriptions::deliveries(&mut conn, subscription.id, 10).await?;
+        assert_eq!(
+            deliveries
+                .iter()
+                .map(|d| (d.attempt, d.status_code, d.error.is_some()))
+                .collect::<Vec<_>>(),
+            vec![(2, Some(500), true), (1, Some(500), true)]
+        );
+
+        Ok(())
+    }
+}
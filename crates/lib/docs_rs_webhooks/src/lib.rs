@@ -0,0 +1,13 @@
+//! Outgoing webhooks for build lifecycle events.
+//!
+//! Subscriptions are managed through the admin CLI. The builder notifies the subscribers
+//! of a crate when a build starts and when it finishes, with a JSON payload signed
+//! like the events crates.io sends to us (see [`docs_rs_crates_io::signature`]).
+//! Deliveries are stored until they succeed or we give up, so retries survive restarts.
+
+mod config;
+mod delivery;
+pub mod subscriptions;
+
+pub use config::Config;
+pub use delivery::{WebhookEvent, Webhooks};
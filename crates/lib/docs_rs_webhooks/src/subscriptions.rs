@@ -0,0 +1,360 @@
+//! Webhook subscriptions and their delivery log.
+//!
+//! We don't store the secrets used to sign the payloads. They are derived from the
+//! configured secret key and the subscription id, see [`secret`].
+
+use chrono::{DateTime, Utc};
+use docs_rs_types::{BuildId, KrateName};
+use futures_util::stream::TryStreamExt;
+use hmac::{Hmac, KeyInit as _, Mac as _};
+use sha2::Sha256;
+use std::time::Duration;
+use url::Url;
+
+type Result<T> = std::result::Result<T, SubscriptionError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SubscriptionError {
+    #[error("webhook subscription {0} does not exist")]
+    SubscriptionNotFound(i32),
+
+    #[error(transparent)]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub id: i32,
+    /// `None` means the subscription receives the events of all crates.
+    pub crate_name: Option<KrateName>,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub build_id: BuildId,
+    pub event: String,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// The secret the payloads for a subscription are signed with.
+///
+/// Changing the secret key changes the secrets of all subscriptions.
+pub fn secret(secret_key: &str, subscription_id: i32) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret_key.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(format!("webhook-subscription-{subscription_id}").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Adds a subscription for the events of a crate, or all crates, and returns its id.
+pub async fn add(
+    conn: &mut sqlx::PgConnection,
+    crate_name: Option<&KrateName>,
+    url: &Url,
+) -> Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO webhook_subscriptions (crate_name, url)
+         VALUES ($1, $2)
+         RETURNING id",
+        crate_name as _,
+        url.as_str(),
+    )
+    .fetch_one(conn)
+    .await?)
+}
+
+/// Returns all subscriptions, sorted by id.
+pub async fn list(conn: &mut sqlx::PgConnection) -> Result<Vec<Subscription>> {
+    Ok(sqlx::query_as!(
+        Subscription,
+        r#"SELECT
+            id,
+            crate_name as "crate_name: KrateName",
+            url,
+            created_at
+         FROM webhook_subscriptions
+         ORDER BY id"#
+    )
+    .fetch(conn)
+    .try_collect()
+    .await?)
+}
+
+/// Returns the subscriptions receiving the events of the given crate.
+pub async fn for_crate(
+    conn: &mut sqlx::PgConnection,
+    name: &KrateName,
+) -> Result<Vec<Subscription>> {
+    Ok(sqlx::query_as!(
+        Subscription,
+        r#"SELECT
+            id,
+            crate_name as "crate_name: KrateName",
+            url,
+            created_at
+         FROM webhook_subscriptions
+         WHERE crate_name IS NULL OR crate_name = $1
+         ORDER BY id"#,
+        name as _,
+    )
+    .fetch(conn)
+    .try_collect()
+    .await?)
+}
+
+/// Removes a subscription, together with its delivery log.
+pub async fn remove(conn: &mut sqlx::PgConnection, id: i32) -> Result<()> {
+    let result = sqlx::query!("DELETE FROM webhook_subscriptions WHERE id = $1", id)
+        .execute(conn)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(SubscriptionError::SubscriptionNotFound(id));
+    }
+
+    Ok(())
+}
+
+/// Returns the latest delivery attempts of a subscription, newest first.
+pub async fn deliveries(
+    conn: &mut sqlx::PgConnection,
+    subscription_id: i32,
+    limit: i64,
+) -> Result<Vec<Delivery>> {
+    Ok(sqlx::query_as!(
+        Delivery,
+        r#"SELECT
+            build_id as "build_id: BuildId",
+            event,
+            attempt,
+            status_code,
+            error,
+            attempted_at
+         FROM webhook_deliveries
+         WHERE subscription_id = $1
+         ORDER BY attempted_at DESC, id DESC
+         LIMIT $2"#,
+        subscription_id,
+        limit,
+    )
+    .fetch(conn)
+    .try_collect()
+    .await?)
+}
+
+/// A delivery that still needs an attempt, with the subscription it goes to.
+#[derive(Debug, Clone)]
+pub(crate) struct PendingDelivery {
+    pub(crate) id: i64,
+    pub(crate) subscription_id: i32,
+    pub(crate) url: String,
+    pub(crate) build_id: BuildId,
+    pub(crate) event: String,
+    pub(crate) payload: serde_json::Value,
+    /// attempts before this one.
+    pub(crate) attempts: i32,
+}
+
+/// Queues a delivery of the payload to every subscriber of the crate, returns their ids.
+pub(crate) async fn queue_deliveries(
+    conn: &mut sqlx::PgConnection,
+    name: &KrateName,
+    build_id: BuildId,
+    event: &str,
+    payload: &serde_json::Value,
+) -> std::result::Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO webhook_pending_deliveries (subscription_id, build_id, event, payload)
+         SELECT id, $2, $3, $4
+         FROM webhook_subscriptions
+         WHERE crate_name IS NULL OR crate_name = $1
+         RETURNING id",
+        name as _,
+        build_id as _,
+        event,
+        payload,
+    )
+    .fetch_all(conn)
+    .await
+}
+
+/// Claims the pending delivery that is due the longest, optionally only one of the given ones.
+///
+/// A claimed delivery isn't due again for `lease`, so concurrent callers don't attempt
+/// it too. When the attempt doesn't finish in time, for example because the process
+/// died, someone else will try again.
+pub(crate) async fn claim_pending_delivery(
+    conn: &mut sqlx::PgConnection,
+    ids: Option<&[i64]>,
+    lease: Duration,
+) -> std::result::Result<Option<PendingDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingDelivery,
+        r#"WITH claimed AS (
+             UPDATE webhook_pending_deliveries
+             SET next_attempt_at = NOW() + make_interval(secs => $2)
+             WHERE id IN (
+                 SELECT id
+                 FROM webhook_pending_deliveries
+                 WHERE next_attempt_at <= NOW() AND ($1::BIGINT[] IS NULL OR id = ANY($1))
+                 ORDER BY next_attempt_at
+                 LIMIT 1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, subscription_id, build_id, event, payload, attempts
+         )
+         SELECT
+             claimed.id,
+             claimed.subscription_id,
+             webhook_subscriptions.url,
+             claimed.build_id as "build_id: BuildId",
+             claimed.event,
+             claimed.payload,
+             claimed.attempts
+         FROM claimed
+         INNER JOIN webhook_subscriptions ON webhook_subscriptions.id = claimed.subscription_id"#,
+        ids,
+        lease.as_secs_f64(),
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+/// Schedules the next attempt of a pending delivery.
+pub(crate) async fn reschedule_pending_delivery(
+    conn: &mut sqlx::PgConnection,
+    id: i64,
+    attempts: i32,
+    backoff: Duration,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE webhook_pending_deliveries
+         SET attempts = $2, next_attempt_at = NOW() + make_interval(secs => $3)
+         WHERE id = $1",
+        id,
+        attempts,
+        backoff.as_secs_f64(),
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Removes a pending delivery after it succeeded, or when we give up on it.
+pub(crate) async fn remove_pending_delivery(
+    conn: &mut sqlx::PgConnection,
+    id: i64,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM webhook_pending_deliveries WHERE id = $1", id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn log_delivery(
+    conn: &mut sqlx::PgConnection,
+    subscription_id: i32,
+    build_id: BuildId,
+    event: &str,
+    payload: &serde_json::Value,
+    attempt: u32,
+    status_code: Option<u16>,
+    error: Option<&str>,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO webhook_deliveries
+            (subscription_id, build_id, event, payload, attempt, status_code, error)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        subscription_id,
+        build_id as _,
+        event,
+        payload,
+        attempt as i32,
+        status_code.map(i32::from),
+        error,
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use docs_rs_config::AppConfig as _;
+    use docs_rs_database::testing::TestDatabase;
+    use docs_rs_opentelemetry::testing::TestMetrics;
+    use docs_rs_types::testing::{BAR, FOO};
+    use pretty_assertions::assert_eq;
+
+    async fn test_db() -> Result<TestDatabase> {
+        let test_metrics = TestMetrics::new();
+        let db = TestDatabase::new(
+            &docs_rs_database::Config::test_config()?,
+            test_metrics.provider(),
+        )
+        .await?;
+        Ok(db)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_add_list_and_remove() -> Result<()> {
+        let db = test_db().await?;
+        let mut conn = db.async_conn().await?;
+
+        let url: Url = "https://example.com/hook".parse()?;
+        let all = add(&mut conn, None, &url).await?;
+        let foo = add(&mut conn, Some(&FOO), &url).await?;
+
+        let subscriptions = list(&mut conn).await?;
+        assert_eq!(
+            subscriptions
+                .iter()
+                .map(|s| (s.id, s.crate_name.clone()))
+                .collect::<Vec<_>>(),
+            vec![(all, None), (foo, Some(FOO))]
+        );
+
+        let ids = |subscriptions: Vec<Subscription>| -> Vec<i32> {
+            subscriptions.into_iter().map(|s| s.id).collect()
+        };
+        assert_eq!(ids(for_crate(&mut conn, &FOO).await?), vec![all, foo]);
+        assert_eq!(ids(for_crate(&mut conn, &BAR).await?), vec![all]);
+
+        remove(&mut conn, all).await?;
+        assert_eq!(ids(list(&mut conn).await?), vec![foo]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_secret() {
+        assert_eq!(secret("key", 1), secret("key", 1));
+        assert_eq!(secret("key", 1).len(), 64);
+        assert_ne!(secret("key", 1), secret("key", 2));
+        assert_ne!(secret("key", 1), secret("other key", 1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_remove_non_existing_subscription() -> Result<()> {
+        let db = test_db().await?;
+        let mut conn = db.async_conn().await?;
+
+        assert!(matches!(
+            remove(&mut conn, 42).await,
+            Err(SubscriptionError::SubscriptionNotFound(42))
+        ));
+
+        Ok(())
+    }
+}
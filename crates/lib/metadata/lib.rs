@@ -38,6 +38,7 @@
 //! ```
 
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::io;
 use std::path::Path;
 
@@ -78,6 +79,110 @@ pub enum MetadataError {
     Parse(#[from] toml::de::Error),
 }
 
+/// The possible errors for [`Metadata::toolchain`].
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ToolchainError {
+    /// The requested toolchain is not a dated nightly.
+    #[error(
+        "invalid toolchain {0:?}, only dated nightly toolchains like `nightly-2024-01-01` are supported"
+    )]
+    Invalid(String),
+    /// The requested toolchain is older than [`NightlyToolchain::OLDEST`].
+    #[error(
+        "toolchain {0} is older than the oldest supported toolchain {oldest}",
+        oldest = NightlyToolchain::OLDEST
+    )]
+    TooOld(NightlyToolchain),
+}
+
+/// A dated nightly toolchain, like `nightly-2024-01-01`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NightlyToolchain {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl NightlyToolchain {
+    /// The oldest toolchain a crate can request.
+    ///
+    /// docs.rs passes unstable flags to `cargo` and `rustdoc` that older nightlies don't know.
+    pub const OLDEST: NightlyToolchain = NightlyToolchain {
+        year: 2024,
+        month: 1,
+        day: 1,
+    };
+
+    /// The year of the nightly.
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    /// The month of the nightly, starting at 1.
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    /// The day of the nightly, starting at 1.
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+}
+
+impl fmt::Display for NightlyToolchain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "nightly-{:04}-{:02}-{:02}",
+            self.year, self.month, self.day
+        )
+    }
+}
+
+impl std::str::FromStr for NightlyToolchain {
+    type Err = ToolchainError;
+
+    /// Parse a toolchain name like `nightly-2024-01-01`.
+    fn from_str(toolchain: &str) -> Result<Self, Self::Err> {
+        let invalid = || ToolchainError::Invalid(toolchain.to_owned());
+
+        let date = toolchain.strip_prefix("nightly-").ok_or_else(invalid)?;
+        let mut parts = date.split('-');
+        let (Some(year), Some(month), Some(day), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if year.len() != 4
+            || month.len() != 2
+            || day.len() != 2
+            || !date.chars().all(|c| c.is_ascii_digit() || c == '-')
+        {
+            return Err(invalid());
+        }
+
+        let year: u16 = year.parse().map_err(|_| invalid())?;
+        let month: u8 = month.parse().map_err(|_| invalid())?;
+        let day: u8 = day.parse().map_err(|_| invalid())?;
+
+        let is_leap_year =
+            year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+        let days_in_month = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if is_leap_year => 29,
+            2 => 28,
+            _ => return Err(invalid()),
+        };
+        if day == 0 || day > days_in_month {
+            return Err(invalid());
+        }
+
+        Ok(NightlyToolchain { year, month, day })
+    }
+}
+
 /// Metadata to set for custom builds.
 ///
 /// This metadata is read from `[package.metadata.docs.rs]` table in `Cargo.toml`.
@@ -97,6 +202,7 @@ pub enum MetadataError {
 /// additional-targets = [ "i686-apple-darwin" ]
 /// rustc-args = [ "--example-rustc-arg" ]
 /// rustdoc-args = [ "--example-rustdoc-arg" ]
+/// toolchain = "nightly-2024-06-01"
 /// ```
 ///
 /// You can define one or more fields in your `Cargo.toml`.
@@ -144,6 +250,9 @@ pub struct Metadata {
     /// List of additional targets to be generated. See [`BuildTargets`].
     #[serde(default)]
     additional_targets: Vec<String>,
+
+    /// The nightly toolchain the crate needs at least, see [`Metadata::toolchain`].
+    toolchain: Option<String>,
 }
 
 /// The targets that should be built for a crate.
@@ -340,6 +449,24 @@ impl Metadata {
         cargo_args
     }
 
+    /// Return the dated nightly toolchain requested with `toolchain`, if any.
+    ///
+    /// Only dated nightlies not older than [`NightlyToolchain::OLDEST`] can be requested.
+    /// The requested nightly is the oldest one the crate can be built with. docs.rs
+    /// builds with the default toolchain when it isn't older, and with the requested
+    /// toolchain only when it is in the range of toolchains docs.rs currently allows.
+    pub fn toolchain(&self) -> Result<Option<NightlyToolchain>, ToolchainError> {
+        let Some(toolchain) = &self.toolchain else {
+            return Ok(None);
+        };
+
+        let toolchain: NightlyToolchain = toolchain.trim().parse()?;
+        if toolchain < NightlyToolchain::OLDEST {
+            return Err(ToolchainError::TooOld(toolchain));
+        }
+        Ok(Some(toolchain))
+    }
+
     /// Return the environment variables that should be set when building this crate.
     pub fn environment_variables(&self) -> HashMap<&'static str, String> {
         let mut map = HashMap::new();
@@ -458,6 +585,59 @@ mod test_parsing {
         assert_eq!(cargo_args.as_slice(), &["-Zbuild-std"]);
     }
 
+    #[test]
+    fn test_toolchain() {
+        let toolchain = |value: &str| {
+            Metadata::from_str(&format!(
+                r#"
+                [package.metadata.docs.rs]
+                toolchain = "{value}"
+            "#
+            ))
+            .unwrap()
+            .toolchain()
+        };
+
+        assert_eq!(Metadata::default().toolchain(), Ok(None));
+
+        let nightly = toolchain("nightly-2024-02-29").unwrap().unwrap();
+        assert_eq!(
+            (nightly.year(), nightly.month(), nightly.day()),
+            (2024, 2, 29)
+        );
+        assert_eq!(nightly.to_string(), "nightly-2024-02-29");
+        assert_eq!(
+            toolchain("nightly-2024-01-01"),
+            Ok(Some(NightlyToolchain::OLDEST))
+        );
+
+        for invalid in [
+            "nightly",
+            "stable",
+            "1.80.0",
+            "beta-2024-06-01",
+            "nightly-2024-6-01",
+            "nightly-2024-13-01",
+            "nightly-2023-02-29",
+            "nightly-2024-06-00",
+            "nightly-2024-06-01-x86_64-unknown-linux-gnu",
+            "nightly-+024-06-01",
+        ] {
+            assert_eq!(
+                toolchain(invalid),
+                Err(ToolchainError::Invalid(invalid.into())),
+                "{invalid}"
+            );
+        }
+
+        assert_eq!(
+            toolchain("nightly-2023-12-31"),
+            Err(ToolchainError::TooOld(
+                "nightly-2023-12-31".parse().unwrap()
+            ))
+        );
+    }
+
     #[test]
     fn test_no_targets() {
         // metadata section but no targets